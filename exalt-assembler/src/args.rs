@@ -1,58 +1,39 @@
-use anyhow::{bail, Result};
-
-use exalt_lir::{CallbackArg, Function, Game};
+use crate::error::{AssemblyError, Result};
 use crate::types::CodeGenTextData;
+use crate::util::WriteEndian;
+use exalt_lir::{CallbackArg, Function, Game};
 
-fn serialize_gcn_args(function: &Function, text_data: &mut CodeGenTextData) -> Result<Vec<u8>> {
+pub fn serialize_args(
+    function: &Function,
+    text_data: &mut CodeGenTextData,
+    game: Game,
+) -> Result<Vec<u8>> {
     if function.event == 0 && !function.args.is_empty() {
-        bail!("function/event arguments cannot be used with function type 0.");
+        return Err(AssemblyError::ArgsOnPlainFunction);
     }
+    let platform = game.platform();
+    let endian = platform.header_endian;
     let mut raw = Vec::new();
     for arg in &function.args {
-        match arg {
-            CallbackArg::Str(v) => {
-                let offset = text_data.offset(v)? as u16;
-                raw.extend(offset.to_le_bytes().iter());
-            }
-            CallbackArg::Int(v) => raw.extend((*v as u16).to_le_bytes().iter()),
-            _ => bail!("GameCube/Wii versions do not support floats"),
+        let value = match arg {
+            CallbackArg::Str(v) => text_data.offset(v)? as u32,
+            CallbackArg::Int(v) => *v as u32,
+            CallbackArg::Float(v) if platform.float_args => v.to_bits(),
+            CallbackArg::Float(_) => return Err(AssemblyError::UnsupportedFloatArg(game)),
+        };
+        match platform.arg_size {
+            2 => raw.put_u16(value as u16, endian),
+            _ => raw.put_u32(value, endian),
         }
     }
 
     // Hack to deal with padding when prefix data is present.
-    if !raw.is_empty() {
-        while (raw.len() + function.prefix.len()) % 4 != 0 {
+    // Wide arguments always end on a word and prefix data is never written alongside them.
+    if !raw.is_empty() && platform.arg_size < 4 {
+        while !(raw.len() + function.prefix.len()).is_multiple_of(4) {
             raw.push(0);
         }
     }
 
     Ok(raw)
 }
-
-fn serialize_three_ds_args(
-    function: &Function,
-    text_data: &mut CodeGenTextData,
-) -> Result<Vec<u8>> {
-    if function.event == 0 && !function.args.is_empty() {
-        bail!("function/event arguments cannot be used with function type 0.");
-    }
-    let mut bytes = Vec::new();
-    for arg in &function.args {
-        match arg {
-            CallbackArg::Str(v) => {
-                let offset = text_data.offset(v)? as u32;
-                bytes.extend(offset.to_le_bytes().iter());
-            }
-            CallbackArg::Int(v) => bytes.extend(v.to_le_bytes().iter()),
-            CallbackArg::Float(v) => bytes.extend(v.to_le_bytes().iter()),
-        }
-    }
-    Ok(bytes)
-}
-
-pub fn serialize_args(function: &Function, text_data: &mut CodeGenTextData, game: Game) -> Result<Vec<u8>> {
-    match game {
-        Game::FE9 | Game::FE10 | Game::FE11 | Game::FE12 => serialize_gcn_args(function, text_data),
-        Game::FE13 | Game::FE14 | Game::FE15 => serialize_three_ds_args(function, text_data),
-    }
-}
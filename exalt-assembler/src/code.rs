@@ -1,20 +1,27 @@
-use anyhow::{bail, Context, Result};
-
+use crate::error::{AssemblyError, Result};
 use crate::types::{CodeGenState, CodeGenTextData};
-use exalt_lir::{Game, Opcode};
+use crate::util::WriteEndian;
+use exalt_lir::{Endian, Game, Opcode, OpcodeSet};
 
-pub fn write_byte_or_short(out: &mut Vec<u8>, value: u16, byte_opcode: u8, short_opcode: u8) {
+pub fn write_byte_or_short(
+    out: &mut Vec<u8>,
+    endian: Endian,
+    value: u16,
+    byte_opcode: u8,
+    short_opcode: u8,
+) {
     if value <= 0x7F {
         out.push(byte_opcode);
         out.push(value as u8);
     } else {
         out.push(short_opcode);
-        out.extend(value.to_be_bytes().iter());
+        out.put_u16(value, endian);
     }
 }
 
 pub fn write_byte_or_short_or_int(
     out: &mut Vec<u8>,
+    endian: Endian,
     value: u32,
     byte_opcode: u8,
     short_opcode: u8,
@@ -25,10 +32,10 @@ pub fn write_byte_or_short_or_int(
         out.push(value as u8);
     } else if value <= 0x7FFF {
         out.push(short_opcode);
-        out.extend((value as u16).to_be_bytes().iter());
+        out.put_u16(value as u16, endian);
     } else {
         out.push(int_opcode);
-        out.extend(value.to_be_bytes().iter());
+        out.put_u32(value, endian);
     }
 }
 
@@ -55,33 +62,33 @@ fn serialize_gcn_opcode(
     let addr = bytes.len();
     match opcode {
         Opcode::Done => bytes.push(0),
-        Opcode::VarLoad(v) => write_byte_or_short(bytes, *v, 0x1, 0x2),
-        Opcode::ArrLoad(v) => write_byte_or_short(bytes, *v, 0x3, 0x4),
-        Opcode::PtrLoad(v) => write_byte_or_short(bytes, *v, 0x5, 0x6),
-        Opcode::VarAddr(v) => write_byte_or_short(bytes, *v, 0x7, 0x8),
-        Opcode::ArrAddr(v) => write_byte_or_short(bytes, *v, 0x9, 0xA),
-        Opcode::PtrAddr(v) => write_byte_or_short(bytes, *v, 0xB, 0xC),
-        Opcode::GlobalVarLoad(v) => write_byte_or_short(bytes, *v, 0xD, 0xE),
-        Opcode::GlobalArrLoad(v) => write_byte_or_short(bytes, *v, 0xF, 0x10),
-        Opcode::GlobalPtrLoad(v) => write_byte_or_short(bytes, *v, 0x11, 0x12),
-        Opcode::GlobalVarAddr(v) => write_byte_or_short(bytes, *v, 0x13, 0x14),
-        Opcode::GlobalArrAddr(v) => write_byte_or_short(bytes, *v, 0x15, 0x16),
-        Opcode::GlobalPtrAddr(v) => write_byte_or_short(bytes, *v, 0x17, 0x18),
+        Opcode::VarLoad(v) => write_byte_or_short(bytes, state.endian, *v, 0x1, 0x2),
+        Opcode::ArrLoad(v) => write_byte_or_short(bytes, state.endian, *v, 0x3, 0x4),
+        Opcode::PtrLoad(v) => write_byte_or_short(bytes, state.endian, *v, 0x5, 0x6),
+        Opcode::VarAddr(v) => write_byte_or_short(bytes, state.endian, *v, 0x7, 0x8),
+        Opcode::ArrAddr(v) => write_byte_or_short(bytes, state.endian, *v, 0x9, 0xA),
+        Opcode::PtrAddr(v) => write_byte_or_short(bytes, state.endian, *v, 0xB, 0xC),
+        Opcode::GlobalVarLoad(v) => write_byte_or_short(bytes, state.endian, *v, 0xD, 0xE),
+        Opcode::GlobalArrLoad(v) => write_byte_or_short(bytes, state.endian, *v, 0xF, 0x10),
+        Opcode::GlobalPtrLoad(v) => write_byte_or_short(bytes, state.endian, *v, 0x11, 0x12),
+        Opcode::GlobalVarAddr(v) => write_byte_or_short(bytes, state.endian, *v, 0x13, 0x14),
+        Opcode::GlobalArrAddr(v) => write_byte_or_short(bytes, state.endian, *v, 0x15, 0x16),
+        Opcode::GlobalPtrAddr(v) => write_byte_or_short(bytes, state.endian, *v, 0x17, 0x18),
         Opcode::IntLoad(v) => {
             if *v >= i8::MIN as i32 && *v <= i8::MAX as i32 {
                 bytes.push(0x19);
                 bytes.extend((*v as i8).to_be_bytes().iter());
             } else if *v >= i16::MIN as i32 && *v <= i16::MAX as i32 {
                 bytes.push(0x1A);
-                bytes.extend((*v as i16).to_be_bytes().iter());
+                bytes.put_i16(*v as i16, state.endian);
             } else {
                 bytes.push(0x1B);
-                bytes.extend((*v).to_be_bytes().iter());
+                bytes.put_i32(*v, state.endian);
             }
         }
         Opcode::StrLoad(v) => {
             let offset = state.text_data.offset(v)?;
-            write_byte_or_short_or_int(bytes, offset as u32, 0x1C, 0x1D, 0x1E);
+            write_byte_or_short_or_int(bytes, state.endian, offset as u32, 0x1C, 0x1D, 0x1E);
         }
         Opcode::Dereference => bytes.push(0x1F),
         Opcode::Consume => bytes.push(0x20),
@@ -114,7 +121,7 @@ fn serialize_gcn_opcode(
         Opcode::CallByName(n, c) => {
             let name_offset = state.text_data.offset(n)? as u16;
             bytes.push(0x38);
-            bytes.extend(name_offset.to_be_bytes().iter());
+            bytes.put_u16(name_offset, state.endian);
             bytes.push(*c);
         }
         Opcode::Return => bytes.push(0x39),
@@ -129,8 +136,8 @@ fn serialize_gcn_opcode(
             bytes.push(0x41);
             bytes.push(*v);
         }
-        Opcode::Label(l) => state.add_label(l, addr)?,
-        _ => bail!("unsupported gcn opcode {:?}", opcode),
+        Opcode::Label(l) => state.add_label(l, addr),
+        _ => return Err(AssemblyError::UnsupportedOpcode(opcode.clone())),
     }
     Ok(())
 }
@@ -143,33 +150,33 @@ fn serialize_wii_opcode(
     let addr = bytes.len();
     match opcode {
         Opcode::Done => bytes.push(0),
-        Opcode::VarLoad(v) => write_byte_or_short(bytes, *v, 0x1, 0x2),
-        Opcode::ArrLoad(v) => write_byte_or_short(bytes, *v, 0x3, 0x4),
-        Opcode::PtrLoad(v) => write_byte_or_short(bytes, *v, 0x5, 0x6),
-        Opcode::VarAddr(v) => write_byte_or_short(bytes, *v, 0x7, 0x8),
-        Opcode::ArrAddr(v) => write_byte_or_short(bytes, *v, 0x9, 0xA),
-        Opcode::PtrAddr(v) => write_byte_or_short(bytes, *v, 0xB, 0xC),
-        Opcode::GlobalVarLoad(v) => write_byte_or_short(bytes, *v, 0xD, 0xE),
-        Opcode::GlobalArrLoad(v) => write_byte_or_short(bytes, *v, 0xF, 0x10),
-        Opcode::GlobalPtrLoad(v) => write_byte_or_short(bytes, *v, 0x11, 0x12),
-        Opcode::GlobalVarAddr(v) => write_byte_or_short(bytes, *v, 0x13, 0x14),
-        Opcode::GlobalArrAddr(v) => write_byte_or_short(bytes, *v, 0x15, 0x16),
-        Opcode::GlobalPtrAddr(v) => write_byte_or_short(bytes, *v, 0x17, 0x18),
+        Opcode::VarLoad(v) => write_byte_or_short(bytes, state.endian, *v, 0x1, 0x2),
+        Opcode::ArrLoad(v) => write_byte_or_short(bytes, state.endian, *v, 0x3, 0x4),
+        Opcode::PtrLoad(v) => write_byte_or_short(bytes, state.endian, *v, 0x5, 0x6),
+        Opcode::VarAddr(v) => write_byte_or_short(bytes, state.endian, *v, 0x7, 0x8),
+        Opcode::ArrAddr(v) => write_byte_or_short(bytes, state.endian, *v, 0x9, 0xA),
+        Opcode::PtrAddr(v) => write_byte_or_short(bytes, state.endian, *v, 0xB, 0xC),
+        Opcode::GlobalVarLoad(v) => write_byte_or_short(bytes, state.endian, *v, 0xD, 0xE),
+        Opcode::GlobalArrLoad(v) => write_byte_or_short(bytes, state.endian, *v, 0xF, 0x10),
+        Opcode::GlobalPtrLoad(v) => write_byte_or_short(bytes, state.endian, *v, 0x11, 0x12),
+        Opcode::GlobalVarAddr(v) => write_byte_or_short(bytes, state.endian, *v, 0x13, 0x14),
+        Opcode::GlobalArrAddr(v) => write_byte_or_short(bytes, state.endian, *v, 0x15, 0x16),
+        Opcode::GlobalPtrAddr(v) => write_byte_or_short(bytes, state.endian, *v, 0x17, 0x18),
         Opcode::IntLoad(v) => {
             if *v >= i8::MIN as i32 && *v <= i8::MAX as i32 {
                 bytes.push(0x19);
                 bytes.extend((*v as i8).to_be_bytes().iter());
             } else if *v >= i16::MIN as i32 && *v <= i16::MAX as i32 {
                 bytes.push(0x1A);
-                bytes.extend((*v as i16).to_be_bytes().iter());
+                bytes.put_i16(*v as i16, state.endian);
             } else {
                 bytes.push(0x1B);
-                bytes.extend((*v).to_be_bytes().iter());
+                bytes.put_i32(*v, state.endian);
             }
         }
         Opcode::StrLoad(v) => {
             let offset = state.text_data.offset(v)?;
-            write_byte_or_short_or_int(bytes, offset as u32, 0x1C, 0x1D, 0x1E);
+            write_byte_or_short_or_int(bytes, state.endian, offset as u32, 0x1C, 0x1D, 0x1E);
         }
         Opcode::Dereference => bytes.push(0x1F),
         Opcode::Consume => bytes.push(0x20),
@@ -201,6 +208,7 @@ fn serialize_wii_opcode(
                 bytes.push(*v as u8);
             } else {
                 let v = *v as u16;
+                // The flag has to come first no matter the platform's byte order
                 let extended = 0x8000 | (v & 0x7F) | ((v & 0x7F80) << 1);
                 bytes.extend(extended.to_be_bytes().iter());
             }
@@ -208,7 +216,7 @@ fn serialize_wii_opcode(
         Opcode::CallByName(n, c) => {
             let name_offset = state.text_data.offset(n)? as u16;
             bytes.push(0x38);
-            bytes.extend(name_offset.to_be_bytes().iter());
+            bytes.put_u16(name_offset, state.endian);
             bytes.push(*c);
         }
         Opcode::Return => bytes.push(0x39),
@@ -229,8 +237,8 @@ fn serialize_wii_opcode(
         Opcode::ReturnFalse => bytes.push(0x45),
         Opcode::ReturnTrue => bytes.push(0x46),
         Opcode::Assign => bytes.push(0x47),
-        Opcode::Label(l) => state.add_label(l, addr)?,
-        _ => bail!("unsupported wii opcode {:?}", opcode),
+        Opcode::Label(l) => state.add_label(l, addr),
+        _ => return Err(AssemblyError::UnsupportedOpcode(opcode.clone())),
     }
     Ok(())
 }
@@ -243,31 +251,31 @@ fn serialize_three_ds_opcode(
     let addr = bytes.len();
     match opcode {
         Opcode::Done => bytes.push(0),
-        Opcode::VarLoad(v) => write_byte_or_short(bytes, *v, 0x1, 0x2),
-        Opcode::ArrLoad(v) => write_byte_or_short(bytes, *v, 0x3, 0x4),
-        Opcode::PtrLoad(v) => write_byte_or_short(bytes, *v, 0x5, 0x6),
-        Opcode::VarAddr(v) => write_byte_or_short(bytes, *v, 0x7, 0x8),
-        Opcode::ArrAddr(v) => write_byte_or_short(bytes, *v, 0x9, 0xA),
-        Opcode::PtrAddr(v) => write_byte_or_short(bytes, *v, 0xB, 0xC),
+        Opcode::VarLoad(v) => write_byte_or_short(bytes, state.endian, *v, 0x1, 0x2),
+        Opcode::ArrLoad(v) => write_byte_or_short(bytes, state.endian, *v, 0x3, 0x4),
+        Opcode::PtrLoad(v) => write_byte_or_short(bytes, state.endian, *v, 0x5, 0x6),
+        Opcode::VarAddr(v) => write_byte_or_short(bytes, state.endian, *v, 0x7, 0x8),
+        Opcode::ArrAddr(v) => write_byte_or_short(bytes, state.endian, *v, 0x9, 0xA),
+        Opcode::PtrAddr(v) => write_byte_or_short(bytes, state.endian, *v, 0xB, 0xC),
         Opcode::IntLoad(v) => {
             if *v >= i8::MIN as i32 && *v <= i8::MAX as i32 {
                 bytes.push(0x19);
                 bytes.extend((*v as i8).to_be_bytes().iter());
             } else if *v >= i16::MIN as i32 && *v <= i16::MAX as i32 {
                 bytes.push(0x1A);
-                bytes.extend((*v as i16).to_be_bytes().iter());
+                bytes.put_i16(*v as i16, state.endian);
             } else {
                 bytes.push(0x1B);
-                bytes.extend((*v).to_be_bytes().iter());
+                bytes.put_i32(*v, state.endian);
             }
         }
         Opcode::StrLoad(v) => {
             let offset = state.text_data.offset(v)?;
-            write_byte_or_short_or_int(bytes, offset as u32, 0x1C, 0x1D, 0x1E);
+            write_byte_or_short_or_int(bytes, state.endian, offset as u32, 0x1C, 0x1D, 0x1E);
         }
         Opcode::FloatLoad(v) => {
             bytes.push(0x1F);
-            bytes.extend_from_slice(&v.to_be_bytes());
+            bytes.put_f32(*v, state.endian);
         }
         Opcode::Dereference => bytes.push(0x20),
         Opcode::Consume => bytes.push(0x21),
@@ -294,7 +302,7 @@ fn serialize_three_ds_opcode(
         Opcode::RightShift => bytes.push(0x37),
         Opcode::Equal => bytes.push(0x38),
         Opcode::FloatEqual => bytes.push(0x39),
-        Opcode::Exlcall => todo!(),
+        Opcode::Exlcall => bytes.push(0x3A),
         Opcode::NotEqual => bytes.push(0x3B),
         Opcode::FloatNotEqual => bytes.push(0x3C),
         Opcode::Nop0x3D => bytes.push(0x3D),
@@ -312,6 +320,7 @@ fn serialize_three_ds_opcode(
                 bytes.push(*v as u8);
             } else {
                 let v = *v as u16;
+                // The flag has to come first no matter the platform's byte order
                 let extended = 0x8000 | (v & 0x7F) | ((v & 0x7F80) << 1);
                 bytes.extend(extended.to_be_bytes().iter());
             }
@@ -319,7 +328,7 @@ fn serialize_three_ds_opcode(
         Opcode::CallByName(n, c) => {
             let name_offset = state.text_data.offset(n)? as u16;
             bytes.push(0x47);
-            bytes.extend(name_offset.to_be_bytes().iter());
+            bytes.put_u16(name_offset, state.endian);
             bytes.push(*c);
         }
         Opcode::Return => bytes.push(0x48),
@@ -338,29 +347,42 @@ fn serialize_three_ds_opcode(
         Opcode::Copy => bytes.push(0x53),
         Opcode::ReturnFalse => bytes.push(0x54),
         Opcode::ReturnTrue => bytes.push(0x55),
-        Opcode::Label(l) => state.add_label(l, addr)?,
-        _ => bail!("unsupported 3ds opcode {:?}", opcode),
+        Opcode::Label(l) => state.add_label(l, addr),
+        _ => return Err(AssemblyError::UnsupportedOpcode(opcode.clone())),
     }
     Ok(())
 }
 
+/// Returns the raw code along with the offset of each opcode in it.
 pub fn serialize_opcodes(
     opcodes: &[Opcode],
     text_data: &mut CodeGenTextData,
     game: Game,
-) -> Result<Vec<u8>> {
-    let assembler = match game {
-        Game::FE9 => serialize_gcn_opcode,
-        Game::FE10 | Game::FE11 | Game::FE12 => serialize_wii_opcode,
-        Game::FE13 | Game::FE14 | Game::FE15 => serialize_three_ds_opcode,
+) -> Result<(Vec<u8>, Vec<usize>)> {
+    let assembler = match game.profile().opcodes {
+        OpcodeSet::Gcn => serialize_gcn_opcode,
+        OpcodeSet::Wii => serialize_wii_opcode,
+        OpcodeSet::ThreeDs => serialize_three_ds_opcode,
     };
-    let mut code_gen_state = CodeGenState::new(text_data);
+    let mut code_gen_state = CodeGenState::new(text_data, game.platform().code_endian);
     let mut raw_code = Vec::new();
-    for op in opcodes {
-        assembler(op, &mut raw_code, &mut code_gen_state)
-            .with_context(|| format!("failed to serialize opcode format: '{:?}'", op))?;
+    let mut offsets = Vec::with_capacity(opcodes.len());
+    for (index, op) in opcodes.iter().enumerate() {
+        offsets.push(raw_code.len());
+        if let Opcode::Unknown(code, rest) = op {
+            // Raw bytes don't depend on the opcode set
+            raw_code.push(*code);
+            raw_code.extend_from_slice(rest);
+            continue;
+        }
+        assembler(op, &mut raw_code, &mut code_gen_state).map_err(|error| {
+            AssemblyError::Opcode {
+                index,
+                error: Box::new(error),
+            }
+        })?;
     }
     raw_code.push(0);
-    code_gen_state.backpatch(&mut raw_code)?;
-    Ok(raw_code)
+    code_gen_state.backpatch(&mut raw_code);
+    Ok((raw_code, offsets))
 }
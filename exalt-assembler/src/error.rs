@@ -0,0 +1,82 @@
+use exalt_lir::{Game, Opcode, TextEncoding};
+use thiserror::Error;
+
+pub(crate) type Result<T> = std::result::Result<T, AssemblyError>;
+
+/// Why a script couldn't be assembled.
+#[derive(Debug, Error)]
+pub enum AssemblyError {
+    #[error("function/event arguments cannot be used with function type 0")]
+    ArgsOnPlainFunction,
+
+    #[error("{0:?} does not support float arguments")]
+    UnsupportedFloatArg(Game),
+
+    #[error("unsupported opcode {0:?}")]
+    UnsupportedOpcode(Opcode),
+
+    #[error("script name '{name}' is too long (limit is {limit} bytes)")]
+    ScriptNameTooLong { name: String, limit: usize },
+
+    #[error("failed to encode string '{text}' as {encoding}")]
+    InvalidText {
+        text: String,
+        encoding: TextEncoding,
+    },
+
+    #[error("label '{label}' is defined by opcodes {first} and {second} in function {}", function_name(*.function, .name))]
+    DuplicateLabel {
+        label: String,
+        first: usize,
+        second: usize,
+        function: usize,
+        name: Option<String>,
+    },
+
+    #[error("undefined label '{label}' referenced by opcode {opcode} in function {}", function_name(*.function, .name))]
+    UndefinedLabel {
+        label: String,
+        opcode: usize,
+        function: usize,
+        name: Option<String>,
+    },
+
+    #[error("'{0}' does not exist in hard coded text data")]
+    MissingText(String),
+
+    #[error("text data at 0x{start:X}..0x{end:X} is out of bounds (script is 0x{len:X} bytes)")]
+    TextDataOutOfBounds {
+        start: usize,
+        end: usize,
+        len: usize,
+    },
+
+    #[error("unexpected end of data")]
+    Truncated,
+
+    #[error("failed to serialize opcode {index}: {error}")]
+    Opcode {
+        index: usize,
+        error: Box<AssemblyError>,
+    },
+
+    #[error("failed to serialize function {index}{}: {error}", quoted_name(.name))]
+    Function {
+        index: usize,
+        name: Option<String>,
+        error: Box<AssemblyError>,
+    },
+}
+
+fn function_name(index: usize, name: &Option<String>) -> String {
+    match name {
+        Some(name) => format!("'{}'", name),
+        None => index.to_string(),
+    }
+}
+
+fn quoted_name(name: &Option<String>) -> String {
+    name.as_ref()
+        .map(|n| format!(" '{}'", n))
+        .unwrap_or_default()
+}
@@ -1,12 +1,45 @@
-use anyhow::{Context, Result};
+use rustc_hash::FxHashMap;
 
+use crate::error::{AssemblyError, Result};
+use crate::util::WriteEndian;
 use crate::{util, args, code};
 
 const GCN_AND_WII_FUNCTION_HEADER_SIZE: usize = 0x14;
 const THREE_DS_FUNCTION_HEADER_SIZE: u32 = 0x18;
 
 use crate::types::{RawFunction, RawFunctionHeader, CodeGenTextData};
-use exalt_lir::{Function, Game};
+use exalt_lir::{Endian, Function, Game, HeaderLayout, Opcode};
+
+/// Check that every label is defined once and every jump goes to a defined label.
+pub fn resolve_labels(function: &Function, index: usize) -> Result<()> {
+    let mut definitions: FxHashMap<&str, usize> = FxHashMap::default();
+    for (i, opcode) in function.code.iter().enumerate() {
+        if let Opcode::Label(label) = opcode {
+            if let Some(first) = definitions.insert(label, i) {
+                return Err(AssemblyError::DuplicateLabel {
+                    label: label.clone(),
+                    first,
+                    second: i,
+                    function: index,
+                    name: function.name.clone(),
+                });
+            }
+        }
+    }
+    for (i, opcode) in function.code.iter().enumerate() {
+        if let Some(label) = opcode.label_target() {
+            if !definitions.contains_key(label) {
+                return Err(AssemblyError::UndefinedLabel {
+                    label: label.to_owned(),
+                    opcode: i,
+                    function: index,
+                    name: function.name.clone(),
+                });
+            }
+        }
+    }
+    Ok(())
+}
 
 fn convert_to_raw_gcn_function(
     function: &Function,
@@ -15,19 +48,18 @@ fn convert_to_raw_gcn_function(
 ) -> Result<RawFunction> {
     let name_bytes = match &function.name {
         Some(v) => {
-            let mut bytes = util::encode_shift_jis(v)?;
+            let mut bytes = util::encode_text(v, text_data.codec)?;
             bytes.push(0);
             // Pad to the next word.
             // Prefix goes between the null terminator and the next word, so it may cover part of the padding.
-            while (bytes.len() + function.prefix.len()) % 4 != 0 {
+            while !(bytes.len() + function.prefix.len()).is_multiple_of(4) {
                 bytes.push(0);
             }
             bytes
         }
         None => Vec::new(),
     };
-    let raw_args = args::serialize_args(function, text_data, game)
-        .context("Failed to write function arguments.")?;
+    let raw_args = args::serialize_args(function, text_data, game)?;
     let code_address = if name_bytes.is_empty() {
         (GCN_AND_WII_FUNCTION_HEADER_SIZE + raw_args.len() + function.prefix.len()) as u32
     } else {
@@ -51,11 +83,13 @@ fn convert_to_raw_gcn_function(
         prefix: function.prefix.clone(),
         suffix: function.suffix.clone(),
     };
+    let (code, opcode_offsets) = code::serialize_opcodes(&function.code, text_data, game)?;
     Ok(RawFunction {
         header: raw_function_header,
         name: name_bytes,
         args: raw_args,
-        code: code::serialize_opcodes(&function.code, text_data, game)?,
+        code,
+        opcode_offsets,
     })
 }
 
@@ -66,7 +100,7 @@ fn convert_to_raw_three_ds_function(
 ) -> Result<RawFunction> {
     let name_bytes = if let Some(name) = &function.name {
         if function.event == 0 && name.contains("::") {
-            let mut bytes = util::encode_shift_jis(name)?;
+            let mut bytes = util::encode_text(name, text_data.codec)?;
             bytes.push(0);
             bytes
         } else {
@@ -75,8 +109,7 @@ fn convert_to_raw_three_ds_function(
     } else {
         Vec::new()
     };
-    let raw_args = args::serialize_args(function, text_data, game)
-        .context("failed to write function arguments")?;
+    let raw_args = args::serialize_args(function, text_data, game)?;
     let extended_header_address = THREE_DS_FUNCTION_HEADER_SIZE;
     let code_address = if name_bytes.is_empty() {
         extended_header_address + (raw_args.len() as u32)
@@ -84,7 +117,7 @@ fn convert_to_raw_three_ds_function(
         extended_header_address + (name_bytes.len() as u32)
     };
     let name_address = if !name_bytes.is_empty() {
-        Some(extended_header_address as u32)
+        Some(extended_header_address)
     } else {
         None
     };
@@ -106,18 +139,20 @@ fn convert_to_raw_three_ds_function(
         prefix: Vec::new(),
         suffix: Vec::new(),
     };
+    let (code, opcode_offsets) = code::serialize_opcodes(&function.code, text_data, game)?;
     Ok(RawFunction {
         header: raw_function_header,
         name: name_bytes,
         args: raw_args,
-        code: code::serialize_opcodes(&function.code, text_data, game)?,
+        code,
+        opcode_offsets,
     })
 }
 
 pub fn convert_to_raw_function(function: &Function, text_data: &mut CodeGenTextData, game: Game) -> Result<RawFunction> {
-    match game {
-        Game::FE9 | Game::FE10 | Game::FE11 | Game::FE12 => convert_to_raw_gcn_function(function, text_data, game),
-        Game::FE13 | Game::FE14 | Game::FE15 => convert_to_raw_three_ds_function(function, text_data, game),
+    match game.profile().header {
+        HeaderLayout::Gcn => convert_to_raw_gcn_function(function, text_data, game),
+        HeaderLayout::ThreeDs => convert_to_raw_three_ds_function(function, text_data, game),
     }
 }
 
@@ -125,24 +160,25 @@ fn serialize_gcn_function(
     function: &RawFunction,
     function_id: u32,
     base_address: u32,
+    endian: Endian,
 ) -> Result<Vec<u8>> {
     let header = &function.header;
     let mut raw = Vec::new();
     match &header.name_address {
-        Some(addr) => raw.extend((*addr + base_address).to_le_bytes().iter()),
-        None => raw.extend(0_u32.to_le_bytes().iter()),
+        Some(addr) => raw.put_u32(*addr + base_address, endian),
+        None => raw.put_u32(0, endian),
     }
-    raw.extend((header.code_address + base_address).to_le_bytes().iter());
+    raw.put_u32(header.code_address + base_address, endian);
     match &header.parent_address {
-        Some(addr) => raw.extend((*addr + base_address).to_le_bytes().iter()),
-        None => raw.extend(0_u32.to_le_bytes().iter()),
+        Some(addr) => raw.put_u32(*addr + base_address, endian),
+        None => raw.put_u32(0, endian),
     }
     raw.push(header.event);
     raw.push(header.arity);
     raw.push(header.param_count);
     raw.push(header.unknown);
-    raw.extend((function_id as u16).to_le_bytes().iter());
-    raw.extend((header.frame_size as u16).to_le_bytes().iter());
+    raw.put_u16(function_id as u16, endian);
+    raw.put_u16(header.frame_size, endian);
     raw.extend_from_slice(&function.name);
     raw.extend_from_slice(&function.args);
     raw.extend_from_slice(&header.prefix);
@@ -151,23 +187,28 @@ fn serialize_gcn_function(
     Ok(raw)
 }
 
-fn serialize_three_ds_function(function: &RawFunction, id: u32, base_address: u32) -> Result<Vec<u8>> {
+fn serialize_three_ds_function(
+    function: &RawFunction,
+    id: u32,
+    base_address: u32,
+    endian: Endian,
+) -> Result<Vec<u8>> {
     let header = &function.header;
     let mut raw = Vec::new();
-    raw.extend((base_address as u32).to_le_bytes().iter());
-    raw.extend((header.code_address + base_address).to_le_bytes().iter());
+    raw.put_u32(base_address, endian);
+    raw.put_u32(header.code_address + base_address, endian);
     raw.push(header.event);
     raw.push(header.arity);
     raw.push(header.frame_size as u8);
     raw.push(0); // Padding
-    raw.extend(id.to_le_bytes().iter());
+    raw.put_u32(id, endian);
     match &header.name_address {
-        Some(addr) => raw.extend((*addr + base_address).to_le_bytes().iter()),
-        None => raw.extend(0_u32.to_le_bytes().iter()),
+        Some(addr) => raw.put_u32(*addr + base_address, endian),
+        None => raw.put_u32(0, endian),
     }
     match &header.args_address {
-        Some(addr) => raw.extend((*addr + base_address).to_le_bytes().iter()),
-        None => raw.extend(0_u32.to_le_bytes().iter()),
+        Some(addr) => raw.put_u32(*addr + base_address, endian),
+        None => raw.put_u32(0, endian),
     }
     raw.extend_from_slice(&function.name);
     raw.extend_from_slice(&function.args);
@@ -176,8 +217,9 @@ fn serialize_three_ds_function(function: &RawFunction, id: u32, base_address: u3
 }
 
 pub fn serialize_function(function: &RawFunction, id: u32, base_address: u32, game: Game) -> Result<Vec<u8>> {
-    match game {
-        Game::FE9 | Game::FE10 | Game::FE11 | Game::FE12 => serialize_gcn_function(function, id, base_address),
-        Game::FE13 | Game::FE14 | Game::FE15 => serialize_three_ds_function(function, id, base_address),
+    let endian = game.platform().header_endian;
+    match game.profile().header {
+        HeaderLayout::Gcn => serialize_gcn_function(function, id, base_address, endian),
+        HeaderLayout::ThreeDs => serialize_three_ds_function(function, id, base_address, endian),
     }
 }
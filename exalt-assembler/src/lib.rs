@@ -1,43 +1,68 @@
 mod args;
 mod code;
+mod error;
 mod function;
 mod header;
 mod types;
 mod util;
 
-use std::io::Cursor;
-
-use anyhow::{Context, Result};
-use byteorder::{LittleEndian, WriteBytesExt};
-use exalt_lir::{Game, RawScript};
+use error::Result;
+use exalt_lir::{Game, Opcode, RawScript, TextOptions};
 use types::VersionInfo;
-pub use types::CodeGenTextData;
+use util::WriteEndian;
+
+pub use error::AssemblyError;
+pub use types::{CodeGenTextData, FunctionLayout, OffsetReport, TextOrder};
 
 fn dump_text_data(raw: &mut Vec<u8>, text_data: &CodeGenTextData) {
     raw.extend_from_slice(text_data.bytes());
-    while raw.len() % 4 != 0 {
+    while !raw.len().is_multiple_of(4) {
         raw.push(0);
     }
 }
 
+/// Optional settings for assembling a script.
+#[derive(Debug, Clone, Default)]
+pub struct AssembleOptions {
+    /// Text data to assemble against. Defaults to generating text data on the fly.
+    pub text_data: Option<CodeGenTextData>,
+
+    /// Revision to embed in the header instead of the game's default.
+    pub revision: Option<u32>,
+
+    /// How to encode strings. Defaults to the script's encoding, falling back to the game's.
+    pub text: TextOptions,
+}
+
 fn generate_script(
     script: &RawScript,
     script_name: &str,
     game: Game,
-    mut text_data: CodeGenTextData,
-) -> Result<Vec<u8>> {
+    options: AssembleOptions,
+) -> Result<(Vec<u8>, OffsetReport)> {
+    let codec = options.text.codec(script.quirks.encoding.unwrap_or(game.profile().encoding));
+    let mut text_data = options.text_data.unwrap_or_default();
+    text_data.codec = codec;
+    text_data.prepare(script)?;
+
     // Build the header.
-    let mut raw =
-        header::build(script, script_name, game).context("failed to build script header")?;
+    let revision = options.revision.or(script.quirks.revision);
+    let mut raw = header::build(script, script_name, game, revision, codec)?;
 
     // Assemble functions.
     // Can't place them in the output yet since some formats place text data first.
     // We can't know the size of text data until we assemble every function...
     let mut raw_functions = Vec::new();
-    for function in &script.functions {
+    for (index, function) in script.functions.iter().enumerate() {
+        function::resolve_labels(function, index)?;
         raw_functions.push(
-            function::convert_to_raw_function(function, &mut text_data, game)
-                .with_context(|| format!("failed to serialize function {:?}", function))?,
+            function::convert_to_raw_function(function, &mut text_data, game).map_err(|error| {
+                AssemblyError::Function {
+                    index,
+                    name: function.name.clone(),
+                    error: Box::new(error),
+                }
+            })?,
         );
     }
 
@@ -46,6 +71,10 @@ fn generate_script(
 
     // If text goes first, write it now.
     let version_info = VersionInfo::for_game(game);
+    let pad_last_event = script
+        .quirks
+        .pad_last_event
+        .unwrap_or(version_info.pad_last_event);
     if version_info.text_first {
         text_data_address = raw.len();
         dump_text_data(&mut raw, &text_data);
@@ -54,28 +83,46 @@ fn generate_script(
     // NOW we can place functions.
     let mut function_bytes = Vec::new();
     let mut function_addresses = Vec::new();
+    let mut layout = Vec::new();
     let function_table_length = (raw_functions.len() + 1) * 4;
     for i in 0..raw_functions.len() {
         let function = &raw_functions[i];
         let base_address = (raw.len() + function_table_length + function_bytes.len()) as u32;
         function_addresses.push(base_address);
+        let code_address = (base_address + function.header.code_address) as usize;
+        let labels = script.functions[i]
+            .code
+            .iter()
+            .zip(&function.opcode_offsets)
+            .filter_map(|(opcode, offset)| match opcode {
+                Opcode::Label(label) => Some((label.clone(), code_address + offset)),
+                _ => None,
+            })
+            .collect();
+        layout.push(FunctionLayout {
+            address: base_address as usize,
+            code_address,
+            opcode_offsets: function.opcode_offsets.clone(),
+            labels,
+        });
         function_bytes.extend(function::serialize_function(
             function,
             i as u32,
             base_address,
             game,
         )?);
-        if i != raw_functions.len() - 1 || version_info.pad_last_event {
+        if i != raw_functions.len() - 1 || pad_last_event {
             while function_bytes.len() % 4 != 0 {
                 function_bytes.push(0);
             }
         }
     }
     let event_table_address = raw.len();
+    let endian = version_info.platform.header_endian;
     for address in function_addresses {
-        raw.extend(address.to_le_bytes().iter());
+        raw.put_u32(address, endian);
     }
-    raw.extend(0_u32.to_le_bytes().iter());
+    raw.put_u32(0, endian);
     raw.extend_from_slice(&function_bytes);
 
     // If text goes last, write it now.
@@ -85,17 +132,32 @@ fn generate_script(
     }
 
     // Fix the header now that we know where text and event sections were placed.
-    let mut cursor = Cursor::new(&mut raw);
-    cursor.set_position(version_info.text_data_pointer_address);
-    cursor.write_u32::<LittleEndian>(text_data_address as u32)?;
-    cursor.set_position(version_info.event_table_pointer_address);
-    cursor.write_u32::<LittleEndian>(event_table_address as u32)?;
+    version_info.write_pointer(
+        &mut raw,
+        version_info.text_data_pointer_address,
+        text_data_address,
+    );
+    version_info.write_pointer(
+        &mut raw,
+        version_info.event_table_pointer_address,
+        event_table_address,
+    );
 
-    Ok(raw)
+    let strings = text_data
+        .strings()
+        .into_iter()
+        .map(|(text, offset)| (text, text_data_address + offset))
+        .collect();
+    let report = OffsetReport {
+        functions: layout,
+        text_data_address,
+        strings,
+    };
+    Ok((raw, report))
 }
 
 pub fn assemble(script: &RawScript, script_name: &str, game: Game) -> Result<Vec<u8>> {
-    generate_script(script, script_name, game, CodeGenTextData::default())
+    generate_script(script, script_name, game, AssembleOptions::default()).map(|(raw, _)| raw)
 }
 
 pub fn assemble_with_hard_coding(
@@ -104,5 +166,38 @@ pub fn assemble_with_hard_coding(
     game: Game,
     text_data: CodeGenTextData,
 ) -> Result<Vec<u8>> {
-    generate_script(script, script_name, game, text_data)
+    let options = AssembleOptions {
+        text_data: Some(text_data),
+        ..Default::default()
+    };
+    generate_script(script, script_name, game, options).map(|(raw, _)| raw)
+}
+
+pub fn assemble_with_options(
+    script: &RawScript,
+    script_name: &str,
+    game: Game,
+    options: AssembleOptions,
+) -> Result<Vec<u8>> {
+    generate_script(script, script_name, game, options).map(|(raw, _)| raw)
+}
+
+/// Assemble a script and report where each function's opcodes were placed.
+pub fn assemble_with_layout(
+    script: &RawScript,
+    script_name: &str,
+    game: Game,
+    options: AssembleOptions,
+) -> Result<(Vec<u8>, Vec<FunctionLayout>)> {
+    generate_script(script, script_name, game, options).map(|(raw, report)| (raw, report.functions))
+}
+
+/// Assemble a script and report the address of every function, label, and string in it.
+pub fn assemble_with_report(
+    script: &RawScript,
+    script_name: &str,
+    game: Game,
+    options: AssembleOptions,
+) -> Result<(Vec<u8>, OffsetReport)> {
+    generate_script(script, script_name, game, options)
 }
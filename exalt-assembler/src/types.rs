@@ -1,9 +1,10 @@
-use std::io::Cursor;
+use std::collections::VecDeque;
 
+use crate::error::{AssemblyError, Result};
 use crate::util;
-use anyhow::Result;
-use byteorder::{BigEndian, WriteBytesExt};
-use exalt_lir::Game;
+use exalt_lir::{
+    CallbackArg, Endian, Game, HeaderLayout, Opcode, Platform, RawScript, TextCodec, TextEncoding,
+};
 use rustc_hash::FxHashMap;
 
 #[derive(Debug)]
@@ -26,11 +27,41 @@ pub struct RawFunction {
     pub name: Vec<u8>,
     pub args: Vec<u8>,
     pub code: Vec<u8>,
+    pub opcode_offsets: Vec<usize>,
+}
+
+/// Where a function's code ended up in an assembled script.
+#[derive(Debug, Clone)]
+pub struct FunctionLayout {
+    /// Address of the function's header in the script.
+    pub address: usize,
+
+    /// Address of the function's first opcode in the script.
+    pub code_address: usize,
+
+    /// Offset of each opcode from the start of the function's code.
+    pub opcode_offsets: Vec<usize>,
+
+    /// Address of each label in the script, in code order.
+    pub labels: Vec<(String, usize)>,
+}
+
+/// Where everything ended up in an assembled script. Addresses are absolute offsets into the script.
+#[derive(Debug, Clone)]
+pub struct OffsetReport {
+    pub functions: Vec<FunctionLayout>,
+
+    /// Address of the text data.
+    pub text_data_address: usize,
+
+    /// Address of every string in the text data, in the order they were written.
+    pub strings: Vec<(String, usize)>,
 }
 
 pub struct CodeGenState<'a> {
     pub labels: FxHashMap<String, CodeGenLabelEntry>,
     pub text_data: &'a mut CodeGenTextData,
+    pub endian: Endian,
 }
 
 pub struct CodeGenLabelEntry {
@@ -44,29 +75,54 @@ pub enum CodeGenTextStrategy {
     HardCoded,
 }
 
+/// Order of strings in text data generated on the fly.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub enum TextOrder {
+    /// Strings are written as the assembler first encounters them.
+    #[default]
+    FirstUse,
+
+    /// Every string in the script is written up front in sorted order.
+    Sorted,
+
+    /// These strings are written up front in this order.
+    /// Strings the script uses that aren't in the list are appended in first use order.
+    Fixed(Vec<String>),
+}
+
 #[derive(Debug, Clone)]
 pub struct CodeGenTextData {
     pub raw_text: Vec<u8>,
     pub offsets: FxHashMap<String, usize>,
     pub strategy: CodeGenTextStrategy,
+
+    /// Reuse the first copy of a string instead of writing it again for every use.
+    /// Only applies to generated text data.
+    pub deduplicate: bool,
+
+    /// Layout of generated text data.
+    pub order: TextOrder,
+
+    // Set from the assembly options before any text is written.
+    pub(crate) codec: TextCodec,
+
+    // Copies written up front that haven't been handed out yet. Only used without deduplication.
+    reserved: FxHashMap<String, VecDeque<usize>>,
 }
 
 impl<'a> CodeGenState<'a> {
-    pub fn new(text_data: &'a mut CodeGenTextData) -> Self {
+    pub fn new(text_data: &'a mut CodeGenTextData, endian: Endian) -> Self {
         CodeGenState {
             labels: FxHashMap::default(),
             text_data,
+            endian,
         }
     }
 
-    pub fn add_label(&mut self, label: &str, addr: usize) -> anyhow::Result<()> {
+    // Labels are checked up front by function::resolve_labels, so every label here is defined exactly once
+    pub fn add_label(&mut self, label: &str, addr: usize) {
         match self.labels.get_mut(label) {
-            Some(label_data) => match label_data.addr {
-                Some(_) => return Err(anyhow::anyhow!("Duplicate entries for label '{}'.", label)),
-                None => {
-                    label_data.addr = Some(addr);
-                }
-            },
+            Some(label_data) => label_data.addr = Some(addr),
             None => {
                 let label_data = CodeGenLabelEntry {
                     addr: Some(addr),
@@ -75,7 +131,6 @@ impl<'a> CodeGenState<'a> {
                 self.labels.insert(label.to_owned(), label_data);
             }
         }
-        Ok(())
     }
 
     pub fn add_jump(&mut self, label: &str, jump_addr: usize) {
@@ -91,57 +146,173 @@ impl<'a> CodeGenState<'a> {
         }
     }
 
-    pub fn backpatch(&self, bytes: &mut [u8]) -> anyhow::Result<()> {
-        let mut cursor = Cursor::new(bytes);
-        for (label, label_data) in &self.labels {
-            match label_data.addr {
-                Some(addr) => {
-                    for jump in &label_data.jumps {
-                        let signed_label_addr = addr as i16;
-                        let signed_jump_addr = *jump as i16;
-                        let diff = signed_label_addr - signed_jump_addr;
-                        cursor.set_position(*jump as u64);
-                        cursor.write_i16::<BigEndian>(diff)?;
-                    }
+    pub fn backpatch(&self, bytes: &mut [u8]) {
+        for label_data in self.labels.values() {
+            if let Some(addr) = label_data.addr {
+                for jump in &label_data.jumps {
+                    let signed_label_addr = addr as i16;
+                    let signed_jump_addr = *jump as i16;
+                    let diff = signed_label_addr - signed_jump_addr;
+                    let raw = match self.endian {
+                        Endian::Little => diff.to_le_bytes(),
+                        Endian::Big => diff.to_be_bytes(),
+                    };
+                    bytes[*jump..*jump + 2].copy_from_slice(&raw);
                 }
-                None => return Err(anyhow::anyhow!("Unresolved label '{}'", label)),
             }
         }
-        Ok(())
     }
 }
 
+fn collect_strings(script: &RawScript) -> Vec<String> {
+    let mut strings = Vec::new();
+    for function in &script.functions {
+        for arg in &function.args {
+            if let CallbackArg::Str(s) = arg {
+                strings.push(s.clone());
+            }
+        }
+        for opcode in &function.code {
+            match opcode {
+                Opcode::StrLoad(s) | Opcode::CallByName(s, _) => strings.push(s.clone()),
+                _ => {}
+            }
+        }
+    }
+    strings
+}
+
+fn split_strings(raw_text: &[u8], encoding: TextEncoding) -> Vec<(String, usize)> {
+    // Only used for lookups and reports, so bad text shouldn't stop anything.
+    let codec = TextCodec {
+        encoding,
+        lossy: true,
+    };
+    let mut strings = Vec::new();
+    let mut offset = 0;
+    while offset < raw_text.len() {
+        let length = raw_text[offset..]
+            .iter()
+            .position(|b| *b == 0)
+            .unwrap_or(raw_text.len() - offset);
+        let text = codec
+            .decode(&raw_text[offset..offset + length])
+            .unwrap_or_default();
+        strings.push((text, offset));
+        offset += length + 1;
+    }
+    strings
+}
+
 impl CodeGenTextData {
     pub fn hard_coded(raw_text: Vec<u8>, offsets: FxHashMap<String, usize>) -> Self {
         CodeGenTextData {
             raw_text,
             offsets,
             strategy: CodeGenTextStrategy::HardCoded,
+            ..Default::default()
+        }
+    }
+
+    /// Hard coded text data that reuses the text section of a compiled script byte for byte.
+    /// Older formats pack strings in an order the assembler can't reproduce, so this is the only way to rebuild them exactly.
+    /// The encoding has to match the one the script is assembled with or strings won't be found.
+    pub fn from_script(script: &[u8], game: Game, encoding: TextEncoding) -> Result<Self> {
+        let version_info = VersionInfo::for_game(game);
+        let start = version_info.read_pointer(script, version_info.text_data_pointer_address)?;
+        let end = if version_info.text_first {
+            version_info.read_pointer(script, version_info.event_table_pointer_address)?
+        } else {
+            script.len()
+        };
+        if start > end || end > script.len() {
+            return Err(AssemblyError::TextDataOutOfBounds {
+                start,
+                end,
+                len: script.len(),
+            });
+        }
+
+        let raw_text = script[start..end].to_vec();
+        let mut offsets = FxHashMap::default();
+        for (text, offset) in split_strings(&raw_text, encoding) {
+            offsets.entry(text).or_insert(offset);
+        }
+        Ok(CodeGenTextData::hard_coded(raw_text, offsets))
+    }
+
+    /// Generated text data that starts with these strings in this order.
+    pub fn with_order(strings: Vec<String>, deduplicate: bool) -> Self {
+        CodeGenTextData {
+            deduplicate,
+            order: TextOrder::Fixed(strings),
+            ..Default::default()
+        }
+    }
+
+    fn append(&mut self, text: &str) -> Result<usize> {
+        let bytes = util::encode_text(text, self.codec)?;
+        let offset = self.raw_text.len();
+        self.raw_text.extend(bytes);
+        self.raw_text.push(0);
+        self.offsets.entry(text.to_owned()).or_insert(offset);
+        Ok(offset)
+    }
+
+    /// Write any strings that the chosen order places ahead of the script's code.
+    pub fn prepare(&mut self, script: &RawScript) -> Result<()> {
+        if !matches!(self.strategy, CodeGenTextStrategy::Dynamic) {
+            return Ok(());
+        }
+        let strings = match &self.order {
+            TextOrder::FirstUse => return Ok(()),
+            TextOrder::Sorted => {
+                let mut strings = collect_strings(script);
+                strings.sort();
+                strings
+            }
+            TextOrder::Fixed(strings) => strings.clone(),
+        };
+        for text in strings {
+            if self.deduplicate {
+                if !self.offsets.contains_key(&text) {
+                    self.append(&text)?;
+                }
+            } else {
+                let offset = self.append(&text)?;
+                self.reserved.entry(text).or_default().push_back(offset);
+            }
         }
+        Ok(())
     }
 
     pub fn offset(&mut self, text: &str) -> Result<usize> {
         match &self.strategy {
-            CodeGenTextStrategy::Dynamic => match self.offsets.get(text) {
-                Some(offset) => Ok(*offset),
-                None => {
-                    let bytes = util::encode_shift_jis(text)?;
-                    let offset = self.raw_text.len();
-                    self.raw_text.extend(bytes.into_iter());
-                    self.raw_text.push(0);
-                    self.offsets.insert(text.to_owned(), offset);
-                    Ok(offset)
+            CodeGenTextStrategy::Dynamic => {
+                if let Some(offset) = self.reserved.get_mut(text).and_then(|r| r.pop_front()) {
+                    return Ok(offset);
                 }
-            },
-            CodeGenTextStrategy::HardCoded => self.offsets.get(text).copied().ok_or_else(|| {
-                anyhow::anyhow!("'{}' does not exist in hard coded text data", text)
-            }),
+                match self.offsets.get(text) {
+                    Some(offset) if self.deduplicate => Ok(*offset),
+                    _ => self.append(text),
+                }
+            }
+            CodeGenTextStrategy::HardCoded => self
+                .offsets
+                .get(text)
+                .copied()
+                .ok_or_else(|| AssemblyError::MissingText(text.to_owned())),
         }
     }
 
     pub fn bytes(&self) -> &[u8] {
         &self.raw_text
     }
+
+    /// Every string in the text data along with its offset, including repeated copies.
+    pub fn strings(&self) -> Vec<(String, usize)> {
+        split_strings(&self.raw_text, self.codec.encoding)
+    }
 }
 
 impl Default for CodeGenTextData {
@@ -150,32 +321,58 @@ impl Default for CodeGenTextData {
             raw_text: Vec::new(),
             offsets: FxHashMap::default(),
             strategy: CodeGenTextStrategy::Dynamic,
+            deduplicate: true,
+            order: TextOrder::default(),
+            codec: TextCodec::default(),
+            reserved: FxHashMap::default(),
         }
     }
 }
 
 pub struct VersionInfo {
-    pub event_table_pointer_address: u64,
-    pub text_data_pointer_address: u64,
+    pub event_table_pointer_address: usize,
+    pub text_data_pointer_address: usize,
     pub text_first: bool,
     pub pad_last_event: bool,
+    pub platform: Platform,
 }
 
 impl VersionInfo {
     pub fn for_game(game: Game) -> Self {
-        match game {
-            Game::FE9 | Game::FE10 | Game::FE11 | Game::FE12 => VersionInfo {
-                event_table_pointer_address: 0x28,
-                text_data_pointer_address: 0x24,
-                text_first: true,
-                pad_last_event: true,
-            },
-            Game::FE13 | Game::FE14 | Game::FE15 => VersionInfo {
-                event_table_pointer_address: 0x1C,
-                text_data_pointer_address: 0x20,
-                text_first: false,
-                pad_last_event: false,
-            }
+        let profile = game.profile();
+        let (event_table_pointer_address, text_data_pointer_address) = match profile.header {
+            HeaderLayout::Gcn => (0x28, 0x24),
+            HeaderLayout::ThreeDs => (0x1C, 0x20),
+        };
+        VersionInfo {
+            event_table_pointer_address,
+            text_data_pointer_address,
+            text_first: profile.text_first,
+            pad_last_event: profile.pad_last_event,
+            platform: profile.platform,
         }
     }
+
+    /// Read one of the header's section pointers from an assembled script.
+    pub fn read_pointer(&self, script: &[u8], address: usize) -> Result<usize> {
+        let raw = script
+            .get(address..address + 4)
+            .ok_or(AssemblyError::Truncated)?
+            .try_into()
+            .unwrap();
+        let pointer = match self.platform.header_endian {
+            Endian::Little => u32::from_le_bytes(raw),
+            Endian::Big => u32::from_be_bytes(raw),
+        };
+        Ok(pointer as usize)
+    }
+
+    /// Fill in one of the header's section pointers once the section has been placed.
+    pub fn write_pointer(&self, script: &mut [u8], address: usize, pointer: usize) {
+        let raw = match self.platform.header_endian {
+            Endian::Little => (pointer as u32).to_le_bytes(),
+            Endian::Big => (pointer as u32).to_be_bytes(),
+        };
+        script[address..address + 4].copy_from_slice(&raw);
+    }
 }
@@ -1,10 +1,48 @@
-use anyhow::{bail, Result};
-use encoding_rs::SHIFT_JIS;
+use crate::error::{AssemblyError, Result};
+use exalt_lir::{Endian, TextCodec};
 
-pub fn encode_shift_jis(text: &str) -> Result<Vec<u8>> {
-    let (bytes, _, errors) = SHIFT_JIS.encode(text);
-    if errors {
-        bail!("Failed to encode string '{}' as SHIFT-JIS.", text);
+pub fn encode_text(text: &str, codec: TextCodec) -> Result<Vec<u8>> {
+    codec
+        .encode(text)
+        .ok_or_else(|| AssemblyError::InvalidText {
+            text: text.to_owned(),
+            encoding: codec.encoding,
+        })
+}
+
+/// Writes that follow a platform's byte order instead of a fixed one.
+pub trait WriteEndian {
+    fn put_u16(&mut self, value: u16, endian: Endian);
+    fn put_i16(&mut self, value: i16, endian: Endian);
+    fn put_u32(&mut self, value: u32, endian: Endian);
+    fn put_i32(&mut self, value: i32, endian: Endian);
+    fn put_f32(&mut self, value: f32, endian: Endian);
+}
+
+impl WriteEndian for Vec<u8> {
+    fn put_u16(&mut self, value: u16, endian: Endian) {
+        match endian {
+            Endian::Little => self.extend(value.to_le_bytes()),
+            Endian::Big => self.extend(value.to_be_bytes()),
+        }
+    }
+
+    fn put_i16(&mut self, value: i16, endian: Endian) {
+        self.put_u16(value as u16, endian)
+    }
+
+    fn put_u32(&mut self, value: u32, endian: Endian) {
+        match endian {
+            Endian::Little => self.extend(value.to_le_bytes()),
+            Endian::Big => self.extend(value.to_be_bytes()),
+        }
+    }
+
+    fn put_i32(&mut self, value: i32, endian: Endian) {
+        self.put_u32(value as u32, endian)
+    }
+
+    fn put_f32(&mut self, value: f32, endian: Endian) {
+        self.put_u32(value.to_bits(), endian)
     }
-    Ok(bytes.into())
 }
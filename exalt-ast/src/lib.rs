@@ -4,7 +4,9 @@ use std::ops::Range;
 use std::rc::Rc;
 
 use derive_new::new;
+use exalt_lir::Opcode;
 use indexmap::IndexMap;
+use serde::{Deserialize, Serialize};
 
 pub mod surface;
 
@@ -16,7 +18,7 @@ pub type Shared<T> = Rc<RefCell<T>>;
 
 /// Represents a representation in some source code.
 /// Can be either text or generated (ex. by the decompiler)
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum Location {
     Source(FileId, Range<usize>),
     Generated,
@@ -48,7 +50,7 @@ impl Location {
 }
 
 /// Container for literal values that can be represented directly in the source / binary.
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum Literal {
     Int(i32),
     Str(String),
@@ -65,6 +67,24 @@ impl Literal {
     }
 }
 
+/// Escape a string so it reads back the same when written between quotes in source.
+/// Control characters are written as \xNN since they're single Shift-JIS bytes.
+pub fn escape_str(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        match c {
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            '\n' => escaped.push_str("\\n"),
+            '\r' => escaped.push_str("\\r"),
+            '\t' => escaped.push_str("\\t"),
+            c if c.is_ascii_control() => escaped.push_str(&format!("\\x{:02X}", c as u32)),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
 /// Notation for increment expressions myVar++ vs. ++myVar
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum Notation {
@@ -77,6 +97,7 @@ pub enum Notation {
 pub enum Precedence {
     Lowest,
     Assignment,
+    Conditional,
     LogicalOr,
     LogicalAnd,
     Bitwise,
@@ -290,7 +311,7 @@ impl Operator {
 }
 
 /// Sum of all Exalt data types
-#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub enum DataType {
     Int,
     Float,
@@ -317,6 +338,16 @@ pub struct ConstSymbol {
     pub value: Literal,
 }
 
+/// Metadata for a constant array
+#[derive(Debug, new)]
+pub struct ConstArraySymbol {
+    pub name: String,
+    pub location: Location,
+    pub values: Vec<Literal>,
+    // Backing global for arrays annotated with @Global. Otherwise every function gets its own copy.
+    pub global: Option<Shared<VarSymbol>>,
+}
+
 /// Metadata for an enum
 #[derive(Debug, new)]
 pub struct EnumSymbol {
@@ -333,6 +364,14 @@ pub struct FunctionSymbol {
     pub arity: usize,
     pub alias: Option<String>,
     pub allow_redefinition: bool,
+    // Declared parameter types, Any for parameters without an annotation
+    #[new(default)]
+    pub parameter_types: Vec<DataType>,
+    #[new(default)]
+    pub return_type: Option<DataType>,
+    // Doc comment from the declaration, shown when editing
+    #[new(default)]
+    pub docs: Option<String>,
 }
 
 impl FunctionSymbol {
@@ -343,6 +382,9 @@ impl FunctionSymbol {
             arity,
             alias,
             allow_redefinition,
+            parameter_types: Vec::new(),
+            return_type: None,
+            docs: None,
         }))
     }
 }
@@ -366,6 +408,15 @@ pub struct VarSymbol {
     pub frame_id: Option<usize>,
     #[new(default)]
     pub array_length: Option<usize>,
+    #[new(default)]
+    pub assigned: bool,
+    #[new(default)]
+    pub used: bool,
+    #[new(default)]
+    pub data_type: Option<DataType>,
+    // Declared by its first assignment rather than a let, so it can still grow into an array
+    #[new(default)]
+    pub implicit: bool,
 }
 
 /// Exalt l-values
@@ -388,6 +439,8 @@ pub enum Expr {
     Ref(Ref),
     Increment(Ref, Operator, Notation),
     AddressOf(Ref),
+    /// Condition, then the values for when it's true and false
+    Conditional(Box<Expr>, Box<Expr>, Box<Expr>),
 }
 
 /// A single case in a match statement
@@ -408,7 +461,12 @@ pub enum Stmt {
     Block(Vec<Stmt>),
     Break,
     Continue,
+    DoWhile {
+        body: Box<Stmt>,
+        condition: Expr,
+    },
     ExprStmt(Expr),
+    Fallthrough,
     For {
         init: Box<Stmt>,
         check: Expr,
@@ -421,6 +479,7 @@ pub enum Stmt {
         then_part: Box<Stmt>,
         else_part: Option<Box<Stmt>>,
     },
+    Asm(Vec<Opcode>),
     Label(Shared<LabelSymbol>),
     Match {
         switch: Expr,
@@ -429,6 +488,8 @@ pub enum Stmt {
     },
     Printf(Vec<Expr>),
     Return(Option<Expr>),
+    /// Marks where the statement that follows was written. Generates no code.
+    SourceLocation(Location),
     VarDecl(Shared<VarSymbol>, Option<usize>),
     While {
         condition: Expr,
@@ -444,6 +505,9 @@ pub enum Annotation {
     Prefix(Vec<u8>),
     Suffix(Vec<u8>),
     Unknown(usize),
+    FrameSize(usize),
+    /// Position in the function table, along with where it was requested.
+    Index(usize, Location),
 }
 
 /// Exalt declarations
@@ -458,12 +522,23 @@ pub enum Decl {
     },
     Callback {
         annotations: Vec<Annotation>,
-        event_type: usize,
+        /// Each event gets its own entry in the function table with a copy of the code.
+        event_types: Vec<usize>,
         args: Vec<Literal>,
         body: Stmt,
     },
 }
 
+impl Decl {
+    /// Number of entries the declaration takes up in the function table.
+    pub fn table_entries(&self) -> usize {
+        match self {
+            Decl::Function { .. } => 1,
+            Decl::Callback { event_types, .. } => event_types.len(),
+        }
+    }
+}
+
 #[derive(Debug, new)]
 pub struct Script {
     pub decls: Vec<Decl>,
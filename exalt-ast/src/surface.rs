@@ -1,6 +1,6 @@
 use derive_new::new;
 
-use crate::{Literal, Location, Notation, Operator};
+use crate::{DataType, Literal, Location, Notation, Operator};
 
 #[derive(Debug, Clone, new)]
 pub struct Identifier {
@@ -8,8 +8,15 @@ pub struct Identifier {
     pub value: String,
 }
 
+/// Raw representation of a function parameter with an optional type annotation
+#[derive(Debug, Clone, new)]
+pub struct Parameter {
+    pub identifier: Identifier,
+    pub data_type: Option<DataType>,
+}
+
 /// Raw representation of variables references / l-values
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub enum Ref {
     Var(Identifier),
     Index(Identifier, Box<Expr>),
@@ -17,7 +24,7 @@ pub enum Ref {
 }
 
 /// Raw representation of an expression
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub enum Expr {
     Array(Location, Vec<Expr>),
     Literal(Location, Literal),
@@ -29,6 +36,8 @@ pub enum Expr {
     Grouped(Location, Box<Expr>),
     Increment(Location, Ref, Operator, Notation),
     AddressOf(Location, Ref),
+    /// `condition ? then : otherwise`
+    Conditional(Location, Box<Expr>, Box<Expr>, Box<Expr>),
 }
 
 impl Expr {
@@ -44,19 +53,28 @@ impl Expr {
             Expr::Grouped(l, _) => l,
             Expr::Increment(l, _, _, _) => l,
             Expr::AddressOf(l, _) => l,
+            Expr::Conditional(l, _, _, _) => l,
         }
     }
 }
 
+/// A single condition in a match case
+#[derive(Debug, Clone)]
+pub enum CaseCondition {
+    Value(Expr),
+    /// Inclusive range of constant values (ex. 1..5)
+    Range(Location, Expr, Expr),
+}
+
 /// A single case in a match statement
-#[derive(Debug, new)]
+#[derive(Debug, Clone, new)]
 pub struct Case {
-    pub conditions: Vec<Expr>,
+    pub conditions: Vec<CaseCondition>,
     pub body: Stmt,
 }
 
 /// Raw representation of statements
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub enum Stmt {
     Assignment {
         location: Location,
@@ -67,7 +85,13 @@ pub enum Stmt {
     Block(Location, Vec<Stmt>),
     Break(Location),
     Continue(Location),
+    DoWhile {
+        location: Location,
+        body: Box<Stmt>,
+        condition: Expr,
+    },
     ExprStmt(Location, Expr),
+    Fallthrough(Location),
     For {
         location: Location,
         init: Box<Stmt>,
@@ -83,6 +107,7 @@ pub enum Stmt {
         else_part: Option<Box<Stmt>>,
     },
     Label(Location, Identifier),
+    Asm(Location, Vec<AsmInstruction>),
     Match {
         location: Location,
         switch: Expr,
@@ -107,11 +132,14 @@ impl Stmt {
             Stmt::Block(location, _) => location,
             Stmt::Break(location) => location,
             Stmt::Continue(location) => location,
+            Stmt::DoWhile { location, .. } => location,
             Stmt::ExprStmt(location, _) => location,
+            Stmt::Fallthrough(location) => location,
             Stmt::For { location, .. } => location,
             Stmt::Goto(location, _) => location,
             Stmt::If { location, .. } => location,
             Stmt::Label(location, _) => location,
+            Stmt::Asm(location, _) => location,
             Stmt::Match { location, .. } => location,
             Stmt::Printf(location, _) => location,
             Stmt::Return(location, _) => location,
@@ -123,32 +151,42 @@ impl Stmt {
 }
 
 /// Raw representation of annotations
-#[derive(Debug, new)]
+#[derive(Debug, Clone, new)]
 pub struct Annotation {
     pub location: Location,
     pub identifier: Identifier,
     pub args: Vec<Expr>,
 }
 
+/// Raw representation of an instruction in an asm block
+#[derive(Debug, Clone, new)]
+pub struct AsmInstruction {
+    pub location: Location,
+    pub identifier: Identifier,
+    pub args: Vec<Expr>,
+}
+
 /// Raw representation of an enum variant
-#[derive(Debug, new)]
+#[derive(Debug, Clone, new)]
 pub struct EnumVariant {
     pub location: Location,
     pub identifier: Identifier,
     pub value: Expr,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub enum IncludePathComponent {
     Node(String),
     Parent,
 }
 
 /// Raw representation of declarations
-#[derive(Debug)]
+/// Function declarations keep the `///` comment written directly above them, if any.
+#[derive(Debug, Clone)]
 pub enum Decl {
     Constant {
         location: Location,
+        annotations: Vec<Annotation>,
         identifier: Identifier,
         value: Expr,
     },
@@ -161,14 +199,30 @@ pub enum Decl {
         location: Location,
         annotations: Vec<Annotation>,
         identifier: Identifier,
-        parameters: Vec<Identifier>,
+        parameters: Vec<Parameter>,
+        return_type: Option<DataType>,
         body: Stmt,
+        docs: Option<String>,
+    },
+    Global {
+        location: Location,
+        identifier: Identifier,
+        count: Option<Expr>,
+        /// Pinned frame slot (ex. `let counter @ 7;`) for globals shared with other scripts.
+        index: Option<Expr>,
+    },
+    Inline {
+        location: Location,
+        identifier: Identifier,
+        parameters: Vec<Parameter>,
+        body: Stmt,
+        docs: Option<String>,
     },
-    Global(Location, Identifier, Option<Expr>),
     Callback {
         location: Location,
         annotations: Vec<Annotation>,
-        event_type: Expr,
+        /// Events sharing the body (ex. `callback[Event.Turn, Event.TurnAfter]`).
+        event_types: Vec<Expr>,
         args: Vec<Expr>,
         body: Stmt,
     },
@@ -180,11 +234,25 @@ pub enum Decl {
         location: Location,
         identifier: Identifier,
         alias: Identifier,
+        docs: Option<String>,
     },
     FunctionExtern {
         location: Location,
         identifier: Identifier,
-        parameters: Vec<Identifier>,
+        parameters: Vec<Parameter>,
+        return_type: Option<DataType>,
+        docs: Option<String>,
+    },
+    GlobalExtern {
+        location: Location,
+        identifier: Identifier,
+        count: Option<Expr>,
+        index: Expr,
+    },
+    Namespace {
+        location: Location,
+        identifier: Identifier,
+        decls: Vec<Decl>,
     },
 }
 
@@ -194,11 +262,14 @@ impl Decl {
             Decl::Constant { location, .. } => location,
             Decl::Enum { location, .. } => location,
             Decl::Function { location, .. } => location,
-            Decl::Global(location, _, _) => location,
+            Decl::Global { location, .. } => location,
+            Decl::Inline { location, .. } => location,
             Decl::Callback { location, .. } => location,
             Decl::Include { location, .. } => location,
             Decl::FunctionAlias { location, .. } => location,
             Decl::FunctionExtern { location, .. } => location,
+            Decl::GlobalExtern { location, .. } => location,
+            Decl::Namespace { location, .. } => location,
         }
     }
 
@@ -208,5 +279,5 @@ impl Decl {
 }
 
 /// Raw representation of an Exalt script
-#[derive(Debug, new)]
+#[derive(Debug, Clone, new)]
 pub struct Script(pub Vec<Decl>);
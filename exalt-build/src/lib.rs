@@ -0,0 +1,210 @@
+use std::path::{Path, PathBuf};
+
+use anyhow::{bail, Context, Result};
+use exalt_compiler::{CompilationCache, CompileRequest, MessageFormat, OptimizationConfig};
+use exalt_lir::{Game, TextOptions};
+use walkdir::WalkDir;
+
+mod lockfile;
+mod manifest;
+mod merge;
+mod package;
+mod parallel;
+mod patch;
+
+pub use lockfile::{LockedFile, LockedTarget, Lockfile, LOCKFILE_NAME};
+pub use manifest::{
+    build_manifest, DefineValue, Manifest, ManifestTarget, TargetOutcome, TargetReport,
+    MANIFEST_NAME,
+};
+pub use merge::{merge, merge_scripts, CollisionStrategy, MergeOptions};
+pub use package::PostBuildStep;
+pub use parallel::run_parallel;
+pub use patch::{patch, patch_script};
+
+/// Where a project's scripts and headers live.
+#[derive(Debug, Clone)]
+pub struct BuildSources {
+    /// Directory containing the scripts to compile.
+    pub source_dir: PathBuf,
+
+    /// Directories searched for includes after the including file's own directory.
+    /// Scripts inside these directories are treated as headers and never compiled on their own.
+    pub include_dirs: Vec<PathBuf>,
+
+    /// Scripts to compile, relative to the source directory.
+    /// If empty, every .exl file under the source directory is compiled.
+    pub targets: Vec<PathBuf>,
+}
+
+#[derive(Debug, Clone)]
+pub struct BuildConfig {
+    pub game: Game,
+
+    /// Relative source and include paths are resolved against this directory.
+    pub root: PathBuf,
+
+    pub sources: BuildSources,
+
+    pub optimizations: OptimizationConfig,
+}
+
+/// A single compiled script.
+#[derive(Debug)]
+pub struct BuildArtifact {
+    /// Path of the script that was compiled, relative to the source directory.
+    pub source: PathBuf,
+
+    /// Name of the compiled script (ex. "bev.cmb").
+    pub script_name: String,
+
+    /// The assembled CMB.
+    pub data: Vec<u8>,
+}
+
+impl BuildArtifact {
+    /// Path of the compiled script relative to an output directory, mirroring the source layout.
+    pub fn output_path(&self) -> PathBuf {
+        self.source.with_file_name(&self.script_name)
+    }
+}
+
+fn resolve(root: &Path, path: &Path) -> PathBuf {
+    if path.is_absolute() {
+        path.to_path_buf()
+    } else {
+        root.join(path)
+    }
+}
+
+fn is_script(path: &Path) -> bool {
+    path.extension().map(|ext| ext == "exl").unwrap_or(false)
+}
+
+fn collect_targets(source_dir: &Path, include_dirs: &[PathBuf]) -> Result<Vec<PathBuf>> {
+    let mut targets = Vec::new();
+    for entry in WalkDir::new(source_dir).sort_by_file_name() {
+        let entry = entry.context("failed to walk source directory")?;
+        let path = entry.path();
+        if !entry.file_type().is_file() || !is_script(path) {
+            continue;
+        }
+        if include_dirs.iter().any(|dir| path.starts_with(dir)) {
+            continue;
+        }
+        targets.push(path.strip_prefix(source_dir)?.to_path_buf());
+    }
+    Ok(targets)
+}
+
+/// Compile every target in a project.
+/// Stops at the first script that fails to compile. Diagnostics are printed as they are encountered.
+pub fn build(config: &BuildConfig) -> Result<Vec<BuildArtifact>> {
+    build_with_cache(config, &mut CompilationCache::new())
+}
+
+/// Source and include directories resolved against the project root, along with the targets to compile.
+struct ResolvedSources {
+    source_dir: PathBuf,
+    include_dirs: Vec<PathBuf>,
+    targets: Vec<PathBuf>,
+}
+
+impl ResolvedSources {
+    fn request(&self, config: &BuildConfig, target: &Path) -> CompileRequest {
+        CompileRequest {
+            game: config.game,
+            target: self.source_dir.join(target),
+            output: None,
+            text_data: None,
+            text: TextOptions::default(),
+            additional_includes: self.include_dirs.clone(),
+            optimizations: config.optimizations.clone(),
+            revision: None,
+            internal_name: None,
+            symbols: None,
+            listing: false,
+            source_map: false,
+            message_format: MessageFormat::default(),
+            defines: Vec::new(),
+            aliases: Vec::new(),
+        }
+    }
+}
+
+fn resolve_sources(config: &BuildConfig) -> Result<ResolvedSources> {
+    let source_dir = resolve(&config.root, &config.sources.source_dir);
+    if !source_dir.is_dir() {
+        bail!("source directory '{}' does not exist", source_dir.display());
+    }
+    let include_dirs: Vec<PathBuf> = config
+        .sources
+        .include_dirs
+        .iter()
+        .map(|dir| resolve(&config.root, dir))
+        .collect();
+    for dir in &include_dirs {
+        if !dir.is_dir() {
+            bail!("include directory '{}' does not exist", dir.display());
+        }
+    }
+
+    let targets = if config.sources.targets.is_empty() {
+        collect_targets(&source_dir, &include_dirs)?
+    } else {
+        config.sources.targets.clone()
+    };
+    Ok(ResolvedSources {
+        source_dir,
+        include_dirs,
+        targets,
+    })
+}
+
+/// Compile every target in a project, reusing work from previous builds that shared the cache.
+/// Includes shared between targets (ex. a prelude) are only parsed and analyzed once.
+pub fn build_with_cache(
+    config: &BuildConfig,
+    cache: &mut CompilationCache,
+) -> Result<Vec<BuildArtifact>> {
+    let sources = resolve_sources(config)?;
+    let mut artifacts = Vec::new();
+    for target in &sources.targets {
+        let request = sources.request(config, target);
+        let script_name = request.script_name()?;
+        let (_, data) = exalt_compiler::compile_with_cache(&request, cache)
+            .with_context(|| format!("failed to compile '{}'", target.display()))?;
+        artifacts.push(BuildArtifact {
+            source: target.clone(),
+            script_name,
+            data,
+        });
+    }
+    Ok(artifacts)
+}
+
+/// Compile every target in a project across several threads (all cores if `jobs` is None).
+/// Every target is compiled even if some fail. Artifacts are returned in the same order as [`build`],
+/// and the first failure in that order is reported.
+/// Nothing is shared between targets, so includes are parsed once per target.
+pub fn build_parallel(config: &BuildConfig, jobs: Option<usize>) -> Result<Vec<BuildArtifact>> {
+    let sources = resolve_sources(config)?;
+    let results = run_parallel(&sources.targets, jobs, |target| {
+        let request = sources.request(config, target);
+        let script_name = request.script_name()?;
+        let data = exalt_compiler::compile_to_vec(&request)?;
+        Ok(BuildArtifact {
+            source: target.clone(),
+            script_name,
+            data,
+        })
+    })?;
+    sources
+        .targets
+        .iter()
+        .zip(results)
+        .map(|(target, result)| {
+            result.with_context(|| format!("failed to compile '{}'", target.display()))
+        })
+        .collect()
+}
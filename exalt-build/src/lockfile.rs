@@ -0,0 +1,99 @@
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+
+use crate::resolve;
+
+/// Name of the file next to the manifest that records what the last build read.
+pub const LOCKFILE_NAME: &str = "exalt.lock";
+
+/// Hashes are only compared against ones written by the same tool, so a hasher that changes
+/// between releases costs at most one full rebuild.
+pub(crate) fn hash_bytes(bytes: &[u8]) -> String {
+    let mut hasher = DefaultHasher::new();
+    bytes.hash(&mut hasher);
+    format!("{:016x}", hasher.finish())
+}
+
+/// A file a target read while compiling, along with a hash of its contents at the time.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LockedFile {
+    /// Relative to the manifest's directory unless the file lives outside it (ex. the standard library).
+    pub path: PathBuf,
+    pub hash: String,
+}
+
+/// What a target was last built from.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LockedTarget {
+    /// Script that was compiled, as written in the manifest.
+    pub source: PathBuf,
+
+    /// Hash of the settings that affect the output besides file contents (game, output path, defines, etc.).
+    pub settings: String,
+
+    /// The source itself and everything it included.
+    #[serde(default, rename = "dependency")]
+    pub dependencies: Vec<LockedFile>,
+}
+
+impl LockedTarget {
+    /// Whether none of the recorded files have changed since the target was built.
+    pub(crate) fn dependencies_unchanged(&self, root: &Path) -> bool {
+        self.dependencies.iter().all(|file| {
+            exalt_compiler::read_source(&resolve(root, &file.path))
+                .map(|contents| hash_bytes(contents.as_bytes()) == file.hash)
+                .unwrap_or(false)
+        })
+    }
+}
+
+/// Build metadata (exalt.lock) used to skip targets whose sources, includes, and settings haven't changed.
+/// Only targets that built successfully are recorded, so failures are always retried.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Lockfile {
+    #[serde(default, rename = "target")]
+    pub targets: Vec<LockedTarget>,
+}
+
+impl Lockfile {
+    /// Load a lockfile, or an empty one if it doesn't exist yet.
+    pub fn load(path: &Path) -> Result<Self> {
+        if !path.exists() {
+            return Ok(Lockfile::default());
+        }
+        let contents = std::fs::read_to_string(path)
+            .with_context(|| format!("failed to read lockfile '{}'", path.display()))?;
+        toml::from_str(&contents)
+            .with_context(|| format!("failed to parse lockfile '{}'", path.display()))
+    }
+
+    pub fn save(&self, path: &Path) -> Result<()> {
+        let contents = toml::to_string(self).context("failed to serialize lockfile")?;
+        std::fs::write(path, contents)
+            .with_context(|| format!("failed to write lockfile '{}'", path.display()))
+    }
+
+    pub fn target(&self, source: &Path) -> Option<&LockedTarget> {
+        self.targets.iter().find(|t| t.source == source)
+    }
+}
+
+/// Record a file a target read, relative to the project root when possible so the project can move.
+pub(crate) fn lock_file(root: &Path, path: &Path) -> Option<LockedFile> {
+    let contents = exalt_compiler::read_source(path).ok()?;
+    // Includes are found through canonical paths, so the root may only match in that form
+    let canonical_root = root.canonicalize().unwrap_or_else(|_| root.to_path_buf());
+    let path = [root, canonical_root.as_path()]
+        .iter()
+        .find_map(|root| path.strip_prefix(root).ok())
+        .unwrap_or(path)
+        .to_path_buf();
+    Some(LockedFile {
+        path,
+        hash: hash_bytes(contents.as_bytes()),
+    })
+}
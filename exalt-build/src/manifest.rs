@@ -0,0 +1,260 @@
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+use anyhow::{bail, Context, Result};
+use exalt_ast::Literal;
+use exalt_compiler::{
+    CompilationCache, CompileRequest, CompilerLog, MessageFormat, OptimizationConfig,
+};
+use exalt_lir::{Game, TextOptions};
+use serde::Deserialize;
+
+use crate::lockfile::{hash_bytes, lock_file, LockedTarget, Lockfile};
+use crate::{resolve, run_parallel, PostBuildStep};
+
+/// Name of the manifest file looked for when one isn't given explicitly.
+pub const MANIFEST_NAME: &str = "exalt.toml";
+
+/// Value of a define, written as a plain TOML integer, float, or string.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(untagged)]
+pub enum DefineValue {
+    Int(i32),
+    Float(f32),
+    Str(String),
+}
+
+impl From<DefineValue> for Literal {
+    fn from(value: DefineValue) -> Self {
+        match value {
+            DefineValue::Int(v) => Literal::Int(v),
+            DefineValue::Float(v) => Literal::Float(v),
+            DefineValue::Str(v) => Literal::Str(v),
+        }
+    }
+}
+
+/// A project file (exalt.toml) listing scripts that are built together.
+/// Relative paths are resolved against the directory containing the manifest.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Manifest {
+    /// Game for targets that don't name their own.
+    pub game: Option<Game>,
+
+    /// Directory compiled scripts are written under. Defaults to the manifest's directory.
+    #[serde(default)]
+    pub output_dir: PathBuf,
+
+    /// Directories searched for includes by every target.
+    #[serde(default)]
+    pub include_dirs: Vec<PathBuf>,
+
+    /// Constants every target can use as if it declared them (ex. DEBUG = 1).
+    #[serde(default)]
+    pub defines: BTreeMap<String, DefineValue>,
+
+    /// Scripts to compile, written as `[[target]]` tables.
+    #[serde(default, rename = "target")]
+    pub targets: Vec<ManifestTarget>,
+
+    /// Steps run in order once every target has built.
+    #[serde(default)]
+    pub post_build: Vec<PostBuildStep>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ManifestTarget {
+    /// Script to compile.
+    pub source: PathBuf,
+
+    /// Game to compile for instead of the manifest's.
+    pub game: Option<Game>,
+
+    /// Where to write the compiled script, relative to the output directory.
+    /// Defaults to the source path with a .cmb extension.
+    pub output: Option<PathBuf>,
+
+    /// Constants for this target only. These replace shared defines with the same name.
+    #[serde(default)]
+    pub defines: BTreeMap<String, DefineValue>,
+}
+
+impl Manifest {
+    pub fn load(path: &Path) -> Result<Self> {
+        let contents = std::fs::read_to_string(path)
+            .with_context(|| format!("failed to read manifest '{}'", path.display()))?;
+        toml::from_str(&contents)
+            .with_context(|| format!("failed to parse manifest '{}'", path.display()))
+    }
+
+    /// Directory compiled scripts are written under for a manifest in `root`.
+    pub fn resolve_output_dir(&self, root: &Path) -> PathBuf {
+        resolve(root, &self.output_dir)
+    }
+}
+
+/// What happened to a target that didn't fail.
+#[derive(Debug)]
+pub enum TargetOutcome {
+    /// Nothing the target depends on changed since the lockfile was written and its output still exists.
+    UpToDate,
+
+    /// The assembled CMB and any warnings.
+    Built(Vec<u8>, CompilerLog),
+}
+
+/// Outcome of building one manifest target.
+#[derive(Debug)]
+pub struct TargetReport {
+    /// Script that was compiled, as written in the manifest.
+    pub source: PathBuf,
+
+    /// Where the compiled script should be written.
+    pub output: PathBuf,
+
+    /// Failures with diagnostics carry them in a [`exalt_compiler::CompilerError::ParseError`].
+    pub result: Result<TargetOutcome>,
+}
+
+fn target_request(
+    manifest: &Manifest,
+    target: &ManifestTarget,
+    root: &Path,
+    game: Game,
+    include_dirs: &[PathBuf],
+    optimizations: &OptimizationConfig,
+) -> CompileRequest {
+    let output = target
+        .output
+        .clone()
+        .unwrap_or_else(|| target.source.with_extension("cmb"));
+    let mut defines = manifest.defines.clone();
+    defines.extend(target.defines.clone());
+    CompileRequest {
+        game: target.game.or(manifest.game).unwrap_or(game),
+        target: resolve(root, &target.source),
+        output: Some(manifest.resolve_output_dir(root).join(output)),
+        text_data: None,
+        text: TextOptions::default(),
+        additional_includes: include_dirs.to_vec(),
+        optimizations: optimizations.clone(),
+        revision: None,
+        internal_name: None,
+        symbols: None,
+        listing: false,
+        source_map: false,
+        message_format: MessageFormat::default(),
+        defines: defines
+            .into_iter()
+            .map(|(name, value)| (name, value.into()))
+            .collect(),
+        aliases: Vec::new(),
+    }
+}
+
+/// Everything that affects a target's output besides the contents of the files it reads.
+fn settings_hash(request: &CompileRequest) -> String {
+    let settings = format!(
+        "{:?}",
+        (
+            request.game,
+            &request.output,
+            &request.additional_includes,
+            &request.optimizations,
+            &request.defines,
+        )
+    );
+    hash_bytes(settings.as_bytes())
+}
+
+fn is_up_to_date(
+    locked: &LockedTarget,
+    request: &CompileRequest,
+    settings: &str,
+    root: &Path,
+) -> bool {
+    locked.settings == settings
+        && request.output.as_deref().map(Path::exists).unwrap_or(false)
+        && locked.dependencies_unchanged(root)
+}
+
+/// Compile every target in a manifest across several threads (all cores if `jobs` is None).
+/// `game` is used for targets when neither they nor the manifest name one.
+/// Every target is compiled even if some fail, and nothing is printed, so diagnostics
+/// can be reported together afterwards. Reports come back in the manifest's order.
+///
+/// Targets the lockfile shows are unchanged are skipped, so pass an empty one to rebuild everything.
+/// The returned lockfile describes this build and should be saved once the outputs are written.
+pub fn build_manifest(
+    manifest: &Manifest,
+    root: &Path,
+    game: Game,
+    optimizations: &OptimizationConfig,
+    jobs: Option<usize>,
+    lockfile: &Lockfile,
+) -> Result<(Vec<TargetReport>, Lockfile)> {
+    if manifest.targets.is_empty() {
+        bail!("manifest has no targets");
+    }
+    let include_dirs: Vec<PathBuf> = manifest
+        .include_dirs
+        .iter()
+        .map(|dir| resolve(root, dir))
+        .collect();
+    for dir in &include_dirs {
+        if !dir.is_dir() {
+            bail!("include directory '{}' does not exist", dir.display());
+        }
+    }
+
+    let requests: Vec<(&ManifestTarget, CompileRequest, String)> = manifest
+        .targets
+        .iter()
+        .map(|target| {
+            let request =
+                target_request(manifest, target, root, game, &include_dirs, optimizations);
+            let settings = settings_hash(&request);
+            (target, request, settings)
+        })
+        .collect();
+    let results = run_parallel(&requests, jobs, |(target, request, settings)| {
+        if let Some(locked) = lockfile.target(&target.source) {
+            if is_up_to_date(locked, request, settings, root) {
+                return Ok((TargetOutcome::UpToDate, Some(locked.clone())));
+            }
+        }
+        let mut cache = CompilationCache::new();
+        let (_, raw, log) = exalt_compiler::compile_deferring_diagnostics(request, &mut cache)?;
+        // A dependency that can't be read back can't be checked later, so leave the target unlocked
+        let locked = cache
+            .dependencies()
+            .map(|path| lock_file(root, path))
+            .collect::<Option<Vec<_>>>()
+            .map(|dependencies| LockedTarget {
+                source: target.source.clone(),
+                settings: settings.clone(),
+                dependencies,
+            });
+        Ok((TargetOutcome::Built(raw, log), locked))
+    })?;
+
+    let mut next_lockfile = Lockfile::default();
+    let reports = requests
+        .into_iter()
+        .zip(results)
+        .map(|((target, request, _), result)| {
+            let result = result.map(|(outcome, locked)| {
+                next_lockfile.targets.extend(locked);
+                outcome
+            });
+            TargetReport {
+                source: target.source.clone(),
+                output: request.output.unwrap_or_default(),
+                result,
+            }
+        })
+        .collect();
+    Ok((reports, next_lockfile))
+}
@@ -0,0 +1,149 @@
+use anyhow::{bail, Context, Result};
+use exalt_assembler::AssembleOptions;
+use exalt_compiler::CompileRequest;
+use exalt_lir::{Function, Opcode, RawScript};
+use strum_macros::EnumString;
+
+/// What to do when the overlay defines something the base script already has.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, EnumString)]
+#[strum(serialize_all = "kebab-case")]
+pub enum CollisionStrategy {
+    /// Stop merging and report the collision.
+    Error,
+
+    /// Keep the base script's version. Calls from the overlay go to it instead.
+    KeepBase,
+
+    /// Overwrite the base script's version in place.
+    Replace,
+
+    /// Keep both. Only meaningful for callbacks, since a name can only be defined once.
+    Append,
+}
+
+#[derive(Debug, Clone)]
+pub struct MergeOptions {
+    /// Strategy for named functions that exist in both scripts.
+    pub functions: CollisionStrategy,
+
+    /// Strategy for callbacks with the same event type and arguments in both scripts.
+    pub callbacks: CollisionStrategy,
+}
+
+impl Default for MergeOptions {
+    fn default() -> Self {
+        MergeOptions {
+            functions: CollisionStrategy::Error,
+            callbacks: CollisionStrategy::Append,
+        }
+    }
+}
+
+fn describe(function: &Function) -> String {
+    match &function.name {
+        Some(name) => format!("function '{}'", name),
+        None => format!("callback for event 0x{:X}", function.event),
+    }
+}
+
+/// Find the function in the base script that an overlay function collides with, if any.
+fn find_collision(base: &RawScript, function: &Function) -> Option<usize> {
+    base.functions
+        .iter()
+        .position(|existing| match &function.name {
+            Some(name) => existing.name.as_deref() == Some(name.as_str()),
+            None => {
+                function.event != 0
+                    && existing.name.is_none()
+                    && existing.event == function.event
+                    && existing.args == function.args
+            }
+        })
+}
+
+/// Merge the functions of an overlay into a base script.
+///
+/// Overlay functions without a counterpart in the base script are appended to the function table,
+/// and calls inside them are renumbered to match. Both scripts share the same globals,
+/// so the overlay should declare any globals it uses in the same order as the base script.
+pub fn merge_scripts(
+    base: &RawScript,
+    overlay: &RawScript,
+    options: &MergeOptions,
+) -> Result<RawScript> {
+    // Decide where every overlay function ends up before rewriting any calls
+    let mut call_ids = Vec::with_capacity(overlay.functions.len());
+    let mut placements = Vec::new();
+    let mut next_id = base.functions.len();
+    for (i, function) in overlay.functions.iter().enumerate() {
+        let strategy = if function.name.is_some() {
+            options.functions
+        } else {
+            options.callbacks
+        };
+        let collision = find_collision(base, function);
+        let target = match (collision, strategy) {
+            (Some(_), CollisionStrategy::Error) => {
+                bail!("{} is defined in both scripts", describe(function))
+            }
+            (Some(_), CollisionStrategy::Append) if function.name.is_some() => {
+                bail!(
+                    "{} can't be appended since names must be unique",
+                    describe(function)
+                )
+            }
+            (Some(existing), CollisionStrategy::KeepBase) => {
+                call_ids.push(existing);
+                continue;
+            }
+            (Some(existing), CollisionStrategy::Replace) => existing,
+            (Some(_), CollisionStrategy::Append) | (None, _) => {
+                next_id += 1;
+                next_id - 1
+            }
+        };
+        call_ids.push(target);
+        placements.push((i, target));
+    }
+
+    let mut merged = base.clone();
+    merged.global_frame_size = base.global_frame_size.max(overlay.global_frame_size);
+    for (source, target) in placements {
+        let mut function = overlay.functions[source].clone();
+        let description = describe(&function);
+        for opcode in &mut function.code {
+            if let Opcode::CallById(id) = opcode {
+                let new_id = call_ids
+                    .get(*id)
+                    .with_context(|| format!("{} calls unknown function {}", description, id))?;
+                *id = *new_id;
+            }
+        }
+        if target < merged.functions.len() {
+            merged.functions[target] = function;
+        } else {
+            merged.functions.push(function);
+        }
+    }
+    Ok(merged)
+}
+
+/// Compile an overlay and merge it into a compiled script.
+/// The merged script keeps the base script's header name and revision.
+pub fn merge(base: &[u8], request: &CompileRequest, options: &MergeOptions) -> Result<Vec<u8>> {
+    let game = request.game;
+    let header = exalt_disassembler::read_header(base, game, request.text)
+        .context("failed to read base script")?;
+    let script = exalt_disassembler::disassemble_with_options(base, game, request.text)
+        .context("failed to disassemble base script")?;
+    let (overlay, _) = exalt_compiler::compile_to_lir_and_vec(request)
+        .with_context(|| format!("failed to compile '{}'", request.target.display()))?;
+    let merged = merge_scripts(&script, &overlay, options)?;
+    let options = AssembleOptions {
+        text_data: None,
+        revision: Some(header.revision),
+        text: request.text,
+    };
+    exalt_assembler::assemble_with_options(&merged, &header.name, game, options)
+        .context("failed to assemble merged script")
+}
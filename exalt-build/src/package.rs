@@ -0,0 +1,110 @@
+use std::fmt::Display;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use anyhow::{bail, Context, Result};
+use serde::Deserialize;
+
+use crate::resolve;
+
+/// Something to do with the outputs once every target in a manifest has built,
+/// written as `[[post_build]]` tables with a `kind` key.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(tag = "kind", rename_all = "lowercase", deny_unknown_fields)]
+pub enum PostBuildStep {
+    /// Copy every output into a directory (ex. an extracted romfs), keeping its path under the output directory.
+    Copy { to: PathBuf },
+
+    /// Lay the outputs out as a LayeredFS patch (luma/titles/<title id>/romfs/...) under a directory.
+    LayeredFs {
+        /// 16 hex digit title id of the game being patched (ex. 0004000000179400).
+        title_id: String,
+
+        /// Directory inside the romfs the outputs belong in (ex. "Scripts").
+        #[serde(default)]
+        romfs_dir: PathBuf,
+
+        to: PathBuf,
+    },
+
+    /// Run a program (ex. a packer) from the manifest's directory.
+    /// `{output_dir}` in any argument is replaced with the output directory.
+    Run { command: Vec<String> },
+}
+
+impl Display for PostBuildStep {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            PostBuildStep::Copy { to } => write!(f, "copy to '{}'", to.display()),
+            PostBuildStep::LayeredFs { title_id, to, .. } => {
+                write!(f, "LayeredFS patch for {} in '{}'", title_id, to.display())
+            }
+            PostBuildStep::Run { command } => write!(f, "run '{}'", command.join(" ")),
+        }
+    }
+}
+
+fn copy_outputs(output_dir: &Path, outputs: &[PathBuf], destination: &Path) -> Result<()> {
+    for output in outputs {
+        let relative = match output.strip_prefix(output_dir) {
+            Ok(relative) => relative,
+            Err(_) => Path::new(output.file_name().unwrap_or(output.as_os_str())),
+        };
+        let path = destination.join(relative);
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)
+                .with_context(|| format!("failed to create directory '{}'", parent.display()))?;
+        }
+        std::fs::copy(output, &path).with_context(|| {
+            format!(
+                "failed to copy '{}' to '{}'",
+                output.display(),
+                path.display()
+            )
+        })?;
+    }
+    Ok(())
+}
+
+impl PostBuildStep {
+    /// Run the step over the outputs of a build. Relative paths are resolved against `root`.
+    pub fn run(&self, root: &Path, output_dir: &Path, outputs: &[PathBuf]) -> Result<()> {
+        match self {
+            PostBuildStep::Copy { to } => copy_outputs(output_dir, outputs, &resolve(root, to)),
+            PostBuildStep::LayeredFs {
+                title_id,
+                romfs_dir,
+                to,
+            } => {
+                if title_id.len() != 16 || !title_id.chars().all(|c| c.is_ascii_hexdigit()) {
+                    bail!("title id '{}' is not 16 hex digits", title_id);
+                }
+                let destination = resolve(root, to)
+                    .join("luma/titles")
+                    .join(title_id.to_ascii_uppercase())
+                    .join("romfs")
+                    .join(romfs_dir);
+                copy_outputs(output_dir, outputs, &destination)
+            }
+            PostBuildStep::Run { command } => {
+                let (program, args) = match command.split_first() {
+                    Some(split) => split,
+                    None => bail!("post build command is empty"),
+                };
+                let output_dir = output_dir.to_string_lossy();
+                let status = Command::new(program)
+                    .args(
+                        args.iter()
+                            .map(|arg| arg.replace("{output_dir}", &output_dir)),
+                    )
+                    .current_dir(root)
+                    .status()
+                    .with_context(|| format!("failed to run '{}'", program))?;
+                if !status.success() {
+                    bail!("'{}' failed with {}", program, status);
+                }
+                Ok(())
+            }
+        }
+    }
+}
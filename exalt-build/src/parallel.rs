@@ -0,0 +1,40 @@
+use std::any::Any;
+use std::panic::{self, AssertUnwindSafe};
+
+use anyhow::{anyhow, Context, Result};
+use rayon::prelude::*;
+
+fn panic_message(payload: &(dyn Any + Send)) -> &str {
+    if let Some(message) = payload.downcast_ref::<&str>() {
+        message
+    } else if let Some(message) = payload.downcast_ref::<String>() {
+        message
+    } else {
+        "unknown cause"
+    }
+}
+
+/// Run a job for every input on a thread pool with the given number of threads (all cores if None).
+/// Results come back in the same order as the inputs.
+/// A job that panics is reported as an error for that input instead of ending the whole run.
+pub fn run_parallel<T, R, F>(inputs: &[T], jobs: Option<usize>, job: F) -> Result<Vec<Result<R>>>
+where
+    T: Sync,
+    R: Send,
+    F: Fn(&T) -> Result<R> + Sync,
+{
+    let pool = rayon::ThreadPoolBuilder::new()
+        .num_threads(jobs.unwrap_or(0))
+        .build()
+        .context("failed to start thread pool")?;
+    Ok(pool.install(|| {
+        inputs
+            .par_iter()
+            .map(|input| {
+                panic::catch_unwind(AssertUnwindSafe(|| job(input))).unwrap_or_else(|payload| {
+                    Err(anyhow!("panicked: {}", panic_message(payload.as_ref())))
+                })
+            })
+            .collect()
+    }))
+}
@@ -0,0 +1,109 @@
+use std::collections::HashMap;
+
+use anyhow::{bail, Context, Result};
+use exalt_assembler::AssembleOptions;
+use exalt_compiler::CompileRequest;
+use exalt_lir::{Opcode, RawScript};
+
+fn index_by_name(script: &RawScript) -> HashMap<&str, usize> {
+    script
+        .functions
+        .iter()
+        .enumerate()
+        .filter_map(|(i, function)| function.name.as_deref().map(|name| (name, i)))
+        .collect()
+}
+
+/// Replace functions in a script with the functions of the same name from a patch.
+///
+/// Calls inside the replacements are resolved by name against the original script,
+/// so the patch can declare stubs for any original functions it needs to call.
+/// Replacements must keep the arity of the function they replace.
+pub fn patch_script(
+    original: &mut RawScript,
+    patch: &RawScript,
+    functions: &[String],
+) -> Result<()> {
+    if patch.global_frame_size > original.global_frame_size {
+        bail!(
+            "patch declares {} globals but the original script only has {}",
+            patch.global_frame_size,
+            original.global_frame_size
+        );
+    }
+    let original_ids = index_by_name(original);
+    let patch_ids = index_by_name(patch);
+
+    // Map every call in the patch to the function with the same name in the original
+    let mut call_ids = HashMap::new();
+    for (i, function) in patch.functions.iter().enumerate() {
+        if let Some(target) = function.name.as_deref().and_then(|n| original_ids.get(n)) {
+            call_ids.insert(i, *target);
+        }
+    }
+
+    let mut replacements = Vec::new();
+    for name in functions {
+        let target = *original_ids
+            .get(name.as_str())
+            .with_context(|| format!("original script has no function named '{}'", name))?;
+        let source = *patch_ids
+            .get(name.as_str())
+            .with_context(|| format!("patch has no function named '{}'", name))?;
+        let replacement = &patch.functions[source];
+        if replacement.arity != original.functions[target].arity {
+            bail!(
+                "'{}' takes {} arguments in the patch but {} in the original script",
+                name,
+                replacement.arity,
+                original.functions[target].arity
+            );
+        }
+        let mut code = Vec::with_capacity(replacement.code.len());
+        for opcode in &replacement.code {
+            code.push(match opcode {
+                Opcode::CallById(id) => match call_ids.get(id) {
+                    Some(id) => Opcode::CallById(*id),
+                    None => bail!(
+                        "'{}' calls a function that does not exist in the original script",
+                        name
+                    ),
+                },
+                _ => opcode.clone(),
+            });
+        }
+        replacements.push((target, replacement, code));
+    }
+
+    // Callback metadata (event, args) stays with the original entry in the function table
+    for (target, replacement, code) in replacements {
+        let function = &mut original.functions[target];
+        function.frame_size = replacement.frame_size;
+        function.unknown = replacement.unknown;
+        function.prefix = replacement.prefix.clone();
+        function.suffix = replacement.suffix.clone();
+        function.code = code;
+        function.comments.clear();
+    }
+    Ok(())
+}
+
+/// Compile a patch and splice the named functions into a compiled script.
+/// Everything else in the original script, including its header name and revision, is left as is.
+pub fn patch(original: &[u8], request: &CompileRequest, functions: &[String]) -> Result<Vec<u8>> {
+    let game = request.game;
+    let header = exalt_disassembler::read_header(original, game, request.text)
+        .context("failed to read original script")?;
+    let mut script = exalt_disassembler::disassemble_with_options(original, game, request.text)
+        .context("failed to disassemble original script")?;
+    let (patch, _) = exalt_compiler::compile_to_lir_and_vec(request)
+        .with_context(|| format!("failed to compile '{}'", request.target.display()))?;
+    patch_script(&mut script, &patch, functions)?;
+    let options = AssembleOptions {
+        text_data: None,
+        revision: Some(header.revision),
+        text: request.text,
+    };
+    exalt_assembler::assemble_with_options(&script, &header.name, game, options)
+        .context("failed to assemble patched script")
+}
@@ -0,0 +1,65 @@
+#ifndef EXALT_H
+#define EXALT_H
+
+/* Generated by cbindgen from exalt-capi. Do not edit by hand. */
+
+#include <stdarg.h>
+#include <stdbool.h>
+#include <stddef.h>
+#include <stdint.h>
+#include <stdlib.h>
+
+// Bytes owned by the library.
+typedef struct ExaltBuffer {
+  uint8_t *data;
+  size_t len;
+} ExaltBuffer;
+
+#ifdef __cplusplus
+extern "C" {
+#endif // __cplusplus
+
+// The message left by the last call on this thread that failed, or null if none has.
+// The string stays valid until the next failing call on the same thread.
+const char *exalt_last_error(void);
+
+// Compile a script held in memory. Returns 0 and fills `out` on success,
+// 1 if the script has errors (the last error holds the diagnostics), and -1 for any other failure.
+//
+// Includes are looked up in `include_names` and `include_sources`, which are parallel arrays of
+// `include_count` strings (ex. "std/fe14/prelude.exl" and its source). Either may be null if the count is 0.
+//
+// # Safety
+// Every string must be a valid nul terminated string and `out` must point to writable memory.
+int32_t exalt_compile(const char *name,
+                      const char *source,
+                      const char *game,
+                      const char *const *include_names,
+                      const char *const *include_sources,
+                      size_t include_count,
+                      struct ExaltBuffer *out);
+
+// Decompile a binary script to source without the standard library's names.
+// Returns null on failure.
+//
+// # Safety
+// `data` must point to `len` readable bytes and `game` must be a valid nul terminated string.
+char *exalt_decompile(const uint8_t *data, size_t len, const char *game);
+
+// Release a buffer returned by `exalt_compile`.
+//
+// # Safety
+// The buffer must come from this library and must not be used afterwards.
+void exalt_free_buffer(struct ExaltBuffer buffer);
+
+// Release a string returned by `exalt_decompile`.
+//
+// # Safety
+// The string must come from this library and must not be used afterwards.
+void exalt_free_string(char *s);
+
+#ifdef __cplusplus
+} // extern "C"
+#endif // __cplusplus
+
+#endif /* EXALT_H */
@@ -0,0 +1,263 @@
+//! C ABI for embedding the compiler and decompiler in other programs (ex. GUI editors).
+//!
+//! Functions that can fail return null or a nonzero status and leave a message behind for
+//! `exalt_last_error`. Internal errors that would panic are reported the same way.
+//! Compiler diagnostics are reported as a JSON array of records.
+//! Anything returned by this library must be released with the matching `exalt_free_*` function.
+
+use std::cell::RefCell;
+use std::ffi::{c_char, CStr, CString};
+use std::panic::{self, AssertUnwindSafe};
+use std::str::FromStr;
+
+use exalt_compiler::{CompilerError, MemoryIncludes};
+use exalt_decompiler::DecompilerOptions;
+use exalt_lir::Game;
+
+thread_local! {
+    static LAST_ERROR: RefCell<Option<CString>> = const { RefCell::new(None) };
+}
+
+/// Bytes owned by the library.
+#[repr(C)]
+pub struct ExaltBuffer {
+    pub data: *mut u8,
+    pub len: usize,
+}
+
+impl ExaltBuffer {
+    fn empty() -> Self {
+        ExaltBuffer {
+            data: std::ptr::null_mut(),
+            len: 0,
+        }
+    }
+}
+
+fn set_last_error(message: impl Into<Vec<u8>>) {
+    // Interior nul bytes can't cross the boundary, so drop them instead of losing the whole message
+    let mut message = message.into();
+    message.retain(|b| *b != 0);
+    let message = CString::new(message).unwrap_or_default();
+    LAST_ERROR.with(|e| *e.borrow_mut() = Some(message));
+}
+
+/// Run a call, turning a panic into a failure with the panic message as the last error.
+/// Unwinding across the C boundary would abort the host program.
+fn catch_panic<T>(failure: T, f: impl FnOnce() -> T) -> T {
+    match panic::catch_unwind(AssertUnwindSafe(f)) {
+        Ok(result) => result,
+        Err(payload) => {
+            let message = if let Some(message) = payload.downcast_ref::<&str>() {
+                message.to_string()
+            } else if let Some(message) = payload.downcast_ref::<String>() {
+                message.clone()
+            } else {
+                "unknown error".to_owned()
+            };
+            set_last_error(format!("internal error: {}", message));
+            failure
+        }
+    }
+}
+
+unsafe fn read_str<'a>(s: *const c_char, what: &str) -> Option<&'a str> {
+    if s.is_null() {
+        set_last_error(format!("{} is null", what));
+        return None;
+    }
+    match CStr::from_ptr(s).to_str() {
+        Ok(s) => Some(s),
+        Err(_) => {
+            set_last_error(format!("{} is not valid utf8", what));
+            None
+        }
+    }
+}
+
+unsafe fn read_game(game: *const c_char) -> Option<Game> {
+    let game = read_str(game, "game")?;
+    match Game::from_str(game) {
+        Ok(game) => Some(game),
+        Err(_) => {
+            set_last_error(format!("unknown game '{}'", game));
+            None
+        }
+    }
+}
+
+/// The message left by the last call on this thread that failed, or null if none has.
+/// The string stays valid until the next failing call on the same thread.
+#[no_mangle]
+pub extern "C" fn exalt_last_error() -> *const c_char {
+    LAST_ERROR.with(|e| match &*e.borrow() {
+        Some(message) => message.as_ptr(),
+        None => std::ptr::null(),
+    })
+}
+
+/// Compile a script held in memory. Returns 0 and fills `out` on success,
+/// 1 if the script has errors (the last error holds the diagnostics), and -1 for any other failure.
+///
+/// Includes are looked up in `include_names` and `include_sources`, which are parallel arrays of
+/// `include_count` strings (ex. "std/fe14/prelude.exl" and its source). Either may be null if the count is 0.
+///
+/// # Safety
+/// Every string must be a valid nul terminated string and `out` must point to writable memory.
+#[no_mangle]
+pub unsafe extern "C" fn exalt_compile(
+    name: *const c_char,
+    source: *const c_char,
+    game: *const c_char,
+    include_names: *const *const c_char,
+    include_sources: *const *const c_char,
+    include_count: usize,
+    out: *mut ExaltBuffer,
+) -> i32 {
+    catch_panic(-1, || {
+        compile(
+            name,
+            source,
+            game,
+            include_names,
+            include_sources,
+            include_count,
+            out,
+        )
+    })
+}
+
+unsafe fn compile(
+    name: *const c_char,
+    source: *const c_char,
+    game: *const c_char,
+    include_names: *const *const c_char,
+    include_sources: *const *const c_char,
+    include_count: usize,
+    out: *mut ExaltBuffer,
+) -> i32 {
+    if out.is_null() {
+        set_last_error("out is null");
+        return -1;
+    }
+    *out = ExaltBuffer::empty();
+    let (name, source, game) = match (
+        read_str(name, "name"),
+        read_str(source, "source"),
+        read_game(game),
+    ) {
+        (Some(name), Some(source), Some(game)) => (name, source, game),
+        _ => return -1,
+    };
+    let mut includes = MemoryIncludes::new();
+    if include_count > 0 {
+        if include_names.is_null() || include_sources.is_null() {
+            set_last_error("includes are null");
+            return -1;
+        }
+        for i in 0..include_count {
+            match (
+                read_str(*include_names.add(i), "include name"),
+                read_str(*include_sources.add(i), "include source"),
+            ) {
+                (Some(name), Some(source)) => includes.insert(name, source),
+                _ => return -1,
+            }
+        }
+    }
+    match exalt_compiler::compile_source(name, source, &includes, game) {
+        Ok(raw) => {
+            let raw = Box::into_raw(raw.into_boxed_slice());
+            *out = ExaltBuffer {
+                data: raw as *mut u8,
+                len: raw.len(),
+            };
+            0
+        }
+        Err(CompilerError::ParseError(log)) => {
+            set_last_error(log.to_json());
+            1
+        }
+        Err(err) => {
+            set_last_error(err.to_string());
+            -1
+        }
+    }
+}
+
+/// Decompile a binary script to source without the standard library's names.
+/// Returns null on failure.
+///
+/// # Safety
+/// `data` must point to `len` readable bytes and `game` must be a valid nul terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn exalt_decompile(
+    data: *const u8,
+    len: usize,
+    game: *const c_char,
+) -> *mut c_char {
+    catch_panic(std::ptr::null_mut(), || decompile(data, len, game))
+}
+
+unsafe fn decompile(data: *const u8, len: usize, game: *const c_char) -> *mut c_char {
+    if data.is_null() {
+        set_last_error("data is null");
+        return std::ptr::null_mut();
+    }
+    let game = match read_game(game) {
+        Some(game) => game,
+        None => return std::ptr::null_mut(),
+    };
+    let result = exalt_disassembler::disassemble(std::slice::from_raw_parts(data, len), game)
+        .map_err(|err| err.to_string())
+        .and_then(|script| {
+            exalt_decompiler::decompile(
+                &script,
+                None,
+                &DecompilerOptions::default(),
+                Vec::new(),
+                game,
+                false,
+                false,
+            )
+            .map_err(|err| err.to_string())
+        });
+    match result {
+        Ok(source) => match CString::new(source) {
+            Ok(source) => source.into_raw(),
+            Err(_) => {
+                set_last_error("decompiled source contains a nul byte");
+                std::ptr::null_mut()
+            }
+        },
+        Err(err) => {
+            set_last_error(err);
+            std::ptr::null_mut()
+        }
+    }
+}
+
+/// Release a buffer returned by `exalt_compile`.
+///
+/// # Safety
+/// The buffer must come from this library and must not be used afterwards.
+#[no_mangle]
+pub unsafe extern "C" fn exalt_free_buffer(buffer: ExaltBuffer) {
+    if !buffer.data.is_null() {
+        drop(Box::from_raw(std::ptr::slice_from_raw_parts_mut(
+            buffer.data,
+            buffer.len,
+        )));
+    }
+}
+
+/// Release a string returned by `exalt_decompile`.
+///
+/// # Safety
+/// The string must come from this library and must not be used afterwards.
+#[no_mangle]
+pub unsafe extern "C" fn exalt_free_string(s: *mut c_char) {
+    if !s.is_null() {
+        drop(CString::from_raw(s));
+    }
+}
@@ -0,0 +1,82 @@
+use std::path::{Component, Path, PathBuf};
+
+use anyhow::Context;
+
+use crate::round_trip::collect_scripts;
+use crate::Decompiler;
+
+fn is_glob(path: &Path) -> bool {
+    path.to_string_lossy().contains(['*', '?', '['])
+}
+
+/// Find the scripts a batch covers along with the directory that output paths mirror.
+/// Globs mirror everything after the last directory that doesn't contain a wildcard.
+fn collect_inputs(input: &Path) -> anyhow::Result<(PathBuf, Vec<PathBuf>)> {
+    if !is_glob(input) {
+        if !input.is_dir() {
+            anyhow::bail!("'{}' is not a directory", input.display());
+        }
+        return Ok((input.to_path_buf(), collect_scripts(input)?));
+    }
+    let root: PathBuf = input
+        .components()
+        .take_while(|c| !matches!(c, Component::Normal(name) if is_glob(Path::new(name))))
+        .collect();
+    let pattern = input.to_string_lossy();
+    let mut scripts = Vec::new();
+    for entry in glob::glob(&pattern).context("invalid glob pattern")? {
+        let path = entry.context("failed to read glob match")?;
+        if path.is_file() {
+            scripts.push(path);
+        }
+    }
+    scripts.sort();
+    Ok((root, scripts))
+}
+
+/// Decompile every script under a directory or matching a glob into a mirrored tree of `.exl` files.
+/// Stops at the first failure unless `keep_going` is set, in which case failures are reported at the end.
+pub fn decompile_all(
+    decompiler: &Decompiler,
+    input: &Path,
+    output: &Path,
+    keep_going: bool,
+) -> anyhow::Result<()> {
+    let (root, scripts) = collect_inputs(input)?;
+    let mut failures = 0;
+    for path in &scripts {
+        let relative = path.strip_prefix(&root).unwrap_or(path);
+        let target = output.join(relative).with_extension("exl");
+        let result = std::fs::read(path)
+            .context("failed to read input file")
+            .and_then(|raw| decompiler.decompile(&raw))
+            .and_then(|source| {
+                if let Some(parent) = target.parent() {
+                    std::fs::create_dir_all(parent).context("failed to create output directory")?;
+                }
+                std::fs::write(&target, source).context("failed to write output file")
+            });
+        match result {
+            Ok(()) => println!("OK   {}", relative.display()),
+            Err(err) if keep_going => {
+                println!("FAIL {}", relative.display());
+                println!("    {:#}", err);
+                failures += 1;
+            }
+            Err(err) => {
+                return Err(err.context(format!("failed to decompile '{}'", path.display())))
+            }
+        }
+    }
+
+    println!();
+    println!(
+        "decompiled {} of {} script(s)",
+        scripts.len() - failures,
+        scripts.len()
+    );
+    if failures > 0 {
+        anyhow::bail!("{} of {} script(s) failed", failures, scripts.len());
+    }
+    Ok(())
+}
@@ -0,0 +1,96 @@
+use std::path::{Path, PathBuf};
+
+use anyhow::Context;
+use exalt_build::{Lockfile, Manifest, TargetOutcome, LOCKFILE_NAME};
+use exalt_compiler::{CompilerError, OptimizationConfig};
+use exalt_lir::Game;
+
+/// Build every target in a manifest, then report diagnostics for all of them in the manifest's order.
+/// Paths given on the command line are relative to the current directory rather than the manifest.
+/// Targets that haven't changed since the last build are skipped unless `force` is set.
+/// Post build steps only run if every target succeeds.
+pub fn build_manifest(
+    game: Game,
+    path: &Path,
+    output: Option<PathBuf>,
+    includes: Vec<PathBuf>,
+    optimizations: OptimizationConfig,
+    jobs: Option<usize>,
+    force: bool,
+) -> anyhow::Result<()> {
+    let mut manifest = Manifest::load(path)?;
+    let current_dir = std::env::current_dir().context("failed to read current directory")?;
+    if let Some(output) = output {
+        manifest.output_dir = current_dir.join(output);
+    }
+    manifest
+        .include_dirs
+        .extend(includes.iter().map(|dir| current_dir.join(dir)));
+    let root = match path.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent,
+        _ => Path::new("."),
+    };
+
+    let lockfile_path = root.join(LOCKFILE_NAME);
+    let lockfile = if force {
+        Lockfile::default()
+    } else {
+        Lockfile::load(&lockfile_path)?
+    };
+
+    let (reports, lockfile) =
+        exalt_build::build_manifest(&manifest, root, game, &optimizations, jobs, &lockfile)?;
+    let (mut failures, mut up_to_date, mut warnings, mut errors) = (0, 0, 0, 0);
+    for report in &reports {
+        match &report.result {
+            Ok(TargetOutcome::UpToDate) => {
+                println!("OK   {} (up to date)", report.source.display());
+                up_to_date += 1;
+            }
+            Ok(TargetOutcome::Built(data, log)) => {
+                log.print();
+                warnings += log.warnings.len();
+                if let Some(parent) = report.output.parent() {
+                    std::fs::create_dir_all(parent).context("failed to create output directory")?;
+                }
+                std::fs::write(&report.output, data)
+                    .with_context(|| format!("failed to write '{}'", report.output.display()))?;
+                println!("OK   {}", report.source.display());
+            }
+            Err(err) => {
+                if let Some(CompilerError::ParseError(log)) = err.downcast_ref() {
+                    log.print();
+                    warnings += log.warnings.len();
+                    errors += log.errors.len();
+                }
+                println!("FAIL {}", report.source.display());
+                println!("    {:#}", err);
+                failures += 1;
+            }
+        }
+    }
+
+    lockfile.save(&lockfile_path)?;
+
+    println!();
+    println!(
+        "{} built, {} up to date, {} failed ({} error(s), {} warning(s))",
+        reports.len() - failures - up_to_date,
+        up_to_date,
+        failures,
+        errors,
+        warnings
+    );
+    if failures > 0 {
+        anyhow::bail!("{} of {} target(s) failed", failures, reports.len());
+    }
+
+    let output_dir = manifest.resolve_output_dir(root);
+    let outputs: Vec<PathBuf> = reports.into_iter().map(|report| report.output).collect();
+    for step in &manifest.post_build {
+        println!("POST {}", step);
+        step.run(root, &output_dir, &outputs)
+            .with_context(|| format!("post build step failed: {}", step))?;
+    }
+    Ok(())
+}
@@ -0,0 +1,70 @@
+use std::path::Path;
+
+use anyhow::Context;
+use exalt_assembler::AssembleOptions;
+use exalt_lir::{callgraph, Game, RawScript, TextOptions};
+
+fn describe(script: &RawScript, index: usize) -> String {
+    let function = &script.functions[index];
+    match &function.name {
+        Some(name) => format!("{} {}", index, name),
+        None if function.event != 0 => format!("{} callback[0x{:X}]", index, function.event),
+        None => format!("{} anonfn{}", index, index),
+    }
+}
+
+/// Print who calls whom in a script and flag functions that no callback can reach.
+/// Functions named in `keep` count as reachable since other scripts or the engine may call them.
+pub fn report(script: &RawScript, keep: &[String]) {
+    let graph = callgraph::build(script);
+    let reachable = graph.reachable_from(&callgraph::entry_points(script, keep));
+    for (i, calls) in graph.calls.iter().enumerate() {
+        let marker = if reachable[i] { "" } else { " (unreachable)" };
+        println!("{}{}", describe(script, i), marker);
+        for callee in calls {
+            if *callee < script.functions.len() {
+                println!("    -> {}", describe(script, *callee));
+            } else {
+                println!("    -> {} (missing)", callee);
+            }
+        }
+        for name in &graph.external[i] {
+            println!("    -> {} (external)", name);
+        }
+    }
+    let unreachable = reachable.iter().filter(|r| !**r).count();
+    println!();
+    println!(
+        "{} function(s), {} unreachable",
+        script.functions.len(),
+        unreachable
+    );
+}
+
+/// Remove unreachable functions from a compiled script, keeping its header name and revision.
+pub fn strip(
+    game: Game,
+    raw: &[u8],
+    script: &mut RawScript,
+    keep: &[String],
+    output: &Path,
+) -> anyhow::Result<()> {
+    let header = exalt_disassembler::read_header(raw, game, TextOptions::default())?;
+    let descriptions: Vec<String> = (0..script.functions.len())
+        .map(|i| describe(script, i))
+        .collect();
+    let removed = callgraph::strip_unreachable(script, keep);
+    let options = AssembleOptions {
+        text_data: None,
+        revision: Some(header.revision),
+        ..Default::default()
+    };
+    let stripped = exalt_assembler::assemble_with_options(script, &header.name, game, options)
+        .context("failed to assemble stripped script")?;
+    std::fs::write(output, stripped).context("failed to write output file")?;
+    for index in &removed {
+        println!("removed {}", descriptions[*index]);
+    }
+    println!("removed {} function(s)", removed.len());
+    Ok(())
+}
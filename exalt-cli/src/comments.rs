@@ -0,0 +1,199 @@
+use std::borrow::Cow;
+use std::path::{Path, PathBuf};
+
+use exalt_lir::ScriptComments;
+
+/// Where the comments for a compiled script are kept (ex. bev.comments.yml for bev.cmb).
+pub fn sidecar_path(script: &Path) -> PathBuf {
+    script.with_extension("comments.yml")
+}
+
+/// Follows a YAML script line by line to find which opcode each line belongs to.
+#[derive(Default)]
+struct CodeTracker {
+    functions_indent: Option<usize>,
+    function_indent: Option<usize>,
+    code_indent: Option<usize>,
+    item_indent: Option<usize>,
+    function: Option<usize>,
+    opcode: Option<usize>,
+}
+
+impl CodeTracker {
+    /// Returns the function and opcode index of a line
+    /// along with whether the line starts the opcode (as opposed to continuing it).
+    fn advance(&mut self, line: &str) -> Option<(usize, usize, bool)> {
+        let text = line.trim();
+        if text.is_empty() {
+            return None;
+        }
+        let indent = line.len() - line.trim_start().len();
+        let is_item = text == "-" || text.starts_with("- ");
+
+        if let Some(code_indent) = self.code_indent {
+            if is_item && self.item_indent.is_none() && indent >= code_indent {
+                self.item_indent = Some(indent);
+            }
+            match self.item_indent {
+                Some(item_indent) if is_item && indent == item_indent => {
+                    let opcode = self.opcode.map(|i| i + 1).unwrap_or_default();
+                    self.opcode = Some(opcode);
+                    return Some((self.function.unwrap_or_default(), opcode, true));
+                }
+                Some(item_indent) if indent > item_indent => {
+                    return self
+                        .opcode
+                        .map(|opcode| (self.function.unwrap_or_default(), opcode, false));
+                }
+                _ => {
+                    self.code_indent = None;
+                    self.item_indent = None;
+                    self.opcode = None;
+                }
+            }
+        }
+
+        if text == "functions:" {
+            self.functions_indent = Some(indent);
+            self.function_indent = None;
+            return None;
+        }
+        let functions_indent = self.functions_indent?;
+        if is_item && self.function_indent.is_none() && indent >= functions_indent {
+            self.function_indent = Some(indent);
+        }
+        let (key, key_indent) = match self.function_indent {
+            Some(function_indent) if is_item && indent == function_indent => {
+                self.function = Some(self.function.map(|i| i + 1).unwrap_or_default());
+                let key = text[1..].trim_start();
+                (key, indent + text.len() - key.len())
+            }
+            Some(function_indent) if indent > function_indent => (text, indent),
+            _ => {
+                self.functions_indent = None;
+                self.function_indent = None;
+                return None;
+            }
+        };
+        if key == "code:" {
+            self.code_indent = Some(key_indent);
+        }
+        None
+    }
+}
+
+/// Split a line into its YAML and the text of a trailing `//` comment.
+fn split_comment(line: &str) -> (&str, Option<&str>) {
+    let mut quote = None;
+    let mut escaped = false;
+    let mut previous = None;
+    for (i, c) in line.char_indices() {
+        match quote {
+            Some('"') if escaped => escaped = false,
+            Some('"') if c == '\\' => escaped = true,
+            Some(q) if c == q => quote = None,
+            Some(_) => {}
+            None => {
+                // Quotes only start a string at the beginning of a scalar (ex. not in "don't")
+                let starts_scalar = previous
+                    .map(|p: char| p.is_whitespace() || matches!(p, '[' | '{' | ','))
+                    .unwrap_or(true);
+                if (c == '"' || c == '\'') && starts_scalar {
+                    quote = Some(c);
+                } else if c == '/'
+                    && line[i..].starts_with("//")
+                    && previous.map(char::is_whitespace).unwrap_or(true)
+                {
+                    return (&line[..i], Some(line[i + 2..].trim()));
+                }
+            }
+        }
+        previous = Some(c);
+    }
+    (line, None)
+}
+
+/// Double quote a plain scalar that would otherwise be cut off at a `//` when the script is read back.
+/// Expects a line written by the YAML serializer, where a plain scalar runs from `key: ` or `- ` to the end of the line.
+fn quote_plain_scalar(line: &str) -> Cow<'_, str> {
+    let (yaml, comment) = split_comment(line);
+    if comment.is_none() {
+        return Cow::Borrowed(line);
+    }
+    let start = match yaml.find(": ") {
+        Some(i) => i + 2,
+        None => {
+            let indent = line.len() - line.trim_start().len();
+            let mut start = indent;
+            while line[start..].starts_with("- ") {
+                start += 2;
+            }
+            start
+        }
+    };
+    let value = line[start..].replace('\\', "\\\\").replace('"', "\\\"");
+    Cow::Owned(format!("{}\"{}\"", &line[..start], value))
+}
+
+/// Strip `//` comments from a YAML script and return them keyed by the opcode they describe.
+/// Comments that are not on an opcode's line belong to the next opcode.
+pub fn extract(text: &str) -> (String, ScriptComments) {
+    let mut tracker = CodeTracker::default();
+    let mut comments = ScriptComments::new();
+    let mut pending = Vec::new();
+    let mut output = String::with_capacity(text.len());
+    for line in text.lines() {
+        let (yaml, comment) = split_comment(line);
+        pending.extend(comment);
+        if let Some((function, opcode, _)) = tracker.advance(yaml) {
+            if !pending.is_empty() {
+                let text = comments
+                    .entry(function)
+                    .or_default()
+                    .entry(opcode)
+                    .or_default();
+                for comment in pending.drain(..) {
+                    if !text.is_empty() {
+                        text.push('\n');
+                    }
+                    text.push_str(comment);
+                }
+            }
+        }
+        // Keep stripped lines so parse errors still point at the right line
+        output.push_str(yaml.trim_end());
+        output.push('\n');
+    }
+    (output, comments)
+}
+
+/// Write comments into a YAML script as `//` comments after the opcodes they describe.
+/// Comments spanning several lines are written above their opcode instead.
+/// Unquoted strings containing `//` are quoted so they aren't mistaken for comments.
+pub fn insert(text: &str, comments: &ScriptComments) -> String {
+    let mut tracker = CodeTracker::default();
+    let mut output = String::with_capacity(text.len());
+    for line in text.lines() {
+        let line = quote_plain_scalar(line);
+        let line = line.as_ref();
+        let comment = match tracker.advance(line) {
+            Some((function, opcode, true)) => comments
+                .get(&function)
+                .and_then(|comments| comments.get(&opcode)),
+            _ => None,
+        };
+        match comment {
+            Some(comment) if comment.contains('\n') => {
+                let indent = &line[..line.len() - line.trim_start().len()];
+                for text in comment.lines() {
+                    output.push_str(&format!("{}// {}\n", indent, text));
+                }
+                output.push_str(line);
+            }
+            Some(comment) => output.push_str(&format!("{}  // {}", line, comment)),
+            None => output.push_str(line),
+        }
+        output.push('\n');
+    }
+    output
+}
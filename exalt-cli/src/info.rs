@@ -0,0 +1,77 @@
+use exalt_disassembler::{CmbHeader, FunctionSummary};
+
+fn describe(index: usize, function: &FunctionSummary) -> String {
+    match &function.name {
+        Some(name) => name.clone(),
+        None if function.event != 0 => format!("callback[0x{:X}]", function.event),
+        None => format!("anonfn{}", index),
+    }
+}
+
+/// Print a script's header, function table, and text data without decoding any code.
+/// `strings` holds each string in the text data along with its encoded size.
+pub fn report(
+    header: &CmbHeader,
+    functions: &[FunctionSummary],
+    strings: &[(String, usize)],
+    script_len: usize,
+) {
+    println!("name:              {}", header.name);
+    println!("revision:          0x{:08X}", header.revision);
+    println!("function table:    0x{:X}", header.function_table_address);
+    println!("text data:         0x{:X}", header.text_data_address);
+    println!("global frame size: {}", header.global_frame_size);
+    match header.init_function_index {
+        Some(index) => println!("init function:     {}", index),
+        None => println!("init function:     none"),
+    }
+
+    println!();
+    println!("{} function(s)", functions.len());
+    println!(
+        "{:>5} {:>8} {:>6} {:>5} {:>5} {:>9}  name",
+        "index", "address", "event", "arity", "frame", "code size"
+    );
+    for (i, function) in functions.iter().enumerate() {
+        println!(
+            "{:>5} {:>8} {:>6} {:>5} {:>5} {:>9}  {}",
+            i,
+            format!("0x{:X}", function.address),
+            function.event,
+            function.arity,
+            function.frame_size,
+            function.code_size,
+            describe(i, function)
+        );
+    }
+
+    // Text data comes before the functions in some games, so it ends wherever the next structure starts.
+    // Without strings, that's the function table at the same address.
+    let text_start = header.text_data_address as usize;
+    let text_end = functions
+        .iter()
+        .flat_map(|f| [f.address, f.code_address])
+        .chain([header.function_table_address as usize, script_len])
+        .filter(|address| *address >= text_start)
+        .min()
+        .unwrap_or(script_len);
+    let text_size = text_end.saturating_sub(text_start);
+    let used: usize = strings.iter().map(|(_, size)| size).sum();
+    let longest = strings
+        .iter()
+        .map(|(_, size)| *size)
+        .max()
+        .unwrap_or_default();
+    println!();
+    println!(
+        "{} string(s) in {} bytes of text data",
+        strings.len(),
+        text_size
+    );
+    println!(
+        "{} bytes of strings, {} bytes of padding",
+        used,
+        text_size.saturating_sub(used)
+    );
+    println!("longest string: {} bytes", longest);
+}
@@ -1,12 +1,32 @@
+mod batch;
+mod build;
+mod call_graph;
+mod comments;
+mod info;
+mod offsets;
+mod round_trip;
+mod verify;
+mod watch;
+
 use anyhow::Context;
+use exalt_assembler::{AssembleOptions, CodeGenTextData};
+use exalt_build::{BuildConfig, BuildSources, CollisionStrategy, MergeOptions};
 use exalt_ast::Literal;
-use exalt_compiler::{CompileRequest, ParseRequest, ParseResult};
-use exalt_decompiler::IrTransform;
-use std::path::PathBuf;
+use exalt_compiler::{
+    AliasFile, CompileRequest, CompilerError, MessageFormat, OptimizationConfig, ParseRequest, ParseResult,
+    PrecompiledHeader, SharedGlobal, StdLibrary, SymbolTable,
+};
+use exalt_decompiler::{DecompilerOptions, IrTransform, ValueTags};
+use exalt_disassembler::FunctionSelector;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
 use strum_macros::EnumString;
 
 use clap::{Parser, Subcommand};
-use exalt_lir::{Game, RawScript};
+use exalt_lir::{
+    FunctionDatabase, Game, RawScript, ScriptComments, ScriptQuirks, SymbolMap, TextEncoding,
+    TextOptions, SCHEMA_VERSION,
+};
 
 #[derive(EnumString)]
 #[strum(serialize_all = "snake_case")]
@@ -14,6 +34,7 @@ enum Format {
     Json,
     Yml,
     Ron,
+    Bin,
 }
 
 #[derive(Parser)]
@@ -21,6 +42,10 @@ struct Args {
     #[clap(short, long, value_name = "GAME")]
     game: Game,
 
+    /// Directory containing the std library folder, overriding EXALT_STD. Defaults to one next to the exe, then the built in copy
+    #[clap(long, value_name = "DIR")]
+    std: Option<PathBuf>,
+
     #[clap(subcommand)]
     command: Commands,
 }
@@ -35,6 +60,30 @@ enum Commands {
 
         #[clap(short, long)]
         format: Format,
+
+        /// Text file listing known engine functions, one per line. Calls to anything else are reported
+        #[clap(long)]
+        functions: Option<PathBuf>,
+
+        /// Only disassemble the function with this index or name. The output can't be assembled back into the original script
+        #[clap(long)]
+        function: Option<FunctionSelector>,
+
+        /// Keep going past functions that can't be read and report them instead. Unreadable functions are written without code
+        #[clap(long)]
+        best_effort: bool,
+
+        /// Record the address of every opcode in the input alongside the code
+        #[clap(long)]
+        offsets: bool,
+
+        /// Text encoding of the input instead of the game's (shift-jis, utf8)
+        #[clap(long)]
+        encoding: Option<TextEncoding>,
+
+        /// Replace malformed text with U+FFFD instead of failing
+        #[clap(long)]
+        lossy_text: bool,
     },
     Assemble {
         input: PathBuf,
@@ -44,6 +93,22 @@ enum Commands {
 
         #[clap(short, long)]
         format: Format,
+
+        /// Compiled script whose text data should be reused byte for byte (ex. the original for FE9-FE12)
+        #[clap(long)]
+        text_from: Option<PathBuf>,
+
+        /// Write the address of every function, label, and string in the output to this JSON file
+        #[clap(long)]
+        offsets: Option<PathBuf>,
+
+        /// Text encoding of the output instead of the script's or game's (shift-jis, utf8)
+        #[clap(long)]
+        encoding: Option<TextEncoding>,
+
+        /// Write '?' for characters the encoding can't hold instead of failing
+        #[clap(long)]
+        lossy_text: bool,
     },
     Decompile {
         input: PathBuf,
@@ -53,80 +118,483 @@ enum Commands {
 
         #[clap(short, long)]
         debug: bool,
+
+        /// YAML file with names for variables
+        #[clap(long)]
+        symbols: Option<PathBuf>,
+
+        /// YAML file with names for engine functions by game (ex. FE14: {bev::FadeIn: bev::フェードイン})
+        #[clap(long)]
+        aliases: Option<PathBuf>,
+
+        /// Global layout script pinning shared globals to slots (ex. `let counter @ 7;`). The output includes it by name
+        #[clap(long)]
+        globals: Option<PathBuf>,
+
+        /// Emit functions that can't be decompiled as asm blocks instead of failing
+        #[clap(long)]
+        lenient: bool,
+
+        /// YAML file with output style options (indent width, brace style, etc.)
+        #[clap(long)]
+        style: Option<PathBuf>,
+
+        /// YAML file mapping function arguments to enums (ex. ev::SetFlag: {1: Flag})
+        #[clap(long)]
+        enum_args: Option<PathBuf>,
+
+        /// YAML file tagging call results and function parameters with enums (ex. results: {GetDifficulty: Difficulty}). Comparisons against them name variants
+        #[clap(long)]
+        value_tags: Option<PathBuf>,
+
+        /// Text file listing known engine functions, one per line. Calls to anything else are reported
+        #[clap(long)]
+        functions: Option<PathBuf>,
+
+        /// Decompile every script in a directory (or matching a glob like "scripts/*.cmb") into a mirrored tree under the output directory
+        #[clap(long)]
+        batch: bool,
+
+        /// With --batch, keep going past scripts that fail and report them at the end
+        #[clap(long)]
+        keep_going: bool,
+
+        /// Text encoding of the input instead of the game's (shift-jis, utf8)
+        #[clap(long)]
+        encoding: Option<TextEncoding>,
+
+        /// Replace malformed text with U+FFFD instead of failing
+        #[clap(long)]
+        lossy_text: bool,
     },
     Compile {
         input: PathBuf,
 
         #[clap(short, long)]
         output: Option<PathBuf>,
+
+        /// Header directories searched for includes, before any listed in EXALT_PATH
+        #[clap(short = 'I', long = "include")]
+        includes: Vec<PathBuf>,
+
+        /// Hoist repeated arithmetic and variable loads into temporaries
+        #[clap(long)]
+        cse: bool,
+
+        /// Remove redundant opcode sequences. The output will no longer match the original game's compiler
+        #[clap(long)]
+        optimize: bool,
+
+        /// Let variables that are never live at the same time share a frame slot
+        #[clap(long)]
+        reuse_slots: bool,
+
+        /// Disassemble the output and check it against the generated code
+        #[clap(long)]
+        verify: bool,
+
+        /// Also decompile the output and check that it parses (implies --verify)
+        #[clap(long)]
+        verify_decompile: bool,
+
+        /// Header revision to embed instead of the game's default (ex. 0x20110819)
+        #[clap(long, parse(try_from_str = parse_u32))]
+        revision: Option<u32>,
+
+        /// Name to embed in the script header instead of the output file name
+        #[clap(long)]
+        script_name: Option<String>,
+
+        /// YAML file with frame indices to keep for named variables
+        #[clap(long)]
+        symbols: Option<PathBuf>,
+
+        /// YAML file with names for engine functions by game, used where the script doesn't declare them
+        #[clap(long)]
+        aliases: Option<PathBuf>,
+
+        /// Also write a listing of the generated opcodes and their addresses (ex. bev.lst)
+        #[clap(long)]
+        listing: bool,
+
+        /// Also write a JSON map from opcode addresses to source lines (ex. bev.map.json)
+        #[clap(long)]
+        source_map: bool,
+
+        /// How to write errors and warnings (human, json)
+        #[clap(long, default_value = "human")]
+        message_format: MessageFormat,
+
+        /// Keep running and recompile whenever the script or anything it includes changes
+        #[clap(long)]
+        watch: bool,
+
+        /// Text encoding of the output instead of the game's (shift-jis, utf8)
+        #[clap(long)]
+        encoding: Option<TextEncoding>,
+
+        /// Write '?' for characters the encoding can't hold instead of failing
+        #[clap(long)]
+        lossy_text: bool,
+    },
+    Precompile {
+        /// Header script to save, usually a prelude
+        input: PathBuf,
+
+        /// Where to write the header (defaults to the input with an .exlh extension)
+        #[clap(short, long)]
+        output: Option<PathBuf>,
+
+        /// Header directories searched for includes, before any listed in EXALT_PATH
+        #[clap(short = 'I', long = "include")]
+        includes: Vec<PathBuf>,
+    },
+    Build {
+        /// Directory containing the scripts to compile. If omitted, targets come from a manifest
+        source_dir: Option<PathBuf>,
+
+        /// Output directory. Required with a source directory, overrides the manifest's otherwise
+        #[clap(short, long)]
+        output: Option<PathBuf>,
+
+        /// Project manifest listing the scripts to build (defaults to exalt.toml in the current directory)
+        #[clap(long)]
+        manifest: Option<PathBuf>,
+
+        /// Header directories searched for includes, before any listed in EXALT_PATH
+        #[clap(short = 'I', long = "include")]
+        includes: Vec<PathBuf>,
+
+        /// Specific scripts to compile, relative to the source directory
+        #[clap(short, long = "target")]
+        targets: Vec<PathBuf>,
+
+        /// Hoist repeated arithmetic and variable loads into temporaries
+        #[clap(long)]
+        cse: bool,
+
+        /// Remove redundant opcode sequences. The output will no longer match the original game's compiler
+        #[clap(long)]
+        optimize: bool,
+
+        /// Let variables that are never live at the same time share a frame slot
+        #[clap(long)]
+        reuse_slots: bool,
+
+        /// Number of scripts to compile at once (defaults to one per core)
+        #[clap(short, long)]
+        jobs: Option<usize>,
+
+        /// Rebuild every manifest target, even ones exalt.lock shows haven't changed
+        #[clap(long)]
+        force: bool,
+    },
+    Fmt {
+        /// Scripts to format in place
+        #[clap(required = true)]
+        inputs: Vec<PathBuf>,
+
+        /// Report scripts that aren't formatted instead of rewriting them
+        #[clap(long)]
+        check: bool,
+    },
+    Test {
+        /// Compiled script or directory of compiled scripts to rebuild
+        input: PathBuf,
+
+        /// Also decompile each script and compile the result
+        #[clap(long)]
+        compile: bool,
+
+        /// Number of scripts to test at once (defaults to one per core)
+        #[clap(short, long)]
+        jobs: Option<usize>,
+    },
+    Diff {
+        /// Compiled script to compare against
+        expected: PathBuf,
+
+        /// Compiled script to compare
+        actual: PathBuf,
+    },
+    Patch {
+        /// Compiled script to patch
+        input: PathBuf,
+
+        #[clap(short, long)]
+        output: PathBuf,
+
+        /// Script containing the replacement functions
+        #[clap(short, long)]
+        source: PathBuf,
+
+        /// Name of a function to replace (ex. my::func)
+        #[clap(short, long = "function", required = true)]
+        functions: Vec<String>,
+
+        /// Text encoding of the scripts instead of the game's (shift-jis, utf8)
+        #[clap(long)]
+        encoding: Option<TextEncoding>,
+
+        /// Replace malformed text instead of failing
+        #[clap(long)]
+        lossy_text: bool,
+    },
+    Merge {
+        /// Compiled script to merge into
+        input: PathBuf,
+
+        #[clap(short, long)]
+        output: PathBuf,
+
+        /// Script containing the functions and callbacks to add
+        #[clap(short, long)]
+        source: PathBuf,
+
+        /// What to do with functions defined in both scripts (error, keep-base, replace)
+        #[clap(long, default_value = "error")]
+        functions: CollisionStrategy,
+
+        /// What to do with callbacks for the same event in both scripts (error, keep-base, replace, append)
+        #[clap(long, default_value = "append")]
+        callbacks: CollisionStrategy,
+
+        /// Text encoding of the scripts instead of the game's (shift-jis, utf8)
+        #[clap(long)]
+        encoding: Option<TextEncoding>,
+
+        /// Replace malformed text instead of failing
+        #[clap(long)]
+        lossy_text: bool,
+    },
+    CallGraph {
+        /// Compiled script to analyze
+        input: PathBuf,
+
+        /// Write a copy of the script without unreachable functions
+        #[clap(long)]
+        strip: Option<PathBuf>,
+
+        /// Named function to treat as reachable, for scripts that are called into from elsewhere
+        #[clap(short, long)]
+        keep: Vec<String>,
+    },
+    Info {
+        input: PathBuf,
+
+        /// Text encoding of the input instead of the game's (shift-jis, utf8)
+        #[clap(long)]
+        encoding: Option<TextEncoding>,
+
+        /// Replace malformed text with U+FFFD instead of failing
+        #[clap(long)]
+        lossy_text: bool,
     },
 }
 
-fn disassemble(game: Game, input: PathBuf, output: PathBuf, format: Format) -> anyhow::Result<()> {
-    let input = std::fs::read(input).context("failed to read input file")?;
-    let script =
-        exalt_disassembler::disassemble(&input, game).context("failed to disassemble script")?;
+fn parse_u32(value: &str) -> Result<u32, std::num::ParseIntError> {
+    match value.strip_prefix("0x").or_else(|| value.strip_prefix("0X")) {
+        Some(hex) => u32::from_str_radix(hex, 16),
+        None => value.parse(),
+    }
+}
+
+struct DisassembleOptions {
+    functions: Option<PathBuf>,
+    function: Option<FunctionSelector>,
+    best_effort: bool,
+    offsets: bool,
+    text: TextOptions,
+}
+
+fn disassemble(
+    game: Game,
+    input: PathBuf,
+    output: PathBuf,
+    format: Format,
+    options: DisassembleOptions,
+) -> anyhow::Result<()> {
+    let data = std::fs::read(&input).context("failed to read input file")?;
+    // A selected function is written out as a script with only that function in it
+    let (mut script, selected) = match &options.function {
+        Some(selector) => {
+            let (index, function) =
+                exalt_disassembler::disassemble_function(&data, game, selector, options.text)
+                    .context("failed to disassemble function")?;
+            let header = exalt_disassembler::read_header(&data, game, options.text)?;
+            let script = RawScript {
+                schema_version: SCHEMA_VERSION,
+                global_frame_size: header.global_frame_size as usize,
+                functions: vec![function],
+                quirks: ScriptQuirks::default(),
+            };
+            (script, Some(index))
+        }
+        None if options.best_effort => {
+            let partial = exalt_disassembler::disassemble_best_effort(&data, game, options.text)
+                .context("failed to disassemble script")?;
+            for error in &partial.errors {
+                println!("WARNING: {}", error);
+            }
+            (partial.script, None)
+        }
+        None => (
+            exalt_disassembler::disassemble_with_options(&data, game, options.text)
+                .context("failed to disassemble script")?,
+            None,
+        ),
+    };
+    if let Some(path) = options.functions {
+        report_unknown_calls(game, &script, &path)?;
+    }
+    if !options.offsets {
+        for function in &mut script.functions {
+            function.offsets.clear();
+        }
+    }
+    let sidecar = comments::sidecar_path(&input);
+    if sidecar.is_file() {
+        let contents = std::fs::read_to_string(&sidecar).context("failed to read comments")?;
+        let mut comments: ScriptComments =
+            serde_yaml::from_str(&contents).context("failed to parse comments")?;
+        if let Some(index) = selected {
+            comments = comments.remove(&index).map(|c| (0, c)).into_iter().collect();
+        }
+        script.apply_comments(comments);
+    }
     let raw = match format {
-        Format::Json => {
-            serde_json::to_string_pretty(&script).context("error serializing script")?
+        Format::Json => serde_json::to_string_pretty(&script)
+            .context("error serializing script")?
+            .into_bytes(),
+        Format::Yml => {
+            // Comments are written inline instead of as a separate field
+            let comments = script.take_comments();
+            let raw = serde_yaml::to_string(&script).context("error serializing script")?;
+            comments::insert(&raw, &comments).into_bytes()
         }
-        Format::Yml => serde_yaml::to_string(&script).context("error serializing script")?,
         Format::Ron => ron::ser::to_string_pretty(&script, ron::ser::PrettyConfig::new())
-            .context("error serializing script")?,
+            .context("error serializing script")?
+            .into_bytes(),
+        Format::Bin => script.to_binary().context("error serializing script")?,
     };
     std::fs::write(output, raw).context("error writing script to disk")?;
     Ok(())
 }
 
-fn assemble(game: Game, input: PathBuf, output: PathBuf, format: Format) -> anyhow::Result<()> {
+fn assemble(
+    game: Game,
+    input: PathBuf,
+    output: PathBuf,
+    format: Format,
+    text_from: Option<PathBuf>,
+    offsets: Option<PathBuf>,
+    text: TextOptions,
+) -> anyhow::Result<()> {
     let input = std::fs::read(input).context("failed to read input file")?;
     let script_name = output
         .file_name()
         .context("failed to parse file name")?
         .to_string_lossy();
-    let script: RawScript = match format {
+    let mut script: RawScript = match format {
         Format::Json => serde_json::from_slice(&input).context("failed to parse script")?,
-        Format::Yml => serde_yaml::from_slice(&input).context("failed to parse script")?,
+        Format::Yml => {
+            let text = String::from_utf8(input).context("failed to read input as utf8")?;
+            let (text, comments) = comments::extract(&text);
+            let mut script: RawScript =
+                serde_yaml::from_str(&text).context("failed to parse script")?;
+            script.apply_comments(comments);
+            script
+        }
         Format::Ron => {
             let text = String::from_utf8(input).context("failed to read input as utf8")?;
             ron::from_str(&text).context("failed to parse script")?
         }
+        Format::Bin => RawScript::from_binary(&input).context("failed to parse script")?,
+    };
+    let errors = script.validate(game);
+    if !errors.is_empty() {
+        for error in &errors {
+            println!("{}", error);
+        }
+        anyhow::bail!("script failed validation with {} error(s)", errors.len());
+    }
+    let text_data = match text_from {
+        Some(path) => {
+            let original = std::fs::read(path).context("failed to read text data source")?;
+            let encoding = text
+                .encoding
+                .or(script.quirks.encoding)
+                .unwrap_or(game.profile().encoding);
+            Some(
+                CodeGenTextData::from_script(&original, game, encoding)
+                    .context("failed to read original text data")?,
+            )
+        }
+        None => None,
     };
-    let raw = exalt_assembler::assemble(&script, &script_name, game)
-        .context("failed to assemble script")?;
-    std::fs::write(output, raw).context("error writing cmb to disk")?;
+    let options = AssembleOptions {
+        text_data,
+        revision: None,
+        text,
+    };
+    let (raw, report) =
+        exalt_assembler::assemble_with_report(&script, &script_name, game, options)
+            .context("failed to assemble script")?;
+    std::fs::write(&output, raw).context("error writing cmb to disk")?;
+    if let Some(path) = offsets {
+        let report = offsets::to_json(&report, &script);
+        let raw = serde_json::to_string_pretty(&report).context("error serializing offsets")?;
+        std::fs::write(path, raw).context("error writing offsets to disk")?;
+    }
+
+    // Comments can't be stored in the script itself, so they're kept next to it for the next disassembly
+    let comments = script.take_comments();
+    let sidecar = comments::sidecar_path(&output);
+    if !comments.is_empty() {
+        let raw = serde_yaml::to_string(&comments).context("error serializing comments")?;
+        std::fs::write(sidecar, raw).context("error writing comments to disk")?;
+    } else if sidecar.is_file() {
+        std::fs::remove_file(sidecar).context("failed to remove stale comments")?;
+    }
     Ok(())
 }
 
-fn load_decompiler_transform(game: Game) -> anyhow::Result<Option<IrTransform>> {
-    let exe_dir = std::env::current_exe()?
-        .parent()
-        .ok_or_else(|| anyhow::anyhow!("current exe has no parent dir"))?
-        .to_path_buf();
-    let target = match game {
-        Game::FE10 => Some("std/fe10/prelude.exl"),
-        Game::FE13 => Some("std/fe13/prelude.exl"),
-        Game::FE14 => Some("std/fe14/prelude.exl"),
-        Game::FE15 => Some("std/fe15/prelude.exl"),
-        _ => None,
+/// Parse the std library prelude for a game, if there is one.
+fn parse_std(game: Game) -> anyhow::Result<Option<SymbolTable>> {
+    let path = match StdLibrary::locate().prelude(game) {
+        Some(path) => path,
+        None => return Ok(None),
     };
-    if let Some(target) = target {
-        // Load std lib files
-        let path = exe_dir.join(target);
-        if !path.is_file() {
-            println!(
-                "WARNING: could not find std library at path '{}'",
-                path.display()
-            );
-            return Ok(None);
-        }
-        let ParseResult { symbol_table, .. } = exalt_compiler::parse(&ParseRequest {
-            game,
-            target: path,
-            source: None,
-            additional_includes: vec![],
-        })?;
+    let source = exalt_compiler::read_source(&path)
+        .with_context(|| format!("failed to read std library '{}'", path.display()))?;
+    // A header that is missing or out of date just means parsing the prelude again
+    if let Ok(header) = PrecompiledHeader::load(&path) {
+        return Ok(Some(header.to_symbol_table()));
+    }
+    let ParseResult { symbol_table, .. } = exalt_compiler::parse(&ParseRequest {
+        game,
+        target: path,
+        source: Some(source),
+        additional_includes: vec![],
+    })?;
+    Ok(Some(symbol_table))
+}
+
+/// Warn about engine functions called by a script that are not in the std library or a function list.
+fn report_unknown_calls(game: Game, script: &RawScript, functions: &Path) -> anyhow::Result<()> {
+    let contents = std::fs::read_to_string(functions).context("failed to read function list")?;
+    let mut database = FunctionDatabase::parse(&contents);
+    if let Some(symbol_table) = parse_std(game)? {
+        database.extend(symbol_table.function_database());
+    }
+    for name in script.unknown_calls(&database) {
+        println!("WARNING: call to unknown engine function '{}'", name);
+    }
+    Ok(())
+}
 
+fn load_decompiler_transform(game: Game) -> anyhow::Result<Option<IrTransform>> {
+    if let Some(symbol_table) = parse_std(game)? {
         // Populate the transform from the symbol table
         let mut transform = IrTransform::default();
         for constant in symbol_table.constants() {
@@ -139,6 +607,15 @@ fn load_decompiler_transform(game: Game) -> anyhow::Result<Option<IrTransform>>
             // Flip because aliases are key=friendly name, value=internal name
             transform.functions.insert(v, k);
         }
+        for symbol in symbol_table.enums() {
+            let e = symbol.borrow();
+            let variants = transform.enums.entry(e.name.clone()).or_default();
+            for (name, variant) in &e.variants {
+                if let Literal::Int(i) = &variant.value {
+                    variants.entry(*i).or_insert_with(|| name.clone());
+                }
+            }
+        }
         if let Some(symbol) = symbol_table.lookup_enum("Event") {
             let e = symbol.borrow();
             for (name, variant) in &e.variants {
@@ -154,23 +631,200 @@ fn load_decompiler_transform(game: Game) -> anyhow::Result<Option<IrTransform>>
     Ok(None)
 }
 
+fn load_symbol_map(path: &Path) -> anyhow::Result<SymbolMap> {
+    let contents = std::fs::read_to_string(path).context("failed to read symbol map")?;
+    serde_yaml::from_str(&contents).context("failed to parse symbol map")
+}
+
+fn load_alias_file(path: &Path) -> anyhow::Result<AliasFile> {
+    let contents = std::fs::read_to_string(path).context("failed to read alias file")?;
+    serde_yaml::from_str(&contents).context("failed to parse alias file")
+}
+
+fn load_global_layout(game: Game, path: &Path) -> anyhow::Result<Vec<SharedGlobal>> {
+    let request = ParseRequest {
+        game,
+        target: path.to_path_buf(),
+        source: None,
+        additional_includes: vec![],
+    };
+    match exalt_compiler::parse_global_layout(&request) {
+        Ok(globals) => Ok(globals),
+        Err(CompilerError::ParseError(log)) => {
+            log.print();
+            anyhow::bail!("failed to parse global layout '{}'", path.display());
+        }
+        Err(err) => Err(err.into()),
+    }
+}
+
+fn load_decompiler_options(path: &Path) -> anyhow::Result<DecompilerOptions> {
+    let contents = std::fs::read_to_string(path).context("failed to read style options")?;
+    serde_yaml::from_str(&contents).context("failed to parse style options")
+}
+
+/// Load function argument to enum mappings, checking that every enum was declared.
+fn load_enum_args(
+    path: &Path,
+    transform: &IrTransform,
+) -> anyhow::Result<HashMap<String, HashMap<usize, String>>> {
+    let contents = std::fs::read_to_string(path).context("failed to read enum mappings")?;
+    let arguments: HashMap<String, HashMap<usize, String>> =
+        serde_yaml::from_str(&contents).context("failed to parse enum mappings")?;
+    for (function, mappings) in &arguments {
+        for enum_name in mappings.values() {
+            if !transform.enums.contains_key(enum_name) {
+                anyhow::bail!(
+                    "unknown enum '{}' in mappings for '{}'",
+                    enum_name,
+                    function
+                );
+            }
+        }
+    }
+    Ok(arguments)
+}
+
+/// Load enum tags for call results and parameters, checking that every enum was declared.
+fn load_value_tags(path: &Path, transform: &IrTransform) -> anyhow::Result<ValueTags> {
+    let contents = std::fs::read_to_string(path).context("failed to read value tags")?;
+    let tags: ValueTags = serde_yaml::from_str(&contents).context("failed to parse value tags")?;
+    for enum_name in tags.enums() {
+        if !transform.enums.contains_key(enum_name) {
+            anyhow::bail!("unknown enum '{}' in value tags", enum_name);
+        }
+    }
+    Ok(tags)
+}
+
+fn decompiler_includes(game: Game, transform: &Option<IrTransform>) -> Vec<String> {
+    match transform {
+        Some(_) => exalt_compiler::prelude_include(game).into_iter().collect(),
+        None => Vec::new(),
+    }
+}
+
+struct DecompileOptions {
+    symbols: Option<PathBuf>,
+    aliases: Option<PathBuf>,
+    globals: Option<PathBuf>,
+    lenient: bool,
+    style: Option<PathBuf>,
+    enum_args: Option<PathBuf>,
+    value_tags: Option<PathBuf>,
+    functions: Option<PathBuf>,
+    batch: bool,
+    keep_going: bool,
+    text: TextOptions,
+}
+
+/// Everything needed to decompile a script, loaded once so batches don't reload it per script.
+struct Decompiler {
+    game: Game,
+    style: DecompilerOptions,
+    transform: IrTransform,
+    includes: Vec<String>,
+    functions: Option<PathBuf>,
+    debug: bool,
+    lenient: bool,
+    text: TextOptions,
+}
+
+impl Decompiler {
+    fn load(game: Game, debug: bool, options: &DecompileOptions) -> anyhow::Result<Self> {
+        let style = match &options.style {
+            Some(path) => load_decompiler_options(path)?,
+            None => DecompilerOptions::default(),
+        };
+        let mut transform = load_decompiler_transform(game)?;
+        let mut includes = decompiler_includes(game, &transform);
+        if let Some(path) = &options.symbols {
+            transform.get_or_insert_with(IrTransform::default).symbols = load_symbol_map(path)?;
+        }
+        if let Some(path) = &options.globals {
+            let transform = transform.get_or_insert_with(IrTransform::default);
+            for global in load_global_layout(game, path)? {
+                transform.symbols.globals.insert(global.frame_id, global.name);
+                transform.shared_globals.push((global.frame_id, global.length));
+            }
+            let name = path
+                .file_stem()
+                .and_then(|stem| stem.to_str())
+                .ok_or_else(|| anyhow::anyhow!("bad global layout file name"))?;
+            includes.push(name.to_owned());
+        }
+        if let Some(path) = &options.aliases {
+            let functions = &mut transform.get_or_insert_with(IrTransform::default).functions;
+            for (name, internal) in load_alias_file(path)?.for_game(game) {
+                functions.insert(internal, name);
+            }
+        }
+        if let Some(path) = &options.enum_args {
+            let transform = transform
+                .as_mut()
+                .ok_or_else(|| anyhow::anyhow!("enum argument mappings need the std library"))?;
+            transform.arguments = load_enum_args(path, transform)?;
+        }
+        if let Some(path) = &options.value_tags {
+            let transform = transform
+                .as_mut()
+                .ok_or_else(|| anyhow::anyhow!("value tags need the std library"))?;
+            transform.value_tags = load_value_tags(path, transform)?;
+        }
+        Ok(Decompiler {
+            game,
+            style,
+            transform: transform.unwrap_or_default(),
+            includes,
+            functions: options.functions.clone(),
+            debug,
+            lenient: options.lenient,
+            text: options.text,
+        })
+    }
+
+    /// Decompile a compiled script to source.
+    fn decompile(&self, raw: &[u8]) -> anyhow::Result<String> {
+        let game = self.game;
+        let script = exalt_disassembler::disassemble_with_options(raw, game, self.text)
+            .context("failed to disassemble script")?;
+        if let Some(path) = &self.functions {
+            report_unknown_calls(game, &script, path)?;
+        }
+        let ir = exalt_decompiler::decompile_to_ir(
+            &script,
+            game,
+            &self.style,
+            self.debug,
+            self.lenient,
+        )
+        .context("failed to decompile script")?;
+        let mut source =
+            exalt_decompiler::ir::pretty_print(&ir, &self.transform, &self.style, &self.includes)
+                .context("failed to decompile script")?;
+        if self.debug {
+            let strings = exalt_disassembler::read_text_data(raw, game, self.text)
+                .context("failed to read text data")?;
+            source.insert_str(0, &exalt_decompiler::text_data_report(&strings)?);
+        }
+        Ok(source)
+    }
+}
+
 fn decompile(
     game: Game,
     input: PathBuf,
     output: Option<PathBuf>,
     debug: bool,
+    options: DecompileOptions,
 ) -> anyhow::Result<()> {
+    let decompiler = Decompiler::load(game, debug, &options)?;
+    if options.batch {
+        let output = output.unwrap_or_else(|| PathBuf::from("."));
+        return batch::decompile_all(&decompiler, &input, &output, options.keep_going);
+    }
     let raw = std::fs::read(&input).context("failed to read input file")?;
-    let transform = load_decompiler_transform(game)?;
-    let includes = match (game, &transform) {
-        (Game::FE10, Some(_)) => vec!["std:fe10:prelude".to_owned()],
-        (Game::FE14, Some(_)) => vec!["std:fe14:prelude".to_owned()],
-        _ => Vec::new(),
-    };
-    let script =
-        exalt_disassembler::disassemble(&raw, game).context("failed to disassemble script")?;
-    let script = exalt_decompiler::decompile(&script, transform, includes, game, debug)
-        .context("failed to decompile script")?;
+    let script = decompiler.decompile(&raw)?;
     let output_path = if let Some(path) = output {
         path
     } else {
@@ -185,37 +839,542 @@ fn decompile(
     Ok(())
 }
 
-fn compile(game: Game, target: PathBuf, output: Option<PathBuf>) -> anyhow::Result<()> {
+struct CompileOptions {
+    includes: Vec<PathBuf>,
+    optimizations: OptimizationConfig,
+    verify: bool,
+    verify_decompile: bool,
+    revision: Option<u32>,
+    script_name: Option<String>,
+    symbols: Option<PathBuf>,
+    aliases: Option<PathBuf>,
+    listing: bool,
+    source_map: bool,
+    message_format: MessageFormat,
+    watch: bool,
+    text: TextOptions,
+}
+
+fn compile(
+    game: Game,
+    target: PathBuf,
+    output: Option<PathBuf>,
+    options: CompileOptions,
+) -> anyhow::Result<()> {
+    let CompileOptions {
+        includes,
+        optimizations,
+        verify,
+        verify_decompile,
+        revision,
+        script_name,
+        symbols,
+        aliases,
+        listing,
+        source_map,
+        message_format,
+        watch,
+        text,
+    } = options;
+    let symbols = symbols.map(|path| load_symbol_map(&path)).transpose()?;
+    let aliases = match aliases {
+        Some(path) => load_alias_file(&path)?.for_game(game),
+        None => Vec::new(),
+    };
     let request = CompileRequest {
         game,
         target,
         output,
         text_data: None,
+        text,
+        additional_includes: includes,
+        optimizations,
+        revision,
+        internal_name: script_name,
+        symbols,
+        listing,
+        source_map,
+        message_format,
+        defines: Vec::new(),
+        aliases,
+    };
+    if watch {
+        if verify || verify_decompile {
+            anyhow::bail!("--watch can't be combined with --verify or --verify-decompile");
+        }
+        return watch::compile_on_change(&request);
+    }
+    if !verify && !verify_decompile {
+        exalt_compiler::compile(&request)?;
+        return Ok(());
+    }
+
+    let (lir, raw) = exalt_compiler::compile_to_lir_and_vec(&request)?;
+    verify::verify_disassembly(game, &raw, text, &lir)?;
+    if verify_decompile {
+        let mut transform = load_decompiler_transform(game)?;
+        let includes = decompiler_includes(game, &transform);
+        if let Some(symbols) = &request.symbols {
+            transform.get_or_insert_with(IrTransform::default).symbols = symbols.clone();
+        }
+        verify::verify_decompilation(
+            game,
+            &raw,
+            text,
+            request.target.clone(),
+            transform,
+            includes,
+        )?;
+    }
+    let output_path = request.output_path()?;
+    if let Some(parent) = output_path.parent() {
+        std::fs::create_dir_all(parent).context("failed to create output directory")?;
+    }
+    std::fs::write(output_path, raw).context("failed to write output file")?;
+    Ok(())
+}
+
+fn precompile(
+    game: Game,
+    input: PathBuf,
+    output: Option<PathBuf>,
+    includes: Vec<PathBuf>,
+) -> anyhow::Result<()> {
+    let output = output.unwrap_or_else(|| input.with_extension("exlh"));
+    let header = match exalt_compiler::precompile_header(&ParseRequest {
+        game,
+        target: input,
+        source: None,
+        additional_includes: includes,
+    }) {
+        Ok(header) => header,
+        Err(CompilerError::ParseError(log)) => {
+            log.print();
+            anyhow::bail!("failed to precompile header");
+        }
+        Err(err) => return Err(err.into()),
+    };
+    std::fs::write(&output, header)
+        .with_context(|| format!("failed to write '{}'", output.display()))?;
+    Ok(())
+}
+
+fn build(
+    game: Game,
+    source_dir: PathBuf,
+    output: Option<PathBuf>,
+    include_dirs: Vec<PathBuf>,
+    targets: Vec<PathBuf>,
+    optimizations: OptimizationConfig,
+    jobs: Option<usize>,
+) -> anyhow::Result<()> {
+    let output = output.context("an output directory is required when building a source directory")?;
+    let config = BuildConfig {
+        game,
+        root: std::env::current_dir().context("failed to read current directory")?,
+        sources: BuildSources {
+            source_dir,
+            include_dirs,
+            targets,
+        },
+        optimizations,
+    };
+    for artifact in exalt_build::build_parallel(&config, jobs)? {
+        let path = output.join(artifact.output_path());
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent).context("failed to create output directory")?;
+        }
+        std::fs::write(&path, &artifact.data)
+            .with_context(|| format!("failed to write '{}'", path.display()))?;
+    }
+    Ok(())
+}
+
+fn fmt(inputs: Vec<PathBuf>, check: bool) -> anyhow::Result<()> {
+    let mut unformatted = 0;
+    for input in inputs {
+        let source = std::fs::read_to_string(&input)
+            .with_context(|| format!("failed to read '{}'", input.display()))?;
+        let source_name = input.to_string_lossy();
+        let formatted = match exalt_compiler::format_source(&source_name, &source) {
+            Ok(formatted) => formatted,
+            Err(CompilerError::ParseError(log)) => {
+                log.print();
+                anyhow::bail!("failed to parse '{}'", input.display());
+            }
+            Err(err) => return Err(err.into()),
+        };
+        if formatted == source {
+            continue;
+        }
+        if check {
+            println!("{}", input.display());
+            unformatted += 1;
+        } else {
+            std::fs::write(&input, formatted)
+                .with_context(|| format!("failed to write '{}'", input.display()))?;
+        }
+    }
+    if unformatted > 0 {
+        anyhow::bail!("{} script(s) need formatting", unformatted);
+    }
+    Ok(())
+}
+
+fn diff(game: Game, expected: PathBuf, actual: PathBuf) -> anyhow::Result<()> {
+    let expected = std::fs::read(&expected)
+        .with_context(|| format!("failed to read '{}'", expected.display()))?;
+    let actual = std::fs::read(&actual)
+        .with_context(|| format!("failed to read '{}'", actual.display()))?;
+    let divergences = exalt_compiler::diff_scripts(&expected, &actual, game, TextOptions::default());
+    for divergence in &divergences {
+        println!("{}", divergence);
+    }
+    if !divergences.is_empty() {
+        anyhow::bail!("scripts differ");
+    }
+    Ok(())
+}
+
+fn patch(
+    game: Game,
+    input: PathBuf,
+    output: PathBuf,
+    source: PathBuf,
+    functions: Vec<String>,
+    text: TextOptions,
+) -> anyhow::Result<()> {
+    let original = std::fs::read(&input).context("failed to read input file")?;
+    let request = CompileRequest {
+        game,
+        target: source,
+        output: None,
+        text_data: None,
+        text,
+        additional_includes: vec![],
+        optimizations: OptimizationConfig::default(),
+        revision: None,
+        internal_name: None,
+        symbols: None,
+        listing: false,
+        source_map: false,
+        message_format: MessageFormat::default(),
+        defines: Vec::new(),
+        aliases: Vec::new(),
+    };
+    let raw = exalt_build::patch(&original, &request, &functions)?;
+    std::fs::write(output, raw).context("failed to write output file")?;
+    Ok(())
+}
+
+fn merge(
+    game: Game,
+    input: PathBuf,
+    output: PathBuf,
+    source: PathBuf,
+    options: MergeOptions,
+    text: TextOptions,
+) -> anyhow::Result<()> {
+    let base = std::fs::read(&input).context("failed to read input file")?;
+    let request = CompileRequest {
+        game,
+        target: source,
+        output: None,
+        text_data: None,
+        text,
         additional_includes: vec![],
+        optimizations: OptimizationConfig::default(),
+        revision: None,
+        internal_name: None,
+        symbols: None,
+        listing: false,
+        source_map: false,
+        message_format: MessageFormat::default(),
+        defines: Vec::new(),
+        aliases: Vec::new(),
     };
-    exalt_compiler::compile(&request)?;
+    let raw = exalt_build::merge(&base, &request, &options)?;
+    std::fs::write(output, raw).context("failed to write output file")?;
+    Ok(())
+}
+
+fn call_graph(
+    game: Game,
+    input: PathBuf,
+    strip: Option<PathBuf>,
+    keep: Vec<String>,
+) -> anyhow::Result<()> {
+    let raw = std::fs::read(&input).context("failed to read input file")?;
+    let mut script =
+        exalt_disassembler::disassemble(&raw, game).context("failed to disassemble script")?;
+    call_graph::report(&script, &keep);
+    if let Some(output) = strip {
+        call_graph::strip(game, &raw, &mut script, &keep, &output)?;
+    }
+    Ok(())
+}
+
+fn info(game: Game, input: PathBuf, text: TextOptions) -> anyhow::Result<()> {
+    let raw = std::fs::read(input).context("failed to read input file")?;
+    let header = exalt_disassembler::read_header(&raw, game, text)?;
+    let functions = exalt_disassembler::summarize_functions(&raw, game, text)
+        .context("failed to read function table")?;
+    let strings =
+        exalt_disassembler::read_text_data(&raw, game, text).context("failed to read text data")?;
+    info::report(&header, &functions, &strings, raw.len());
     Ok(())
 }
 
 fn main() -> anyhow::Result<()> {
     let args = Args::parse();
     let game = args.game;
+    if let Some(dir) = &args.std {
+        // Set before anything else runs so includes and child processes see the same library
+        std::env::set_var(exalt_compiler::EXALT_STD, dir);
+    }
     match args.command {
         Commands::Disassemble {
             input,
             output,
             format,
-        } => disassemble(game, input, output, format),
+            functions,
+            function,
+            best_effort,
+            offsets,
+            encoding,
+            lossy_text,
+        } => disassemble(
+            game,
+            input,
+            output,
+            format,
+            DisassembleOptions {
+                functions,
+                function,
+                best_effort,
+                offsets,
+                text: TextOptions {
+                    encoding,
+                    lossy: lossy_text,
+                },
+            },
+        ),
         Commands::Assemble {
             input,
             output,
             format,
-        } => assemble(game, input, output, format),
+            text_from,
+            offsets,
+            encoding,
+            lossy_text,
+        } => assemble(
+            game,
+            input,
+            output,
+            format,
+            text_from,
+            offsets,
+            TextOptions {
+                encoding,
+                lossy: lossy_text,
+            },
+        ),
         Commands::Decompile {
             input,
             output,
             debug,
-        } => decompile(game, input, output, debug),
-        Commands::Compile { input, output } => compile(game, input, output),
+            symbols,
+            aliases,
+            globals,
+            lenient,
+            style,
+            enum_args,
+            value_tags,
+            functions,
+            batch,
+            keep_going,
+            encoding,
+            lossy_text,
+        } => decompile(
+            game,
+            input,
+            output,
+            debug,
+            DecompileOptions {
+                symbols,
+                aliases,
+                globals,
+                lenient,
+                style,
+                enum_args,
+                value_tags,
+                functions,
+                batch,
+                keep_going,
+                text: TextOptions {
+                    encoding,
+                    lossy: lossy_text,
+                },
+            },
+        ),
+        Commands::Compile {
+            input,
+            output,
+            includes,
+            cse,
+            optimize,
+            reuse_slots,
+            verify,
+            verify_decompile,
+            revision,
+            script_name,
+            symbols,
+            aliases,
+            listing,
+            source_map,
+            message_format,
+            watch,
+            encoding,
+            lossy_text,
+        } => compile(
+            game,
+            input,
+            output,
+            CompileOptions {
+                includes,
+                optimizations: OptimizationConfig {
+                    cse,
+                    peephole: optimize,
+                    reuse_frame_slots: reuse_slots,
+                },
+                verify,
+                verify_decompile,
+                revision,
+                script_name,
+                symbols,
+                aliases,
+                listing,
+                source_map,
+                message_format,
+                watch,
+                text: TextOptions {
+                    encoding,
+                    lossy: lossy_text,
+                },
+            },
+        ),
+        Commands::Precompile {
+            input,
+            output,
+            includes,
+        } => precompile(game, input, output, includes),
+        Commands::Build {
+            source_dir,
+            output,
+            manifest,
+            includes,
+            targets,
+            cse,
+            optimize,
+            reuse_slots,
+            jobs,
+            force,
+        } => {
+            let optimizations = OptimizationConfig {
+                cse,
+                peephole: optimize,
+                reuse_frame_slots: reuse_slots,
+            };
+            match (source_dir, manifest) {
+                (Some(_), Some(_)) => {
+                    anyhow::bail!("a source directory can't be combined with --manifest")
+                }
+                (Some(source_dir), None) => build(
+                    game,
+                    source_dir,
+                    output,
+                    includes,
+                    targets,
+                    optimizations,
+                    jobs,
+                ),
+                (None, manifest) => {
+                    if !targets.is_empty() {
+                        anyhow::bail!("--target can't be used with a manifest");
+                    }
+                    let manifest =
+                        manifest.unwrap_or_else(|| PathBuf::from(exalt_build::MANIFEST_NAME));
+                    build::build_manifest(
+                        game,
+                        &manifest,
+                        output,
+                        includes,
+                        optimizations,
+                        jobs,
+                        force,
+                    )
+                }
+            }
+        }
+        Commands::Fmt { inputs, check } => fmt(inputs, check),
+        Commands::Test {
+            input,
+            compile,
+            jobs,
+        } => round_trip::test_scripts(game, &input, compile, jobs),
+        Commands::Diff { expected, actual } => diff(game, expected, actual),
+        Commands::Patch {
+            input,
+            output,
+            source,
+            functions,
+            encoding,
+            lossy_text,
+        } => patch(
+            game,
+            input,
+            output,
+            source,
+            functions,
+            TextOptions {
+                encoding,
+                lossy: lossy_text,
+            },
+        ),
+        Commands::Merge {
+            input,
+            output,
+            source,
+            functions,
+            callbacks,
+            encoding,
+            lossy_text,
+        } => merge(
+            game,
+            input,
+            output,
+            source,
+            MergeOptions {
+                functions,
+                callbacks,
+            },
+            TextOptions {
+                encoding,
+                lossy: lossy_text,
+            },
+        ),
+        Commands::CallGraph { input, strip, keep } => call_graph(game, input, strip, keep),
+        Commands::Info {
+            input,
+            encoding,
+            lossy_text,
+        } => info(
+            game,
+            input,
+            TextOptions {
+                encoding,
+                lossy: lossy_text,
+            },
+        ),
     }
 }
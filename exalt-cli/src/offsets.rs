@@ -0,0 +1,38 @@
+use exalt_assembler::OffsetReport;
+use exalt_lir::RawScript;
+use serde_json::{json, Value};
+
+/// Describe where every function, label, and string landed for patching tools.
+pub fn to_json(report: &OffsetReport, script: &RawScript) -> Value {
+    let functions: Vec<Value> = report
+        .functions
+        .iter()
+        .zip(&script.functions)
+        .enumerate()
+        .map(|(index, (layout, function))| {
+            let labels: Vec<Value> = layout
+                .labels
+                .iter()
+                .map(|(label, address)| json!({ "label": label, "address": address }))
+                .collect();
+            json!({
+                "index": index,
+                "name": function.name,
+                "event": function.event,
+                "address": layout.address,
+                "code_address": layout.code_address,
+                "labels": labels,
+            })
+        })
+        .collect();
+    let strings: Vec<Value> = report
+        .strings
+        .iter()
+        .map(|(text, address)| json!({ "text": text, "address": address }))
+        .collect();
+    json!({
+        "text_data_address": report.text_data_address,
+        "functions": functions,
+        "strings": strings,
+    })
+}
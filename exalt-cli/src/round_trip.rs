@@ -0,0 +1,120 @@
+use std::path::{Path, PathBuf};
+
+use anyhow::Context;
+use exalt_compiler::{Divergence, RoundTripOptions};
+use exalt_lir::Game;
+use walkdir::WalkDir;
+
+fn is_compiled_script(path: &Path) -> bool {
+    path.extension().map(|ext| ext == "cmb").unwrap_or(false)
+}
+
+pub fn collect_scripts(root: &Path) -> anyhow::Result<Vec<PathBuf>> {
+    if root.is_file() {
+        return Ok(vec![root.to_path_buf()]);
+    }
+    let mut scripts = Vec::new();
+    for entry in WalkDir::new(root).sort_by_file_name() {
+        let entry = entry.context("failed to walk input directory")?;
+        if entry.file_type().is_file() && is_compiled_script(entry.path()) {
+            scripts.push(entry.into_path());
+        }
+    }
+    Ok(scripts)
+}
+
+#[derive(Default)]
+struct StageSummary {
+    passed: usize,
+    failed: usize,
+}
+
+impl StageSummary {
+    fn record(&mut self, divergences: &[Divergence]) {
+        if divergences.is_empty() {
+            self.passed += 1;
+        } else {
+            self.failed += 1;
+        }
+    }
+
+    fn print(&self, stage: &str) {
+        println!(
+            "{:<10} {:>8} {:>8} {:>8}",
+            stage,
+            self.passed,
+            self.failed,
+            self.passed + self.failed
+        );
+    }
+}
+
+fn print_divergences(stage: &str, divergences: &[Divergence]) {
+    for divergence in divergences {
+        println!("    {}: {}", stage, divergence);
+    }
+}
+
+/// Rebuild every compiled script under a directory and report the ones that don't match the original.
+/// Scripts are tested in parallel but reported in file name order.
+pub fn test_scripts(
+    game: Game,
+    input: &Path,
+    compile: bool,
+    jobs: Option<usize>,
+) -> anyhow::Result<()> {
+    let scripts = collect_scripts(input)?;
+    let options = RoundTripOptions {
+        recompile: compile,
+        ..Default::default()
+    };
+    let reports = exalt_build::run_parallel(&scripts, jobs, |path| {
+        let raw = std::fs::read(path)
+            .with_context(|| format!("failed to read '{}'", path.display()))?;
+        Ok(exalt_compiler::verify_round_trip(&raw, game, &options)?)
+    })?;
+    let mut assembled = StageSummary::default();
+    let mut compiled = StageSummary::default();
+    let mut unreadable = 0;
+    let mut failures = 0;
+    for (path, report) in scripts.iter().zip(reports) {
+        let display = path.strip_prefix(input).unwrap_or(path).display();
+        match report {
+            Ok(report) if report.is_identical() => {
+                println!("PASS {}", display);
+                assembled.record(&report.reassembled);
+                compiled.record(&[]);
+            }
+            Ok(report) => {
+                println!("FAIL {}", display);
+                print_divergences("assemble", &report.reassembled);
+                assembled.record(&report.reassembled);
+                if let Some(divergences) = &report.recompiled {
+                    print_divergences("compile", divergences);
+                    compiled.record(divergences);
+                }
+                failures += 1;
+            }
+            Err(err) => {
+                println!("FAIL {}", display);
+                println!("    {:#}", err);
+                unreadable += 1;
+                failures += 1;
+            }
+        }
+    }
+
+    println!();
+    println!("{:<10} {:>8} {:>8} {:>8}", "stage", "passed", "failed", "total");
+    assembled.print("assemble");
+    if compile {
+        compiled.print("compile");
+    }
+    if unreadable > 0 {
+        println!("{} script(s) could not be read or disassembled", unreadable);
+    }
+    if failures > 0 {
+        anyhow::bail!("{} of {} script(s) failed", failures, scripts.len());
+    }
+    Ok(())
+}
@@ -0,0 +1,112 @@
+use std::path::PathBuf;
+
+use anyhow::{bail, Context};
+use exalt_compiler::ParseRequest;
+use exalt_decompiler::{DecompilerOptions, IrTransform};
+use exalt_lir::{Function, Game, Opcode, RawScript, TextOptions};
+
+fn compare_functions(index: usize, expected: &Function, actual: &Function) -> anyhow::Result<()> {
+    let fields = [
+        ("frame size", expected.frame_size != actual.frame_size),
+        ("event", expected.event != actual.event),
+        ("arity", expected.arity != actual.arity),
+        ("unknown", expected.unknown != actual.unknown),
+        ("prefix", expected.prefix != actual.prefix),
+        ("suffix", expected.suffix != actual.suffix),
+        ("name", expected.name != actual.name),
+        ("args", expected.args != actual.args),
+    ];
+    for (field, differs) in fields {
+        if differs {
+            bail!(
+                "function {} has a different {} after disassembly",
+                index,
+                field
+            );
+        }
+    }
+    let expected_code = Opcode::normalize_labels(&expected.code);
+    let actual_code = Opcode::normalize_labels(&actual.code);
+    for (i, (e, a)) in expected_code.iter().zip(&actual_code).enumerate() {
+        if e != a {
+            bail!(
+                "function {} differs at opcode {}: expected '{:?}' but found '{:?}'",
+                index,
+                i,
+                e,
+                a
+            );
+        }
+    }
+    if expected_code.len() != actual_code.len() {
+        bail!(
+            "function {} has {} opcodes but disassembly has {}",
+            index,
+            expected_code.len(),
+            actual_code.len()
+        );
+    }
+    Ok(())
+}
+
+/// Disassemble a freshly compiled script and check that it matches the LIR it was built from.
+pub fn verify_disassembly(
+    game: Game,
+    raw: &[u8],
+    text: TextOptions,
+    expected: &RawScript,
+) -> anyhow::Result<()> {
+    let actual = exalt_disassembler::disassemble_with_options(raw, game, text)
+        .context("verification failed: could not disassemble output")?;
+    if expected.global_frame_size != actual.global_frame_size {
+        bail!(
+            "verification failed: global frame size is {} but disassembly has {}",
+            expected.global_frame_size,
+            actual.global_frame_size
+        );
+    }
+    if expected.functions.len() != actual.functions.len() {
+        bail!(
+            "verification failed: script has {} functions but disassembly has {}",
+            expected.functions.len(),
+            actual.functions.len()
+        );
+    }
+    for (i, (e, a)) in expected.functions.iter().zip(&actual.functions).enumerate() {
+        compare_functions(i, e, a).context("verification failed")?;
+    }
+    Ok(())
+}
+
+/// Decompile a freshly compiled script and make sure the result parses again.
+pub fn verify_decompilation(
+    game: Game,
+    raw: &[u8],
+    text: TextOptions,
+    target: PathBuf,
+    transform: Option<IrTransform>,
+    includes: Vec<String>,
+) -> anyhow::Result<()> {
+    let script = exalt_disassembler::disassemble_with_options(raw, game, text)
+        .context("verification failed: could not disassemble output")?;
+    let options = DecompilerOptions::default();
+    let source =
+        exalt_decompiler::decompile(&script, transform, &options, includes, game, false, false)
+            .context("verification failed: could not decompile output")?;
+    let request = ParseRequest {
+        game,
+        target,
+        source: Some(source),
+        additional_includes: vec![],
+    };
+    if let Err(err) = exalt_compiler::parse(&request) {
+        if let exalt_compiler::CompilerError::ParseError(log) = &err {
+            log.print();
+        }
+        bail!(
+            "verification failed: decompiled output does not parse: {}",
+            err
+        );
+    }
+    Ok(())
+}
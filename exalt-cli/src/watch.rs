@@ -0,0 +1,56 @@
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+use anyhow::Context;
+use exalt_compiler::{CompilationCache, CompileRequest, CompilerError};
+
+const POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+/// Modification time and size of each file, or None if it can't be read (ex. mid save).
+fn snapshot(files: &[PathBuf]) -> Vec<Option<(SystemTime, u64)>> {
+    files
+        .iter()
+        .map(|path| {
+            let metadata = std::fs::metadata(path).ok()?;
+            Some((metadata.modified().ok()?, metadata.len()))
+        })
+        .collect()
+}
+
+fn compile_once(request: &CompileRequest, cache: &mut CompilationCache) -> anyhow::Result<()> {
+    let (_, raw) = exalt_compiler::compile_with_cache(request, cache)?;
+    let output_path = request.output_path()?;
+    if let Some(parent) = output_path.parent() {
+        std::fs::create_dir_all(parent).context("failed to create output directory")?;
+    }
+    std::fs::write(&output_path, raw).context("failed to write output file")?;
+    println!("compiled '{}'", output_path.display());
+    Ok(())
+}
+
+/// Compile the target, then recompile whenever it or anything it includes changes.
+/// Diagnostics are printed as each compile finishes. Runs until interrupted.
+pub fn compile_on_change(request: &CompileRequest) -> anyhow::Result<()> {
+    let mut cache = CompilationCache::new();
+    loop {
+        match compile_once(request, &mut cache) {
+            Ok(()) => {}
+            // The diagnostics were already emitted in the requested format
+            Err(err) if matches!(err.downcast_ref(), Some(CompilerError::ParseError(_))) => {
+                println!("compile failed");
+            }
+            Err(err) => println!("error: {:#}", err),
+        }
+
+        let mut files: Vec<PathBuf> = cache.dependencies().map(Path::to_path_buf).collect();
+        if !files.contains(&request.target) {
+            files.push(request.target.clone());
+        }
+        println!("watching {} file(s) for changes...", files.len());
+        let before = snapshot(&files);
+        while snapshot(&files) == before {
+            std::thread::sleep(POLL_INTERVAL);
+        }
+        println!();
+    }
+}
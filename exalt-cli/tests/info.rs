@@ -0,0 +1,80 @@
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+use std::process::Command;
+
+use exalt_lir::{Function, Game, Opcode, RawScript, ScriptQuirks, SCHEMA_VERSION};
+
+fn script(code: Vec<Opcode>) -> RawScript {
+    RawScript {
+        schema_version: SCHEMA_VERSION,
+        global_frame_size: 0,
+        functions: vec![Function {
+            frame_size: 0,
+            event: 0,
+            arity: 0,
+            unknown: 0,
+            prefix: Vec::new(),
+            suffix: Vec::new(),
+            name: Some("f".to_owned()),
+            args: Vec::new(),
+            code,
+            comments: BTreeMap::new(),
+            offsets: Vec::new(),
+        }],
+        quirks: ScriptQuirks::default(),
+    }
+}
+
+/// Assemble a script and run the info command on it, keeping the output file under `name`.
+fn info(game: Game, name: &str, script: &RawScript) -> String {
+    let raw = exalt_assembler::assemble(script, "t", game).unwrap();
+    let path = PathBuf::from(env!("CARGO_TARGET_TMPDIR")).join(format!("{}-{:?}.cmb", name, game));
+    std::fs::write(&path, raw).unwrap();
+    let output = Command::new(env!("CARGO_BIN_EXE_exalt-cli"))
+        .args(["-g", &format!("{:?}", game), "info"])
+        .arg(&path)
+        .output()
+        .unwrap();
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(
+        output.status.success(),
+        "{:?}: {}",
+        game,
+        String::from_utf8_lossy(&output.stderr)
+    );
+    stdout
+}
+
+#[test]
+fn script_without_strings_reports_empty_text_data() {
+    // Text data sits at the function table on these games, so an empty section has no bytes at all
+    for game in [Game::FE9, Game::FE10, Game::FE12] {
+        let stdout = info(game, "no-strings", &script(vec![Opcode::Return]));
+        assert!(
+            stdout.contains("0 string(s) in 0 bytes of text data"),
+            "{:?}: {}",
+            game,
+            stdout
+        );
+    }
+    let stdout = info(Game::FE14, "no-strings", &script(vec![Opcode::Return]));
+    assert!(stdout.contains("0 string(s)"), "{}", stdout);
+}
+
+#[test]
+fn script_with_strings_reports_text_data() {
+    let code = vec![
+        Opcode::StrLoad("hi".to_owned()),
+        Opcode::Consume,
+        Opcode::Return,
+    ];
+    for game in [Game::FE10, Game::FE14] {
+        let stdout = info(game, "strings", &script(code.clone()));
+        assert!(
+            stdout.contains("1 string(s)") && stdout.contains("3 bytes of strings"),
+            "{:?}: {}",
+            game,
+            stdout
+        );
+    }
+}
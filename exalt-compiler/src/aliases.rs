@@ -0,0 +1,33 @@
+use std::collections::{BTreeMap, HashMap};
+
+use exalt_lir::Game;
+use serde::Deserialize;
+
+/// Names for engine functions kept outside of scripts (ex. a community naming database)
+/// so they can be shared without editing the std library.
+/// Written as a map from game to `friendly name: internal name` pairs:
+///
+/// ```yaml
+/// FE14:
+///   bev::FadeIn: bev::フェードイン
+/// ```
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(transparent)]
+pub struct AliasFile {
+    games: HashMap<Game, BTreeMap<String, String>>,
+}
+
+impl AliasFile {
+    /// Aliases for a game as (friendly name, internal name) pairs.
+    pub fn for_game(&self, game: Game) -> Vec<(String, String)> {
+        self.games
+            .get(&game)
+            .map(|aliases| {
+                aliases
+                    .iter()
+                    .map(|(name, internal)| (name.clone(), internal.clone()))
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+}
@@ -0,0 +1,168 @@
+use exalt_ast::Literal;
+use exalt_lir::Opcode;
+
+fn expect_args(args: &[Literal], count: usize) -> Result<(), String> {
+    if args.len() == count {
+        Ok(())
+    } else {
+        Err(format!(
+            "instruction takes {} argument(s) but {} were given",
+            count,
+            args.len()
+        ))
+    }
+}
+
+fn int_arg(args: &[Literal], index: usize) -> Result<i32, String> {
+    match &args[index] {
+        Literal::Int(v) => Ok(*v),
+        _ => Err(format!("argument {} must be an int", index + 1)),
+    }
+}
+
+fn u16_arg(args: &[Literal], index: usize) -> Result<u16, String> {
+    u16::try_from(int_arg(args, index)?)
+        .map_err(|_| format!("argument {} must be a frame index", index + 1))
+}
+
+fn u8_arg(args: &[Literal], index: usize) -> Result<u8, String> {
+    u8::try_from(int_arg(args, index)?)
+        .map_err(|_| format!("argument {} must fit in a byte", index + 1))
+}
+
+fn str_arg(args: &[Literal], index: usize) -> Result<String, String> {
+    match &args[index] {
+        Literal::Str(v) => Ok(v.clone()),
+        _ => Err(format!("argument {} must be a string", index + 1)),
+    }
+}
+
+fn float_arg(args: &[Literal], index: usize) -> Result<f32, String> {
+    match &args[index] {
+        Literal::Float(v) => Ok(*v),
+        Literal::Int(v) => Ok(*v as f32),
+        _ => Err(format!("argument {} must be a float", index + 1)),
+    }
+}
+
+fn frame_opcode(args: &[Literal], build: fn(u16) -> Opcode) -> Result<Opcode, String> {
+    expect_args(args, 1)?;
+    Ok(build(u16_arg(args, 0)?))
+}
+
+fn label_opcode(args: &[Literal], build: fn(String) -> Opcode) -> Result<Opcode, String> {
+    expect_args(args, 1)?;
+    Ok(build(str_arg(args, 0)?))
+}
+
+/// Build an opcode from an instruction in an asm block.
+/// Instructions use the same names as the disassembler (ex. VarLoad(0) or Jump("l1")).
+pub fn to_opcode(name: &str, args: &[Literal]) -> Result<Opcode, String> {
+    let opcode = match name {
+        "VarLoad" => return frame_opcode(args, Opcode::VarLoad),
+        "ArrLoad" => return frame_opcode(args, Opcode::ArrLoad),
+        "PtrLoad" => return frame_opcode(args, Opcode::PtrLoad),
+        "VarAddr" => return frame_opcode(args, Opcode::VarAddr),
+        "ArrAddr" => return frame_opcode(args, Opcode::ArrAddr),
+        "PtrAddr" => return frame_opcode(args, Opcode::PtrAddr),
+        "GlobalVarLoad" => return frame_opcode(args, Opcode::GlobalVarLoad),
+        "GlobalArrLoad" => return frame_opcode(args, Opcode::GlobalArrLoad),
+        "GlobalPtrLoad" => return frame_opcode(args, Opcode::GlobalPtrLoad),
+        "GlobalVarAddr" => return frame_opcode(args, Opcode::GlobalVarAddr),
+        "GlobalArrAddr" => return frame_opcode(args, Opcode::GlobalArrAddr),
+        "GlobalPtrAddr" => return frame_opcode(args, Opcode::GlobalPtrAddr),
+        "Jump" => return label_opcode(args, Opcode::Jump),
+        "JumpNotZero" => return label_opcode(args, Opcode::JumpNotZero),
+        "Or" => return label_opcode(args, Opcode::Or),
+        "JumpZero" => return label_opcode(args, Opcode::JumpZero),
+        "And" => return label_opcode(args, Opcode::And),
+        "Label" => return label_opcode(args, Opcode::Label),
+        "Unknown" => {
+            if args.is_empty() {
+                return Err("instruction takes at least 1 argument but 0 were given".to_owned());
+            }
+            let bytes = (0..args.len())
+                .map(|i| u8_arg(args, i))
+                .collect::<Result<Vec<_>, _>>()?;
+            return Ok(Opcode::Unknown(bytes[0], bytes[1..].to_vec()));
+        }
+        "IntLoad" => {
+            expect_args(args, 1)?;
+            return Ok(Opcode::IntLoad(int_arg(args, 0)?));
+        }
+        "StrLoad" => {
+            expect_args(args, 1)?;
+            return Ok(Opcode::StrLoad(str_arg(args, 0)?));
+        }
+        "FloatLoad" => {
+            expect_args(args, 1)?;
+            return Ok(Opcode::FloatLoad(float_arg(args, 0)?));
+        }
+        "CallById" => {
+            expect_args(args, 1)?;
+            let id = usize::try_from(int_arg(args, 0)?)
+                .map_err(|_| "argument 1 must be a function id".to_owned())?;
+            return Ok(Opcode::CallById(id));
+        }
+        "CallByName" => {
+            expect_args(args, 2)?;
+            return Ok(Opcode::CallByName(str_arg(args, 0)?, u8_arg(args, 1)?));
+        }
+        "Format" => {
+            expect_args(args, 1)?;
+            return Ok(Opcode::Format(u8_arg(args, 0)?));
+        }
+        "Done" => Opcode::Done,
+        "Dereference" => Opcode::Dereference,
+        "Consume" => Opcode::Consume,
+        "CompleteAssign" => Opcode::CompleteAssign,
+        "Fix" => Opcode::Fix,
+        "Float" => Opcode::Float,
+        "Add" => Opcode::Add,
+        "FloatAdd" => Opcode::FloatAdd,
+        "Subtract" => Opcode::Subtract,
+        "FloatSubtract" => Opcode::FloatSubtract,
+        "Multiply" => Opcode::Multiply,
+        "FloatMultiply" => Opcode::FloatMultiply,
+        "Divide" => Opcode::Divide,
+        "FloatDivide" => Opcode::FloatDivide,
+        "Modulo" => Opcode::Modulo,
+        "IntNegate" => Opcode::IntNegate,
+        "FloatNegate" => Opcode::FloatNegate,
+        "BinaryNot" => Opcode::BinaryNot,
+        "LogicalNot" => Opcode::LogicalNot,
+        "BinaryOr" => Opcode::BinaryOr,
+        "BinaryAnd" => Opcode::BinaryAnd,
+        "Xor" => Opcode::Xor,
+        "LeftShift" => Opcode::LeftShift,
+        "RightShift" => Opcode::RightShift,
+        "Equal" => Opcode::Equal,
+        "FloatEqual" => Opcode::FloatEqual,
+        "Exlcall" => Opcode::Exlcall,
+        "NotEqual" => Opcode::NotEqual,
+        "FloatNotEqual" => Opcode::FloatNotEqual,
+        "Nop0x3D" => Opcode::Nop0x3D,
+        "LessThan" => Opcode::LessThan,
+        "FloatLessThan" => Opcode::FloatLessThan,
+        "LessThanEqualTo" => Opcode::LessThanEqualTo,
+        "FloatLessThanEqualTo" => Opcode::FloatLessThanEqualTo,
+        "GreaterThan" => Opcode::GreaterThan,
+        "FloatGreaterThan" => Opcode::FloatGreaterThan,
+        "GreaterThanEqualTo" => Opcode::GreaterThanEqualTo,
+        "FloatGreaterThanEqualTo" => Opcode::FloatGreaterThanEqualTo,
+        "Return" => Opcode::Return,
+        "Yield" => Opcode::Yield,
+        "Inc" => Opcode::Inc,
+        "Dec" => Opcode::Dec,
+        "Copy" => Opcode::Copy,
+        "ReturnFalse" => Opcode::ReturnFalse,
+        "ReturnTrue" => Opcode::ReturnTrue,
+        "StringEquals" => Opcode::StringEquals,
+        "StringNotEquals" => Opcode::StringNotEquals,
+        "Nop0x40" => Opcode::Nop0x40,
+        "Assign" => Opcode::Assign,
+        _ => return Err(format!("unknown instruction '{}'", name)),
+    };
+    expect_args(args, 0)?;
+    Ok(opcode)
+}
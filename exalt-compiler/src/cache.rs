@@ -0,0 +1,319 @@
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
+use std::hash::{Hash, Hasher};
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+
+use codespan_reporting::files::SimpleFiles;
+use exalt_ast::surface::Script;
+use exalt_ast::FileId;
+use exalt_lir::RawScript;
+
+use crate::header::PrecompiledHeader;
+use crate::reporting::WarningMessage;
+use crate::{includes, parser, semantic, stdlib, CompilerLog};
+
+fn hash_source(source: &str) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    source.hash(&mut hasher);
+    hasher.finish()
+}
+
+/// Whether an include search would find a script at a path.
+pub(crate) fn script_exists(path: &Path) -> bool {
+    path.is_file() || stdlib::is_embedded(path)
+}
+
+/// Check that files still have the contents they were hashed with and that missing files are still missing.
+fn up_to_date(dependencies: &[(PathBuf, u64)], absent: &[PathBuf]) -> bool {
+    dependencies
+        .iter()
+        .all(|(path, hash)| match crate::read_source(path) {
+            Ok(source) => hash_source(&source) == *hash,
+            Err(_) => false,
+        })
+        && !absent.iter().any(|path| script_exists(path))
+}
+
+struct ParsedFile {
+    hash: u64,
+    script: Script,
+}
+
+/// An include's declarations, analyzed on their own and saved like an in-memory precompiled header.
+pub(crate) struct AnalyzedFile {
+    hash: u64,
+
+    // What the include's own includes were resolved against
+    root: PathBuf,
+    additional_includes: Vec<PathBuf>,
+
+    /// Every file the analysis read, starting with the include itself.
+    pub dependencies: Vec<(PathBuf, u64)>,
+
+    /// Paths searched for includes that had nothing there. A new file at one of them could change what gets included.
+    pub absent: Vec<PathBuf>,
+
+    /// None if the include can't stand on its own (ex. it generates code or uses constants from the script including it).
+    pub header: Option<PrecompiledHeader>,
+}
+
+struct CompiledOutput {
+    // Hash of the compile settings that produced this output
+    settings: u64,
+    dependencies: Vec<(PathBuf, u64)>,
+    absent: Vec<PathBuf>,
+    warnings: Vec<WarningMessage>,
+    lir: RawScript,
+    raw: Vec<u8>,
+}
+
+/// Keeps parse trees, analyzed includes, and compiled output between compiles so unchanged files are not processed again.
+///
+/// Every file the cache has seen keeps the same file id for the lifetime of the cache, even when its contents change.
+/// Logs for cached compiles start from that file database so locations in cached parse trees stay valid.
+pub struct CompilationCache {
+    files: SimpleFiles<String, String>,
+    next_file_id: usize,
+    file_ids: HashMap<PathBuf, FileId>,
+    parsed: HashMap<PathBuf, ParsedFile>,
+    analyzed: HashMap<PathBuf, Rc<AnalyzedFile>>,
+    outputs: HashMap<PathBuf, CompiledOutput>,
+
+    // Files loaded and include paths found empty by the compile currently in progress
+    dependencies: Vec<(PathBuf, u64)>,
+    absent: Vec<PathBuf>,
+}
+
+impl CompilationCache {
+    pub fn new() -> Self {
+        CompilationCache {
+            files: SimpleFiles::new(),
+            next_file_id: 0,
+            file_ids: HashMap::new(),
+            parsed: HashMap::new(),
+            analyzed: HashMap::new(),
+            outputs: HashMap::new(),
+            dependencies: Vec::new(),
+            absent: Vec::new(),
+        }
+    }
+
+    /// Forget everything. Useful if the cache has grown too large.
+    pub fn clear(&mut self) {
+        *self = Self::new();
+    }
+
+    pub(crate) fn create_log(&mut self) -> CompilerLog {
+        self.dependencies.clear();
+        self.absent.clear();
+        CompilerLog::with_files(self.files.clone(), self.next_file_id)
+    }
+
+    /// Keep the files registered by a finished compile so their ids stay stable.
+    pub(crate) fn finish_log(&mut self, log: &CompilerLog) {
+        self.files = log.files.clone();
+        self.next_file_id = log.peek_file_id();
+    }
+
+    /// Files read by the most recent compile, including one answered from the cache.
+    pub fn dependencies(&self) -> impl Iterator<Item = &Path> {
+        self.dependencies.iter().map(|(path, _)| path.as_path())
+    }
+
+    /// Note a file the compile in progress depends on without parsing it (ex. the source of a precompiled header).
+    pub(crate) fn record_dependency(&mut self, path: &Path, source: &str) {
+        self.dependencies
+            .push((path.to_path_buf(), hash_source(source)));
+    }
+
+    /// Note an include path that had nothing at it, so a file created there later invalidates the output.
+    pub(crate) fn record_absence(&mut self, path: &Path) {
+        self.absent.push(path.to_path_buf());
+    }
+
+    /// Give a file its id in the log, reusing the one it had before if the cache has seen it.
+    pub(crate) fn register(&mut self, path: &Path, source: &str, log: &mut CompilerLog) -> FileId {
+        match self.file_ids.get(path) {
+            Some(file_id) => {
+                if log.source(*file_id).as_deref() != Some(source) {
+                    log.replace_source(*file_id, source.to_owned());
+                }
+                *file_id
+            }
+            None => {
+                let file_id = log.add(path.to_string_lossy().to_string(), source.to_owned());
+                self.file_ids.insert(path.to_path_buf(), file_id);
+                file_id
+            }
+        }
+    }
+
+    /// Parse a file, reusing the previous parse tree if its contents have not changed.
+    pub(crate) fn parse(&mut self, path: &Path, source: String, log: &mut CompilerLog) -> Script {
+        let hash = hash_source(&source);
+        self.dependencies.push((path.to_path_buf(), hash));
+        let file_id = self.register(path, &source, log);
+        if let Some(parsed) = self.parsed.get(path) {
+            if parsed.hash == hash {
+                return parsed.script.clone();
+            }
+        }
+
+        let error_count = log.errors.len();
+        let script = parser::parse(file_id, &source, log);
+
+        // Don't hold on to broken files. Reusing them would lose their diagnostics.
+        if log.errors.len() == error_count {
+            self.parsed.insert(
+                path.to_path_buf(),
+                ParsedFile {
+                    hash,
+                    script: script.clone(),
+                },
+            );
+        } else {
+            self.parsed.remove(path);
+        }
+        script
+    }
+
+    /// The declarations of an include analyzed on their own, if they can be.
+    ///
+    /// Includes are only analyzed this way once they've been parsed before with the same contents,
+    /// so compiling a script once costs no more than it would without a cache.
+    /// `root` is the script being compiled, which decides where the include's own includes are found.
+    pub(crate) fn analysis(
+        &mut self,
+        path: &Path,
+        source: &str,
+        root: &Path,
+        additional_includes: &[PathBuf],
+    ) -> Option<Rc<AnalyzedFile>> {
+        let hash = hash_source(source);
+        let cached = self.analyzed.get(path).filter(|analyzed| {
+            analyzed.hash == hash
+                && analyzed.root == root
+                && analyzed.additional_includes == additional_includes
+                && up_to_date(&analyzed.dependencies[1..], &analyzed.absent)
+        });
+        let analyzed = match cached {
+            Some(analyzed) => analyzed.clone(),
+            None => {
+                if self.parsed.get(path).map(|parsed| parsed.hash) != Some(hash) {
+                    return None;
+                }
+                let analyzed = Rc::new(analyze_include(
+                    path,
+                    source,
+                    hash,
+                    root,
+                    additional_includes,
+                ));
+                self.analyzed.insert(path.to_path_buf(), analyzed.clone());
+                analyzed
+            }
+        };
+        analyzed.header.as_ref()?;
+        self.dependencies
+            .extend(analyzed.dependencies.iter().cloned());
+        self.absent.extend(analyzed.absent.iter().cloned());
+        Some(analyzed)
+    }
+
+    /// Look up the output of an earlier compile, replaying its warnings into the log.
+    pub(crate) fn lookup_output(
+        &mut self,
+        target: &Path,
+        settings: u64,
+        log: &mut CompilerLog,
+    ) -> Option<(RawScript, Vec<u8>)> {
+        let output = self.outputs.get(target)?;
+        if output.settings != settings || !up_to_date(&output.dependencies, &output.absent) {
+            return None;
+        }
+        self.dependencies = output.dependencies.clone();
+        self.absent = output.absent.clone();
+        log.warnings.extend(output.warnings.iter().cloned());
+        let result = (output.lir.clone(), output.raw.clone());
+
+        // A file may have changed and changed back since, so make sure the log shows what the warnings were made against.
+        for (path, _) in self.dependencies.clone() {
+            if let Ok(source) = crate::read_source(&path) {
+                self.register(&path, &source, log);
+            }
+        }
+        Some(result)
+    }
+
+    pub(crate) fn store_output(
+        &mut self,
+        target: &Path,
+        settings: u64,
+        lir: &RawScript,
+        raw: &[u8],
+        log: &CompilerLog,
+    ) {
+        self.outputs.insert(
+            target.to_path_buf(),
+            CompiledOutput {
+                settings,
+                dependencies: self.dependencies.clone(),
+                absent: self.absent.clone(),
+                warnings: log.warnings.clone(),
+                lir: lir.clone(),
+                raw: raw.to_vec(),
+            },
+        );
+    }
+}
+
+impl Default for CompilationCache {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Analyze an include the same way as a precompiled header. Only includes that do so without any diagnostics are kept.
+fn analyze_include(
+    path: &Path,
+    source: &str,
+    hash: u64,
+    root: &Path,
+    additional_includes: &[PathBuf],
+) -> AnalyzedFile {
+    let mut log = CompilerLog::new();
+    let mut cache = CompilationCache::new();
+    let script = cache.parse(path, source.to_owned(), &mut log);
+    let built = includes::build_include_on_its_own(
+        path.to_path_buf(),
+        root,
+        script,
+        &mut log,
+        additional_includes,
+        &mut cache,
+    );
+    let header = match built {
+        Ok((script, symbol_table)) if !log.has_errors() => {
+            crate::check_header_decls(&script.0, &mut log);
+            semantic::analyze(&script, symbol_table, &mut log)
+                .filter(|_| !log.has_errors() && !log.has_warnings())
+                .map(|(_, symbol_table)| PrecompiledHeader::new(&symbol_table, &log))
+        }
+        _ => None,
+    };
+    let mut dependencies = vec![(path.to_path_buf(), hash)];
+    dependencies.extend(cache.dependencies.into_iter().filter(|(p, _)| p != path));
+    AnalyzedFile {
+        hash,
+        root: root.to_path_buf(),
+        additional_includes: additional_includes.to_vec(),
+        dependencies,
+        absent: cache.absent,
+        header,
+    }
+}
+
+pub(crate) fn hash_settings(settings: &str) -> u64 {
+    hash_source(settings)
+}
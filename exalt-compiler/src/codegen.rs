@@ -1,23 +1,38 @@
-use std::collections::{HashMap, HashSet};
+use std::collections::{BTreeMap, HashMap, HashSet};
 
-use exalt_assembler::CodeGenTextData;
-use exalt_ast::{Annotation, Decl, Expr, Literal, Notation, Operator, Ref, Script, Stmt};
-use exalt_lir::{CallbackArg, Game, Opcode, RawScript};
+use exalt_assembler::{AssembleOptions, AssemblyError, FunctionLayout};
+use exalt_ast::{Annotation, Decl, Expr, Literal, Location, Notation, Operator, Ref, Script, Stmt};
+use exalt_lir::{
+    CallbackArg, Game, Opcode, RawScript, ScriptQuirks, SymbolMap, SCHEMA_VERSION,
+};
 
 use thiserror::Error;
 
+use crate::slots::{self, FrameAllocation};
 use crate::symbol::SymbolTable;
 
 type RawFunction = exalt_lir::Function;
 type Result<T> = std::result::Result<T, CodeGenerationError>;
 
+/// Where the statements in a function were written, keyed by the index of their first opcode.
+pub type SourceLocations = Vec<(usize, Location)>;
+
 #[derive(Debug, Error)]
 pub enum CodeGenerationError {
     #[error("break or continue without a target")]
     BadBreakOrContinue,
 
-    #[error("{0}")]
-    BadAssembly(String),
+    #[error("fallthrough without a following case")]
+    BadFallthrough,
+
+    #[error(transparent)]
+    BadAssembly(#[from] AssemblyError),
+
+    #[error("exlcall is not supported by {0:?}")]
+    UnsupportedExlCall(Game),
+
+    #[error("'{name}' can't go in frame slot {frame_id} because another variable or parameter already uses it")]
+    FrameIdCollision { name: String, frame_id: usize },
 }
 
 #[derive(Debug)]
@@ -26,6 +41,7 @@ struct FunctionGenerationConfig {
     unknown_value: u8,
     suffix: Vec<u8>,
     prefix: Vec<u8>,
+    min_frame_size: usize,
 }
 
 impl Default for FunctionGenerationConfig {
@@ -35,6 +51,7 @@ impl Default for FunctionGenerationConfig {
             unknown_value: 0,
             suffix: Vec::new(),
             prefix: Vec::new(),
+            min_frame_size: 0,
         }
     }
 }
@@ -89,37 +106,98 @@ struct CodeGenerator<'a> {
     function_to_call_id: HashMap<String, usize>,
     next_label: usize,
     frame_size: usize,
+    next_frame_id: usize,
+    symbols: Option<&'a SymbolMap>,
+    // Frame indices requested by the symbol map for the current function
+    fixed_frame_ids: HashMap<String, usize>,
+    // Variables from the symbol map that already have their slot in the current function
+    fixed_names: HashSet<String>,
     continue_labels: Vec<String>,
     break_labels: Vec<String>,
-    assigned_variables: HashSet<String>,
+    // Body label of the case after the one being generated, if any
+    fallthrough_labels: Vec<Option<String>>,
+    locations: SourceLocations,
     game: Game,
+    // Liveness based slot reuse, along with the function state it needs
+    reuse_frame_slots: bool,
+    arities: Vec<usize>,
+    allocations: Vec<FrameAllocation>,
+    escaped_frame_ids: HashSet<usize>,
+    has_asm: bool,
 }
 
 impl<'a> CodeGenerator<'a> {
-    pub fn serialize(script: &Script, symbol_table: &SymbolTable, game: Game) -> Result<RawScript> {
+    pub fn serialize(
+        script: &Script,
+        symbol_table: &'a SymbolTable,
+        game: Game,
+        symbols: Option<&'a SymbolMap>,
+        reuse_frame_slots: bool,
+    ) -> Result<(RawScript, Vec<SourceLocations>)> {
         let mut functions = Vec::new();
+        let mut locations = Vec::new();
         let mut generator = CodeGenerator {
             symbol_table,
             function_to_call_id: CodeGenerator::generate_function_to_call_id(script),
             next_label: 0,
             frame_size: 0,
+            next_frame_id: 0,
+            symbols,
+            fixed_frame_ids: HashMap::new(),
+            fixed_names: HashSet::new(),
             continue_labels: Vec::new(),
             break_labels: Vec::new(),
-            assigned_variables: HashSet::new(),
+            fallthrough_labels: Vec::new(),
+            locations: Vec::new(),
             game,
+            reuse_frame_slots,
+            arities: script
+                .decls
+                .iter()
+                .flat_map(|decl| match decl {
+                    Decl::Function { parameters, .. } => vec![parameters.len()],
+                    Decl::Callback { event_types, .. } => vec![0; event_types.len()],
+                })
+                .collect(),
+            allocations: Vec::new(),
+            escaped_frame_ids: HashSet::new(),
+            has_asm: false,
         };
         for decl in &script.decls {
-            functions.push(generator.generate_function_data(decl)?);
+            generator.load_fixed_frame_ids(decl, functions.len());
+            let function = generator.generate_function_data(decl)?;
+            let function_locations = std::mem::take(&mut generator.locations);
+            // Callbacks with several events get a copy of the code for each event after the first
+            let copies: Vec<RawFunction> = match decl {
+                Decl::Callback { event_types, .. } => event_types[1..]
+                    .iter()
+                    .map(|event| RawFunction {
+                        event: *event as u8,
+                        ..function.clone()
+                    })
+                    .collect(),
+                Decl::Function { .. } => Vec::new(),
+            };
+            functions.push(function);
+            locations.push(function_locations.clone());
+            for copy in copies {
+                functions.push(copy);
+                locations.push(function_locations.clone());
+            }
         }
-        Ok(RawScript {
+        let script = RawScript {
+            schema_version: SCHEMA_VERSION,
             functions,
             global_frame_size: script.globals,
-        })
+            quirks: ScriptQuirks::default(),
+        };
+        Ok((script, locations))
     }
 
     fn generate_function_to_call_id(script: &Script) -> HashMap<String, usize> {
         let mut entries = HashMap::new();
-        for (call_id, decl) in script.decls.iter().enumerate() {
+        let mut call_id = 0;
+        for decl in &script.decls {
             if let Decl::Function {
                 annotations: _,
                 symbol,
@@ -130,15 +208,111 @@ impl<'a> CodeGenerator<'a> {
                 let name = symbol.borrow().name.clone();
                 entries.insert(name, call_id);
             }
+            call_id += decl.table_entries();
         }
         entries
     }
 
+    fn load_fixed_frame_ids(&mut self, decl: &Decl, index: usize) {
+        self.fixed_frame_ids.clear();
+        let symbols = match self.symbols {
+            Some(symbols) => symbols,
+            None => return,
+        };
+        let key = match decl {
+            Decl::Function { symbol, .. } => {
+                let name = symbol.borrow().name.clone();
+                match self.symbol_table.lookup_alias(&name) {
+                    Some((_, alias)) => alias.clone(),
+                    None => name,
+                }
+            }
+            Decl::Callback { .. } => SymbolMap::callback_key(index),
+        };
+        if let Some(locals) = symbols.locals(&key) {
+            for (frame_id, name) in locals {
+                self.fixed_frame_ids.insert(name.clone(), *frame_id);
+            }
+        }
+    }
+
+    /// Whether any slot in a block is already handed out, counting parameters and every slot of an array.
+    fn overlaps_allocation(&self, frame_id: usize, count: usize) -> bool {
+        self.allocations
+            .iter()
+            .any(|a| a.frame_id < frame_id + count && frame_id < a.frame_id + a.count)
+    }
+
+    /// Pick a frame index for a new variable.
+    /// Variables named in the symbol map keep their index and other variables are packed around them.
+    fn allocate_frame_id(&mut self, name: &str, count: usize) -> Result<usize> {
+        let fixed = self.fixed_frame_ids.contains_key(name);
+        let frame_id = match self.fixed_frame_ids.get(name) {
+            Some(frame_id) => {
+                let frame_id = *frame_id;
+                // Variables declared again under the same name (ex. in sibling scopes) share their slot
+                let redeclared = self.fixed_names.contains(name);
+                let collides = self.allocations.iter().any(|a| {
+                    !(redeclared && a.frame_id == frame_id)
+                        && a.frame_id < frame_id + count
+                        && frame_id < a.frame_id + a.count
+                });
+                if collides {
+                    return Err(CodeGenerationError::FrameIdCollision {
+                        name: name.to_owned(),
+                        frame_id,
+                    });
+                }
+                self.fixed_names.insert(name.to_owned());
+                frame_id
+            }
+            None => {
+                let mut frame_id = self.next_frame_id;
+                while self.overlaps_allocation(frame_id, count)
+                    || self
+                        .fixed_frame_ids
+                        .values()
+                        .any(|fixed| (frame_id..frame_id + count).contains(fixed))
+                {
+                    frame_id += 1;
+                }
+                self.next_frame_id = frame_id + count;
+                frame_id
+            }
+        };
+        self.frame_size = self.frame_size.max(frame_id + count);
+        self.allocations.push(FrameAllocation {
+            frame_id,
+            count,
+            pinned: fixed,
+        });
+        Ok(frame_id)
+    }
+
+    /// Frame size of the function that was just generated, after packing its slots if requested.
+    fn finish_frame(&mut self, code: &mut [Opcode]) -> usize {
+        // Inline asm can touch any slot
+        if !self.reuse_frame_slots || self.has_asm {
+            return self.frame_size;
+        }
+        for allocation in &mut self.allocations {
+            allocation.pinned |= self.escaped_frame_ids.contains(&allocation.frame_id);
+        }
+        let arities = &self.arities;
+        slots::reallocate(code, &self.allocations, |id| arities.get(id).copied())
+            .unwrap_or(self.frame_size)
+    }
+
     fn generate_function_data(&mut self, decl: &Decl) -> Result<RawFunction> {
         self.frame_size = 0;
+        self.next_frame_id = 0;
+        self.allocations.clear();
+        self.fixed_names.clear();
+        self.escaped_frame_ids.clear();
+        self.has_asm = false;
         self.continue_labels.clear();
         self.break_labels.clear();
-        self.assigned_variables.clear();
+        self.fallthrough_labels.clear();
 
         match decl {
             Decl::Function {
@@ -150,60 +324,61 @@ impl<'a> CodeGenerator<'a> {
                 let config = CodeGenerator::annotations_to_config(annotations);
                 for (i, p) in parameters.iter().enumerate() {
                     p.borrow_mut().frame_id = Some(i);
+                    self.allocations.push(FrameAllocation {
+                        frame_id: i,
+                        count: 1,
+                        pinned: true,
+                    });
                 }
                 self.frame_size += parameters.len();
+                self.next_frame_id = self.frame_size;
                 let mut code = Vec::new();
                 self.convert_stmt_to_opcodes(&mut code, body)?;
                 if config.default_return {
-                    match self.game {
-                        Game::FE9 => {
-                            code.push(Opcode::IntLoad(0));
-                            code.push(Opcode::Return);
-                        }
-                        _ => code.push(Opcode::ReturnFalse),
+                    if self.game.profile().bool_returns {
+                        code.push(Opcode::ReturnFalse);
+                    } else {
+                        code.push(Opcode::IntLoad(0));
+                        code.push(Opcode::Return);
                     }
                 }
+                let frame_size = self.finish_frame(&mut code);
                 let symbol = symbol.borrow();
                 Ok(RawFunction {
                     event: 0,
                     arity: parameters.len() as u8,
-                    frame_size: self.frame_size,
+                    frame_size: frame_size.max(config.min_frame_size),
                     unknown: config.unknown_value,
                     prefix: config.prefix,
                     suffix: config.suffix,
-                    name: match self.game {
-                        Game::FE9 | Game::FE10 | Game::FE11 | Game::FE12 => {
-                            if symbol.name.contains("anonfn") {
-                                None
-                            } else if let Some((_, alias)) =
-                                self.symbol_table.lookup_alias(&symbol.name)
-                            {
-                                Some(alias.clone())
-                            } else {
-                                Some(symbol.name.clone())
-                            }
+                    name: if self.game.profile().keep_function_names {
+                        if exalt_lir::is_anonymous_function_name(&symbol.name) {
+                            None
+                        } else if let Some((_, alias)) =
+                            self.symbol_table.lookup_alias(&symbol.name)
+                        {
+                            Some(alias.clone())
+                        } else {
+                            Some(symbol.name.clone())
                         }
-                        Game::FE13 | Game::FE14 | Game::FE15 => {
-                            if symbol.name.contains("::") {
-                                if let Some((_, alias)) =
-                                    self.symbol_table.lookup_alias(&symbol.name)
-                                {
-                                    Some(alias.clone())
-                                } else {
-                                    Some(symbol.name.clone())
-                                }
-                            } else {
-                                None
-                            }
+                    } else if symbol.name.contains("::") {
+                        if let Some((_, alias)) = self.symbol_table.lookup_alias(&symbol.name) {
+                            Some(alias.clone())
+                        } else {
+                            Some(symbol.name.clone())
                         }
+                    } else {
+                        None
                     },
                     args: Vec::new(),
                     code,
+                    comments: BTreeMap::new(),
+                    offsets: Vec::new(),
                 })
             }
             Decl::Callback {
                 annotations,
-                event_type,
+                event_types,
                 args,
                 body,
             } => {
@@ -219,27 +394,30 @@ impl<'a> CodeGenerator<'a> {
                 let mut code = Vec::new();
                 self.convert_stmt_to_opcodes(&mut code, body)?;
                 if config.default_return {
-                    match self.game {
-                        Game::FE9 | Game::FE10 | Game::FE11 | Game::FE12 => {
-                            code.push(Opcode::IntLoad(0));
-                            code.push(Opcode::Return);
-                        }
-                        Game::FE13 | Game::FE14 | Game::FE15 => code.push(Opcode::ReturnFalse),
+                    if self.game.profile().callback_bool_return {
+                        code.push(Opcode::ReturnFalse);
+                    } else {
+                        code.push(Opcode::IntLoad(0));
+                        code.push(Opcode::Return);
                     }
                 }
+                let frame_size = self.finish_frame(&mut code);
                 Ok(RawFunction {
-                    event: *event_type as u8,
-                    arity: match self.game {
-                        Game::FE9 | Game::FE10 | Game::FE11 | Game::FE12 => 0,
-                        Game::FE13 | Game::FE14 | Game::FE15 => args.len() as u8,
+                    event: event_types[0] as u8,
+                    arity: if self.game.profile().callback_arity_is_arg_count {
+                        args.len() as u8
+                    } else {
+                        0
                     },
-                    frame_size: self.frame_size,
+                    frame_size: frame_size.max(config.min_frame_size),
                     unknown: config.unknown_value,
                     prefix: config.prefix,
                     suffix: config.suffix,
                     name: None,
                     args: event_args,
                     code,
+                    comments: BTreeMap::new(),
+                    offsets: Vec::new(),
                 })
             }
         }
@@ -253,6 +431,9 @@ impl<'a> CodeGenerator<'a> {
                 Annotation::Prefix(v) => config.prefix.clone_from(v),
                 Annotation::Suffix(v) => config.suffix.clone_from(v),
                 Annotation::Unknown(v) => config.unknown_value = *v as u8,
+                Annotation::FrameSize(v) => config.min_frame_size = *v,
+                // Applied during analysis by reordering the script
+                Annotation::Index(_, _) => {}
             }
         }
         config
@@ -271,40 +452,31 @@ impl<'a> CodeGenerator<'a> {
                     let frame_id = self.process_assignment_lhs(left, right)?;
                     match right {
                         Expr::Array(values) => {
+                            let global = match left {
+                                Ref::Var(symbol) => symbol.borrow().global,
+                                _ => false,
+                            };
                             for (i, value) in values.iter().enumerate() {
-                                opcodes.push(Opcode::VarAddr((frame_id + i) as u16));
-                                self.convert_expr_to_opcodes(opcodes, value)?;
-                                opcodes.push(if self.game == Game::FE9 {
-                                    Opcode::CompleteAssign
+                                let frame_id = (frame_id + i) as u16;
+                                opcodes.push(if global {
+                                    Opcode::GlobalVarAddr(frame_id)
                                 } else {
-                                    Opcode::Assign
+                                    Opcode::VarAddr(frame_id)
                                 });
+                                self.convert_expr_to_opcodes(opcodes, value)?;
+                                opcodes.push(self.assign_opcode());
                             }
                         }
                         _ => {
                             self.convert_ref_to_opcodes(opcodes, left, ValueCategory::LValue)?;
                             self.convert_expr_to_opcodes(opcodes, right)?;
-                            opcodes.push(if self.game == Game::FE9 {
-                                Opcode::CompleteAssign
-                            } else {
-                                Opcode::Assign
-                            });
+                            opcodes.push(self.assign_opcode());
                         }
                     }
 
                     Ok(())
                 } else {
-                    let op = op.unwrap_shorthand().unwrap();
-                    self.convert_ref_to_opcodes(opcodes, left, ValueCategory::LValue)?;
-                    opcodes.push(Opcode::Dereference);
-                    self.convert_expr_to_opcodes(opcodes, right)?;
-                    opcodes.push(to_opcode(op));
-                    opcodes.push(if self.game == Game::FE9 {
-                        Opcode::CompleteAssign
-                    } else {
-                        Opcode::Assign
-                    });
-                    Ok(())
+                    self.convert_compound_assignment(opcodes, left, *op, right)
                 }
             }
             Stmt::Block(stmts) => {
@@ -329,6 +501,14 @@ impl<'a> CodeGenerator<'a> {
                     Err(CodeGenerationError::BadBreakOrContinue)
                 }
             }
+            Stmt::Fallthrough => {
+                if let Some(Some(l)) = self.fallthrough_labels.last() {
+                    opcodes.push(Opcode::Jump(l.clone()));
+                    Ok(())
+                } else {
+                    Err(CodeGenerationError::BadFallthrough)
+                }
+            }
             Stmt::ExprStmt(e) => {
                 self.convert_expr_to_opcodes(opcodes, e)?;
                 opcodes.push(Opcode::Consume);
@@ -367,32 +547,22 @@ impl<'a> CodeGenerator<'a> {
                 condition,
                 then_part,
                 else_part,
-            } => {
-                self.convert_expr_to_opcodes(opcodes, condition)?;
-                let done_label = self.generate_label();
-                let else_label = if else_part.is_some() {
-                    Some(self.generate_label())
-                } else {
-                    None
-                };
-                opcodes.push(Opcode::JumpZero(if else_part.is_none() {
-                    done_label.clone()
-                } else {
-                    else_label.clone().unwrap()
-                }));
-                self.convert_stmt_to_opcodes(opcodes, then_part)?;
-                if let Some(p) = else_part {
-                    opcodes.push(Opcode::Jump(done_label.clone()));
-                    opcodes.push(Opcode::Label(else_label.unwrap()));
-                    self.convert_stmt_to_opcodes(opcodes, p)?;
-                }
-                opcodes.push(Opcode::Label(done_label));
-                Ok(())
-            }
+            } => self.convert_if_to_opcodes(
+                opcodes,
+                condition,
+                then_part,
+                else_part.as_deref(),
+                None,
+            ),
             Stmt::Label(symbol) => {
                 opcodes.push(Opcode::Label(symbol.borrow().name.clone()));
                 Ok(())
             }
+            Stmt::Asm(code) => {
+                self.has_asm = true;
+                opcodes.extend(code.iter().cloned());
+                Ok(())
+            }
             Stmt::Match {
                 switch,
                 cases,
@@ -402,14 +572,21 @@ impl<'a> CodeGenerator<'a> {
                 let done_label = self.generate_label();
                 self.break_labels.push(done_label.clone());
                 let mut next_case_label = self.generate_label();
+                let block_labels: Vec<String> =
+                    cases.iter().map(|_| self.generate_label()).collect();
                 for (i, case) in cases.iter().enumerate() {
                     opcodes.push(Opcode::Label(next_case_label));
-                    let block_label = self.generate_label();
+                    let block_label = &block_labels[i];
                     next_case_label = if i == cases.len() - 1 && default.is_none() {
                         done_label.clone()
                     } else {
                         self.generate_label()
                     };
+                    // The last case falls through to the default, which starts at the next case label
+                    let fallthrough_label = match block_labels.get(i + 1) {
+                        Some(label) => Some(label.clone()),
+                        None => default.as_ref().map(|_| next_case_label.clone()),
+                    };
                     for condition in &case.conditions {
                         opcodes.push(Opcode::Copy);
                         self.convert_expr_to_opcodes(opcodes, condition)?;
@@ -418,12 +595,16 @@ impl<'a> CodeGenerator<'a> {
                     }
                     opcodes.push(Opcode::Jump(next_case_label.clone()));
                     opcodes.push(Opcode::Label(block_label.clone()));
+                    self.fallthrough_labels.push(fallthrough_label);
                     self.convert_stmt_to_opcodes(opcodes, &case.body)?;
+                    self.fallthrough_labels.pop();
                     opcodes.push(Opcode::Jump(done_label.clone()));
                 }
                 if let Some(stmt) = default {
                     opcodes.push(Opcode::Label(next_case_label));
+                    self.fallthrough_labels.push(None);
                     self.convert_stmt_to_opcodes(opcodes, stmt)?;
+                    self.fallthrough_labels.pop();
                     opcodes.push(Opcode::Jump(done_label.clone()));
                 }
                 opcodes.push(Opcode::Label(done_label));
@@ -438,13 +619,18 @@ impl<'a> CodeGenerator<'a> {
                 opcodes.push(Opcode::Format(args.len() as u8));
                 Ok(())
             }
+            Stmt::SourceLocation(location) => {
+                self.locations.push((opcodes.len(), location.clone()));
+                Ok(())
+            }
             Stmt::Return(v) => match v {
                 Some(v) => {
                     match v {
                         Expr::Literal(Literal::Int(i)) => {
-                            if *i == 0 && self.game != Game::FE9 {
+                            let bool_returns = self.game.profile().bool_returns;
+                            if *i == 0 && bool_returns {
                                 opcodes.push(Opcode::ReturnFalse);
-                            } else if *i == 1 && self.game != Game::FE9 {
+                            } else if *i == 1 && bool_returns {
                                 opcodes.push(Opcode::ReturnTrue);
                             } else {
                                 self.convert_expr_to_opcodes(opcodes, v)?;
@@ -465,8 +651,9 @@ impl<'a> CodeGenerator<'a> {
                 }
             },
             Stmt::VarDecl(symbol, count) => {
-                symbol.borrow_mut().frame_id = Some(self.frame_size);
-                self.frame_size += count.unwrap_or(1);
+                let name = symbol.borrow().name.clone();
+                let frame_id = self.allocate_frame_id(&name, count.unwrap_or(1))?;
+                symbol.borrow_mut().frame_id = Some(frame_id);
                 Ok(())
             }
             Stmt::While { condition, body } => {
@@ -484,6 +671,23 @@ impl<'a> CodeGenerator<'a> {
                 self.break_labels.pop();
                 Ok(())
             }
+            Stmt::DoWhile { body, condition } => {
+                let start_label = self.generate_label();
+                let check_label = self.generate_label();
+                let done_label = self.generate_label();
+                self.continue_labels.push(check_label.clone());
+                self.break_labels.push(done_label.clone());
+                opcodes.push(Opcode::Label(start_label.clone()));
+                self.convert_stmt_to_opcodes(opcodes, body)?;
+                opcodes.push(Opcode::Label(check_label));
+                self.convert_expr_to_opcodes(opcodes, condition)?;
+                opcodes.push(Opcode::JumpZero(done_label.clone()));
+                opcodes.push(Opcode::Jump(start_label));
+                opcodes.push(Opcode::Label(done_label));
+                self.continue_labels.pop();
+                self.break_labels.pop();
+                Ok(())
+            }
             Stmt::Yield => {
                 opcodes.push(Opcode::Yield);
                 Ok(())
@@ -491,6 +695,85 @@ impl<'a> CodeGenerator<'a> {
         }
     }
 
+    /// Every branch of an else if chain jumps straight to the end of the chain like the games' scripts do.
+    /// `chain_label` is the end label of the enclosing if when this is an else if.
+    fn convert_if_to_opcodes(
+        &mut self,
+        opcodes: &mut Vec<Opcode>,
+        condition: &Expr,
+        then_part: &Stmt,
+        else_part: Option<&Stmt>,
+        chain_label: Option<&str>,
+    ) -> Result<()> {
+        self.convert_expr_to_opcodes(opcodes, condition)?;
+        let done_label = match chain_label {
+            Some(label) => label.to_owned(),
+            None => self.generate_label(),
+        };
+        match else_part {
+            Some(else_part) => {
+                let else_label = self.generate_label();
+                opcodes.push(Opcode::JumpZero(else_label.clone()));
+                self.convert_stmt_to_opcodes(opcodes, then_part)?;
+                opcodes.push(Opcode::Jump(done_label.clone()));
+                opcodes.push(Opcode::Label(else_label));
+                match else_part {
+                    Stmt::If {
+                        condition,
+                        then_part,
+                        else_part,
+                    } => self.convert_if_to_opcodes(
+                        opcodes,
+                        condition,
+                        then_part,
+                        else_part.as_deref(),
+                        Some(&done_label),
+                    )?,
+                    _ => self.convert_stmt_to_opcodes(opcodes, else_part)?,
+                }
+            }
+            None => {
+                opcodes.push(Opcode::JumpZero(done_label.clone()));
+                self.convert_stmt_to_opcodes(opcodes, then_part)?;
+            }
+        }
+        if chain_label.is_none() {
+            opcodes.push(Opcode::Label(done_label));
+        }
+        Ok(())
+    }
+
+    /// The opcode that stores a value through the address beneath it on the stack.
+    fn assign_opcode(&self) -> Opcode {
+        if self.game.profile().assignment_expressions {
+            Opcode::CompleteAssign
+        } else {
+            Opcode::Assign
+        }
+    }
+
+    /// Lower a compound assignment (ex. `arr[i] += x`) the way the original compiler does.
+    /// The target's address is computed once and Dereference reads the old value back through it,
+    /// so an index or pointer offset is never evaluated twice:
+    /// - `v += x` is `VarAddr v, Dereference, x, Add, Assign`
+    /// - `arr[i] += x` is `i, ArrAddr arr, Dereference, x, Add, Assign`
+    /// - `*p[i] += x` is `i, PtrAddr p, Dereference, x, Add, Assign`, with 0 for `i` in `*p += x`
+    fn convert_compound_assignment(
+        &mut self,
+        opcodes: &mut Vec<Opcode>,
+        left: &Ref,
+        op: Operator,
+        right: &Expr,
+    ) -> Result<()> {
+        let op = op.unwrap_shorthand().unwrap();
+        self.convert_ref_to_opcodes(opcodes, left, ValueCategory::LValue)?;
+        opcodes.push(Opcode::Dereference);
+        self.convert_expr_to_opcodes(opcodes, right)?;
+        opcodes.push(to_opcode(op));
+        opcodes.push(self.assign_opcode());
+        Ok(())
+    }
+
     fn process_assignment_lhs(&mut self, reference: &Ref, right: &Expr) -> Result<usize> {
         let mut symbol = match reference {
             Ref::Var(symbol) => symbol,
@@ -498,17 +781,15 @@ impl<'a> CodeGenerator<'a> {
             Ref::Dereference(symbol, _) => symbol,
         }
         .borrow_mut();
-        if !self.assigned_variables.contains(&symbol.name) {
-            if symbol.frame_id.is_none() {
-                symbol.frame_id = Some(self.frame_size);
-                match right {
-                    Expr::Array(elements) => {
-                        self.frame_size += elements.len();
-                    }
-                    _ => self.frame_size += 1,
-                }
-            }
-            self.assigned_variables.insert(symbol.name.clone());
+        // Variables declared implicitly by their first assignment won't have a frame index yet
+        // Reserve room for the largest array the variable is ever assigned, not just this one
+        if symbol.frame_id.is_none() {
+            let count = match right {
+                Expr::Array(elements) => elements.len(),
+                _ => 1,
+            };
+            let count = count.max(symbol.array_length.unwrap_or(1));
+            symbol.frame_id = Some(self.allocate_frame_id(&symbol.name, count)?);
         }
 
         Ok(symbol.frame_id.unwrap())
@@ -548,6 +829,18 @@ impl<'a> CodeGenerator<'a> {
                 }
                 Ok(())
             }
+            Expr::Conditional(condition, then, otherwise) => {
+                let else_label = self.generate_label();
+                let end_label = self.generate_label();
+                self.convert_expr_to_opcodes(opcodes, condition)?;
+                opcodes.push(Opcode::JumpZero(else_label.clone()));
+                self.convert_expr_to_opcodes(opcodes, then)?;
+                opcodes.push(Opcode::Jump(end_label.clone()));
+                opcodes.push(Opcode::Label(else_label));
+                self.convert_expr_to_opcodes(opcodes, otherwise)?;
+                opcodes.push(Opcode::Label(end_label));
+                Ok(())
+            }
             Expr::FunctionCall(symbol, args) => {
                 let symbol = symbol.borrow();
                 for arg in args {
@@ -555,10 +848,19 @@ impl<'a> CodeGenerator<'a> {
                 }
                 match symbol.name.as_str() {
                     "negate" => opcodes.push(Opcode::IntNegate),
+                    "negatef" => opcodes.push(Opcode::FloatNegate),
                     "fix" => opcodes.push(Opcode::Fix),
                     "float" => opcodes.push(Opcode::Float),
                     "streq" => opcodes.push(Opcode::StringEquals),
                     "strne" => opcodes.push(Opcode::StringNotEquals),
+                    "exlcall" => {
+                        if !self.game.profile().exlcall {
+                            return Err(CodeGenerationError::UnsupportedExlCall(self.game));
+                        }
+                        // The call id and arguments are already on the stack, followed by the argument count
+                        opcodes.push(Opcode::IntLoad(args.len() as i32 - 1));
+                        opcodes.push(Opcode::Exlcall);
+                    }
                     _ => match self.function_to_call_id.get(&symbol.name) {
                         Some(id) => {
                             opcodes.push(Opcode::CallById(*id));
@@ -593,7 +895,15 @@ impl<'a> CodeGenerator<'a> {
                 }
                 Ok(())
             }
-            Expr::AddressOf(r) => self.convert_ref_to_opcodes(opcodes, r, ValueCategory::LValue),
+            Expr::AddressOf(r) => {
+                if let Ref::Var(symbol) = r {
+                    let symbol = symbol.borrow();
+                    if let (Some(frame_id), false) = (symbol.frame_id, symbol.global) {
+                        self.escaped_frame_ids.insert(frame_id);
+                    }
+                }
+                self.convert_ref_to_opcodes(opcodes, r, ValueCategory::LValue)
+            }
         }
     }
 
@@ -665,19 +975,24 @@ impl<'a> CodeGenerator<'a> {
     }
 }
 
-pub fn serialize(
-    script_name: &str,
+/// Generate code along with where each function's statements were written.
+pub fn generate(
     script: &Script,
     symbol_table: &SymbolTable,
     game: Game,
-    text_data: Option<CodeGenTextData>,
-) -> Result<Vec<u8>> {
-    let script_binary = CodeGenerator::serialize(script, symbol_table, game)?;
-    let result = match text_data {
-        Some(td) => {
-            exalt_assembler::assemble_with_hard_coding(&script_binary, script_name, game, td)
-        }
-        None => exalt_assembler::assemble(&script_binary, script_name, game),
-    };
-    result.map_err(|err| CodeGenerationError::BadAssembly(format!("{:?}", err)))
+    symbols: Option<&SymbolMap>,
+    reuse_frame_slots: bool,
+) -> Result<(RawScript, Vec<SourceLocations>)> {
+    CodeGenerator::serialize(script, symbol_table, game, symbols, reuse_frame_slots)
+}
+
+/// Assemble a script and report where each function's opcodes were placed.
+pub fn assemble(
+    script_name: &str,
+    script: &RawScript,
+    game: Game,
+    options: AssembleOptions,
+) -> Result<(Vec<u8>, Vec<FunctionLayout>)> {
+    exalt_assembler::assemble_with_layout(script, script_name, game, options)
+        .map_err(CodeGenerationError::BadAssembly)
 }
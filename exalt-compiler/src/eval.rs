@@ -1,10 +1,26 @@
-use crate::reporting::SemanticError;
+use crate::reporting::{SemanticError, Suggestion};
 use crate::symbol::{SymbolTable, Variable};
 use exalt_ast::surface::{Expr, Identifier, Ref};
-use exalt_ast::{Literal, Location, Operator};
+use exalt_ast::{DataType, Literal, Location, Operator};
 
 type Result<T> = std::result::Result<T, SemanticError>;
 
+/// Longest string constant folding may produce, in bytes. Keeps repetition from exhausting memory.
+pub(crate) const MAX_STRING_LENGTH: usize = 0x10000;
+
+/// Error for a variable that isn't in scope, with any close names as replacements.
+pub(crate) fn undefined_variable(
+    symbol_table: &SymbolTable,
+    identifier: &Identifier,
+) -> SemanticError {
+    let suggestions = symbol_table
+        .similar_variable_names(&identifier.value)
+        .into_iter()
+        .map(|name| Suggestion::new(identifier.location.clone(), name))
+        .collect();
+    SemanticError::UndefinedVariable(identifier.clone(), suggestions)
+}
+
 /// Evaluate a constant expression like 2 + 4 * 3
 /// Supports constants and enums which are already defined
 pub(crate) fn evaluate_const_expr(symbol_table: &SymbolTable, expr: &Expr) -> Result<Literal> {
@@ -14,11 +30,45 @@ pub(crate) fn evaluate_const_expr(symbol_table: &SymbolTable, expr: &Expr) -> Re
         Expr::EnumAccess(_, name, variant) => evaluate_enum_access(symbol_table, name, variant),
         Expr::Unary(l, e, o) => evaluate_const_unary(symbol_table, l, e, *o),
         Expr::Binary(loc, l, o, r) => evaluate_const_binary(symbol_table, loc, l, *o, r),
+        Expr::FunctionCall(l, identifier, args) if identifier.value == "float_bits" => {
+            evaluate_float_bits(symbol_table, l, args)
+        }
         Expr::FunctionCall(l, _, _) => Err(SemanticError::ExpectedConstExpr(l.clone())),
         Expr::Ref(l, r) => evaluate_const_ref(symbol_table, l, r),
         Expr::Grouped(_, e) => evaluate_const_expr(symbol_table, e),
         Expr::Increment(l, _, _, _) => Err(SemanticError::ExpectedConstExpr(l.clone())),
         Expr::AddressOf(l, _) => Err(SemanticError::ExpectedConstExpr(l.clone())),
+        Expr::Conditional(l, condition, then, otherwise) => {
+            match evaluate_const_expr(symbol_table, condition)? {
+                Literal::Int(0) => evaluate_const_expr(symbol_table, otherwise),
+                Literal::Int(_) => evaluate_const_expr(symbol_table, then),
+                other => Err(SemanticError::InvalidType(
+                    l.clone(),
+                    DataType::Int.name(),
+                    other.data_type().name(),
+                )),
+            }
+        }
+    }
+}
+
+/// Build a float from its raw bits, ex. float_bits(0x7FC00000) for NaN.
+/// Lets scripts spell floats exactly when a decimal literal can't.
+pub(crate) fn evaluate_float_bits(
+    symbol_table: &SymbolTable,
+    location: &Location,
+    args: &[Expr],
+) -> Result<Literal> {
+    if args.len() != 1 {
+        return Err(SemanticError::BadArgCount(location.clone(), 1, args.len()));
+    }
+    match evaluate_const_expr(symbol_table, &args[0])? {
+        Literal::Int(bits) => Ok(Literal::Float(f32::from_bits(bits as u32))),
+        other => Err(SemanticError::InvalidType(
+            args[0].location().clone(),
+            DataType::Int.name(),
+            other.data_type().name(),
+        )),
     }
 }
 
@@ -44,9 +94,9 @@ fn evaluate_const_unary(
 ) -> Result<Literal> {
     let operand = evaluate_const_expr(symbol_table, expr)?;
     match (operand, op) {
-        (Literal::Int(i), Operator::LogicalNot) => Ok(Literal::Int(if i == 0 { 0 } else { 1 })),
+        (Literal::Int(i), Operator::LogicalNot) => Ok(bool_literal(i == 0)),
         (Literal::Int(i), Operator::BitwiseNot) => Ok(Literal::Int(!i)),
-        (Literal::Int(i), Operator::Negate) => Ok(Literal::Int(-i)),
+        (Literal::Int(i), Operator::Negate) => Ok(Literal::Int(i.wrapping_neg())),
         (Literal::Float(f), Operator::FloatNegate) => Ok(Literal::Float(-f)),
         (operand, _) => Err(SemanticError::IncompatibleOperator(
             location.clone(),
@@ -56,6 +106,10 @@ fn evaluate_const_unary(
     }
 }
 
+fn bool_literal(value: bool) -> Literal {
+    Literal::Int(value as i32)
+}
+
 fn evaluate_const_binary(
     symbol_table: &SymbolTable,
     location: &Location,
@@ -65,6 +119,35 @@ fn evaluate_const_binary(
 ) -> Result<Literal> {
     let left = evaluate_const_expr(symbol_table, left)?;
     let right = evaluate_const_expr(symbol_table, right)?;
+    fold_binary(location, left, op, right)
+}
+
+/// Apply a binary operator to two already evaluated operands
+pub(crate) fn fold_binary(
+    location: &Location,
+    left: Literal,
+    op: Operator,
+    right: Literal,
+) -> Result<Literal> {
+    // String repetition is the only operator that mixes types
+    match (&left, op, &right) {
+        (Literal::Str(s), Operator::Multiply, Literal::Int(n))
+        | (Literal::Int(n), Operator::Multiply, Literal::Str(s)) => {
+            if *n < 0 {
+                return Err(SemanticError::NegativeRepeat(location.clone(), *n));
+            }
+            return match s.len().checked_mul(*n as usize) {
+                Some(length) if length <= MAX_STRING_LENGTH => {
+                    Ok(Literal::Str(s.repeat(*n as usize)))
+                }
+                length => Err(SemanticError::StringTooLong(
+                    location.clone(),
+                    length.unwrap_or(usize::MAX),
+                )),
+            };
+        }
+        _ => {}
+    }
     if left.data_type() != right.data_type() {
         return Err(SemanticError::IncompatibleOperands(
             location.clone(),
@@ -73,25 +156,34 @@ fn evaluate_const_binary(
         ));
     }
     match (left, op, right) {
-        (Literal::Int(l), Operator::Add, Literal::Int(r)) => Ok(Literal::Int(l + r)),
-        (Literal::Int(l), Operator::Subtract, Literal::Int(r)) => Ok(Literal::Int(l - r)),
-        (Literal::Int(l), Operator::Multiply, Literal::Int(r)) => Ok(Literal::Int(l * r)),
+        // Ints are 32 bit in the engine, so overflow wraps instead of failing
+        (Literal::Int(l), Operator::Add, Literal::Int(r)) => Ok(Literal::Int(l.wrapping_add(r))),
+        (Literal::Int(l), Operator::Subtract, Literal::Int(r)) => {
+            Ok(Literal::Int(l.wrapping_sub(r)))
+        }
+        (Literal::Int(l), Operator::Multiply, Literal::Int(r)) => {
+            Ok(Literal::Int(l.wrapping_mul(r)))
+        }
         (Literal::Int(l), Operator::Divide, Literal::Int(r)) => {
             if r == 0 {
                 Err(SemanticError::DivideByZero(location.clone()))
             } else {
-                Ok(Literal::Int(l / r))
+                Ok(Literal::Int(l.wrapping_div(r)))
             }
         }
         (Literal::Int(l), Operator::Modulo, Literal::Int(r)) => {
             if r == 0 {
                 Err(SemanticError::DivideByZero(location.clone()))
             } else {
-                Ok(Literal::Int(l % r))
+                Ok(Literal::Int(l.wrapping_rem(r)))
             }
         }
-        (Literal::Int(l), Operator::LeftShift, Literal::Int(r)) => Ok(Literal::Int(l << r)),
-        (Literal::Int(l), Operator::RightShift, Literal::Int(r)) => Ok(Literal::Int(l >> r)),
+        (Literal::Int(l), Operator::LeftShift, Literal::Int(r)) => {
+            Ok(Literal::Int(l.wrapping_shl(r as u32)))
+        }
+        (Literal::Int(l), Operator::RightShift, Literal::Int(r)) => {
+            Ok(Literal::Int(l.wrapping_shr(r as u32)))
+        }
         (Literal::Int(l), Operator::BitwiseAnd, Literal::Int(r)) => Ok(Literal::Int(l & r)),
         (Literal::Int(l), Operator::BitwiseOr, Literal::Int(r)) => Ok(Literal::Int(l | r)),
         (Literal::Int(l), Operator::Xor, Literal::Int(r)) => Ok(Literal::Int(l ^ r)),
@@ -105,6 +197,23 @@ fn evaluate_const_binary(
         } else {
             Literal::Int(0)
         }),
+        (Literal::Int(l), Operator::Equal, Literal::Int(r)) => Ok(bool_literal(l == r)),
+        (Literal::Int(l), Operator::NotEqual, Literal::Int(r)) => Ok(bool_literal(l != r)),
+        (Literal::Int(l), Operator::LessThan, Literal::Int(r)) => Ok(bool_literal(l < r)),
+        (Literal::Int(l), Operator::LessThanEqualTo, Literal::Int(r)) => Ok(bool_literal(l <= r)),
+        (Literal::Int(l), Operator::GreaterThan, Literal::Int(r)) => Ok(bool_literal(l > r)),
+        (Literal::Int(l), Operator::GreaterThanEqualTo, Literal::Int(r)) => {
+            Ok(bool_literal(l >= r))
+        }
+        (Literal::Str(l), Operator::Add, Literal::Str(r)) => {
+            if l.len() + r.len() > MAX_STRING_LENGTH {
+                return Err(SemanticError::StringTooLong(
+                    location.clone(),
+                    l.len() + r.len(),
+                ));
+            }
+            Ok(Literal::Str(l + &r))
+        }
         (Literal::Float(l), Operator::FloatAdd, Literal::Float(r)) => Ok(Literal::Float(l + r)),
         (Literal::Float(l), Operator::FloatSubtract, Literal::Float(r)) => {
             Ok(Literal::Float(l - r))
@@ -119,6 +228,19 @@ fn evaluate_const_binary(
                 Ok(Literal::Float(l / r))
             }
         }
+        // Float comparisons produce ints just like int comparisons
+        (Literal::Float(l), Operator::FloatEqual, Literal::Float(r)) => Ok(bool_literal(l == r)),
+        (Literal::Float(l), Operator::FloatNotEqual, Literal::Float(r)) => Ok(bool_literal(l != r)),
+        (Literal::Float(l), Operator::FloatLessThan, Literal::Float(r)) => Ok(bool_literal(l < r)),
+        (Literal::Float(l), Operator::FloatLessThanEqualTo, Literal::Float(r)) => {
+            Ok(bool_literal(l <= r))
+        }
+        (Literal::Float(l), Operator::FloatGreaterThan, Literal::Float(r)) => {
+            Ok(bool_literal(l > r))
+        }
+        (Literal::Float(l), Operator::FloatGreaterThanEqualTo, Literal::Float(r)) => {
+            Ok(bool_literal(l >= r))
+        }
         (left, op, _) => Err(SemanticError::IncompatibleOperator(
             location.clone(),
             left.data_type().name(),
@@ -136,9 +258,22 @@ fn evaluate_const_ref(
         Ref::Var(i) => match symbol_table.lookup_variable(&i.value) {
             Some(v) => match v {
                 Variable::Const(c) => Ok(c.borrow().value.clone()),
-                Variable::Var(_) => Err(SemanticError::ExpectedConstExpr(location.clone())),
+                Variable::ConstArray(_) | Variable::Var(_) => {
+                    Err(SemanticError::ExpectedConstExpr(location.clone()))
+                }
+            },
+            None => Err(undefined_variable(symbol_table, i)),
+        },
+        Ref::Index(i, index) => match symbol_table.lookup_variable(&i.value) {
+            Some(Variable::ConstArray(c)) => match evaluate_const_expr(symbol_table, index)? {
+                Literal::Int(n) => usize::try_from(n)
+                    .ok()
+                    .and_then(|n| c.borrow().values.get(n).cloned())
+                    .ok_or_else(|| SemanticError::ExpectedConstExpr(location.clone())),
+                _ => Err(SemanticError::ExpectedConstExpr(location.clone())),
             },
-            None => Err(SemanticError::UndefinedVariable(i.clone())),
+            Some(_) => Err(SemanticError::ExpectedConstExpr(location.clone())),
+            None => Err(undefined_variable(symbol_table, i)),
         },
         _ => Err(SemanticError::ExpectedConstExpr(location.clone())),
     }
@@ -0,0 +1,777 @@
+use std::ops::Range;
+
+use exalt_ast::surface::{
+    Annotation, AsmInstruction, CaseCondition, Decl, Expr, IncludePathComponent, Parameter, Ref,
+    Script, Stmt,
+};
+use exalt_ast::{DataType, Literal, Location, Notation, Operator};
+use itertools::Itertools;
+
+const INDENT: &str = "    ";
+
+/// Lists are broken up one item per line once a line would grow past this many characters.
+const MAX_WIDTH: usize = 100;
+
+fn span(location: &Location) -> Range<usize> {
+    match location {
+        Location::Source(_, range) => range.clone(),
+        _ => 0..0,
+    }
+}
+
+/// Find the byte ranges of every comment in a script.
+pub(crate) fn find_comments(source: &str) -> Vec<Range<usize>> {
+    let mut comments = Vec::new();
+    let mut chars = source.char_indices().peekable();
+    while let Some((i, c)) = chars.next() {
+        let is_comment = match c {
+            '"' => {
+                while let Some((_, c)) = chars.next() {
+                    match c {
+                        '\\' => {
+                            chars.next();
+                        }
+                        '"' => break,
+                        _ => {}
+                    }
+                }
+                false
+            }
+            '/' => matches!(chars.peek(), Some((_, '/'))),
+            '#' => true,
+            _ => false,
+        };
+        if is_comment {
+            let end = source[i..]
+                .find('\n')
+                .map(|j| i + j)
+                .unwrap_or(source.len());
+            while matches!(chars.peek(), Some((j, _)) if *j < end) {
+                chars.next();
+            }
+            comments.push(i..end);
+        }
+    }
+    comments
+}
+
+fn is_block_like(decl: &Decl) -> bool {
+    matches!(
+        decl,
+        Decl::Function { .. }
+            | Decl::Inline { .. }
+            | Decl::Callback { .. }
+            | Decl::Enum { .. }
+            | Decl::Namespace { .. }
+    )
+}
+
+fn data_type(data_type: &DataType) -> &'static str {
+    match data_type {
+        DataType::Int => "int",
+        DataType::Float => "float",
+        DataType::Str => "string",
+        DataType::Any => "any",
+    }
+}
+
+fn parameters(parameters: &[Parameter]) -> String {
+    parameters
+        .iter()
+        .map(|p| match &p.data_type {
+            Some(t) => format!("{}: {}", p.identifier.value, data_type(t)),
+            None => p.identifier.value.clone(),
+        })
+        .join(", ")
+}
+
+fn return_type(return_type: &Option<DataType>) -> String {
+    match return_type {
+        Some(t) => format!(" -> {}", data_type(t)),
+        None => String::new(),
+    }
+}
+
+/// Pretty prints a parsed script using its source to recover comments and literal spellings.
+struct Printer<'a> {
+    source: &'a str,
+    comments: Vec<Range<usize>>,
+    next_comment: usize,
+    /// End of the last piece of source that was written.
+    last_end: usize,
+    output: String,
+}
+
+impl<'a> Printer<'a> {
+    fn new(source: &'a str) -> Self {
+        Printer {
+            source,
+            comments: find_comments(source),
+            next_comment: 0,
+            last_end: 0,
+            output: String::new(),
+        }
+    }
+
+    fn indent(&mut self, indent: usize) {
+        for _ in 0..indent {
+            self.output.push_str(INDENT);
+        }
+    }
+
+    fn column(&self) -> usize {
+        let line = self.output.rsplit('\n').next().unwrap_or_default();
+        line.chars().count()
+    }
+
+    fn fits(&self, text: &str) -> bool {
+        self.column() + text.chars().count() <= MAX_WIDTH
+    }
+
+    fn has_blank_line(&self, from: usize, to: usize) -> bool {
+        let lines: Vec<&str> = self.source[from.min(to)..to].split('\n').collect();
+        lines.len() > 2
+            && lines[1..lines.len() - 1]
+                .iter()
+                .any(|l| l.trim().is_empty())
+    }
+
+    /// Write a blank line if the source had one or one is required.
+    /// Never writes one at the start of a block or the script.
+    fn separate(&mut self, from: usize, to: usize, required: bool) {
+        let at_start = self.output.is_empty() || self.output.ends_with("{\n");
+        if !at_start
+            && !self.output.ends_with("\n\n")
+            && (required || self.has_blank_line(from, to))
+        {
+            self.output.push('\n');
+        }
+    }
+
+    /// Write every comment that starts before an offset.
+    /// Comments after code on the same line stay at the end of that line.
+    /// Returns whether a required blank line still needs to be written.
+    fn comments_before(&mut self, limit: usize, indent: usize, mut blank: bool) -> bool {
+        while let Some(range) = self.comments.get(self.next_comment).cloned() {
+            if range.start >= limit {
+                break;
+            }
+            self.next_comment += 1;
+            let from = self.last_end.min(range.start);
+            let text = self.source[range.clone()].trim_end();
+            let trailing = !self.source[from..range.start].contains('\n');
+            if trailing && self.output.ends_with('\n') {
+                self.output.pop();
+                self.output.push_str("  ");
+                self.output.push_str(text);
+                self.output.push('\n');
+            } else {
+                self.separate(from, range.start, blank);
+                blank = false;
+                self.indent(indent);
+                self.output.push_str(text);
+                self.output.push('\n');
+            }
+            self.last_end = range.end;
+        }
+        blank
+    }
+
+    /// Write comments and spacing for a node starting at an offset.
+    /// Comments up to the end of the node's head are written before it so they can't end up mid line.
+    fn begin(&mut self, start: usize, head_end: usize, indent: usize, blank: bool) {
+        let blank = self.comments_before(head_end, indent, blank);
+        self.separate(self.last_end, start, blank);
+        self.indent(indent);
+    }
+
+    fn literal(&self, location: &Location, literal: &Literal) -> String {
+        let slice = &self.source[span(location)];
+        match literal {
+            // Keep hex, octal, and binary spellings
+            Literal::Int(_)
+                if slice.starts_with("0x")
+                    || slice.starts_with("0o")
+                    || slice.starts_with("0b") =>
+            {
+                slice.to_owned()
+            }
+            Literal::Int(v) => v.to_string(),
+            // Keep escapes as they were written
+            Literal::Float(_) | Literal::Str(_) => slice.to_owned(),
+        }
+    }
+
+    fn reference(&self, reference: &Ref) -> String {
+        match reference {
+            Ref::Var(identifier) => identifier.value.clone(),
+            Ref::Index(identifier, index) => format!("{}[{}]", identifier.value, self.expr(index)),
+            Ref::Dereference(identifier, None) => format!("*{}", identifier.value),
+            Ref::Dereference(identifier, Some(index)) => {
+                format!("*{}[{}]", identifier.value, self.expr(index))
+            }
+        }
+    }
+
+    fn exprs(&self, exprs: &[Expr]) -> Vec<String> {
+        exprs.iter().map(|e| self.expr(e)).collect()
+    }
+
+    fn expr(&self, expr: &Expr) -> String {
+        match expr {
+            Expr::Array(_, values) => format!("[{}]", self.exprs(values).join(", ")),
+            Expr::Literal(location, literal) => self.literal(location, literal),
+            Expr::EnumAccess(_, identifier, variant) => {
+                format!("{}.{}", identifier.value, variant.value)
+            }
+            Expr::Unary(_, operand, op) => {
+                let operand = self.expr(operand);
+                // Keep "- -1" from turning into a decrement and "-f x" from running together
+                if operand.starts_with('-') || matches!(op, Operator::FloatNegate) {
+                    format!("{} {}", op, operand)
+                } else {
+                    format!("{}{}", op, operand)
+                }
+            }
+            Expr::Binary(_, left, op, right) => {
+                format!("{} {} {}", self.expr(left), op, self.expr(right))
+            }
+            Expr::FunctionCall(_, identifier, args) => {
+                format!("{}({})", identifier.value, self.exprs(args).join(", "))
+            }
+            Expr::Ref(_, reference) => self.reference(reference),
+            Expr::Grouped(_, inner) => format!("({})", self.expr(inner)),
+            Expr::Increment(_, reference, op, Notation::Prefix) => {
+                format!("{}{}", op, self.reference(reference))
+            }
+            Expr::Increment(_, reference, op, Notation::Postfix) => {
+                format!("{}{}", self.reference(reference), op)
+            }
+            Expr::AddressOf(_, reference) => format!("&{}", self.reference(reference)),
+            Expr::Conditional(_, condition, then, otherwise) => format!(
+                "{} ? {} : {}",
+                self.expr(condition),
+                self.expr(then),
+                self.expr(otherwise)
+            ),
+        }
+    }
+
+    /// Write a list of items, breaking it up one item per line if it doesn't fit.
+    fn list(&mut self, open: &str, items: &[String], close: &str, indent: usize) {
+        let flat = format!("{}{}{}", open, items.join(", "), close);
+        if items.is_empty() || self.fits(&flat) {
+            self.output.push_str(&flat);
+            return;
+        }
+        self.output.push_str(open);
+        self.output.push('\n');
+        for item in items {
+            self.indent(indent + 1);
+            self.output.push_str(item);
+            self.output.push_str(",\n");
+        }
+        self.indent(indent);
+        self.output.push_str(close);
+    }
+
+    /// Write an expression, breaking up its arguments or values if it's too long.
+    fn wrapped_expr(&mut self, expr: &Expr, suffix: &str, indent: usize) {
+        match expr {
+            Expr::FunctionCall(_, identifier, args) => {
+                let args = self.exprs(args);
+                self.list(
+                    &format!("{}(", identifier.value),
+                    &args,
+                    &format!("){}", suffix),
+                    indent,
+                );
+            }
+            Expr::Array(_, values) => {
+                let values = self.exprs(values);
+                self.list("[", &values, &format!("]{}", suffix), indent);
+            }
+            _ => {
+                let text = self.expr(expr);
+                self.output.push_str(&text);
+                self.output.push_str(suffix);
+            }
+        }
+    }
+
+    /// Assignments and expression statements without their semicolon, as they appear in for loops.
+    fn simple_stmt(&self, stmt: &Stmt) -> String {
+        match stmt {
+            Stmt::Assignment {
+                left, op, right, ..
+            } => {
+                format!("{} {} {}", self.reference(left), op, self.expr(right))
+            }
+            Stmt::ExprStmt(_, expr) => self.expr(expr),
+            _ => String::new(),
+        }
+    }
+
+    /// Where comments stop being written ahead of a statement.
+    fn head_end(stmt: &Stmt) -> usize {
+        match stmt {
+            Stmt::Block(location, _) | Stmt::Asm(location, _) => span(location).start,
+            Stmt::If {
+                then_part: body, ..
+            }
+            | Stmt::While { body, .. }
+            | Stmt::DoWhile { body, .. }
+            | Stmt::For { body, .. } => span(body.location()).start,
+            Stmt::Match {
+                location,
+                cases,
+                default,
+                ..
+            } => cases
+                .first()
+                .map(|case| match &case.conditions[0] {
+                    CaseCondition::Value(expr) => span(expr.location()).start,
+                    CaseCondition::Range(location, _, _) => span(location).start,
+                })
+                .or_else(|| default.as_ref().map(|d| span(d.location()).start))
+                .unwrap_or(span(location).end),
+            _ => span(stmt.location()).end,
+        }
+    }
+
+    fn block(&mut self, location: &Location, stmts: &[Stmt], indent: usize) {
+        let range = span(location);
+        let has_comments = matches!(
+            self.comments.get(self.next_comment),
+            Some(comment) if comment.start < range.end
+        );
+        if stmts.is_empty() && !has_comments {
+            self.output.push_str("{}");
+            self.last_end = range.end;
+            return;
+        }
+        self.output.push_str("{\n");
+        self.last_end = range.start + 1;
+        for stmt in stmts {
+            let start = span(stmt.location()).start;
+            self.begin(start, Self::head_end(stmt), indent + 1, false);
+            self.stmt(stmt, indent + 1);
+            self.output.push('\n');
+            self.last_end = self.last_end.max(span(stmt.location()).end);
+        }
+        self.comments_before(range.end.saturating_sub(1), indent + 1, false);
+        self.indent(indent);
+        self.output.push('}');
+        self.last_end = range.end;
+    }
+
+    fn asm(&mut self, location: &Location, instructions: &[AsmInstruction], indent: usize) {
+        let range = span(location);
+        self.output.push_str("asm {\n");
+        for instruction in instructions {
+            let instruction_range = span(&instruction.location);
+            self.begin(
+                instruction_range.start,
+                instruction_range.end,
+                indent + 1,
+                false,
+            );
+            if instruction.args.is_empty() {
+                self.output.push_str(&instruction.identifier.value);
+                self.output.push(';');
+            } else {
+                let args = self.exprs(&instruction.args);
+                let open = format!("{}(", instruction.identifier.value);
+                self.list(&open, &args, ");", indent + 1);
+            }
+            self.output.push('\n');
+            self.last_end = instruction_range.end;
+        }
+        self.comments_before(range.end.saturating_sub(1), indent + 1, false);
+        self.indent(indent);
+        self.output.push('}');
+        self.last_end = range.end;
+    }
+
+    fn printf(&mut self, args: &[Expr], indent: usize) {
+        let mut items = Vec::new();
+        let mut placeholders: Option<Range<usize>> = None;
+        for (i, arg) in args.iter().enumerate() {
+            let range = span(arg.location());
+            match arg {
+                // Write interpolated formats as they were written instead of their desugared form
+                Expr::Literal(_, Literal::Str(format)) if i == 0 => {
+                    let slice = &self.source[range.clone()];
+                    if slice[1..slice.len() - 1] != *format {
+                        placeholders = Some(range);
+                    }
+                    items.push(slice.to_owned());
+                }
+                _ if matches!(&placeholders, Some(p) if p.start <= range.start && range.end <= p.end) =>
+                    {}
+                _ => items.push(self.expr(arg)),
+            }
+        }
+        self.list("printf(", &items, ");", indent);
+    }
+
+    fn stmt(&mut self, stmt: &Stmt, indent: usize) {
+        match stmt {
+            Stmt::Assignment {
+                left, op, right, ..
+            } => {
+                let left = format!("{} {} ", self.reference(left), op);
+                self.output.push_str(&left);
+                self.wrapped_expr(right, ";", indent);
+            }
+            Stmt::Block(location, stmts) => self.block(location, stmts, indent),
+            Stmt::Break(_) => self.output.push_str("break;"),
+            Stmt::Continue(_) => self.output.push_str("continue;"),
+            Stmt::DoWhile {
+                body, condition, ..
+            } => {
+                self.output.push_str("do ");
+                self.stmt(body, indent);
+                let condition = format!(" while ({});", self.expr(condition));
+                self.output.push_str(&condition);
+            }
+            Stmt::ExprStmt(_, expr) => self.wrapped_expr(expr, ";", indent),
+            Stmt::Fallthrough(_) => self.output.push_str("fallthrough;"),
+            Stmt::For {
+                init,
+                check,
+                step,
+                body,
+                ..
+            } => {
+                let head = format!(
+                    "for ({}; {}; {}) ",
+                    self.simple_stmt(init),
+                    self.expr(check),
+                    self.simple_stmt(step)
+                );
+                self.output.push_str(&head);
+                self.stmt(body, indent);
+            }
+            Stmt::Goto(_, identifier) => {
+                self.output.push_str(&format!("goto {};", identifier.value))
+            }
+            Stmt::If {
+                condition,
+                then_part,
+                else_part,
+                ..
+            } => {
+                let head = format!("if ({}) ", self.expr(condition));
+                self.output.push_str(&head);
+                self.stmt(then_part, indent);
+                if let Some(else_part) = else_part {
+                    if let Stmt::Block(..) = then_part.as_ref() {
+                        self.output.push(' ');
+                    } else {
+                        self.output.push('\n');
+                        self.indent(indent);
+                    }
+                    self.output.push_str("else ");
+                    self.stmt(else_part, indent);
+                }
+            }
+            Stmt::Label(_, identifier) => self
+                .output
+                .push_str(&format!("label {};", identifier.value)),
+            Stmt::Asm(location, instructions) => self.asm(location, instructions, indent),
+            Stmt::Match {
+                location,
+                switch,
+                cases,
+                default,
+            } => {
+                let head = format!("match ({}) {{\n", self.expr(switch));
+                self.output.push_str(&head);
+                for case in cases {
+                    let conditions = case
+                        .conditions
+                        .iter()
+                        .map(|condition| match condition {
+                            CaseCondition::Value(expr) => self.expr(expr),
+                            CaseCondition::Range(_, start, end) => {
+                                format!("{}..{}", self.expr(start), self.expr(end))
+                            }
+                        })
+                        .join(", ");
+                    let start = match &case.conditions[0] {
+                        CaseCondition::Value(expr) => span(expr.location()).start,
+                        CaseCondition::Range(location, _, _) => span(location).start,
+                    };
+                    self.begin(start, start, indent + 1, false);
+                    self.output.push_str(&conditions);
+                    self.output.push_str(" -> ");
+                    self.stmt(&case.body, indent + 1);
+                    self.output.push('\n');
+                }
+                if let Some(default) = default {
+                    let start = span(default.location()).start;
+                    self.begin(start, start, indent + 1, false);
+                    self.output.push_str("else -> ");
+                    self.stmt(default, indent + 1);
+                    self.output.push('\n');
+                }
+                self.comments_before(span(location).end.saturating_sub(1), indent + 1, false);
+                self.indent(indent);
+                self.output.push('}');
+            }
+            Stmt::Printf(_, args) => self.printf(args, indent),
+            Stmt::Return(_, None) => self.output.push_str("return;"),
+            Stmt::Return(_, Some(value)) => {
+                self.output.push_str("return ");
+                self.wrapped_expr(value, ";", indent);
+            }
+            Stmt::VarDecl(_, identifier, None) => {
+                self.output.push_str(&format!("let {};", identifier.value))
+            }
+            Stmt::VarDecl(_, identifier, Some(count)) => {
+                let text = format!("let {}[{}];", identifier.value, self.expr(count));
+                self.output.push_str(&text);
+            }
+            Stmt::While {
+                condition, body, ..
+            } => {
+                let head = format!("while ({}) ", self.expr(condition));
+                self.output.push_str(&head);
+                self.stmt(body, indent);
+            }
+            Stmt::Yield(_) => self.output.push_str("yield;"),
+        }
+    }
+
+    fn annotations(&mut self, annotations: &[Annotation], indent: usize) {
+        for annotation in annotations {
+            self.output.push('@');
+            self.output.push_str(&annotation.identifier.value);
+            if !annotation.args.is_empty() {
+                let args = self.exprs(&annotation.args);
+                self.list("(", &args, ")", indent);
+            }
+            self.output.push('\n');
+            self.indent(indent);
+        }
+    }
+
+    fn decl_start(decl: &Decl) -> usize {
+        let annotations = match decl {
+            Decl::Constant { annotations, .. }
+            | Decl::Function { annotations, .. }
+            | Decl::Callback { annotations, .. } => annotations.as_slice(),
+            _ => &[],
+        };
+        annotations
+            .first()
+            .map(|a| span(&a.location).start)
+            .unwrap_or_else(|| span(decl.location()).start)
+    }
+
+    fn decl_end(decl: &Decl) -> usize {
+        match decl {
+            Decl::Function { body, .. } | Decl::Inline { body, .. } => span(body.location()).end,
+            _ => span(decl.location()).end,
+        }
+    }
+
+    fn decl_head_end(decl: &Decl) -> usize {
+        match decl {
+            Decl::Function { body, .. }
+            | Decl::Inline { body, .. }
+            | Decl::Callback { body, .. } => span(body.location()).start,
+            Decl::Enum { identifier, .. } | Decl::Namespace { identifier, .. } => {
+                span(&identifier.location).end
+            }
+            _ => Self::decl_end(decl),
+        }
+    }
+
+    fn decls(&mut self, decls: &[Decl], indent: usize) {
+        for (i, decl) in decls.iter().enumerate() {
+            let separate = i > 0 && (is_block_like(&decls[i - 1]) || is_block_like(decl));
+            self.begin(
+                Self::decl_start(decl),
+                Self::decl_head_end(decl),
+                indent,
+                separate,
+            );
+            self.decl(decl, indent);
+            self.output.push('\n');
+            self.last_end = self.last_end.max(Self::decl_end(decl));
+        }
+    }
+
+    fn decl(&mut self, decl: &Decl, indent: usize) {
+        match decl {
+            Decl::Constant {
+                annotations,
+                identifier,
+                value,
+                ..
+            } => {
+                self.annotations(annotations, indent);
+                self.output
+                    .push_str(&format!("const {} = ", identifier.value));
+                self.wrapped_expr(value, ";", indent);
+            }
+            Decl::Enum {
+                location,
+                identifier,
+                variants,
+            } => {
+                self.output
+                    .push_str(&format!("enum {} {{\n", identifier.value));
+                self.last_end = span(&identifier.location).end;
+                for variant in variants {
+                    let range = span(&variant.location);
+                    self.begin(range.start, range.end, indent + 1, false);
+                    let text = format!(
+                        "{} = {},",
+                        variant.identifier.value,
+                        self.expr(&variant.value)
+                    );
+                    self.output.push_str(&text);
+                    self.output.push('\n');
+                    self.last_end = range.end;
+                }
+                self.comments_before(span(location).end.saturating_sub(1), indent + 1, false);
+                self.indent(indent);
+                self.output.push('}');
+            }
+            Decl::Function {
+                annotations,
+                identifier,
+                parameters: params,
+                return_type: ret,
+                body,
+                ..
+            } => {
+                self.annotations(annotations, indent);
+                let head = format!(
+                    "def {}({}){} ",
+                    identifier.value,
+                    parameters(params),
+                    return_type(ret)
+                );
+                self.output.push_str(&head);
+                self.stmt(body, indent);
+            }
+            Decl::Global {
+                identifier,
+                count,
+                index,
+                ..
+            } => {
+                let count = count
+                    .as_ref()
+                    .map(|count| format!("[{}]", self.expr(count)))
+                    .unwrap_or_default();
+                let index = index
+                    .as_ref()
+                    .map(|index| format!(" @ {}", self.expr(index)))
+                    .unwrap_or_default();
+                let text = format!("let {}{}{};", identifier.value, count, index);
+                self.output.push_str(&text);
+            }
+            Decl::Inline {
+                identifier,
+                parameters: params,
+                body,
+                ..
+            } => {
+                let head = format!("inline def {}({}) ", identifier.value, parameters(params));
+                self.output.push_str(&head);
+                self.stmt(body, indent);
+            }
+            Decl::Callback {
+                annotations,
+                event_types,
+                args,
+                body,
+                ..
+            } => {
+                self.annotations(annotations, indent);
+                let open = format!("callback[{}](", self.exprs(event_types).join(", "));
+                let args = self.exprs(args);
+                self.list(&open, &args, ") ", indent);
+                self.stmt(body, indent);
+            }
+            Decl::Include { path, .. } => {
+                let path = path
+                    .iter()
+                    .map(|component| match component {
+                        IncludePathComponent::Node(name) => name.as_str(),
+                        IncludePathComponent::Parent => "..",
+                    })
+                    .join(":");
+                self.output.push_str(&format!("include {};", path));
+            }
+            Decl::FunctionAlias {
+                identifier, alias, ..
+            } => {
+                let text = format!("alias def {} -> {};", identifier.value, alias.value);
+                self.output.push_str(&text);
+            }
+            Decl::FunctionExtern {
+                identifier,
+                parameters: params,
+                return_type: ret,
+                ..
+            } => {
+                let text = format!(
+                    "extern def {}({}){};",
+                    identifier.value,
+                    parameters(params),
+                    return_type(ret)
+                );
+                self.output.push_str(&text);
+            }
+            Decl::GlobalExtern {
+                identifier,
+                count,
+                index,
+                ..
+            } => {
+                let count = count
+                    .as_ref()
+                    .map(|count| format!("[{}]", self.expr(count)))
+                    .unwrap_or_default();
+                let text = format!(
+                    "extern let {}{} @ {};",
+                    identifier.value,
+                    count,
+                    self.expr(index)
+                );
+                self.output.push_str(&text);
+            }
+            Decl::Namespace {
+                location,
+                identifier,
+                decls,
+            } => {
+                self.output
+                    .push_str(&format!("namespace {} {{\n", identifier.value));
+                self.last_end = span(&identifier.location).end;
+                self.decls(decls, indent + 1);
+                self.comments_before(span(location).end.saturating_sub(1), indent + 1, false);
+                self.indent(indent);
+                self.output.push('}');
+            }
+        }
+    }
+}
+
+/// Pretty print a script with canonical indentation, spacing, and line breaks.
+/// The script must be parsed from the given source, which is used to keep comments and literal spellings.
+pub fn format_script(script: &Script, source: &str) -> String {
+    let mut printer = Printer::new(source);
+    printer.decls(&script.0, 0);
+    printer.comments_before(usize::MAX, 0, false);
+    let mut output = printer.output;
+    output.truncate(output.trim_end().len());
+    output.push('\n');
+    output
+}
@@ -0,0 +1,382 @@
+use std::path::{Path, PathBuf};
+
+use exalt_ast::{
+    ConstArraySymbol, ConstSymbol, DataType, EnumSymbol, FunctionSymbol, Literal, Location,
+    VarSymbol,
+};
+use indexmap::IndexMap;
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use crate::symbol::{SymbolTable, Variable};
+use crate::CompilerLog;
+
+const MAGIC: &[u8; 4] = b"EXLH";
+const VERSION: u32 = 2;
+
+#[derive(Debug, Error)]
+pub enum HeaderError {
+    #[error("not a precompiled header")]
+    BadMagic,
+
+    #[error(
+        "precompiled header version {0} is not supported (expected {})",
+        VERSION
+    )]
+    UnsupportedVersion(u32),
+
+    #[error("precompiled header is corrupt: {0}")]
+    Corrupt(#[from] bincode::Error),
+
+    #[error("'{0}' has changed since the header was compiled")]
+    OutOfDate(PathBuf),
+
+    #[error(transparent)]
+    IOError(#[from] std::io::Error),
+}
+
+#[derive(Serialize, Deserialize)]
+struct SavedFile {
+    name: String,
+    source: String,
+}
+
+#[derive(Serialize, Deserialize)]
+struct SavedConst {
+    name: String,
+    location: Location,
+    value: Literal,
+}
+
+#[derive(Serialize, Deserialize)]
+enum SavedVariable {
+    Const(SavedConst),
+    ConstArray {
+        name: String,
+        location: Location,
+        values: Vec<Literal>,
+    },
+    Global {
+        name: String,
+        location: Location,
+        frame_id: Option<usize>,
+        array_length: Option<usize>,
+        data_type: Option<DataType>,
+    },
+}
+
+#[derive(Serialize, Deserialize)]
+struct SavedEnum {
+    name: String,
+    location: Location,
+    variants: Vec<SavedConst>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct SavedFunction {
+    name: String,
+    location: Location,
+    arity: usize,
+    alias: Option<String>,
+    allow_redefinition: bool,
+    parameter_types: Vec<DataType>,
+    return_type: Option<DataType>,
+    docs: Option<String>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct SavedAlias {
+    name: String,
+    location: Location,
+    alias: String,
+    docs: Option<String>,
+}
+
+/// Declarations from a header script saved so they can be loaded without parsing or analysis.
+///
+/// The sources the header was built from are saved with it so diagnostics can still point into them.
+/// Locations in the saved symbols refer to those files by index, and the first file is the header itself.
+#[derive(Serialize, Deserialize)]
+pub struct PrecompiledHeader {
+    files: Vec<SavedFile>,
+    variables: Vec<SavedVariable>,
+    enums: Vec<SavedEnum>,
+    functions: Vec<SavedFunction>,
+    aliases: Vec<SavedAlias>,
+
+    // Where the header's own source lives, known once the header is loaded from disk
+    #[serde(skip)]
+    source_path: Option<PathBuf>,
+}
+
+impl PrecompiledHeader {
+    /// Save the declarations in a symbol table. Every file in the log is saved as a source.
+    pub(crate) fn new(symbol_table: &SymbolTable, log: &CompilerLog) -> Self {
+        let files = (0..log.peek_file_id())
+            .map(|file_id| SavedFile {
+                name: log.file(file_id).unwrap_or_default(),
+                source: log.source(file_id).unwrap_or_default(),
+            })
+            .collect();
+        let variables = symbol_table
+            .global_variables()
+            .into_iter()
+            .map(|v| match v {
+                Variable::Const(c) => SavedVariable::Const(save_const(&c.borrow())),
+                Variable::ConstArray(c) => {
+                    let c = c.borrow();
+                    SavedVariable::ConstArray {
+                        name: c.name.clone(),
+                        location: c.location.clone(),
+                        values: c.values.clone(),
+                    }
+                }
+                Variable::Var(v) => {
+                    let v = v.borrow();
+                    SavedVariable::Global {
+                        name: v.name.clone(),
+                        location: v.location.clone(),
+                        frame_id: v.frame_id,
+                        array_length: v.array_length,
+                        data_type: v.data_type,
+                    }
+                }
+            })
+            .collect();
+        let enums = symbol_table
+            .enums()
+            .into_iter()
+            .map(|e| {
+                let e = e.borrow();
+                SavedEnum {
+                    name: e.name.clone(),
+                    location: e.location.clone(),
+                    variants: e.variants.values().map(save_const).collect(),
+                }
+            })
+            .collect();
+        // Built in functions are already in every table and unknown calls aren't declarations
+        let functions = symbol_table
+            .functions()
+            .into_iter()
+            .filter(|f| {
+                !matches!(
+                    f.borrow().location,
+                    Location::Generated | Location::External
+                )
+            })
+            .map(|f| {
+                let f = f.borrow();
+                SavedFunction {
+                    name: f.name.clone(),
+                    location: f.location.clone(),
+                    arity: f.arity,
+                    alias: f.alias.clone(),
+                    allow_redefinition: f.allow_redefinition,
+                    parameter_types: f.parameter_types.clone(),
+                    return_type: f.return_type,
+                    docs: f.docs.clone(),
+                }
+            })
+            .collect();
+        let aliases = symbol_table
+            .alias_declarations()
+            .into_iter()
+            .map(|(name, location, alias)| SavedAlias {
+                docs: symbol_table.alias_docs(&name).map(|d| d.to_owned()),
+                name,
+                location,
+                alias,
+            })
+            .collect();
+        PrecompiledHeader {
+            files,
+            variables,
+            enums,
+            functions,
+            aliases,
+            source_path: None,
+        }
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, HeaderError> {
+        if bytes.len() < 8 || &bytes[..4] != MAGIC {
+            return Err(HeaderError::BadMagic);
+        }
+        let version = u32::from_le_bytes([bytes[4], bytes[5], bytes[6], bytes[7]]);
+        if version != VERSION {
+            return Err(HeaderError::UnsupportedVersion(version));
+        }
+        Ok(bincode::deserialize(&bytes[8..])?)
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = MAGIC.to_vec();
+        bytes.extend(VERSION.to_le_bytes());
+        bytes.extend(bincode::serialize(self).unwrap_or_default());
+        bytes
+    }
+
+    /// Load the header for a script (ex. prelude.exlh for prelude.exl).
+    /// Fails if any of the sources it was built from have changed since.
+    pub fn load(source_path: &Path) -> Result<Self, HeaderError> {
+        let bytes = std::fs::read(source_path.with_extension("exlh"))?;
+        let mut header = Self::from_bytes(&bytes)?;
+        header.source_path = Some(source_path.to_path_buf());
+        for (path, file) in header.paths().iter().zip(&header.files) {
+            match std::fs::read_to_string(path) {
+                Ok(source) if source == file.source => {}
+                _ => return Err(HeaderError::OutOfDate(path.clone())),
+            }
+        }
+        Ok(header)
+    }
+
+    /// Paths of the sources the header was built from, starting with the header itself.
+    /// Only known for headers loaded from disk.
+    pub fn paths(&self) -> Vec<PathBuf> {
+        let source_path = match &self.source_path {
+            Some(path) => path,
+            None => return Vec::new(),
+        };
+        // Included files are always registered under their full path
+        std::iter::once(source_path.clone())
+            .chain(self.files.iter().skip(1).map(|f| PathBuf::from(&f.name)))
+            .collect()
+    }
+
+    /// Sources the header was built from and their contents when it was built.
+    pub(crate) fn sources(&self) -> Vec<(PathBuf, &str)> {
+        self.paths()
+            .into_iter()
+            .zip(self.files.iter().map(|f| f.source.as_str()))
+            .collect()
+    }
+
+    /// Register the header's sources with a log and define its symbols in a table.
+    pub fn define_symbols(&self, symbol_table: &mut SymbolTable, log: &mut CompilerLog) {
+        self.define_symbols_with(symbol_table, log, |log, name, source| {
+            log.add(name.to_owned(), source.to_owned())
+        });
+    }
+
+    /// Same as define_symbols, but the caller decides which file id each source gets
+    /// (ex. to reuse the id a cache already gave it).
+    pub(crate) fn define_symbols_with(
+        &self,
+        symbol_table: &mut SymbolTable,
+        log: &mut CompilerLog,
+        mut register: impl FnMut(&mut CompilerLog, &str, &str) -> usize,
+    ) {
+        let paths = self.paths();
+        let file_ids: Vec<usize> = self
+            .files
+            .iter()
+            .enumerate()
+            .map(|(i, file)| {
+                let name = match paths.get(i) {
+                    Some(path) => path.to_string_lossy().to_string(),
+                    None => file.name.clone(),
+                };
+                register(log, &name, &file.source)
+            })
+            .collect();
+        let location = |location: &Location| match location {
+            Location::Source(index, range) => match file_ids.get(*index) {
+                Some(file_id) => Location::Source(*file_id, range.clone()),
+                None => Location::Generated,
+            },
+            other => other.clone(),
+        };
+        let load_const = |c: &SavedConst| {
+            ConstSymbol::new(c.name.clone(), location(&c.location), c.value.clone())
+        };
+
+        let mut results = Vec::new();
+        for variable in &self.variables {
+            let (name, variable) = match variable {
+                SavedVariable::Const(c) => (c.name.clone(), Variable::Const(shared(load_const(c)))),
+                SavedVariable::ConstArray {
+                    name,
+                    location: l,
+                    values,
+                } => {
+                    let symbol =
+                        ConstArraySymbol::new(name.clone(), location(l), values.clone(), None);
+                    (name.clone(), Variable::ConstArray(shared(symbol)))
+                }
+                SavedVariable::Global {
+                    name,
+                    location: l,
+                    frame_id,
+                    array_length,
+                    data_type,
+                } => {
+                    let mut symbol = VarSymbol::new(name.clone(), location(l), true);
+                    symbol.frame_id = *frame_id;
+                    symbol.array_length = *array_length;
+                    symbol.data_type = *data_type;
+                    (name.clone(), Variable::Var(shared(symbol)))
+                }
+            };
+            results.push(symbol_table.define_variable(name, variable));
+        }
+        for e in &self.enums {
+            let variants: IndexMap<String, ConstSymbol> = e
+                .variants
+                .iter()
+                .map(|v| (v.name.clone(), load_const(v)))
+                .collect();
+            let symbol = EnumSymbol::new(e.name.clone(), location(&e.location), variants);
+            results.push(symbol_table.define_enum(e.name.clone(), shared(symbol)));
+        }
+        for f in &self.functions {
+            let symbol = FunctionSymbol::shared(
+                f.name.clone(),
+                location(&f.location),
+                f.arity,
+                f.alias.clone(),
+                f.allow_redefinition,
+            );
+            {
+                let mut symbol = symbol.borrow_mut();
+                symbol.parameter_types = f.parameter_types.clone();
+                symbol.return_type = f.return_type;
+                symbol.docs = f.docs.clone();
+            }
+            results.push(symbol_table.define_function(f.name.clone(), symbol));
+        }
+        for a in &self.aliases {
+            results.push(symbol_table.define_alias(
+                a.name.clone(),
+                a.alias.clone(),
+                location(&a.location),
+            ));
+            if let Some(docs) = &a.docs {
+                symbol_table.document_alias(a.name.clone(), docs.clone());
+            }
+        }
+        for err in results.into_iter().filter_map(|r| r.err()) {
+            log.log_error(err.into());
+        }
+    }
+
+    /// A fresh symbol table holding only the header's declarations.
+    pub fn to_symbol_table(&self) -> SymbolTable {
+        let mut symbol_table = SymbolTable::new();
+        self.define_symbols(&mut symbol_table, &mut CompilerLog::new());
+        symbol_table
+    }
+}
+
+fn shared<T>(value: T) -> exalt_ast::Shared<T> {
+    std::rc::Rc::new(std::cell::RefCell::new(value))
+}
+
+fn save_const(symbol: &ConstSymbol) -> SavedConst {
+    SavedConst {
+        name: symbol.name.clone(),
+        location: symbol.location.clone(),
+        value: symbol.value.clone(),
+    }
+}
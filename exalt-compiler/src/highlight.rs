@@ -0,0 +1,95 @@
+use std::ops::Range;
+
+use logos::Logos;
+
+use crate::format::find_comments;
+use crate::lexer::Token;
+
+/// Broad categories of tokens for syntax highlighting.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TokenClass {
+    Keyword,
+    Identifier,
+    Number,
+    String,
+    Comment,
+    /// Operators and punctuation.
+    Operator,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ClassifiedToken {
+    pub span: Range<usize>,
+    pub class: TokenClass,
+}
+
+fn classify(token: Token) -> Option<TokenClass> {
+    match token {
+        Token::Alias
+        | Token::Array
+        | Token::Asm
+        | Token::Break
+        | Token::Event
+        | Token::Const
+        | Token::Continue
+        | Token::Do
+        | Token::Func
+        | Token::Else
+        | Token::Enum
+        | Token::Extern
+        | Token::Fallthrough
+        | Token::For
+        | Token::Goto
+        | Token::Include
+        | Token::Inline
+        | Token::If
+        | Token::Label
+        | Token::Let
+        | Token::Match
+        | Token::Namespace
+        | Token::Printf
+        | Token::Return
+        | Token::Static
+        | Token::Struct
+        | Token::While
+        | Token::Yield => Some(TokenClass::Keyword),
+        Token::Identifier => Some(TokenClass::Identifier),
+        Token::Int | Token::Float => Some(TokenClass::Number),
+        Token::Str => Some(TokenClass::String),
+        Token::Error => None,
+        _ => Some(TokenClass::Operator),
+    }
+}
+
+/// Split a script into classified tokens in source order, including comments.
+/// Works on scripts that don't parse. Anything the lexer can't make sense of is left out.
+pub fn classify_tokens(source: &str) -> Vec<ClassifiedToken> {
+    let comments = find_comments(source);
+    let mut tokens = Vec::new();
+    let mut next_comment = comments.iter().peekable();
+    let mut lexer = Token::lexer(source);
+    while let Some(token) = lexer.next() {
+        let span = lexer.span();
+        while let Some(comment) = next_comment.next_if(|c| c.start < span.end) {
+            tokens.push(ClassifiedToken {
+                span: comment.clone(),
+                class: TokenClass::Comment,
+            });
+        }
+        // The lexer only skips comments that end in a newline, so one at the very end comes out as other tokens
+        let in_comment = tokens
+            .last()
+            .is_some_and(|t| t.class == TokenClass::Comment && span.start < t.span.end);
+        if in_comment {
+            continue;
+        }
+        if let Some(class) = classify(token) {
+            tokens.push(ClassifiedToken { span, class });
+        }
+    }
+    tokens.extend(next_comment.map(|comment| ClassifiedToken {
+        span: comment.clone(),
+        class: TokenClass::Comment,
+    }));
+    tokens
+}
@@ -1,15 +1,81 @@
-use std::collections::HashSet;
+use std::collections::{HashMap, HashSet};
 use std::path::{Path, PathBuf};
 
 use exalt_ast::surface::{Decl, IncludePathComponent, Script};
 use exalt_ast::Location;
 use normpath::PathExt;
 
-use crate::reporting::ParserError;
-use crate::{parser, CompilerLog};
+use crate::header::PrecompiledHeader;
+use crate::parser;
+use crate::reporting::{ParserError, WarningMessage};
+use crate::stdlib::{self, StdLibrary};
+use crate::cache;
+use crate::{CompilationCache, CompilerLog, SymbolTable};
 
 type Result<T> = std::result::Result<T, ParserError>;
 
+/// Environment variable with extra include directories, separated the same way as PATH.
+pub const EXALT_PATH: &str = "EXALT_PATH";
+
+/// Finds included scripts for code compiled without a filesystem (ex. unsaved editor buffers).
+pub trait IncludeProvider {
+    /// Find the script an include refers to, returning its name and source.
+    /// `from` is the name of the script containing the include.
+    /// Names identify scripts, so the same script must always come back under the same name.
+    fn resolve(&self, from: &str, path: &[IncludePathComponent]) -> Option<(String, String)>;
+}
+
+/// Scripts held in memory under '/' separated names (ex. "std/fe14/prelude.exl").
+/// Includes are looked up next to the including script first, then from the root.
+#[derive(Debug, Clone, Default)]
+pub struct MemoryIncludes {
+    scripts: HashMap<String, String>,
+}
+
+impl MemoryIncludes {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn insert(&mut self, name: impl Into<String>, source: impl Into<String>) {
+        self.scripts.insert(name.into(), source.into());
+    }
+}
+
+impl From<HashMap<String, String>> for MemoryIncludes {
+    fn from(scripts: HashMap<String, String>) -> Self {
+        MemoryIncludes { scripts }
+    }
+}
+
+/// None if the path climbs out of the root.
+fn construct_memory_name(dir: &[&str], path: &[IncludePathComponent]) -> Option<String> {
+    let mut parts = dir.to_vec();
+    for component in path {
+        match component {
+            IncludePathComponent::Node(name) => parts.push(name),
+            IncludePathComponent::Parent => {
+                parts.pop()?;
+            }
+        }
+    }
+    Some(parts.join("/") + ".exl")
+}
+
+impl IncludeProvider for MemoryIncludes {
+    fn resolve(&self, from: &str, path: &[IncludePathComponent]) -> Option<(String, String)> {
+        let mut dir: Vec<&str> = from.split('/').collect();
+        dir.pop();
+        [dir, Vec::new()]
+            .iter()
+            .filter_map(|dir| construct_memory_name(dir, path))
+            .find_map(|name| {
+                let source = self.scripts.get(&name)?.clone();
+                Some((name, source))
+            })
+    }
+}
+
 fn construct_fs_path(source_path: &[IncludePathComponent]) -> PathBuf {
     let mut buf = PathBuf::new();
     for component in source_path {
@@ -21,89 +87,207 @@ fn construct_fs_path(source_path: &[IncludePathComponent]) -> PathBuf {
     buf
 }
 
-fn find_script(path: &[IncludePathComponent], search_paths: &[PathBuf]) -> Option<PathBuf> {
+/// Paths searched without finding anything are recorded with the cache, since creating a file there changes the result.
+fn find_script(
+    path: &[IncludePathComponent],
+    search_paths: &[PathBuf],
+    cache: &mut CompilationCache,
+) -> Option<PathBuf> {
     let path = construct_fs_path(path);
     for search_path in search_paths {
         let mut full_path = search_path.join(&path);
         full_path.set_extension("exl");
-        if full_path.exists() && full_path.is_file() {
+        if cache::script_exists(&full_path) {
             return Some(full_path);
         }
+        cache.record_absence(&full_path);
     }
     None
 }
 
+/// Directories searched for includes, in order:
+/// 1. The directory of the file containing the include, then its embedded copy if it's part of the standard library
+/// 2. The directory of the script being compiled, so headers can include files relative to the project
+/// 3. Directories passed in by the caller (ex. -I), in the order given
+/// 4. Directories listed in the EXALT_PATH environment variable
+/// 5. The standard library's directory (see [`StdLibrary::locate`]), then its embedded copy
 fn build_search_paths(
     additional_includes: &[PathBuf],
     location: Location,
     current_file_path: &Path,
+    root_file_path: &Path,
 ) -> Result<Vec<PathBuf>> {
     let mut paths = Vec::new();
-    // Parent dir of the current file
-    paths.push(
-        current_file_path
-            .parent()
-            .ok_or_else(|| ParserError::IncludeError(location.clone()))?
-            .to_path_buf(),
-    );
-    // Path of the compiler exe
-    // This is where we expect to find the standard library
-    paths.push(
-        std::env::current_exe()
-            .map_err(|_| ParserError::IncludeError(location.clone()))?
-            .parent()
-            .ok_or_else(|| ParserError::IncludeError(location.clone()))?
-            .normalize()
-            .map_err(|_| {
-                ParserError::PathNormalizationError(
-                    location.clone(),
-                    current_file_path.to_path_buf(),
-                )
-            })?
-            .into_path_buf(),
-    );
+    for file_path in [current_file_path, root_file_path] {
+        paths.push(
+            file_path
+                .parent()
+                .ok_or_else(|| ParserError::IncludeError(location.clone()))?
+                .to_path_buf(),
+        );
+    }
+    if let Some(dir) = current_file_path.parent().and_then(stdlib::embedded_counterpart) {
+        paths.insert(1, dir);
+    }
     paths.extend(additional_includes.iter().cloned());
+    if let Some(exalt_path) = std::env::var_os(EXALT_PATH) {
+        paths.extend(std::env::split_paths(&exalt_path).filter(|p| !p.as_os_str().is_empty()));
+    }
+    paths.extend(StdLibrary::locate().include_dirs());
+    let mut seen = HashSet::new();
+    paths.retain(|p| seen.insert(p.clone()));
     Ok(paths)
 }
 
+/// Shared state while resolving the includes of one script
+struct IncludeContext<'a> {
+    log: &'a mut CompilerLog,
+    cache: &'a mut CompilationCache,
+    additional_includes: &'a [PathBuf],
+    included_paths: HashSet<PathBuf>,
+
+    /// The script being compiled, whose directory is searched for every include
+    root: PathBuf,
+
+    /// Include statements leading to the file being resolved and the files they pulled in
+    chain: Vec<(Location, PathBuf)>,
+    scripts: Vec<Script>,
+
+    /// Declarations loaded from precompiled headers instead of being parsed
+    symbol_table: SymbolTable,
+}
+
+/// Use the precompiled header for an include if there is one and it's still up to date.
+fn load_header(location: &Location, source_path: &Path, context: &mut IncludeContext) -> bool {
+    if !source_path.with_extension("exlh").is_file() {
+        return false;
+    }
+    match PrecompiledHeader::load(source_path) {
+        Ok(header) => {
+            for (path, source) in header.sources() {
+                context.cache.record_dependency(&path, source);
+                context.included_paths.insert(path);
+            }
+            let cache = &mut *context.cache;
+            header.define_symbols_with(&mut context.symbol_table, context.log, |log, name, source| {
+                cache.register(Path::new(name), source, log)
+            });
+            true
+        }
+        Err(err) => {
+            context
+                .log
+                .log_warning(WarningMessage::IgnoredHeader(location.clone(), err.to_string()));
+            false
+        }
+    }
+}
+
+fn check_cycle(
+    location: &Location,
+    source_path: &Path,
+    chain: &[(Location, PathBuf)],
+) -> Result<()> {
+    if chain.iter().any(|(_, p)| p == source_path) {
+        let mut cycle = chain[1..].to_vec();
+        cycle.push((location.clone(), source_path.to_path_buf()));
+        return Err(ParserError::IncludeCycle(
+            location.clone(),
+            chain[0].1.clone(),
+            cycle,
+        ));
+    }
+    Ok(())
+}
+
+fn resolve_include(
+    location: &Location,
+    path: &[IncludePathComponent],
+    search_paths: &[PathBuf],
+    context: &mut IncludeContext,
+) -> Result<()> {
+    // Find the file in the source paths and load it.
+    let source_path = find_script(path, search_paths, context.cache)
+        .ok_or_else(|| ParserError::IncludeNotFound(location.clone(), search_paths.to_vec()))?;
+    // Embedded files aren't on disk, so there's nothing to normalize against
+    let source_path = if stdlib::is_embedded(&source_path) {
+        source_path
+    } else {
+        source_path
+            .normalize()
+            .map_err(|_| ParserError::IncludeError(location.clone()))?
+            .into_path_buf()
+    };
+    check_cycle(location, &source_path, &context.chain)?;
+    // Only try to pull in the file if it hasn't been included yet.
+    if !context.included_paths.contains(&source_path) && !load_header(location, &source_path, context) {
+        let contents = stdlib::read_source(&source_path)
+            .map_err(|_| ParserError::IncludeError(location.clone()))?;
+        if !load_analysis(&source_path, &contents, context) {
+            let script = context.cache.parse(&source_path, contents, context.log);
+            pull_in_scripts_recursive(location.clone(), source_path, script, context)?;
+        }
+    }
+    Ok(())
+}
+
+/// Use the cache's analysis of an include instead of parsing it, if it has one.
+fn load_analysis(source_path: &Path, source: &str, context: &mut IncludeContext) -> bool {
+    let analyzed = match context.cache.analysis(
+        source_path,
+        source,
+        &context.root,
+        context.additional_includes,
+    ) {
+        Some(analyzed) => analyzed,
+        None => return false,
+    };
+    let header = match &analyzed.header {
+        Some(header) => header,
+        None => return false,
+    };
+    // Anything it pulled in that's already been included would be defined twice
+    if analyzed
+        .dependencies
+        .iter()
+        .any(|(path, _)| context.included_paths.contains(path))
+    {
+        return false;
+    }
+    for (path, _) in &analyzed.dependencies {
+        context.included_paths.insert(path.clone());
+    }
+    let cache = &mut *context.cache;
+    header.define_symbols_with(&mut context.symbol_table, context.log, |log, name, source| {
+        cache.register(Path::new(name), source, log)
+    });
+    true
+}
+
 fn pull_in_scripts_recursive(
     location: Location,
     path: PathBuf,
     script: Script,
-    log: &mut CompilerLog,
-    included_paths: &mut HashSet<PathBuf>,
-    scripts: &mut Vec<Script>,
-    additional_includes: &[PathBuf],
+    context: &mut IncludeContext,
 ) -> Result<()> {
-    let search_paths = build_search_paths(additional_includes, location, &path)?;
-    included_paths.insert(path);
+    let search_paths = build_search_paths(
+        context.additional_includes,
+        location.clone(),
+        &path,
+        &context.root,
+    )?;
+    context.included_paths.insert(path.clone());
+    context.chain.push((location, path));
     for decl in &script.0 {
         if let Decl::Include { location, path } = decl {
-            // Find the file in the source paths and load it.
-            let source_path = find_script(path, &search_paths)
-                .ok_or_else(|| ParserError::IncludeNotFound(location.clone()))?
-                .normalize()
-                .map_err(|_| ParserError::IncludeError(location.clone()))?
-                .into_path_buf();
-            // Only try to pull in the file if it hasn't been included yet.
-            if !included_paths.contains(&source_path) {
-                let contents = std::fs::read_to_string(&source_path)
-                    .map_err(|_| ParserError::IncludeError(location.clone()))?;
-                let script = parser::parse(log.peek_file_id(), &contents, log);
-                log.add(source_path.to_string_lossy().to_string(), contents.clone());
-                pull_in_scripts_recursive(
-                    location.clone(),
-                    source_path,
-                    script,
-                    log,
-                    included_paths,
-                    scripts,
-                    additional_includes,
-                )?;
+            // A bad include only loses its own declarations, so keep going to report the rest
+            if let Err(err) = resolve_include(location, path, &search_paths, context) {
+                context.log.log_error(err.into());
             }
         }
     }
-    scripts.push(script);
+    context.chain.pop();
+    context.scripts.push(script);
     Ok(())
 }
 
@@ -112,21 +296,129 @@ pub fn build_script_with_includes(
     script: Script,
     log: &mut CompilerLog,
     additional_includes: &[PathBuf],
-) -> Result<Script> {
-    let mut included_paths = HashSet::new();
-    let mut scripts = Vec::new();
-    let normalized_path = path
-        .normalize()
-        .map_err(|_| ParserError::PathNormalizationError(Location::Generated, path.clone()))?
-        .into_path_buf();
-    pull_in_scripts_recursive(
-        Location::Generated,
+    cache: &mut CompilationCache,
+) -> Result<(Script, SymbolTable)> {
+    let normalized_path = normalize_root(path)?;
+    build_with_root(
+        normalized_path.clone(),
         normalized_path,
         script,
         log,
-        &mut included_paths,
-        &mut scripts,
         additional_includes,
-    )?;
-    Ok(Script(scripts.into_iter().flat_map(|s| s.0).collect()))
+        cache,
+    )
+}
+
+/// Resolve the includes of an include as if the script at `root` were being compiled.
+pub(crate) fn build_include_on_its_own(
+    path: PathBuf,
+    root: &Path,
+    script: Script,
+    log: &mut CompilerLog,
+    additional_includes: &[PathBuf],
+    cache: &mut CompilationCache,
+) -> Result<(Script, SymbolTable)> {
+    build_with_root(path, root.to_path_buf(), script, log, additional_includes, cache)
+}
+
+fn normalize_root(path: PathBuf) -> Result<PathBuf> {
+    if stdlib::is_embedded(&path) {
+        Ok(path)
+    } else {
+        Ok(path
+            .normalize()
+            .map_err(|_| ParserError::PathNormalizationError(Location::Generated, path.clone()))?
+            .into_path_buf())
+    }
+}
+
+fn build_with_root(
+    path: PathBuf,
+    root: PathBuf,
+    script: Script,
+    log: &mut CompilerLog,
+    additional_includes: &[PathBuf],
+    cache: &mut CompilationCache,
+) -> Result<(Script, SymbolTable)> {
+    let mut context = IncludeContext {
+        log,
+        cache,
+        additional_includes,
+        included_paths: HashSet::new(),
+        root,
+        chain: Vec::new(),
+        scripts: Vec::new(),
+        symbol_table: SymbolTable::new(),
+    };
+    pull_in_scripts_recursive(Location::Generated, path, script, &mut context)?;
+    let script = Script(context.scripts.into_iter().flat_map(|s| s.0).collect());
+    Ok((script, context.symbol_table))
+}
+
+/// Shared state while resolving the includes of a script through an IncludeProvider
+struct ProviderContext<'a> {
+    log: &'a mut CompilerLog,
+    provider: &'a dyn IncludeProvider,
+    included_names: HashSet<String>,
+    chain: Vec<(Location, PathBuf)>,
+    scripts: Vec<Script>,
+}
+
+fn resolve_provided_include(
+    location: &Location,
+    path: &[IncludePathComponent],
+    from: &str,
+    context: &mut ProviderContext,
+) -> Result<()> {
+    let (name, source) = context
+        .provider
+        .resolve(from, path)
+        .or_else(|| stdlib::embedded_includes().resolve(from, path))
+        .ok_or_else(|| ParserError::IncludeNotFound(location.clone(), Vec::new()))?;
+    check_cycle(location, Path::new(&name), &context.chain)?;
+    if !context.included_names.contains(&name) {
+        let script = parser::parse(context.log.peek_file_id(), &source, context.log);
+        context.log.add(name.clone(), source);
+        pull_in_provided_scripts(location.clone(), name, script, context);
+    }
+    Ok(())
+}
+
+fn pull_in_provided_scripts(
+    location: Location,
+    name: String,
+    script: Script,
+    context: &mut ProviderContext,
+) {
+    context.included_names.insert(name.clone());
+    context.chain.push((location, PathBuf::from(&name)));
+    for decl in &script.0 {
+        if let Decl::Include { location, path } = decl {
+            if let Err(err) = resolve_provided_include(location, path, &name, context) {
+                context.log.log_error(err.into());
+            }
+        }
+    }
+    context.chain.pop();
+    context.scripts.push(script);
+}
+
+/// Same as build_script_with_includes, but every include comes from the provider instead of the filesystem.
+/// Precompiled headers aren't used since there's no way to tell if they're up to date.
+pub fn build_script_from_provider(
+    name: &str,
+    script: Script,
+    log: &mut CompilerLog,
+    provider: &dyn IncludeProvider,
+) -> (Script, SymbolTable) {
+    let mut context = ProviderContext {
+        log,
+        provider,
+        included_names: HashSet::new(),
+        chain: Vec::new(),
+        scripts: Vec::new(),
+    };
+    pull_in_provided_scripts(Location::Generated, name.to_owned(), script, &mut context);
+    let script = Script(context.scripts.into_iter().flat_map(|s| s.0).collect());
+    (script, SymbolTable::new())
 }
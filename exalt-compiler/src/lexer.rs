@@ -93,6 +93,8 @@ pub enum Token {
     AssignLeftShift,
     #[token(":")]
     Colon,
+    #[token("?")]
+    QuestionMark,
     #[token(",")]
     Comma,
     #[token("(")]
@@ -121,6 +123,8 @@ pub enum Token {
     Alias,
     #[token("array")]
     Array,
+    #[token("asm")]
+    Asm,
     #[token("break")]
     Break,
     #[token("event")]
@@ -130,6 +134,8 @@ pub enum Token {
     Const,
     #[token("continue")]
     Continue,
+    #[token("do")]
+    Do,
     #[token("func")]
     #[token("def")]
     Func,
@@ -139,12 +145,16 @@ pub enum Token {
     Enum,
     #[token("extern")]
     Extern,
+    #[token("fallthrough")]
+    Fallthrough,
     #[token("for")]
     For,
     #[token("goto")]
     Goto,
     #[token("include")]
     Include,
+    #[token("inline")]
+    Inline,
     #[token("if")]
     If,
     #[token("label")]
@@ -153,6 +163,8 @@ pub enum Token {
     Let,
     #[token("match")]
     Match,
+    #[token("namespace")]
+    Namespace,
     #[token("printf")]
     Printf,
     #[token("return")]
@@ -171,7 +183,7 @@ pub enum Token {
     Float,
     #[regex(r"[^\W0-9](::|\w|・|？)*")]
     Identifier,
-    #[regex("\"[^\"]*\"")]
+    #[regex(r#""([^"\\]|\\.)*""#)]
     Str,
     #[error]
     #[regex(r"([ \t\n\r\f]+)", logos::skip)]
@@ -231,6 +243,7 @@ impl Display for Token {
                 Token::AssignLeftShift => "<<=",
                 Token::Comma => ",",
                 Token::Colon => ":",
+                Token::QuestionMark => "?",
                 Token::LeftParen => "(",
                 Token::RightParen => ")",
                 Token::LeftBracket => "[",
@@ -244,21 +257,26 @@ impl Display for Token {
                 Token::DotDot => "..",
                 Token::Alias => "alias",
                 Token::Array => "array",
+                Token::Asm => "asm",
                 Token::Break => "break",
                 Token::Event => "event",
                 Token::Extern => "extern",
                 Token::Const => "const",
                 Token::Continue => "continue",
+                Token::Do => "do",
                 Token::Else => "else",
                 Token::Enum => "enum",
+                Token::Fallthrough => "fallthrough",
                 Token::For => "for",
                 Token::Func => "func",
                 Token::Goto => "goto",
                 Token::Include => "include",
+                Token::Inline => "inline",
                 Token::If => "if",
                 Token::Label => "label",
                 Token::Let => "let",
                 Token::Match => "match",
+                Token::Namespace => "namespace",
                 Token::Static => "static",
                 Token::Struct => "struct",
                 Token::Printf => "printf",
@@ -279,6 +297,7 @@ impl Display for Token {
 pub struct Peekable<'source> {
     lexer: Lexer<'source, Token>,
     peeked: Option<Option<Token>>,
+    last_end: usize,
 }
 
 impl<'source> Peekable<'source> {
@@ -286,6 +305,7 @@ impl<'source> Peekable<'source> {
         Self {
             lexer: Token::lexer(source),
             peeked: None,
+            last_end: 0,
         }
     }
 
@@ -303,16 +323,27 @@ impl<'source> Peekable<'source> {
     pub fn span(&self) -> Range<usize> {
         self.lexer.span()
     }
+
+    pub fn source(&self) -> &'source str {
+        self.lexer.source()
+    }
+
+    /// End of the last token returned by next, even if another token has been peeked since.
+    pub fn last_end(&self) -> usize {
+        self.last_end
+    }
 }
 
 impl<'source> Iterator for Peekable<'source> {
     type Item = Token;
 
     fn next(&mut self) -> Option<Token> {
-        if let Some(peeked) = self.peeked.take() {
+        let token = if let Some(peeked) = self.peeked.take() {
             peeked
         } else {
             self.lexer.next()
-        }
+        };
+        self.last_end = self.lexer.span().end;
+        token
     }
 }
@@ -1,22 +1,50 @@
+mod aliases;
+mod asm;
+mod cache;
 mod codegen;
 mod completion;
 mod eval;
+mod format;
+mod header;
+mod highlight;
 mod includes;
 mod lexer;
+mod listing;
+mod optimize;
 pub mod parser;
+mod peephole;
 mod reporting;
+mod round_trip;
 mod semantic;
+mod slots;
+mod source_map;
+mod stdlib;
 mod symbol;
 
 use std::path::PathBuf;
 
+pub use aliases::AliasFile;
+pub use cache::CompilationCache;
 pub use codegen::CodeGenerationError;
-use exalt_assembler::CodeGenTextData;
-use exalt_ast::Script;
-use exalt_lir::Game;
+pub use header::{HeaderError, PrecompiledHeader};
+pub use highlight::{classify_tokens, ClassifiedToken, TokenClass};
+pub use includes::{IncludeProvider, MemoryIncludes, EXALT_PATH};
+use exalt_assembler::{AssembleOptions, CodeGenTextData};
+use exalt_ast::surface::Decl;
+use exalt_ast::{Literal, Script};
+use exalt_lir::{Game, RawScript, SymbolMap, TextOptions};
 pub use lexer::{Peekable, Token};
-pub use reporting::CompilerLog;
+pub use optimize::OptimizationConfig;
+pub use stdlib::{prelude_include, read_source, StdLibrary, EXALT_STD};
+pub use reporting::{
+    CompilerLog, DiagnosticRecord, MessageFormat, Span, Suggestion, SuggestionRecord,
+};
+use reporting::SemanticError;
+pub use round_trip::{
+    diff_scripts, verify_round_trip, Divergence, RoundTripOptions, RoundTripReport,
+};
 pub use symbol::{Scope, SymbolTable};
+use symbol::Variable;
 use thiserror::Error;
 
 #[derive(Debug, Error)]
@@ -43,7 +71,37 @@ pub struct CompileRequest {
     pub target: PathBuf,
     pub output: Option<PathBuf>,
     pub text_data: Option<CodeGenTextData>,
+
+    /// How strings are encoded. Defaults to the game's encoding.
+    pub text: TextOptions,
+
+    /// Extra directories searched for includes after the script's own directory and before EXALT_PATH.
     pub additional_includes: Vec<PathBuf>,
+    pub optimizations: OptimizationConfig,
+
+    /// Header revision to use instead of the game's default.
+    pub revision: Option<u32>,
+
+    /// Name to embed in the script header instead of one derived from the output path.
+    pub internal_name: Option<String>,
+
+    /// Frame indices to use for named local variables.
+    pub symbols: Option<SymbolMap>,
+
+    /// Write a listing of the generated opcodes and their addresses next to the output.
+    pub listing: bool,
+
+    /// Write a JSON map from opcode addresses back to source locations next to the output.
+    pub source_map: bool,
+
+    /// How errors and warnings are written out.
+    pub message_format: MessageFormat,
+
+    /// Constants defined for the script as if it had declared them itself (ex. DEBUG = 1).
+    pub defines: Vec<(String, Literal)>,
+
+    /// Function aliases as (friendly name, internal name) pairs, used when the script doesn't declare the name itself.
+    pub aliases: Vec<(String, String)>,
 }
 
 pub struct ParseRequest {
@@ -99,6 +157,14 @@ impl CompileRequest {
             Ok(parent.join(self.script_name()?))
         }
     }
+
+    pub fn listing_path(&self) -> Result<PathBuf, CompilerError> {
+        Ok(self.output_path()?.with_extension("lst"))
+    }
+
+    pub fn source_map_path(&self) -> Result<PathBuf, CompilerError> {
+        Ok(self.output_path()?.with_extension("map.json"))
+    }
 }
 
 pub fn compile(request: &CompileRequest) -> Result<(), CompilerError> {
@@ -111,61 +177,238 @@ pub fn compile(request: &CompileRequest) -> Result<(), CompilerError> {
 }
 
 pub fn compile_to_vec(request: &CompileRequest) -> Result<Vec<u8>, CompilerError> {
+    compile_to_lir_and_vec(request).map(|(_, raw)| raw)
+}
+
+/// Compile a script, returning both the generated LIR and the assembled binary.
+pub fn compile_to_lir_and_vec(
+    request: &CompileRequest,
+) -> Result<(RawScript, Vec<u8>), CompilerError> {
+    compile_with_cache(request, &mut CompilationCache::new())
+}
+
+/// Compile a script, skipping work for any files that have not changed since the cache last saw them.
+pub fn compile_with_cache(
+    request: &CompileRequest,
+    cache: &mut CompilationCache,
+) -> Result<(RawScript, Vec<u8>), CompilerError> {
+    match compile_deferring_diagnostics(request, cache) {
+        Ok((lir, raw, log)) => {
+            if !log.warnings.is_empty() {
+                log.emit(request.message_format);
+            }
+            Ok((lir, raw))
+        }
+        Err(CompilerError::ParseError(log)) => {
+            log.emit(request.message_format);
+            Err(CompilerError::ParseError(log))
+        }
+        Err(err) => Err(err),
+    }
+}
+
+/// Like [`compile_with_cache`], but hands diagnostics back instead of printing them
+/// so callers compiling several scripts at once can report them together.
+/// Warnings come back with the output and errors come back in [`CompilerError::ParseError`].
+/// Output from the cache comes back with the warnings it was first compiled with.
+pub fn compile_deferring_diagnostics(
+    request: &CompileRequest,
+    cache: &mut CompilationCache,
+) -> Result<(RawScript, Vec<u8>, CompilerLog), CompilerError> {
+    // Hard coded text data isn't hashable, so those requests always run the full pipeline.
+    // Listings and source maps are written during code generation, so those requests can't skip it either.
+    let settings = if request.text_data.is_none() && !request.listing && !request.source_map {
+        let settings = cache::hash_settings(&format!(
+            "{:?}",
+            (
+                request.game,
+                request.script_name()?,
+                &request.additional_includes,
+                &request.optimizations,
+                request.revision,
+                request.text,
+                &request.internal_name,
+                &request.symbols,
+                &request.defines,
+                &request.aliases,
+            )
+        ));
+        Some(settings)
+    } else {
+        None
+    };
+
+    let mut log = cache.create_log();
+    if let Some(settings) = settings {
+        if let Some((lir, raw)) = cache.lookup_output(&request.target, settings, &mut log) {
+            cache.finish_log(&log);
+            return Ok((lir, raw, log));
+        }
+    }
+    let result = compile_with_log(request, cache, &mut log);
+    cache.finish_log(&log);
+    match result? {
+        Some((lir, raw)) => {
+            if let Some(settings) = settings {
+                cache.store_output(&request.target, settings, &lir, &raw, &log);
+            }
+            Ok((lir, raw, log))
+        }
+        None => Err(CompilerError::ParseError(log)),
+    }
+}
+
+/// Returns None if compiling failed with diagnostics in the log.
+fn compile_with_log(
+    request: &CompileRequest,
+    cache: &mut CompilationCache,
+    log: &mut CompilerLog,
+) -> Result<Option<(RawScript, Vec<u8>)>, CompilerError> {
     // Load input
     let contents = std::fs::read_to_string(&request.target)
         .map_err(|_| CompilerError::FileNotFound(request.target.clone()))?;
 
     // Parse sources
-    let mut log = CompilerLog::new();
-    let script = parser::parse(log.peek_file_id(), &contents, &mut log);
-    log.add(request.source_name()?, contents.clone());
-
-    let script = match includes::build_script_with_includes(
+    let script = cache.parse(&request.target, contents, log);
+    let (script, symbol_table) = match includes::build_script_with_includes(
         request.target.clone(),
         script,
-        &mut log,
+        log,
         &request.additional_includes,
+        cache,
     ) {
-        Ok(script) => script,
+        Ok(result) => result,
         Err(err) => {
             log.log_error(err.into());
-            log.print();
-            return Err(CompilerError::ParseError(log));
+            return Ok(None);
         }
     };
     if log.has_errors() {
-        log.print();
-        return Err(CompilerError::ParseError(log));
+        return Ok(None);
     }
 
+    generate(&script, symbol_table, request, log)
+}
+
+/// Run everything after parsing: analysis, optimization, code generation, and assembly.
+/// Returns None if analysis failed with diagnostics in the log.
+fn generate(
+    script: &exalt_ast::surface::Script,
+    symbol_table: SymbolTable,
+    request: &CompileRequest,
+    log: &mut CompilerLog,
+) -> Result<Option<(RawScript, Vec<u8>)>, CompilerError> {
     // Evaluate sources
-    let (script, symbol_table) = if let Some(script) = semantic::analyze(&script, &mut log) {
+    let mut symbol_table = symbol_table;
+    semantic::define_constants(&mut symbol_table, &request.defines, log);
+    semantic::define_aliases(&mut symbol_table, &request.aliases);
+    let (mut script, symbol_table) = if let Some(script) = semantic::analyze(script, symbol_table, log) {
         script
     } else {
-        log.print();
-        return Err(CompilerError::ParseError(log));
+        return Ok(None);
     };
+    optimize::optimize(&mut script, &request.optimizations);
 
     // Generate code
-    let script_name = request.script_name()?;
-    match codegen::serialize(
-        &script_name,
+    let script_name = match &request.internal_name {
+        Some(name) => name.clone(),
+        None => request.script_name()?,
+    };
+    let (mut lir, mut locations) = codegen::generate(
         &script,
         &symbol_table,
         request.game,
-        request.text_data.as_ref().cloned(),
-    ) {
-        Ok(raw) => Ok(raw),
-        Err(err) => Err(CompilerError::CodeGenerationError(err)),
+        request.symbols.as_ref(),
+        request.optimizations.reuse_frame_slots,
+    )?;
+    if request.optimizations.peephole {
+        peephole::optimize(&mut lir, &mut locations);
+    }
+    let options = AssembleOptions {
+        text_data: request.text_data.clone(),
+        revision: request.revision,
+        text: request.text,
+    };
+    let (raw, layout) = codegen::assemble(&script_name, &lir, request.game, options)?;
+    if request.listing {
+        let listing = listing::render(&script, &lir, &locations, &layout, &log.files);
+        std::fs::write(request.listing_path()?, listing)?;
+    }
+    if request.source_map {
+        let map = source_map::build(&script_name, &locations, &layout, &log.files)?;
+        std::fs::write(request.source_map_path()?, map)?;
+    }
+    Ok(Some((lir, raw)))
+}
+
+/// Compile a script held in memory without touching the filesystem. Includes are resolved through the provider,
+/// falling back to the embedded standard library for anything it doesn't have (ex. `include std:fe14:prelude;`).
+/// Diagnostics are returned in the error instead of being printed.
+pub fn compile_source(
+    name: &str,
+    source: &str,
+    includes: &dyn IncludeProvider,
+    game: Game,
+) -> Result<Vec<u8>, CompilerError> {
+    let request = CompileRequest {
+        game,
+        target: PathBuf::from(name),
+        output: None,
+        text_data: None,
+        text: TextOptions::default(),
+        additional_includes: Vec::new(),
+        optimizations: OptimizationConfig::default(),
+        revision: None,
+        internal_name: None,
+        symbols: None,
+        listing: false,
+        source_map: false,
+        message_format: MessageFormat::default(),
+        defines: Vec::new(),
+        aliases: Vec::new(),
+    };
+    compile_request_source(&request, source, includes)
+}
+
+/// Compile source held in memory with the settings of a request, naming it after the request's target.
+pub(crate) fn compile_request_source(
+    request: &CompileRequest,
+    source: &str,
+    includes: &dyn IncludeProvider,
+) -> Result<Vec<u8>, CompilerError> {
+    let name = request.target.to_string_lossy();
+    let mut log = CompilerLog::new();
+    let script = parser::parse(log.peek_file_id(), source, &mut log);
+    log.add(name.to_string(), source.to_owned());
+    let (script, symbol_table) =
+        includes::build_script_from_provider(&name, script, &mut log, includes);
+    if log.has_errors() {
+        return Err(CompilerError::ParseError(log));
+    }
+    match generate(&script, symbol_table, request, &mut log)? {
+        Some((_, raw)) => Ok(raw),
+        None => Err(CompilerError::ParseError(log)),
     }
 }
 
+/// Format a script with canonical indentation, spacing, and line breaks without changing what it compiles to.
+/// Fails with the log's diagnostics if the script doesn't parse.
+pub fn format_source(source_name: &str, source: &str) -> Result<String, CompilerError> {
+    let mut log = CompilerLog::new();
+    let script = parser::parse(log.peek_file_id(), source, &mut log);
+    log.add(source_name.to_owned(), source.to_owned());
+    if log.has_errors() {
+        return Err(CompilerError::ParseError(log));
+    }
+    Ok(format::format_script(&script, source))
+}
+
 pub fn parse(request: &ParseRequest) -> Result<ParseResult, CompilerError> {
     // Load input
     let contents = if let Some(source) = &request.source {
         source.clone()
     } else {
-        std::fs::read_to_string(&request.target)
+        read_source(&request.target)
             .map_err(|_| CompilerError::FileNotFound(request.target.clone()))?
     };
 
@@ -173,13 +416,14 @@ pub fn parse(request: &ParseRequest) -> Result<ParseResult, CompilerError> {
     let mut log = CompilerLog::new();
     let parse_tree = parser::parse(log.peek_file_id(), &contents, &mut log);
     log.add(request.source_name()?, contents.clone());
-    let parse_tree = match includes::build_script_with_includes(
+    let (parse_tree, symbol_table) = match includes::build_script_with_includes(
         request.target.clone(),
         parse_tree,
         &mut log,
         &request.additional_includes,
+        &mut CompilationCache::new(),
     ) {
-        Ok(parse_tree) => parse_tree,
+        Ok(result) => result,
         Err(err) => {
             log.log_error(err.into());
             return Err(CompilerError::ParseError(log));
@@ -190,7 +434,7 @@ pub fn parse(request: &ParseRequest) -> Result<ParseResult, CompilerError> {
     }
 
     // Evaluate sources
-    if let Some((script, symbol_table)) = semantic::analyze(&parse_tree, &mut log) {
+    if let Some((script, symbol_table)) = semantic::analyze(&parse_tree, symbol_table, &mut log) {
         Ok(ParseResult {
             parse_tree,
             script,
@@ -201,3 +445,102 @@ pub fn parse(request: &ParseRequest) -> Result<ParseResult, CompilerError> {
         Err(CompilerError::ParseError(log))
     }
 }
+
+/// Parse a header script (ex. a prelude) and save its declarations so scripts including it can skip parsing it.
+/// Headers may only declare things: anything that generates code or claims a frame slot is an error.
+pub fn precompile_header(request: &ParseRequest) -> Result<Vec<u8>, CompilerError> {
+    let mut result = parse(request)?;
+    check_header_decls(&result.parse_tree.0, &mut result.log);
+    if result.log.has_errors() {
+        return Err(CompilerError::ParseError(result.log));
+    }
+    Ok(PrecompiledHeader::new(&result.symbol_table, &result.log).to_bytes())
+}
+
+/// A global declared by a layout file.
+#[derive(Debug, Clone)]
+pub struct SharedGlobal {
+    pub name: String,
+    pub frame_id: usize,
+    /// Number of slots covered, which is more than one for arrays.
+    pub length: usize,
+}
+
+/// Parse a global layout file: a script that pins the globals several scripts share to fixed slots
+/// (ex. `let counter @ 7;`). Scripts include it to agree on the layout, and the decompiler uses it to name globals.
+/// Layouts may only declare things, and every global needs an explicit index. Colliding indices are errors.
+pub fn parse_global_layout(request: &ParseRequest) -> Result<Vec<SharedGlobal>, CompilerError> {
+    let mut result = parse(request)?;
+    check_layout_decls(&result.parse_tree.0, &mut result.log);
+    if result.log.has_errors() {
+        return Err(CompilerError::ParseError(result.log));
+    }
+    let mut globals: Vec<SharedGlobal> = result
+        .symbol_table
+        .global_variables()
+        .into_iter()
+        .filter_map(|v| match v {
+            Variable::Var(v) => {
+                let v = v.borrow();
+                Some(SharedGlobal {
+                    name: v.name.clone(),
+                    frame_id: v.frame_id?,
+                    length: v.array_length.unwrap_or(1),
+                })
+            }
+            _ => None,
+        })
+        .collect();
+    globals.sort_by_key(|g| g.frame_id);
+    Ok(globals)
+}
+
+fn check_layout_decls(decls: &[Decl], log: &mut CompilerLog) {
+    for decl in decls {
+        match decl {
+            Decl::Function { location, .. }
+            | Decl::Callback { location, .. }
+            | Decl::Inline { location, .. } => {
+                log.log_error(SemanticError::DefinitionInLayout(location.clone()).into())
+            }
+            Decl::Constant {
+                location,
+                annotations,
+                ..
+            } if !annotations.is_empty() => {
+                log.log_error(SemanticError::DefinitionInLayout(location.clone()).into())
+            }
+            Decl::Global {
+                identifier,
+                index: None,
+                ..
+            } => log.log_error(
+                SemanticError::UnpinnedLayoutGlobal(identifier.location.clone()).into(),
+            ),
+            Decl::Namespace { decls, .. } => check_layout_decls(decls, log),
+            _ => {}
+        }
+    }
+}
+
+pub(crate) fn check_header_decls(decls: &[Decl], log: &mut CompilerLog) {
+    for decl in decls {
+        match decl {
+            Decl::Function { location, .. }
+            | Decl::Callback { location, .. }
+            | Decl::Inline { location, .. }
+            | Decl::Global { location, .. } => {
+                log.log_error(SemanticError::DefinitionInHeader(location.clone()).into())
+            }
+            Decl::Constant {
+                location,
+                annotations,
+                ..
+            } if !annotations.is_empty() => {
+                log.log_error(SemanticError::DefinitionInHeader(location.clone()).into())
+            }
+            Decl::Namespace { decls, .. } => check_header_decls(decls, log),
+            _ => {}
+        }
+    }
+}
@@ -0,0 +1,71 @@
+use std::path::Path;
+
+use codespan_reporting::files::{Files, SimpleFiles};
+use exalt_assembler::FunctionLayout;
+use exalt_ast::{Decl, Location, Script};
+use exalt_lir::RawScript;
+
+use crate::codegen::SourceLocations;
+
+/// Description of each function table entry a declaration fills.
+fn describe_decl(decl: &Decl) -> Vec<String> {
+    match decl {
+        Decl::Function { symbol, .. } => vec![symbol.borrow().name.clone()],
+        Decl::Callback { event_types, .. } => event_types
+            .iter()
+            .map(|event_type| format!("callback[0x{:X}]", event_type))
+            .collect(),
+    }
+}
+
+/// First line of the statement at a location, prefixed with where it was written (ex. "bev.exl:12").
+fn describe_location(location: &Location, files: &SimpleFiles<String, String>) -> Option<String> {
+    let (file_id, range) = match location {
+        Location::Source(file_id, range) => (*file_id, range),
+        _ => return None,
+    };
+    let file = files.get(file_id).ok()?;
+    let line = files.line_index(file_id, range.start).ok()?;
+    let text = &file.source()[files.line_range(file_id, line).ok()?];
+    let name = Path::new(file.name())
+        .file_name()
+        .map(|name| name.to_string_lossy())
+        .unwrap_or_default();
+    Some(format!("{}:{}: {}", name, line + 1, text.trim()))
+}
+
+/// Render a human readable listing of a compiled script.
+/// Each statement's first source line is followed by its opcodes and their addresses in the script.
+pub fn render(
+    script: &Script,
+    lir: &RawScript,
+    locations: &[SourceLocations],
+    layout: &[FunctionLayout],
+    files: &SimpleFiles<String, String>,
+) -> String {
+    let mut listing = String::new();
+    let descriptions = script.decls.iter().flat_map(describe_decl);
+    let functions = descriptions.zip(&lir.functions).zip(layout);
+    for (index, ((description, function), layout)) in functions.enumerate() {
+        if index > 0 {
+            listing.push('\n');
+        }
+        listing.push_str(&format!("; function {}: {}\n", index, description));
+        let mut statements = locations
+            .get(index)
+            .map(|locations| locations.as_slice())
+            .unwrap_or_default()
+            .iter()
+            .peekable();
+        for (i, opcode) in function.code.iter().enumerate() {
+            while let Some((_, location)) = statements.next_if(|(start, _)| *start <= i) {
+                if let Some(line) = describe_location(location, files) {
+                    listing.push_str(&format!(";   {}\n", line));
+                }
+            }
+            let address = layout.code_address + layout.opcode_offsets[i];
+            listing.push_str(&format!("{:08X}  {:?}\n", address, opcode));
+        }
+    }
+    listing
+}
@@ -0,0 +1,570 @@
+use std::collections::{HashMap, HashSet};
+use std::rc::Rc;
+
+use exalt_ast::{Decl, Expr, Location, Operator, Ref, Script, Shared, Stmt, VarSymbol};
+
+/// Controls which optional passes run between semantic analysis and the final opcodes.
+/// Everything is off by default so compiled scripts round trip byte for byte.
+#[derive(Debug, Clone, Default)]
+pub struct OptimizationConfig {
+    /// Hoist repeated pure expressions into temporaries.
+    pub cse: bool,
+
+    /// Remove redundant opcode sequences after code generation.
+    pub peephole: bool,
+
+    /// Let local variables with disjoint lifetimes share frame slots.
+    pub reuse_frame_slots: bool,
+}
+
+pub fn optimize(script: &mut Script, config: &OptimizationConfig) {
+    if config.cse {
+        let mut pass = CommonSubexpressionElimination::default();
+        for decl in &mut script.decls {
+            let body = match decl {
+                Decl::Function { body, .. } => body,
+                Decl::Callback { body, .. } => body,
+            };
+            pass.escaped = collect_escaped_variables(body);
+            pass.optimize_stmt(body);
+        }
+    }
+}
+
+type VarId = *const std::cell::RefCell<VarSymbol>;
+
+fn var_id(symbol: &Shared<VarSymbol>) -> VarId {
+    Rc::as_ptr(symbol)
+}
+
+/// Side effects that an expression or statement can have on variables.
+#[derive(Default)]
+struct Clobbers {
+    vars: HashSet<VarId>,
+    // Function calls and pointer writes can reach globals and escaped locals
+    memory: bool,
+}
+
+impl Clobbers {
+    fn add_ref(&mut self, reference: &Ref) {
+        match reference {
+            Ref::Var(symbol) | Ref::Index(symbol, _) => {
+                self.vars.insert(var_id(symbol));
+            }
+            Ref::Dereference(_, _) => self.memory = true,
+        }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.vars.is_empty() && !self.memory
+    }
+}
+
+/// The variables that a pure expression reads.
+#[derive(Clone)]
+struct Reads {
+    vars: HashSet<VarId>,
+    memory: bool,
+}
+
+impl Reads {
+    fn clobbered_by(&self, clobbers: &Clobbers) -> bool {
+        (self.memory && clobbers.memory) || !self.vars.is_disjoint(&clobbers.vars)
+    }
+}
+
+struct Occurrence {
+    stmt: usize,
+    ordinal: usize,
+    // Number of candidate nodes in the subtree, including this one
+    span: usize,
+}
+
+struct Group {
+    expr: Expr,
+    reads: Reads,
+    size: usize,
+    occurrences: Vec<Occurrence>,
+}
+
+impl Group {
+    // Hoisting costs a store and one load per use, so only do it when that is cheaper
+    fn is_profitable(&self) -> bool {
+        let uses = self.occurrences.len();
+        uses >= 2 && uses * self.size > self.size + 2 + uses
+    }
+}
+
+enum WalkMode<'a> {
+    Collect(&'a mut Vec<(usize, usize, Expr)>),
+    Replace(&'a HashMap<usize, Shared<VarSymbol>>),
+}
+
+#[derive(Default)]
+struct CommonSubexpressionElimination {
+    escaped: HashSet<VarId>,
+    next_temp: usize,
+}
+
+impl CommonSubexpressionElimination {
+    fn optimize_stmt(&mut self, stmt: &mut Stmt) {
+        match stmt {
+            Stmt::Block(stmts) => self.optimize_block(stmts),
+            Stmt::For { body, .. } | Stmt::While { body, .. } | Stmt::DoWhile { body, .. } => {
+                self.optimize_stmt(body)
+            }
+            Stmt::If {
+                then_part,
+                else_part,
+                ..
+            } => {
+                self.optimize_stmt(then_part);
+                if let Some(else_part) = else_part {
+                    self.optimize_stmt(else_part);
+                }
+            }
+            Stmt::Match { cases, default, .. } => {
+                for case in cases {
+                    self.optimize_stmt(&mut case.body);
+                }
+                if let Some(default) = default {
+                    self.optimize_stmt(default);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn optimize_block(&mut self, stmts: &mut Vec<Stmt>) {
+        let mut window = Vec::new();
+        for mut stmt in std::mem::take(stmts) {
+            match stmt {
+                Stmt::Assignment { .. }
+                | Stmt::ExprStmt(_)
+                | Stmt::Printf(_)
+                | Stmt::SourceLocation(_)
+                | Stmt::VarDecl(_, _) => window.push(stmt),
+                Stmt::Return(_) => {
+                    window.push(stmt);
+                    stmts.extend(self.optimize_straight_line(std::mem::take(&mut window)));
+                }
+                _ => {
+                    stmts.extend(self.optimize_straight_line(std::mem::take(&mut window)));
+                    self.optimize_stmt(&mut stmt);
+                    stmts.push(stmt);
+                }
+            }
+        }
+        stmts.extend(self.optimize_straight_line(window));
+    }
+
+    fn optimize_straight_line(&mut self, mut window: Vec<Stmt>) -> Vec<Stmt> {
+        // Group occurrences of each expression until something overwrites what it reads
+        let mut live: HashMap<String, Group> = HashMap::new();
+        let mut groups = Vec::new();
+        for (i, stmt) in window.iter_mut().enumerate() {
+            let mut inner = Clobbers::default();
+            for expr in stmt_exprs(stmt) {
+                collect_clobbers(expr, &mut inner);
+            }
+
+            let mut nodes = Vec::new();
+            let mut counter = 0;
+            for expr in stmt_exprs_mut(stmt) {
+                walk(expr, &mut counter, &mut WalkMode::Collect(&mut nodes));
+            }
+            for (ordinal, span, expr) in nodes {
+                let mut key = String::new();
+                let mut reads = Reads {
+                    vars: HashSet::new(),
+                    memory: false,
+                };
+                let size = self.describe(&expr, &mut key, &mut reads);
+                if reads.clobbered_by(&inner) {
+                    continue;
+                }
+                live.entry(key)
+                    .or_insert_with(|| Group {
+                        expr,
+                        reads,
+                        size,
+                        occurrences: Vec::new(),
+                    })
+                    .occurrences
+                    .push(Occurrence {
+                        stmt: i,
+                        ordinal,
+                        span,
+                    });
+            }
+
+            if let Stmt::Assignment { left, .. } = stmt {
+                inner.add_ref(left);
+            }
+            if !inner.is_empty() {
+                let killed: Vec<String> = live
+                    .iter()
+                    .filter(|(_, g)| g.reads.clobbered_by(&inner))
+                    .map(|(k, _)| k.clone())
+                    .collect();
+                for key in killed {
+                    groups.push(live.remove(&key).unwrap());
+                }
+            }
+        }
+        groups.extend(live.into_values());
+
+        // Prefer the largest expressions and skip anything nested inside one we already took
+        let mut groups: Vec<Group> = groups.into_iter().filter(|g| g.is_profitable()).collect();
+        groups.sort_by(|a, b| {
+            b.size
+                .cmp(&a.size)
+                .then(a.occurrences[0].stmt.cmp(&b.occurrences[0].stmt))
+                .then(a.occurrences[0].ordinal.cmp(&b.occurrences[0].ordinal))
+        });
+        let mut taken: Vec<(usize, usize, usize)> = Vec::new();
+        let mut replacements: Vec<HashMap<usize, Shared<VarSymbol>>> =
+            window.iter().map(|_| HashMap::new()).collect();
+        let mut inits: Vec<Vec<Stmt>> = window.iter().map(|_| Vec::new()).collect();
+        for group in groups {
+            let overlaps = group.occurrences.iter().any(|o| {
+                taken.iter().any(|(stmt, ordinal, span)| {
+                    *stmt == o.stmt && o.ordinal < ordinal + span && *ordinal < o.ordinal + o.span
+                })
+            });
+            if overlaps {
+                continue;
+            }
+            let temp = self.create_temp();
+            for o in &group.occurrences {
+                taken.push((o.stmt, o.ordinal, o.span));
+                replacements[o.stmt].insert(o.ordinal, temp.clone());
+            }
+            inits[group.occurrences[0].stmt].push(Stmt::Assignment {
+                left: Ref::Var(temp),
+                op: Operator::Assign,
+                right: group.expr,
+            });
+        }
+
+        let mut result = Vec::new();
+        for ((mut stmt, init), replacements) in window.into_iter().zip(inits).zip(replacements) {
+            result.extend(init);
+            if !replacements.is_empty() {
+                let mut counter = 0;
+                for expr in stmt_exprs_mut(&mut stmt) {
+                    walk(expr, &mut counter, &mut WalkMode::Replace(&replacements));
+                }
+            }
+            result.push(stmt);
+        }
+        result
+    }
+
+    fn create_temp(&mut self) -> Shared<VarSymbol> {
+        let name = format!("___exalt__cse__temp___{}", self.next_temp);
+        self.next_temp += 1;
+        Rc::new(std::cell::RefCell::new(VarSymbol::new(
+            name,
+            Location::Generated,
+            false,
+        )))
+    }
+
+    /// Build a structural key for a pure expression, record what it reads, and return its size.
+    fn describe(&self, expr: &Expr, key: &mut String, reads: &mut Reads) -> usize {
+        match expr {
+            Expr::Literal(l) => {
+                key.push_str(&format!("{:?}", l));
+                1
+            }
+            Expr::Grouped(e) => self.describe(e, key, reads),
+            Expr::Unary(op, e) => {
+                key.push_str(&format!("({:?} ", op));
+                let size = self.describe(e, key, reads);
+                key.push(')');
+                size + 1
+            }
+            Expr::Binary(l, op, r) => {
+                key.push_str(&format!("({:?} ", op));
+                let left = self.describe(l, key, reads);
+                key.push(' ');
+                let right = self.describe(r, key, reads);
+                key.push(')');
+                left + right + 1
+            }
+            Expr::Ref(Ref::Var(symbol)) => {
+                self.record_read(symbol, reads);
+                key.push_str(&format!("v{:?}", var_id(symbol)));
+                1
+            }
+            Expr::Ref(Ref::Index(symbol, index)) => {
+                self.record_read(symbol, reads);
+                key.push_str(&format!("(v{:?} ", var_id(symbol)));
+                let size = self.describe(index, key, reads);
+                key.push(')');
+                size + 1
+            }
+            _ => unreachable!(),
+        }
+    }
+
+    fn record_read(&self, symbol: &Shared<VarSymbol>, reads: &mut Reads) {
+        let id = var_id(symbol);
+        reads.vars.insert(id);
+        if symbol.borrow().global || self.escaped.contains(&id) {
+            reads.memory = true;
+        }
+    }
+}
+
+fn stmt_exprs(stmt: &Stmt) -> Vec<&Expr> {
+    match stmt {
+        Stmt::Assignment { left, right, .. } => {
+            let mut exprs = ref_exprs(left);
+            exprs.push(right);
+            exprs
+        }
+        Stmt::ExprStmt(e) => vec![e],
+        Stmt::Printf(args) => args.iter().collect(),
+        Stmt::Return(Some(e)) => vec![e],
+        _ => Vec::new(),
+    }
+}
+
+fn stmt_exprs_mut(stmt: &mut Stmt) -> Vec<&mut Expr> {
+    match stmt {
+        Stmt::Assignment { left, right, .. } => {
+            let mut exprs = ref_exprs_mut(left);
+            exprs.push(right);
+            exprs
+        }
+        Stmt::ExprStmt(e) => vec![e],
+        Stmt::Printf(args) => args.iter_mut().collect(),
+        Stmt::Return(Some(e)) => vec![e],
+        _ => Vec::new(),
+    }
+}
+
+fn ref_exprs(reference: &Ref) -> Vec<&Expr> {
+    match reference {
+        Ref::Var(_) | Ref::Dereference(_, None) => Vec::new(),
+        Ref::Index(_, e) | Ref::Dereference(_, Some(e)) => vec![e],
+    }
+}
+
+fn ref_exprs_mut(reference: &mut Ref) -> Vec<&mut Expr> {
+    match reference {
+        Ref::Var(_) | Ref::Dereference(_, None) => Vec::new(),
+        Ref::Index(_, e) | Ref::Dereference(_, Some(e)) => vec![e],
+    }
+}
+
+/// Whether an expression has no side effects and only reads plain variables.
+fn is_pure(expr: &Expr) -> bool {
+    match expr {
+        Expr::Literal(_) => true,
+        Expr::Grouped(e) | Expr::Unary(_, e) => is_pure(e),
+        Expr::Binary(l, _, r) => is_pure(l) && is_pure(r),
+        Expr::Ref(Ref::Var(_)) => true,
+        Expr::Ref(Ref::Index(_, e)) => is_pure(e),
+        _ => false,
+    }
+}
+
+fn is_candidate(expr: &Expr) -> bool {
+    matches!(expr, Expr::Unary(_, _) | Expr::Binary(_, _, _)) && is_pure(expr)
+}
+
+fn is_short_circuit(op: &Operator) -> bool {
+    matches!(op, Operator::LogicalAnd | Operator::LogicalOr)
+}
+
+/// Visit candidate nodes in a fixed order so collection and replacement agree on ordinals.
+/// The right side of a short circuiting operator is never visited since it may not run.
+fn walk(expr: &mut Expr, counter: &mut usize, mode: &mut WalkMode) {
+    if is_candidate(expr) {
+        let ordinal = *counter;
+        match mode {
+            WalkMode::Collect(nodes) => {
+                let start = nodes.len();
+                nodes.push((ordinal, 0, expr.clone()));
+                *counter += 1;
+                walk_children(expr, counter, mode);
+                if let WalkMode::Collect(nodes) = mode {
+                    nodes[start].1 = *counter - ordinal;
+                }
+            }
+            WalkMode::Replace(replacements) => {
+                if let Some(temp) = replacements.get(&ordinal) {
+                    *counter += count_candidates(expr);
+                    *expr = Expr::Ref(Ref::Var(temp.clone()));
+                } else {
+                    *counter += 1;
+                    walk_children(expr, counter, mode);
+                }
+            }
+        }
+    } else {
+        walk_children(expr, counter, mode);
+    }
+}
+
+fn walk_children(expr: &mut Expr, counter: &mut usize, mode: &mut WalkMode) {
+    match expr {
+        Expr::Array(elements) => {
+            for e in elements {
+                walk(e, counter, mode);
+            }
+        }
+        Expr::Literal(_) => {}
+        Expr::Grouped(e) | Expr::Unary(_, e) => walk(e, counter, mode),
+        Expr::Binary(l, op, r) => {
+            walk(l, counter, mode);
+            if !is_short_circuit(op) {
+                walk(r, counter, mode);
+            }
+        }
+        // Only one branch runs, so nothing inside either can be shared
+        Expr::Conditional(condition, _, _) => walk(condition, counter, mode),
+        Expr::FunctionCall(_, args) => {
+            for e in args {
+                walk(e, counter, mode);
+            }
+        }
+        Expr::Ref(r) | Expr::Increment(r, _, _) | Expr::AddressOf(r) => {
+            for e in ref_exprs_mut(r) {
+                walk(e, counter, mode);
+            }
+        }
+    }
+}
+
+fn count_candidates(expr: &mut Expr) -> usize {
+    let mut nodes = Vec::new();
+    let mut counter = 0;
+    walk(expr, &mut counter, &mut WalkMode::Collect(&mut nodes));
+    counter
+}
+
+fn collect_clobbers(expr: &Expr, clobbers: &mut Clobbers) {
+    match expr {
+        Expr::Array(elements) => {
+            for e in elements {
+                collect_clobbers(e, clobbers);
+            }
+        }
+        Expr::Literal(_) => {}
+        Expr::Grouped(e) | Expr::Unary(_, e) => collect_clobbers(e, clobbers),
+        Expr::Binary(l, _, r) => {
+            collect_clobbers(l, clobbers);
+            collect_clobbers(r, clobbers);
+        }
+        Expr::Conditional(condition, then, otherwise) => {
+            collect_clobbers(condition, clobbers);
+            collect_clobbers(then, clobbers);
+            collect_clobbers(otherwise, clobbers);
+        }
+        Expr::FunctionCall(_, args) => {
+            clobbers.memory = true;
+            for e in args {
+                collect_clobbers(e, clobbers);
+            }
+        }
+        Expr::Increment(r, _, _) => {
+            clobbers.add_ref(r);
+            for e in ref_exprs(r) {
+                collect_clobbers(e, clobbers);
+            }
+        }
+        Expr::Ref(r) | Expr::AddressOf(r) => {
+            for e in ref_exprs(r) {
+                collect_clobbers(e, clobbers);
+            }
+        }
+    }
+}
+
+/// Find locals whose address is taken, since pointer writes and calls may modify them.
+fn collect_escaped_variables(stmt: &Stmt) -> HashSet<VarId> {
+    fn visit_expr(expr: &Expr, escaped: &mut HashSet<VarId>) {
+        match expr {
+            Expr::Array(elements) => elements.iter().for_each(|e| visit_expr(e, escaped)),
+            Expr::Literal(_) => {}
+            Expr::Grouped(e) | Expr::Unary(_, e) => visit_expr(e, escaped),
+            Expr::Binary(l, _, r) => {
+                visit_expr(l, escaped);
+                visit_expr(r, escaped);
+            }
+            Expr::Conditional(condition, then, otherwise) => {
+                visit_expr(condition, escaped);
+                visit_expr(then, escaped);
+                visit_expr(otherwise, escaped);
+            }
+            Expr::FunctionCall(_, args) => args.iter().for_each(|e| visit_expr(e, escaped)),
+            Expr::AddressOf(r) => {
+                match r {
+                    Ref::Var(symbol) | Ref::Index(symbol, _) | Ref::Dereference(symbol, _) => {
+                        escaped.insert(var_id(symbol));
+                    }
+                }
+                ref_exprs(r).into_iter().for_each(|e| visit_expr(e, escaped));
+            }
+            Expr::Ref(r) | Expr::Increment(r, _, _) => {
+                ref_exprs(r).into_iter().for_each(|e| visit_expr(e, escaped))
+            }
+        }
+    }
+
+    fn visit_stmt(stmt: &Stmt, escaped: &mut HashSet<VarId>) {
+        stmt_exprs(stmt).into_iter().for_each(|e| visit_expr(e, escaped));
+        match stmt {
+            Stmt::Block(stmts) => stmts.iter().for_each(|s| visit_stmt(s, escaped)),
+            Stmt::For {
+                init,
+                check,
+                step,
+                body,
+            } => {
+                visit_stmt(init, escaped);
+                visit_expr(check, escaped);
+                visit_stmt(step, escaped);
+                visit_stmt(body, escaped);
+            }
+            Stmt::If {
+                condition,
+                then_part,
+                else_part,
+            } => {
+                visit_expr(condition, escaped);
+                visit_stmt(then_part, escaped);
+                if let Some(else_part) = else_part {
+                    visit_stmt(else_part, escaped);
+                }
+            }
+            Stmt::Match {
+                switch,
+                cases,
+                default,
+            } => {
+                visit_expr(switch, escaped);
+                for case in cases {
+                    case.conditions.iter().for_each(|e| visit_expr(e, escaped));
+                    visit_stmt(&case.body, escaped);
+                }
+                if let Some(default) = default {
+                    visit_stmt(default, escaped);
+                }
+            }
+            Stmt::While { condition, body } | Stmt::DoWhile { body, condition } => {
+                visit_expr(condition, escaped);
+                visit_stmt(body, escaped);
+            }
+            _ => {}
+        }
+    }
+
+    let mut escaped = HashSet::new();
+    visit_stmt(stmt, &mut escaped);
+    escaped
+}
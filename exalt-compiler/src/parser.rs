@@ -1,9 +1,10 @@
 use crate::lexer::{Peekable, Token};
-use crate::reporting::{CompilerLog, ParserError};
+use crate::reporting::{CompilerLog, ParserError, Suggestion};
 use exalt_ast::surface::{
-    Annotation, Case, Decl, EnumVariant, Expr, Identifier, IncludePathComponent, Ref, Script, Stmt,
+    Annotation, AsmInstruction, Case, CaseCondition, Decl, EnumVariant, Expr, Identifier, IncludePathComponent, Parameter, Ref, Script, Stmt,
 };
-use exalt_ast::{FileId, Literal, Location, Notation, Operator, Precedence};
+use exalt_ast::{DataType, FileId, Literal, Location, Notation, Operator, Precedence};
+use std::ops::Range;
 
 type Result<T> = std::result::Result<T, ParserError>;
 
@@ -39,37 +40,108 @@ impl<'a, 'source> Parser<'a, 'source> {
 
     fn skip_to_next_decl(&mut self) {
         while let Some(t) = self.lex.peek() {
+            if starts_decl(t) || t == Token::Let {
+                break;
+            }
+            self.lex.next();
+        }
+    }
+
+    /// Skip the rest of a bad statement so the enclosing block can carry on.
+    /// Stops after a semicolon, after a nested block (and any else branch), before a variable declaration,
+    /// or before the end of the block.
+    fn skip_to_next_stmt(&mut self) {
+        let mut depth = 0;
+        let mut skipped = false;
+        while let Some(t) = self.lex.peek() {
+            match t {
+                Token::RightBrace if depth == 0 => break,
+                // Statements that fail on their first token still need to be skipped
+                Token::Let if depth == 0 && skipped => break,
+                t if depth == 0 && starts_decl(t) => break,
+                _ => {}
+            }
+            self.lex.next();
+            skipped = true;
             match t {
-                Token::Event | Token::Func | Token::Enum | Token::Const => break,
-                _ => {
-                    self.lex.next();
+                Token::Semicolon if depth == 0 => break,
+                Token::LeftBrace => depth += 1,
+                Token::RightBrace => {
+                    depth -= 1;
+                    if depth == 0 && self.lex.peek() != Some(Token::Else) {
+                        break;
+                    }
                 }
+                _ => {}
             }
         }
     }
 
+    fn at_decl_start(&mut self) -> bool {
+        self.lex.peek().is_none_or(starts_decl)
+    }
+
     fn at_end(&mut self) -> bool {
         self.lex.peek().is_none()
     }
 
     fn parse_decl(&mut self) -> Result<Decl> {
-        match self.peek_token()? {
+        let docs = self.doc_comment();
+        let mut decl = match self.peek_token()? {
             Token::Alias => self.parse_alias(),
             Token::Extern => self.parse_extern(),
-            Token::Const => self.parse_const(),
+            Token::Const => self.parse_const(Vec::new()),
             Token::Enum => self.parse_enum(),
             Token::Let => self.parse_global(),
             Token::Include => self.parse_include(),
+            Token::Inline => self.parse_inline(),
+            Token::Namespace => self.parse_namespace(),
             Token::AtSign | Token::Func | Token::Event => {
                 let annotations = self.parse_annotations()?;
                 match self.peek_token()? {
                     Token::Func => self.parse_function(annotations),
                     Token::Event => self.parse_callback(annotations),
+                    Token::Const => self.parse_const(annotations),
                     _ => Err(ParserError::ExpectedDecl(self.location())),
                 }
             }
             _ => Err(ParserError::ExpectedDecl(self.location())),
+        }?;
+        match &mut decl {
+            Decl::Function { docs: d, .. }
+            | Decl::Inline { docs: d, .. }
+            | Decl::FunctionAlias { docs: d, .. }
+            | Decl::FunctionExtern { docs: d, .. } => *d = docs,
+            _ => {}
         }
+        Ok(decl)
+    }
+
+    /// The `///` comment lines directly above the next token, without their markers.
+    fn doc_comment(&mut self) -> Option<String> {
+        self.lex.peek()?;
+        let source = self.lex.source();
+        let before = &source[..self.lex.span().start];
+        let mut lines = Vec::new();
+        // The first line is whatever precedes the token on its own line, which has to be blank
+        for (i, line) in before.rsplit('\n').enumerate() {
+            let line = line.trim();
+            if i == 0 {
+                if !line.is_empty() {
+                    return None;
+                }
+                continue;
+            }
+            match line.strip_prefix("///") {
+                Some(text) => lines.push(text.strip_prefix(' ').unwrap_or(text).trim_end()),
+                None => break,
+            }
+        }
+        if lines.is_empty() {
+            return None;
+        }
+        lines.reverse();
+        Some(lines.join("\n"))
     }
 
     fn parse_annotations(&mut self) -> Result<Vec<Annotation>> {
@@ -138,33 +210,61 @@ impl<'a, 'source> Parser<'a, 'source> {
             location: self.location().merge(&identifier.location),
             identifier,
             alias,
+            docs: None,
         })
     }
 
     fn parse_extern(&mut self) -> Result<Decl> {
         self.consume(Token::Extern)?;
+        if let Token::Let = self.peek_token()? {
+            return self.parse_extern_global();
+        }
         self.consume(Token::Func)?;
         let loc = self.location();
         let identifier = self.parse_identifier()?;
         let parameters = self.parse_function_parameters()?;
+        let return_type = self.parse_return_type()?;
         let location = self.location().merge(&loc);
         self.consume(Token::Semicolon)?;
         Ok(Decl::FunctionExtern {
             location,
             identifier,
             parameters,
+            return_type,
+            docs: None,
         })
     }
 
-    fn parse_const(&mut self) -> Result<Decl> {
+    fn parse_extern_global(&mut self) -> Result<Decl> {
+        self.consume(Token::Let)?;
+        let loc = self.location();
+        let identifier = self.parse_identifier()?;
+        let count = self.parse_global_count()?;
+        self.consume(Token::AtSign)?;
+        let index = self.parse_expression(Precedence::Lowest)?;
+        self.consume(Token::Semicolon)?;
+        Ok(Decl::GlobalExtern {
+            location: self.location().merge(&loc),
+            identifier,
+            count,
+            index,
+        })
+    }
+
+    fn parse_const(&mut self, annotations: Vec<Annotation>) -> Result<Decl> {
         self.consume(Token::Const)?;
         let loc = self.location();
         let identifier = self.parse_identifier()?;
         self.consume(Token::Assign)?;
-        let value = self.parse_expression(Precedence::Lowest)?;
+        let value = if let Token::LeftBracket = self.peek_token()? {
+            self.parse_static_array_init()?
+        } else {
+            self.parse_expression(Precedence::Lowest)?
+        };
         self.consume(Token::Semicolon)?;
         Ok(Decl::Constant {
             location: self.location().merge(&loc),
+            annotations,
             identifier,
             value,
         })
@@ -193,6 +293,27 @@ impl<'a, 'source> Parser<'a, 'source> {
         })
     }
 
+    fn parse_namespace(&mut self) -> Result<Decl> {
+        self.consume(Token::Namespace)?;
+        let loc = self.location();
+        let identifier = self.parse_identifier()?;
+        self.consume(Token::LeftBrace)?;
+        let mut decls = Vec::new();
+        while self.peek_token()? != Token::RightBrace {
+            // Includes are flattened into the top level, so they can't be scoped
+            if self.peek_token()? == Token::Include {
+                return Err(ParserError::ExpectedDecl(self.location()));
+            }
+            decls.push(self.parse_decl()?);
+        }
+        self.consume(Token::RightBrace)?;
+        Ok(Decl::Namespace {
+            location: self.location().merge(&loc),
+            identifier,
+            decls,
+        })
+    }
+
     fn parse_enum_variant(&mut self) -> Result<EnumVariant> {
         let identifier = self.parse_identifier()?;
         let loc = self.location();
@@ -210,6 +331,7 @@ impl<'a, 'source> Parser<'a, 'source> {
         let loc = self.location();
         let identifier = self.parse_identifier()?;
         let parameters = self.parse_function_parameters()?;
+        let return_type = self.parse_return_type()?;
         let signature_location = self.location().merge(&loc);
         let body = self.parse_block()?;
         Ok(Decl::Function {
@@ -217,37 +339,81 @@ impl<'a, 'source> Parser<'a, 'source> {
             annotations,
             identifier,
             parameters,
+            return_type,
+            body,
+            docs: None,
+        })
+    }
+
+    fn parse_inline(&mut self) -> Result<Decl> {
+        self.consume(Token::Inline)?;
+        self.consume(Token::Func)?;
+        let loc = self.location();
+        let identifier = self.parse_identifier()?;
+        let parameters = self.parse_function_parameters()?;
+        let signature_location = self.location().merge(&loc);
+        let body = self.parse_block()?;
+        Ok(Decl::Inline {
+            location: signature_location,
+            identifier,
+            parameters,
             body,
+            docs: None,
         })
     }
 
-    fn parse_function_parameters(&mut self) -> Result<Vec<Identifier>> {
+    fn parse_function_parameters(&mut self) -> Result<Vec<Parameter>> {
         self.consume(Token::LeftParen)?;
-        let parameters = if let Token::RightParen = self.peek_token()? {
-            Vec::new()
-        } else {
-            self.parse_identifiers(Token::RightParen)?
-        };
+        let mut parameters = Vec::new();
+        if self.peek_token()? != Token::RightParen {
+            parameters.push(self.parse_parameter()?);
+            while self.peek_token()? == Token::Comma {
+                self.consume(Token::Comma)?;
+                if self.peek_token()? != Token::RightParen {
+                    parameters.push(self.parse_parameter()?);
+                }
+            }
+        }
         self.consume(Token::RightParen)?;
         Ok(parameters)
     }
 
-    fn parse_identifiers(&mut self, terminator: Token) -> Result<Vec<Identifier>> {
-        let mut identifiers = vec![self.parse_identifier()?];
-        while self.peek_token()? == Token::Comma {
-            self.consume(Token::Comma)?;
-            if self.peek_token()? != terminator {
-                identifiers.push(self.parse_identifier()?);
-            }
+    fn parse_parameter(&mut self) -> Result<Parameter> {
+        let identifier = self.parse_identifier()?;
+        let data_type = if self.peek_token()? == Token::Colon {
+            self.consume(Token::Colon)?;
+            Some(self.parse_type()?)
+        } else {
+            None
+        };
+        Ok(Parameter::new(identifier, data_type))
+    }
+
+    fn parse_return_type(&mut self) -> Result<Option<DataType>> {
+        if self.peek_token()? == Token::Arrow {
+            self.consume(Token::Arrow)?;
+            Ok(Some(self.parse_type()?))
+        } else {
+            Ok(None)
+        }
+    }
+
+    fn parse_type(&mut self) -> Result<DataType> {
+        let identifier = self.parse_identifier()?;
+        match identifier.value.as_str() {
+            "int" => Ok(DataType::Int),
+            "float" => Ok(DataType::Float),
+            "string" => Ok(DataType::Str),
+            "any" => Ok(DataType::Any),
+            _ => Err(ParserError::ExpectedType(identifier.location)),
         }
-        Ok(identifiers)
     }
 
     fn parse_callback(&mut self, annotations: Vec<Annotation>) -> Result<Decl> {
         self.consume(Token::Event)?;
         let loc = self.location();
         self.consume(Token::LeftBracket)?;
-        let event_type = self.parse_expression(Precedence::Lowest)?;
+        let event_types = self.parse_comma_separated_expressions(Token::RightBracket)?;
         self.consume(Token::RightBracket)?;
         self.consume(Token::LeftParen)?;
         let args = if let Token::RightParen = self.peek_token()? {
@@ -260,7 +426,7 @@ impl<'a, 'source> Parser<'a, 'source> {
         Ok(Decl::Callback {
             location: self.location().merge(&loc),
             annotations,
-            event_type,
+            event_types,
             args,
             body,
         })
@@ -270,27 +436,41 @@ impl<'a, 'source> Parser<'a, 'source> {
         self.consume(Token::Let)?;
         let start_loc = self.location();
         let ident = self.parse_identifier()?;
-        let count = if let Token::LeftBracket = self.peek_token()? {
-            self.consume(Token::LeftBracket)?;
-            let count = self.parse_expression(Precedence::Lowest)?;
-            self.consume(Token::RightBracket)?;
-            Some(count)
+        let count = self.parse_global_count()?;
+        let index = if let Token::AtSign = self.peek_token()? {
+            self.consume(Token::AtSign)?;
+            Some(self.parse_expression(Precedence::Lowest)?)
         } else {
             None
         };
         self.consume(Token::Semicolon)?;
-        Ok(Decl::Global(
-            self.location().merge(&start_loc),
-            ident,
+        Ok(Decl::Global {
+            location: self.location().merge(&start_loc),
+            identifier: ident,
             count,
-        ))
+            index,
+        })
+    }
+
+    fn parse_global_count(&mut self) -> Result<Option<Expr>> {
+        if let Token::LeftBracket = self.peek_token()? {
+            self.consume(Token::LeftBracket)?;
+            let count = self.parse_expression(Precedence::Lowest)?;
+            self.consume(Token::RightBracket)?;
+            Ok(Some(count))
+        } else {
+            Ok(None)
+        }
     }
 
     fn parse_concrete_stmt(&mut self) -> Result<Stmt> {
         match self.peek_token()? {
             Token::LeftBrace => self.parse_block(),
+            Token::Asm => self.parse_asm(),
             Token::Break => self.parse_break(),
             Token::Continue => self.parse_continue(),
+            Token::Do => self.parse_do_while(),
+            Token::Fallthrough => self.parse_fallthrough(),
             Token::For => self.parse_for(),
             Token::Goto => self.parse_goto(),
             Token::If => self.parse_if(),
@@ -310,12 +490,54 @@ impl<'a, 'source> Parser<'a, 'source> {
         let loc = self.location();
         let mut contents = Vec::new();
         while self.peek_token()? != Token::RightBrace {
-            contents.push(self.parse_concrete_stmt()?);
+            match self.parse_concrete_stmt() {
+                Ok(stmt) => contents.push(stmt),
+                Err(ParserError::UnexpectedEof) => return Err(ParserError::UnexpectedEof),
+                Err(err) => {
+                    self.skip_to_next_stmt();
+                    // The block was never closed, so let parse_script recover at the next declaration
+                    if self.at_decl_start() {
+                        return Err(err);
+                    }
+                    self.log.log_error(err.into());
+                }
+            }
         }
         self.consume(Token::RightBrace)?;
         Ok(Stmt::Block(self.location().merge(&loc), contents))
     }
 
+    fn parse_asm(&mut self) -> Result<Stmt> {
+        self.consume(Token::Asm)?;
+        let loc = self.location();
+        self.consume(Token::LeftBrace)?;
+        let mut instructions = Vec::new();
+        while self.peek_token()? != Token::RightBrace {
+            let identifier = self.parse_identifier()?;
+            let start_loc = identifier.location.clone();
+            let args = if let Token::LeftParen = self.peek_token()? {
+                self.consume(Token::LeftParen)?;
+                let args = if let Token::RightParen = self.peek_token()? {
+                    Vec::new()
+                } else {
+                    self.parse_comma_separated_expressions(Token::RightParen)?
+                };
+                self.consume(Token::RightParen)?;
+                args
+            } else {
+                Vec::new()
+            };
+            instructions.push(AsmInstruction::new(
+                self.location().merge(&start_loc),
+                identifier,
+                args,
+            ));
+            self.consume(Token::Semicolon)?;
+        }
+        self.consume(Token::RightBrace)?;
+        Ok(Stmt::Asm(self.location().merge(&loc), instructions))
+    }
+
     fn parse_break(&mut self) -> Result<Stmt> {
         self.consume(Token::Break)?;
         let loc = self.location();
@@ -330,6 +552,13 @@ impl<'a, 'source> Parser<'a, 'source> {
         Ok(Stmt::Continue(loc))
     }
 
+    fn parse_fallthrough(&mut self) -> Result<Stmt> {
+        self.consume(Token::Fallthrough)?;
+        let loc = self.location();
+        self.consume(Token::Semicolon)?;
+        Ok(Stmt::Fallthrough(loc))
+    }
+
     fn parse_for(&mut self) -> Result<Stmt> {
         self.consume(Token::For)?;
         let start_loc = self.location();
@@ -429,22 +658,140 @@ impl<'a, 'source> Parser<'a, 'source> {
     }
 
     fn parse_match_case(&mut self) -> Result<Case> {
-        let conditions = self.parse_comma_separated_expressions(Token::Arrow)?;
+        let mut conditions = vec![self.parse_case_condition()?];
+        while self.peek_token()? == Token::Comma {
+            self.consume(Token::Comma)?;
+            if self.peek_token()? != Token::Arrow {
+                conditions.push(self.parse_case_condition()?);
+            }
+        }
         self.consume(Token::Arrow)?;
         let body = self.parse_block()?;
         Ok(Case::new(conditions, body))
     }
 
+    fn parse_case_condition(&mut self) -> Result<CaseCondition> {
+        let start = self.parse_expression(Precedence::Lowest)?;
+        if self.peek_token()? == Token::DotDot {
+            self.consume(Token::DotDot)?;
+            let end = self.parse_expression(Precedence::Lowest)?;
+            let location = start.location().merge(end.location());
+            Ok(CaseCondition::Range(location, start, end))
+        } else {
+            Ok(CaseCondition::Value(start))
+        }
+    }
+
     fn parse_printf(&mut self) -> Result<Stmt> {
         self.consume(Token::Printf)?;
         let start_loc = self.location();
         self.consume(Token::LeftParen)?;
-        let args = self.parse_comma_separated_expressions(Token::RightParen)?;
+        let mut args = self.parse_comma_separated_expressions(Token::RightParen)?;
         self.consume(Token::RightParen)?;
         self.consume(Token::Semicolon)?;
+        // Formats with other arguments are plain printf formats, so their braces are literal text
+        if let [Expr::Literal(location, Literal::Str(format))] = args.as_slice() {
+            if let Some((format, placeholders)) = self.desugar_interpolation(location, format)? {
+                args[0] = Expr::Literal(location.clone(), Literal::Str(format));
+                args.extend(placeholders);
+            }
+        }
         Ok(Stmt::Printf(self.location().merge(&start_loc), args))
     }
 
+    /// Turn a format like "count={count} name={name:s}" into a plain format and the variables it prints.
+    /// Placeholders print with %d unless they name a format after a colon. Braces are escaped by doubling them.
+    /// Returns None if the format has no placeholders or escaped braces, in which case any braces are literal text.
+    fn desugar_interpolation(
+        &self,
+        location: &Location,
+        format: &str,
+    ) -> Result<Option<(String, Vec<Expr>)>> {
+        // Skip the opening quote so offsets line up with the source
+        let (base, offsets) = match location {
+            Location::Source(_, range) => (
+                range.start + 1,
+                escape_offsets(&self.lex.source()[range.start + 1..range.end - 1]),
+            ),
+            _ => (0, Vec::new()),
+        };
+        let placeholder_location = |start: usize, end: usize| {
+            let offset = |i: usize| offsets.get(i).copied().unwrap_or(i);
+            Location::Source(self.file_id, base + offset(start)..base + offset(end))
+        };
+        let mut desugared = String::new();
+        let mut placeholders = Vec::new();
+        let mut escaped = false;
+        let mut invalid = None;
+        let mut chars = format.char_indices().peekable();
+        while let Some((i, c)) = chars.next() {
+            match c {
+                '{' if matches!(chars.peek(), Some((_, '{'))) => {
+                    chars.next();
+                    desugared.push('{');
+                    escaped = true;
+                }
+                '}' if matches!(chars.peek(), Some((_, '}'))) => {
+                    chars.next();
+                    desugared.push('}');
+                    escaped = true;
+                }
+                '{' => {
+                    let end = match format[i..].find('}') {
+                        Some(offset) => i + offset,
+                        None => {
+                            invalid.get_or_insert(placeholder_location(i, format.len()));
+                            continue;
+                        }
+                    };
+                    let contents = &format[i + 1..end];
+                    // Namespaced names contain "::", so only a lone colon starts the format
+                    let (name, spec) = match contents.rsplit_once(':') {
+                        Some((name, spec)) if !name.ends_with(':') => (name, spec),
+                        _ => (contents, "d"),
+                    };
+                    let is_name = name.starts_with(|c: char| c.is_alphabetic() || c == '_')
+                        && name
+                            .chars()
+                            .all(|c| c.is_alphanumeric() || c == '_' || c == ':');
+                    let is_spec = !spec.is_empty()
+                        && spec
+                            .chars()
+                            .all(|c| c.is_alphanumeric() || c == '-' || c == '.');
+                    if !is_name || !is_spec {
+                        invalid.get_or_insert(placeholder_location(i, end + 1));
+                        continue;
+                    }
+                    let identifier = Identifier::new(
+                        placeholder_location(i + 1, i + 1 + name.len()),
+                        name.to_owned(),
+                    );
+                    placeholders.push(Expr::Ref(
+                        placeholder_location(i, end + 1),
+                        Ref::Var(identifier),
+                    ));
+                    desugared.push('%');
+                    desugared.push_str(spec);
+                    while matches!(chars.peek(), Some((j, _)) if *j <= end) {
+                        chars.next();
+                    }
+                }
+                '}' => {
+                    invalid.get_or_insert(placeholder_location(i, i + 1));
+                }
+                _ => desugared.push(c),
+            }
+        }
+        if placeholders.is_empty() && !escaped {
+            return Ok(None);
+        }
+        // Stray braces next to placeholders are more likely a typo than literal text
+        match invalid {
+            Some(location) => Err(ParserError::InvalidPlaceholder(location)),
+            None => Ok(Some((desugared, placeholders))),
+        }
+    }
+
     fn parse_return(&mut self) -> Result<Stmt> {
         self.consume(Token::Return)?;
         let start_loc = self.location();
@@ -492,6 +839,22 @@ impl<'a, 'source> Parser<'a, 'source> {
         })
     }
 
+    fn parse_do_while(&mut self) -> Result<Stmt> {
+        self.consume(Token::Do)?;
+        let start_loc = self.location();
+        let body = self.parse_concrete_stmt()?;
+        self.consume(Token::While)?;
+        self.consume(Token::LeftParen)?;
+        let condition = self.parse_expression(Precedence::Lowest)?;
+        self.consume(Token::RightParen)?;
+        self.consume(Token::Semicolon)?;
+        Ok(Stmt::DoWhile {
+            location: self.location().merge(&start_loc),
+            body: Box::new(body),
+            condition,
+        })
+    }
+
     fn parse_yield(&mut self) -> Result<Stmt> {
         self.consume(Token::Yield)?;
         let loc = self.location();
@@ -600,6 +963,7 @@ impl<'a, 'source> Parser<'a, 'source> {
             Token::Ampersand | Token::BinaryOr | Token::Xor => Precedence::Bitwise,
             Token::LogicalAnd => Precedence::LogicalAnd,
             Token::LogicalOr => Precedence::LogicalOr,
+            Token::QuestionMark => Precedence::Conditional,
             Token::Increment | Token::Decrement | Token::LogicalNot | Token::BinaryNot => {
                 Precedence::Unary
             }
@@ -762,10 +1126,16 @@ impl<'a, 'source> Parser<'a, 'source> {
 
     fn parse_string(&mut self) -> Result<Expr> {
         self.consume(Token::Str)?;
-        Ok(Expr::Literal(
-            self.location(),
-            Literal::Str(self.lex.slice()[1..self.lex.slice().len() - 1].to_owned()),
-        ))
+        let slice = self.lex.slice();
+        let value = unescape(&slice[1..slice.len() - 1]).map_err(|range| {
+            // Skip the opening quote so offsets line up with the source
+            let base = self.lex.span().start + 1;
+            ParserError::InvalidEscape(Location::Source(
+                self.file_id,
+                base + range.start..base + range.end,
+            ))
+        })?;
+        Ok(Expr::Literal(self.location(), Literal::Str(value)))
     }
 
     fn parse_int(&mut self, negated: bool) -> Result<Expr> {
@@ -854,6 +1224,7 @@ impl<'a, 'source> Parser<'a, 'source> {
                 self.parse_binary_expr(left, Token::LogicalAnd, Operator::LogicalAnd)
             }
             Token::LogicalOr => self.parse_binary_expr(left, Token::LogicalOr, Operator::LogicalOr),
+            Token::QuestionMark => self.parse_conditional_expr(left),
             Token::BinaryNot => {
                 self.parse_binary_expr(left, Token::BinaryNot, Operator::BitwiseNot)
             }
@@ -890,6 +1261,21 @@ impl<'a, 'source> Parser<'a, 'source> {
         }
     }
 
+    /// Parse the rest of `condition ? then : otherwise`.
+    /// Nested conditionals group to the right, so `a ? b : c ? d : e` picks between b and `c ? d : e`.
+    fn parse_conditional_expr(&mut self, condition: Expr) -> Result<Expr> {
+        self.consume(Token::QuestionMark)?;
+        let then = self.parse_expression(Precedence::Lowest)?;
+        self.consume(Token::Colon)?;
+        let otherwise = self.parse_expression(Precedence::Assignment)?;
+        Ok(Expr::Conditional(
+            condition.location().merge(otherwise.location()),
+            Box::new(condition),
+            Box::new(then),
+            Box::new(otherwise),
+        ))
+    }
+
     fn parse_binary_expr(&mut self, left: Expr, expected: Token, op: Operator) -> Result<Expr> {
         self.consume(expected)?;
         let right = self.parse_expression(op.into())?;
@@ -913,9 +1299,17 @@ impl<'a, 'source> Parser<'a, 'source> {
 
     /// Consume a token and error if its not the expected token.
     fn consume(&mut self, expected: Token) -> Result<()> {
+        let previous_end = self.lex.last_end();
         let actual = self.next_token()?;
         if expected == actual {
             Ok(())
+        } else if expected == Token::Semicolon {
+            // Point just past the previous token since that's where the semicolon belongs
+            let location = Location::Source(self.file_id, previous_end..previous_end);
+            Err(ParserError::MissingSemicolon(
+                location.clone(),
+                vec![Suggestion::new(location, ";".to_owned())],
+            ))
         } else {
             Err(ParserError::UnexpectedToken(
                 self.location(),
@@ -951,6 +1345,94 @@ impl<'a, 'source> Parser<'a, 'source> {
     }
 }
 
+/// Resolve the escape sequences in the contents of a string literal.
+/// On failure, returns the range of the bad escape within the contents.
+fn unescape(raw: &str) -> std::result::Result<String, Range<usize>> {
+    let mut value = String::with_capacity(raw.len());
+    let mut rest = raw;
+    while let Some(index) = rest.find('\\') {
+        value.push_str(&rest[..index]);
+        let start = raw.len() - rest.len() + index;
+        let escape = &rest[index + 1..];
+        let (c, length) = parse_escape(escape).ok_or_else(|| {
+            let length = escape.chars().next().map_or(0, char::len_utf8);
+            start..start + 1 + length
+        })?;
+        value.push(c);
+        rest = &escape[length..];
+    }
+    value.push_str(rest);
+    Ok(value)
+}
+
+/// Where each byte of an unescaped string starts in the source text, followed by the end of the text.
+/// Every byte of a character written as an escape maps to its backslash.
+fn escape_offsets(raw: &str) -> Vec<usize> {
+    let mut offsets = Vec::with_capacity(raw.len() + 1);
+    let mut i = 0;
+    while let Some(first) = raw[i..].chars().next() {
+        let (c, length) = match raw[i..].strip_prefix('\\').and_then(parse_escape) {
+            Some((c, length)) => (c, length + 1),
+            None => (first, first.len_utf8()),
+        };
+        offsets.extend(std::iter::repeat_n(i, c.len_utf8()));
+        i += length;
+    }
+    offsets.push(raw.len());
+    offsets
+}
+
+/// Parse an escape sequence after its backslash, returning the character and how many bytes it used.
+/// Strings are stored as text, so \xNN can only name single-byte Shift-JIS characters (ASCII and half-width katakana).
+/// Null is rejected since it would end the string in the compiled script.
+fn parse_escape(escape: &str) -> Option<(char, usize)> {
+    let is_hex = |digits: &&str| !digits.is_empty() && digits.bytes().all(|b| b.is_ascii_hexdigit());
+    let (c, length) = match escape.chars().next()? {
+        '"' => ('"', 1),
+        '\\' => ('\\', 1),
+        'n' => ('\n', 1),
+        'r' => ('\r', 1),
+        't' => ('\t', 1),
+        'x' => {
+            let byte = u8::from_str_radix(escape.get(1..3).filter(is_hex)?, 16).ok()?;
+            let c = match byte {
+                0x00..=0x7F => char::from(byte),
+                0xA1..=0xDF => char::from_u32(0xFF61 + u32::from(byte - 0xA1))?,
+                _ => return None,
+            };
+            (c, 3)
+        }
+        'u' => {
+            let end = escape.find('}')?;
+            let digits = escape.get(1..end)?.strip_prefix('{').filter(is_hex)?;
+            let c = char::from_u32(u32::from_str_radix(digits, 16).ok()?)?;
+            (c, end + 1)
+        }
+        _ => return None,
+    };
+    if c == '\0' {
+        None
+    } else {
+        Some((c, length))
+    }
+}
+
+/// Whether a token can only begin a declaration. `let` also begins statements, so it isn't included.
+fn starts_decl(token: Token) -> bool {
+    matches!(
+        token,
+        Token::Event
+            | Token::Func
+            | Token::Enum
+            | Token::Const
+            | Token::Inline
+            | Token::Namespace
+            | Token::Extern
+            | Token::Include
+            | Token::Alias
+    )
+}
+
 pub fn parse(file_id: FileId, source: &str, log: &mut CompilerLog) -> Script {
     Parser::new(file_id, source, log).parse_script()
 }
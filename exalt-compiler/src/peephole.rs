@@ -0,0 +1,93 @@
+use std::collections::HashSet;
+
+use exalt_lir::{Opcode, RawScript};
+
+use crate::codegen::SourceLocations;
+
+/// Remove redundant opcode sequences from generated code.
+/// Source locations are moved to follow the opcodes they point at.
+pub fn optimize(script: &mut RawScript, locations: &mut [SourceLocations]) {
+    for (function, locations) in script.functions.iter_mut().zip(locations) {
+        loop {
+            let removed = find_redundant(&function.code);
+            if !removed.contains(&true) {
+                break;
+            }
+            remove(&mut function.code, locations, &removed);
+        }
+    }
+}
+
+/// Opcodes that push a value without any other effect.
+fn is_pure_push(opcode: &Opcode) -> bool {
+    matches!(
+        opcode,
+        Opcode::VarLoad(_)
+            | Opcode::VarAddr(_)
+            | Opcode::GlobalVarLoad(_)
+            | Opcode::GlobalVarAddr(_)
+            | Opcode::IntLoad(_)
+            | Opcode::StrLoad(_)
+            | Opcode::FloatLoad(_)
+    )
+}
+
+/// Unary opcodes that undo themselves when applied twice.
+/// LogicalNot is left out since applying it twice turns any nonzero value into 1.
+fn is_involution(opcode: &Opcode) -> bool {
+    matches!(
+        opcode,
+        Opcode::IntNegate | Opcode::FloatNegate | Opcode::BinaryNot
+    )
+}
+
+fn find_redundant(code: &[Opcode]) -> Vec<bool> {
+    let referenced: HashSet<&str> = code.iter().filter_map(Opcode::label_target).collect();
+    let mut removed = vec![false; code.len()];
+    let mut i = 0;
+    while i < code.len() {
+        match &code[i] {
+            // Nothing jumps here, so execution only ever falls through
+            Opcode::Label(l) if !referenced.contains(l.as_str()) => removed[i] = true,
+            // Jump to a label that directly follows, possibly behind other labels
+            Opcode::Jump(target) => {
+                let jumps_to_next = code[i + 1..]
+                    .iter()
+                    .take_while(|o| matches!(o, Opcode::Label(_)))
+                    .any(|o| matches!(o, Opcode::Label(l) if l == target));
+                removed[i] = jumps_to_next;
+            }
+            opcode if i + 1 < code.len() => {
+                let next = &code[i + 1];
+                let pair = (is_pure_push(opcode) && *next == Opcode::Consume)
+                    || (is_involution(opcode) && next == opcode);
+                if pair {
+                    removed[i] = true;
+                    removed[i + 1] = true;
+                    i += 1;
+                }
+            }
+            _ => {}
+        }
+        i += 1;
+    }
+    removed
+}
+
+fn remove(code: &mut Vec<Opcode>, locations: &mut SourceLocations, removed: &[bool]) {
+    // New index of every opcode, plus one past the end
+    let mut new_indices = Vec::with_capacity(code.len() + 1);
+    let mut next = 0;
+    for is_removed in removed {
+        new_indices.push(next);
+        if !is_removed {
+            next += 1;
+        }
+    }
+    new_indices.push(next);
+    for (index, _) in locations.iter_mut() {
+        *index = new_indices[*index];
+    }
+    let mut flags = removed.iter();
+    code.retain(|_| !flags.next().unwrap());
+}
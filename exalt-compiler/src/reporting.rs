@@ -1,12 +1,89 @@
 use std::borrow::Cow;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 
-use codespan_reporting::diagnostic::{Diagnostic, Label};
-use codespan_reporting::files::SimpleFiles;
+use crate::eval::MAX_STRING_LENGTH;
+use crate::semantic::MAX_RANGE_VALUES;
+use codespan_reporting::diagnostic::{Diagnostic, Label, LabelStyle, Severity};
+use codespan_reporting::files::{Files, SimpleFiles};
 use codespan_reporting::term::termcolor::{ColorChoice, StandardStream};
 use codespan_reporting::term::{self};
 use exalt_ast::surface::Identifier;
 use exalt_ast::{FileId, Location, Operator};
+use serde::Serialize;
+use strum_macros::{EnumString, IntoStaticStr};
+
+/// How the compiler writes out errors and warnings.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, EnumString)]
+#[strum(serialize_all = "snake_case")]
+pub enum MessageFormat {
+    /// Colored snippets for a terminal.
+    #[default]
+    Human,
+
+    /// One JSON array of [`DiagnosticRecord`] for editors and CI.
+    Json,
+}
+
+/// Where a diagnostic points in its file. Lines and columns start at 1.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Span {
+    pub start: usize,
+    pub end: usize,
+    pub line: usize,
+    pub column: usize,
+    pub end_line: usize,
+    pub end_column: usize,
+}
+
+/// A machine-applicable fix: replace the text at a location. An empty range inserts.
+#[derive(Debug, Clone)]
+pub struct Suggestion {
+    pub location: Location,
+    pub replacement: String,
+}
+
+impl Suggestion {
+    pub fn new(location: Location, replacement: String) -> Self {
+        Suggestion {
+            location,
+            replacement,
+        }
+    }
+
+    fn note(&self) -> String {
+        match self.location.range() {
+            Some(r) if r.is_empty() => format!("help: insert '{}'", self.replacement),
+            _ => format!("help: replace with '{}'", self.replacement),
+        }
+    }
+}
+
+/// A [`Suggestion`] in a form that can be serialized.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct SuggestionRecord {
+    pub file: Option<String>,
+    pub span: Option<Span>,
+    pub replacement: String,
+}
+
+/// An error or warning in a form that can be serialized.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct DiagnosticRecord {
+    /// Either "error" or "warning".
+    pub severity: &'static str,
+
+    /// Stable name for the kind of problem (ex. undefined-variable).
+    pub code: &'static str,
+    pub message: String,
+    pub file: Option<String>,
+    pub span: Option<Span>,
+
+    /// Extra details, including any secondary locations as "file:line:column: message".
+    pub notes: Vec<String>,
+
+    /// Fixes an editor can apply without asking for more input.
+    pub suggestions: Vec<SuggestionRecord>,
+}
 
 /// Aggregator for issues found while compiling
 #[derive(Debug)]
@@ -27,10 +104,24 @@ impl CompilerLog {
         }
     }
 
+    /// Create a log that continues from an existing file database.
+    pub fn with_files(files: SimpleFiles<String, String>, next_file_id: usize) -> Self {
+        CompilerLog {
+            errors: Vec::new(),
+            warnings: Vec::new(),
+            files,
+            next_file_id,
+        }
+    }
+
     pub fn file(&self, file_id: FileId) -> Option<String> {
         self.files.get(file_id).ok().map(|f| f.name().to_string())
     }
 
+    pub fn source(&self, file_id: FileId) -> Option<String> {
+        self.files.get(file_id).ok().map(|f| f.source().to_string())
+    }
+
     pub fn peek_file_id(&self) -> FileId {
         self.next_file_id
     }
@@ -40,6 +131,20 @@ impl CompilerLog {
         self.files.add(name, source)
     }
 
+    /// Swap in new contents for a file without giving it a new id.
+    pub(crate) fn replace_source(&mut self, file_id: FileId, source: String) {
+        let mut files = SimpleFiles::new();
+        for id in 0..self.next_file_id {
+            let (name, contents) = match self.files.get(id) {
+                Ok(file) if id == file_id => (file.name().clone(), source.clone()),
+                Ok(file) => (file.name().clone(), file.source().clone()),
+                Err(_) => (String::new(), String::new()),
+            };
+            files.add(name, contents);
+        }
+        self.files = files;
+    }
+
     pub fn has_errors(&self) -> bool {
         !self.errors.is_empty()
     }
@@ -56,6 +161,100 @@ impl CompilerLog {
         self.warnings.push(warning)
     }
 
+    /// Line and column information for a range in one of the log's files.
+    pub fn span(&self, file_id: FileId, range: &std::ops::Range<usize>) -> Option<Span> {
+        let start = self.files.location(file_id, range.start).ok()?;
+        let end = self.files.location(file_id, range.end).ok()?;
+        Some(Span {
+            start: range.start,
+            end: range.end,
+            line: start.line_number,
+            column: start.column_number,
+            end_line: end.line_number,
+            end_column: end.column_number,
+        })
+    }
+
+    fn record(
+        &self,
+        code: &'static str,
+        diagnostic: Diagnostic<FileId>,
+        suggestions: &[Suggestion],
+    ) -> DiagnosticRecord {
+        let severity = match diagnostic.severity {
+            Severity::Bug | Severity::Error => "error",
+            _ => "warning",
+        };
+        let primary = diagnostic
+            .labels
+            .iter()
+            .find(|l| l.style == LabelStyle::Primary);
+        let mut notes = Vec::new();
+        for label in &diagnostic.labels {
+            if label.style != LabelStyle::Secondary {
+                continue;
+            }
+            if let (Some(file), Some(span)) = (
+                self.file(label.file_id),
+                self.span(label.file_id, &label.range),
+            ) {
+                notes.push(format!(
+                    "{}:{}:{}: {}",
+                    file, span.line, span.column, label.message
+                ));
+            }
+        }
+        notes.extend(diagnostic.notes);
+        DiagnosticRecord {
+            severity,
+            code,
+            message: diagnostic.message,
+            file: primary.and_then(|l| self.file(l.file_id)),
+            span: primary.and_then(|l| self.span(l.file_id, &l.range)),
+            notes,
+            suggestions: suggestions
+                .iter()
+                .map(|s| match &s.location {
+                    Location::Source(file_id, range) => SuggestionRecord {
+                        file: self.file(*file_id),
+                        span: self.span(*file_id, range),
+                        replacement: s.replacement.clone(),
+                    },
+                    _ => SuggestionRecord {
+                        file: None,
+                        span: None,
+                        replacement: s.replacement.clone(),
+                    },
+                })
+                .collect(),
+        }
+    }
+
+    /// Every warning and error in the log, warnings first.
+    pub fn diagnostics(&self) -> Vec<DiagnosticRecord> {
+        let warnings = self
+            .warnings
+            .iter()
+            .map(|w| self.record(w.code(), w.to_diagnostic(), &[]));
+        let errors = self
+            .errors
+            .iter()
+            .map(|e| self.record(e.code(), e.to_diagnostic(), e.suggestions()));
+        warnings.chain(errors).collect()
+    }
+
+    pub fn to_json(&self) -> String {
+        serde_json::to_string(&self.diagnostics()).unwrap_or_default()
+    }
+
+    /// Write the log to the terminal. Human output goes to stderr and JSON to stdout.
+    pub fn emit(&self, format: MessageFormat) {
+        match format {
+            MessageFormat::Human => self.print(),
+            MessageFormat::Json => println!("{}", self.to_json()),
+        }
+    }
+
     pub fn print(&self) {
         let writer = StandardStream::stderr(ColorChoice::Always);
         let config = codespan_reporting::term::Config::default();
@@ -91,13 +290,27 @@ impl ErrorMessage {
         }
     }
 
-    pub fn message(&self) -> Cow<str> {
+    pub fn message(&self) -> Cow<'_, str> {
         match self {
             ErrorMessage::Parser(err) => err.message(),
             ErrorMessage::Semantic(err) => err.message(),
         }
     }
 
+    pub fn code(&self) -> &'static str {
+        match self {
+            ErrorMessage::Parser(err) => err.into(),
+            ErrorMessage::Semantic(err) => err.into(),
+        }
+    }
+
+    pub fn suggestions(&self) -> &[Suggestion] {
+        match self {
+            ErrorMessage::Parser(err) => err.suggestions(),
+            ErrorMessage::Semantic(err) => err.suggestions(),
+        }
+    }
+
     pub fn to_diagnostic(&self) -> Diagnostic<FileId> {
         match self {
             ErrorMessage::Parser(err) => err.to_diagnostic(),
@@ -119,11 +332,13 @@ impl From<SemanticError> for ErrorMessage {
 }
 
 /// Parser-specific error messages
-#[derive(Debug)]
+#[derive(Debug, IntoStaticStr)]
+#[strum(serialize_all = "kebab-case")]
 pub enum ParserError {
     InvalidToken(Location),
     UnexpectedEof,
     UnexpectedToken(Location, String, String),
+    MissingSemicolon(Location, Vec<Suggestion>),
     InvalidInt(Location),
     InvalidFloat(Location),
     ExpectedAssignment(Location),
@@ -132,12 +347,16 @@ pub enum ParserError {
     ExpectedLoopRange(Location),
     ExpectedStmt(Location),
     ExpectedDecl(Location),
+    ExpectedType(Location),
+    InvalidPlaceholder(Location),
+    InvalidEscape(Location),
     MultipleDefaultCases(Location, Location),
     DoubleDereference(Location),
     ExpectedIncludePathComponent(Location),
     PathNormalizationError(Location, PathBuf),
-    IncludeNotFound(Location),
+    IncludeNotFound(Location, Vec<PathBuf>),
     IncludeError(Location),
+    IncludeCycle(Location, PathBuf, Vec<(Location, PathBuf)>),
 }
 
 impl ParserError {
@@ -146,6 +365,7 @@ impl ParserError {
             ParserError::InvalidToken(l) => Some(l),
             ParserError::UnexpectedEof => None,
             ParserError::UnexpectedToken(l, _, _) => Some(l),
+            ParserError::MissingSemicolon(l, _) => Some(l),
             ParserError::InvalidInt(l) => Some(l),
             ParserError::InvalidFloat(l) => Some(l),
             ParserError::ExpectedAssignment(l) => Some(l),
@@ -154,22 +374,27 @@ impl ParserError {
             ParserError::ExpectedLoopRange(l) => Some(l),
             ParserError::ExpectedStmt(l) => Some(l),
             ParserError::ExpectedDecl(l) => Some(l),
+            ParserError::ExpectedType(l) => Some(l),
+            ParserError::InvalidPlaceholder(l) => Some(l),
+            ParserError::InvalidEscape(l) => Some(l),
             ParserError::MultipleDefaultCases(l, _) => Some(l),
             ParserError::DoubleDereference(l) => Some(l),
             ParserError::ExpectedIncludePathComponent(l) => Some(l),
             ParserError::PathNormalizationError(l, _) => Some(l),
-            ParserError::IncludeNotFound(l) => Some(l),
+            ParserError::IncludeNotFound(l, _) => Some(l),
             ParserError::IncludeError(l) => Some(l),
+            ParserError::IncludeCycle(l, _, _) => Some(l),
         }
     }
 
-    pub fn message(&self) -> Cow<str> {
+    pub fn message(&self) -> Cow<'_, str> {
         match self {
             ParserError::InvalidToken(_) => Cow::Borrowed("invalid token"),
             ParserError::UnexpectedEof => Cow::Borrowed("unexpected end of file"),
             ParserError::UnexpectedToken(_, e, a) => {
                 Cow::Owned(format!("expected token '{}' found '{}'", e, a))
             }
+            ParserError::MissingSemicolon(_, _) => Cow::Borrowed("expected ';'"),
             ParserError::InvalidInt(_) => Cow::Borrowed("int value must fit in 32 bits"),
             ParserError::InvalidFloat(_) => Cow::Borrowed("float value must fit in 32 bits"),
             ParserError::ExpectedAssignment(_) => Cow::Borrowed("expected assignment"),
@@ -178,6 +403,9 @@ impl ParserError {
             ParserError::ExpectedLoopRange(_) => Cow::Borrowed("expected loop range"),
             ParserError::ExpectedStmt(_) => Cow::Borrowed("expected statement"),
             ParserError::ExpectedDecl(_) => Cow::Borrowed("expected declaration"),
+            ParserError::ExpectedType(_) => Cow::Borrowed("expected type"),
+            ParserError::InvalidPlaceholder(_) => Cow::Borrowed("invalid printf placeholder"),
+            ParserError::InvalidEscape(_) => Cow::Borrowed("invalid escape sequence"),
             ParserError::MultipleDefaultCases(_, _) => {
                 Cow::Borrowed("match can only have one default case")
             }
@@ -190,8 +418,16 @@ impl ParserError {
             ParserError::PathNormalizationError(_, p) => {
                 Cow::Owned(format!("unable to normalize path {}", p.display()))
             }
-            ParserError::IncludeNotFound(_) => Cow::Borrowed("unable to resolve path"),
+            ParserError::IncludeNotFound(_, _) => Cow::Borrowed("unable to resolve path"),
             ParserError::IncludeError(_) => Cow::Borrowed("undefined include error"),
+            ParserError::IncludeCycle(_, _, _) => Cow::Borrowed("include cycle"),
+        }
+    }
+
+    pub fn suggestions(&self) -> &[Suggestion] {
+        match self {
+            ParserError::MissingSemicolon(_, suggestions) => suggestions,
+            _ => &[],
         }
     }
 
@@ -210,6 +446,12 @@ impl ParserError {
                 .with_labels(option_to_vec(
                     primary(l).map(|v| v.with_message("invalid token")),
                 )),
+            ParserError::MissingSemicolon(l, suggestions) => Diagnostic::error()
+                .with_message("expected ';'")
+                .with_labels(option_to_vec(
+                    primary(l).map(|v| v.with_message("missing ';' here")),
+                ))
+                .with_notes(suggestions.iter().map(Suggestion::note).collect()),
             ParserError::InvalidInt(l) => Diagnostic::error()
                 .with_message("int value must fit in 32 bits")
                 .with_labels(option_to_vec(primary(l))),
@@ -246,6 +488,24 @@ impl ParserError {
                 .with_labels(option_to_vec(
                     primary(l).map(|v| v.with_message("expected declaration")),
                 )),
+            ParserError::ExpectedType(l) => Diagnostic::error()
+                .with_message("expected type")
+                .with_labels(option_to_vec(
+                    primary(l).map(|v| v.with_message("expected int, float, string, or any")),
+                )),
+            ParserError::InvalidPlaceholder(l) => Diagnostic::error()
+                .with_message("invalid printf placeholder")
+                .with_labels(option_to_vec(primary(l).map(|v| {
+                    v.with_message("expected a variable and an optional format (ex. {count:x})")
+                })))
+                .with_notes(vec!["write {{ and }} for literal braces".to_owned()]),
+            ParserError::InvalidEscape(l) => Diagnostic::error()
+                .with_message("invalid escape sequence")
+                .with_labels(option_to_vec(primary(l).map(|v| {
+                    v.with_message(
+                        "expected \\\", \\\\, \\n, \\r, \\t, \\xNN (single-byte Shift-JIS), or \\u{...}",
+                    )
+                }))),
             ParserError::MultipleDefaultCases(first, second) => Diagnostic::error()
                 .with_message("match can only have one default case")
                 .with_labels({
@@ -274,26 +534,57 @@ impl ParserError {
                 .with_labels(option_to_vec(
                     primary(l).map(|v| v.with_message("unable to normalize path")),
                 )),
-            ParserError::IncludeNotFound(l) => Diagnostic::error()
+            ParserError::IncludeNotFound(l, searched) => Diagnostic::error()
                 .with_message("unable to resolve path")
                 .with_labels(option_to_vec(
                     primary(l).map(|v| v.with_message("could not find this file")),
-                )),
+                ))
+                // Includes resolved in memory don't search any directories
+                .with_notes(if searched.is_empty() {
+                    Vec::new()
+                } else {
+                    vec![std::iter::once("searched these directories:".to_owned())
+                        .chain(searched.iter().map(|p| format!("    {}", p.display())))
+                        .collect::<Vec<_>>()
+                        .join("\n")]
+                }),
             ParserError::IncludeError(l) => Diagnostic::error()
                 .with_message("undefined include error")
                 .with_labels(option_to_vec(
                     primary(l).map(|v| v.with_message("undefined include error")),
                 )),
+            ParserError::IncludeCycle(l, root, chain) => Diagnostic::error()
+                .with_message("include cycle")
+                .with_labels({
+                    // The last include in the chain is the one that closes the cycle
+                    let mut labels = option_to_vec(
+                        primary(l).map(|v| v.with_message("file includes itself through here")),
+                    );
+                    for (location, path) in chain.split_last().map_or(&[][..], |(_, c)| c) {
+                        let message = format!("includes '{}'", file_name(path));
+                        labels.extend(secondary(location).map(|v| v.with_message(message)));
+                    }
+                    labels
+                })
+                .with_notes(vec![format!(
+                    "include path: {}",
+                    std::iter::once(root)
+                        .chain(chain.iter().map(|(_, p)| p))
+                        .map(|p| file_name(p))
+                        .collect::<Vec<_>>()
+                        .join(" -> ")
+                )]),
         }
     }
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, IntoStaticStr)]
+#[strum(serialize_all = "kebab-case")]
 pub enum SemanticError {
     ExpectedConstExpr(Location),
     SymbolRedefinition(Location, Location, String),
-    UndefinedVariable(Identifier),
-    UndefinedAnnotation(Identifier),
+    UndefinedVariable(Identifier, Vec<Suggestion>),
+    UndefinedAnnotation(Identifier, Vec<Suggestion>),
     UndefinedEnum(Identifier),
     UndefinedVariant(Identifier),
     IncompatibleOperator(Location, String, Operator),
@@ -302,12 +593,30 @@ pub enum SemanticError {
     ExpectedReferenceOperand(Location),
     BadBreak(Location),
     BadContinue(Location),
+    BadFallthrough(Location),
     UnresolvedLabel(Location, usize),
     InvalidType(Location, String, String),
     SignatureDisagreement(Location, String),
     BadArgCount(Location, usize, usize),
     BadExlCall(Location),
     NegativeArrayLength(Location),
+    NegativeGlobalIndex(Location),
+    OverlappingGlobal(Location, Location, String),
+    BadAsmInstruction(Location, String),
+    InlineAsExpression(Location),
+    RecursiveInline(Location, String),
+    UnsupportedInInline(Location),
+    ArrayInitTooLong(Location, String, usize, usize),
+    EmptyRange(Location, i32, i32),
+    RangeTooLarge(Location, i32, i32),
+    NegativeRepeat(Location, i32),
+    StringTooLong(Location, usize),
+    DefinitionInHeader(Location),
+    DefinitionInLayout(Location),
+    UnpinnedLayoutGlobal(Location),
+    DuplicateFunctionIndex(Location, Location, usize),
+    FunctionIndexOutOfRange(Location, usize, usize),
+    FunctionIndexOverlap(Location, usize),
 }
 
 impl SemanticError {
@@ -315,8 +624,8 @@ impl SemanticError {
         match self {
             SemanticError::ExpectedConstExpr(l) => l,
             SemanticError::SymbolRedefinition(l, _, _) => l,
-            SemanticError::UndefinedVariable(i) => &i.location,
-            SemanticError::UndefinedAnnotation(i) => &i.location,
+            SemanticError::UndefinedVariable(i, _) => &i.location,
+            SemanticError::UndefinedAnnotation(i, _) => &i.location,
             SemanticError::UndefinedEnum(i) => &i.location,
             SemanticError::UndefinedVariant(i) => &i.location,
             SemanticError::IncompatibleOperator(l, _, _) => l,
@@ -325,23 +634,41 @@ impl SemanticError {
             SemanticError::ExpectedReferenceOperand(l) => l,
             SemanticError::BadBreak(l) => l,
             SemanticError::BadContinue(l) => l,
+            SemanticError::BadFallthrough(l) => l,
             SemanticError::UnresolvedLabel(l, _) => l,
             SemanticError::InvalidType(l, _, _) => l,
             SemanticError::SignatureDisagreement(l, _) => l,
             SemanticError::BadArgCount(l, _, _) => l,
             SemanticError::BadExlCall(l) => l,
             SemanticError::NegativeArrayLength(l) => l,
+            SemanticError::NegativeGlobalIndex(l) => l,
+            SemanticError::OverlappingGlobal(_, l, _) => l,
+            SemanticError::BadAsmInstruction(l, _) => l,
+            SemanticError::InlineAsExpression(l) => l,
+            SemanticError::RecursiveInline(l, _) => l,
+            SemanticError::UnsupportedInInline(l) => l,
+            SemanticError::ArrayInitTooLong(l, _, _, _) => l,
+            SemanticError::EmptyRange(l, _, _) => l,
+            SemanticError::RangeTooLarge(l, _, _) => l,
+            SemanticError::NegativeRepeat(l, _) => l,
+            SemanticError::StringTooLong(l, _) => l,
+            SemanticError::DefinitionInHeader(l) => l,
+            SemanticError::DefinitionInLayout(l) => l,
+            SemanticError::UnpinnedLayoutGlobal(l) => l,
+            SemanticError::DuplicateFunctionIndex(_, l, _) => l,
+            SemanticError::FunctionIndexOutOfRange(l, _, _) => l,
+            SemanticError::FunctionIndexOverlap(l, _) => l,
         }
     }
 
-    pub fn message(&self) -> Cow<str> {
+    pub fn message(&self) -> Cow<'_, str> {
         match self {
             SemanticError::ExpectedConstExpr(_) => Cow::Borrowed("expected constant expression"),
             SemanticError::SymbolRedefinition(_, _, _) => {
                 Cow::Borrowed("symbol redefined in the same scope")
             }
-            SemanticError::UndefinedVariable(_) => Cow::Borrowed("undefined variable"),
-            SemanticError::UndefinedAnnotation(_) => Cow::Borrowed("undefined annotation"),
+            SemanticError::UndefinedVariable(_, _) => Cow::Borrowed("undefined variable"),
+            SemanticError::UndefinedAnnotation(_, _) => Cow::Borrowed("undefined annotation"),
             SemanticError::UndefinedEnum(_) => Cow::Borrowed("undefined enum"),
             SemanticError::UndefinedVariant(_) => Cow::Borrowed("undefined variant"),
             SemanticError::IncompatibleOperator(_, _, _) => {
@@ -358,6 +685,9 @@ impl SemanticError {
             SemanticError::BadContinue(_) => {
                 Cow::Borrowed("continue cannot be used in this context")
             }
+            SemanticError::BadFallthrough(_) => {
+                Cow::Borrowed("fallthrough cannot be used in this context")
+            }
             SemanticError::UnresolvedLabel(_, _) => Cow::Borrowed("unresolved label"),
             SemanticError::InvalidType(_, _, _) => Cow::Borrowed("type mismatch"),
             SemanticError::SignatureDisagreement(_, _) => {
@@ -370,6 +700,63 @@ impl SemanticError {
             SemanticError::NegativeArrayLength(_) => {
                 Cow::Borrowed("array length cannot be negative")
             }
+            SemanticError::NegativeGlobalIndex(_) => {
+                Cow::Borrowed("global index cannot be negative")
+            }
+            SemanticError::OverlappingGlobal(_, _, _) => {
+                Cow::Borrowed("global overlaps a slot that is already in use")
+            }
+            SemanticError::BadAsmInstruction(_, _) => Cow::Borrowed("invalid asm instruction"),
+            SemanticError::InlineAsExpression(_) => {
+                Cow::Borrowed("inline functions cannot be used as expressions")
+            }
+            SemanticError::RecursiveInline(_, name) => {
+                Cow::Owned(format!("inline function '{}' expands into itself", name))
+            }
+            SemanticError::UnsupportedInInline(_) => {
+                Cow::Borrowed("statement cannot be used in an inline function")
+            }
+            SemanticError::ArrayInitTooLong(_, name, _, _) => {
+                Cow::Owned(format!("array initializer does not fit in '{}'", name))
+            }
+            SemanticError::EmptyRange(_, _, _) => {
+                Cow::Borrowed("range does not contain any values")
+            }
+            SemanticError::RangeTooLarge(_, _, _) => {
+                Cow::Borrowed("range contains too many values")
+            }
+            SemanticError::NegativeRepeat(_, _) => {
+                Cow::Borrowed("string cannot be repeated a negative number of times")
+            }
+            SemanticError::StringTooLong(_, _) => Cow::Borrowed("string is too long"),
+            SemanticError::DefinitionInHeader(_) => {
+                Cow::Borrowed("precompiled headers can only contain declarations")
+            }
+            SemanticError::DefinitionInLayout(_) => {
+                Cow::Borrowed("global layouts can only contain declarations")
+            }
+            SemanticError::UnpinnedLayoutGlobal(_) => {
+                Cow::Borrowed("globals in a layout need an explicit index")
+            }
+            SemanticError::DuplicateFunctionIndex(_, _, index) => {
+                Cow::Owned(format!("more than one function is placed at index {}", index))
+            }
+            SemanticError::FunctionIndexOutOfRange(_, index, count) => Cow::Owned(format!(
+                "index {} is past the end of a function table with {} entries",
+                index, count
+            )),
+            SemanticError::FunctionIndexOverlap(_, index) => Cow::Owned(format!(
+                "index {} is covered by a callback with several events",
+                index
+            )),
+        }
+    }
+
+    pub fn suggestions(&self) -> &[Suggestion] {
+        match self {
+            SemanticError::UndefinedVariable(_, suggestions)
+            | SemanticError::UndefinedAnnotation(_, suggestions) => suggestions,
+            _ => &[],
         }
     }
 
@@ -389,16 +776,18 @@ impl SemanticError {
                     })));
                     labels
                 }),
-            SemanticError::UndefinedVariable(id) => Diagnostic::error()
+            SemanticError::UndefinedVariable(id, suggestions) => Diagnostic::error()
                 .with_message("undefined variable")
                 .with_labels(option_to_vec(primary(&id.location).map(|v| {
                     v.with_message(format!("variable '{}' is undefined", &id.value))
-                }))),
-            SemanticError::UndefinedAnnotation(id) => Diagnostic::error()
+                })))
+                .with_notes(suggestions.iter().map(Suggestion::note).collect()),
+            SemanticError::UndefinedAnnotation(id, suggestions) => Diagnostic::error()
                 .with_message("undefined annotation")
                 .with_labels(option_to_vec(primary(&id.location).map(|v| {
                     v.with_message(format!("annotation '{}' is undefined", &id.value))
-                }))),
+                })))
+                .with_notes(suggestions.iter().map(Suggestion::note).collect()),
             SemanticError::UndefinedEnum(id) => Diagnostic::error()
                 .with_message("undefined enum")
                 .with_labels(option_to_vec(primary(&id.location).map(|v| {
@@ -443,6 +832,11 @@ impl SemanticError {
                 .with_labels(option_to_vec(
                     primary(l).map(|v| v.with_message("continue can only be used inside a loop")),
                 )),
+            SemanticError::BadFallthrough(l) => Diagnostic::error()
+                .with_message("fallthrough cannot be used in this context")
+                .with_labels(option_to_vec(primary(l).map(|v| {
+                    v.with_message("fallthrough must be followed by another case or a default")
+                }))),
             SemanticError::UnresolvedLabel(l, count) => Diagnostic::error()
                 .with_message("unresolved label")
                 .with_labels(option_to_vec(primary(l).map(|v| {
@@ -482,29 +876,200 @@ impl SemanticError {
                 .with_labels(option_to_vec(
                     primary(l).map(|v| v.with_message("array length cannot be negative")),
                 )),
+            SemanticError::NegativeGlobalIndex(l) => Diagnostic::error()
+                .with_message("global index cannot be negative")
+                .with_labels(option_to_vec(
+                    primary(l).map(|v| v.with_message("global index cannot be negative")),
+                )),
+            SemanticError::OverlappingGlobal(original, new, name) => Diagnostic::error()
+                .with_message("global overlaps a slot that is already in use")
+                .with_labels({
+                    let mut labels = option_to_vec(
+                        primary(new).map(|v| v.with_message(format!("'{}' declared here", name))),
+                    );
+                    labels.extend(option_to_vec(
+                        secondary(original).map(|v| v.with_message("slot already claimed here")),
+                    ));
+                    labels
+                }),
+            SemanticError::BadAsmInstruction(l, reason) => Diagnostic::error()
+                .with_message("invalid asm instruction")
+                .with_labels(option_to_vec(primary(l).map(|v| v.with_message(reason)))),
+            SemanticError::InlineAsExpression(l) => Diagnostic::error()
+                .with_message("inline functions cannot be used as expressions")
+                .with_labels(option_to_vec(
+                    primary(l).map(|v| v.with_message("call this as a statement instead")),
+                )),
+            SemanticError::RecursiveInline(l, name) => Diagnostic::error()
+                .with_message("recursive inline function")
+                .with_labels(option_to_vec(primary(l).map(|v| {
+                    v.with_message(format!("'{}' is already being expanded here", name))
+                }))),
+            SemanticError::UnsupportedInInline(l) => Diagnostic::error()
+                .with_message("statement cannot be used in an inline function")
+                .with_labels(option_to_vec(primary(l).map(|v| {
+                    v.with_message("return, goto and labels are not allowed in inline functions")
+                }))),
+            SemanticError::ArrayInitTooLong(l, name, length, actual) => Diagnostic::error()
+                .with_message("array initializer is too long")
+                .with_labels(option_to_vec(primary(l).map(|v| {
+                    v.with_message(format!(
+                        "'{}' holds {} element(s) but {} were given",
+                        name, length, actual
+                    ))
+                }))),
+            SemanticError::EmptyRange(l, start, end) => Diagnostic::error()
+                .with_message("range does not contain any values")
+                .with_labels(option_to_vec(primary(l).map(|v| {
+                    v.with_message(format!("{} is greater than {}", start, end))
+                }))),
+            SemanticError::RangeTooLarge(l, start, end) => Diagnostic::error()
+                .with_message("range contains too many values")
+                .with_labels(option_to_vec(primary(l).map(|v| {
+                    v.with_message(format!(
+                        "{}..{} has {} values but a case can check at most {}",
+                        start,
+                        end,
+                        *end as i64 - *start as i64 + 1,
+                        MAX_RANGE_VALUES
+                    ))
+                })))
+                .with_notes(vec![
+                    "each value is checked separately, so split the range or use an if instead"
+                        .to_owned(),
+                ]),
+            SemanticError::NegativeRepeat(l, count) => Diagnostic::error()
+                .with_message("string cannot be repeated a negative number of times")
+                .with_labels(option_to_vec(
+                    primary(l).map(|v| v.with_message(format!("repeat count is {}", count))),
+                )),
+            SemanticError::StringTooLong(l, length) => Diagnostic::error()
+                .with_message("string is too long")
+                .with_labels(option_to_vec(primary(l).map(|v| {
+                    v.with_message(format!(
+                        "result would be {} bytes but the limit is {}",
+                        length, MAX_STRING_LENGTH
+                    ))
+                }))),
+            SemanticError::DefinitionInHeader(l) => Diagnostic::error()
+                .with_message("precompiled headers can only contain declarations")
+                .with_labels(option_to_vec(primary(l).map(|v| {
+                    v.with_message("this needs code or a frame slot, so it can't be saved")
+                })))
+                .with_notes(vec![
+                    "move it into the scripts that include the header".to_owned()
+                ]),
+            SemanticError::DefinitionInLayout(l) => Diagnostic::error()
+                .with_message("global layouts can only contain declarations")
+                .with_labels(option_to_vec(primary(l).map(|v| {
+                    v.with_message("this needs code, so it belongs in a script")
+                }))),
+            SemanticError::UnpinnedLayoutGlobal(l) => Diagnostic::error()
+                .with_message("globals in a layout need an explicit index")
+                .with_labels(option_to_vec(
+                    primary(l).map(|v| v.with_message("slot depends on declaration order")),
+                ))
+                .with_notes(vec![
+                    "pin it to a slot (ex. `let counter @ 7;`) so every script agrees on it"
+                        .to_owned(),
+                ]),
+            SemanticError::DuplicateFunctionIndex(original, new, index) => Diagnostic::error()
+                .with_message(format!("more than one function is placed at index {}", index))
+                .with_labels({
+                    let mut labels = option_to_vec(primary(new));
+                    labels.extend(option_to_vec(
+                        secondary(original).map(|v| v.with_message("index already claimed here")),
+                    ));
+                    labels
+                }),
+            SemanticError::FunctionIndexOutOfRange(l, index, count) => Diagnostic::error()
+                .with_message(format!(
+                    "index {} is past the end of a function table with {} entries",
+                    index, count
+                ))
+                .with_labels(option_to_vec(primary(l))),
+            SemanticError::FunctionIndexOverlap(l, index) => Diagnostic::error()
+                .with_message(format!(
+                    "index {} is covered by a callback with several events",
+                    index
+                ))
+                .with_labels(option_to_vec(primary(l)))
+                .with_notes(vec![
+                    "give the callback before it an @Index too, or move it".to_owned()
+                ]),
         }
     }
 }
 
 /// Top-level warning type
-#[derive(Debug)]
+#[derive(Debug, Clone, IntoStaticStr)]
+#[strum(serialize_all = "kebab-case")]
 pub enum WarningMessage {
     DeadCode(Location),
     UnusedLabel(Location),
+    UnusedVariable(Location, String),
+    UseBeforeAssignment(Location, String),
+    IndexOfScalar(Location, String),
+    IndexOutOfBounds(Location, i32, usize),
+    ArrayAsScalar(Location, String),
+    FormatArgCount(Location, usize, usize),
+    UnknownFunction(Location, String, Vec<String>),
+    IgnoredHeader(Location, String),
 }
 
 impl WarningMessage {
+    pub fn code(&self) -> &'static str {
+        self.into()
+    }
+
     pub fn location(&self) -> &Location {
         match self {
             WarningMessage::DeadCode(l) => l,
             WarningMessage::UnusedLabel(l) => l,
+            WarningMessage::UnusedVariable(l, _) => l,
+            WarningMessage::UseBeforeAssignment(l, _) => l,
+            WarningMessage::IndexOfScalar(l, _) => l,
+            WarningMessage::IndexOutOfBounds(l, _, _) => l,
+            WarningMessage::ArrayAsScalar(l, _) => l,
+            WarningMessage::FormatArgCount(l, _, _) => l,
+            WarningMessage::UnknownFunction(l, _, _) => l,
+            WarningMessage::IgnoredHeader(l, _) => l,
         }
     }
 
-    pub fn message(&self) -> Cow<str> {
+    pub fn message(&self) -> Cow<'_, str> {
         match self {
             WarningMessage::DeadCode(_) => Cow::Borrowed("unreachable code"),
             WarningMessage::UnusedLabel(_) => Cow::Borrowed("label is never used"),
+            WarningMessage::UnusedVariable(_, name) => {
+                Cow::Owned(format!("variable '{}' is never used", name))
+            }
+            WarningMessage::UseBeforeAssignment(_, name) => {
+                Cow::Owned(format!("variable '{}' is used before it is assigned", name))
+            }
+            WarningMessage::IndexOfScalar(_, name) => {
+                Cow::Owned(format!("variable '{}' is not an array", name))
+            }
+            WarningMessage::IndexOutOfBounds(_, index, length) => Cow::Owned(format!(
+                "index {} is out of bounds for an array of length {}",
+                index, length
+            )),
+            WarningMessage::ArrayAsScalar(_, name) => {
+                Cow::Owned(format!("array '{}' is used as a single value", name))
+            }
+            WarningMessage::FormatArgCount(_, expected, actual) => Cow::Owned(format!(
+                "format has {} placeholder(s) but {} argument(s) were given",
+                expected, actual
+            )),
+            WarningMessage::UnknownFunction(_, name, similar) => Cow::Owned(format!(
+                "function '{}' is not declared, did you mean {}?",
+                name,
+                quote_names(similar)
+            )),
+            WarningMessage::IgnoredHeader(_, reason) => Cow::Owned(format!(
+                "precompiled header was ignored: {}",
+                reason
+            )),
         }
     }
 
@@ -516,6 +1081,53 @@ impl WarningMessage {
             WarningMessage::UnusedLabel(l) => Diagnostic::warning()
                 .with_message("label is never used")
                 .with_labels(option_to_vec(primary(l))),
+            WarningMessage::UnusedVariable(l, name) => Diagnostic::warning()
+                .with_message("variable is never used")
+                .with_labels(option_to_vec(
+                    primary(l).map(|v| v.with_message(format!("'{}' is never read", name))),
+                )),
+            WarningMessage::UseBeforeAssignment(l, name) => Diagnostic::warning()
+                .with_message("variable is used before it is assigned")
+                .with_labels(option_to_vec(
+                    primary(l).map(|v| v.with_message(format!("'{}' has no value yet", name))),
+                )),
+            WarningMessage::IndexOfScalar(l, name) => Diagnostic::warning()
+                .with_message("indexing a variable that is not an array")
+                .with_labels(option_to_vec(
+                    primary(l).map(|v| v.with_message(format!("'{}' is not an array", name))),
+                )),
+            WarningMessage::IndexOutOfBounds(l, index, length) => Diagnostic::warning()
+                .with_message("array index out of bounds")
+                .with_labels(option_to_vec(primary(l).map(|v| {
+                    v.with_message(format!(
+                        "index {} is outside an array of length {}",
+                        index, length
+                    ))
+                }))),
+            WarningMessage::ArrayAsScalar(l, name) => Diagnostic::warning()
+                .with_message("array used as a single value")
+                .with_labels(option_to_vec(primary(l).map(|v| {
+                    v.with_message(format!("this only reads the first element of '{}'", name))
+                }))),
+            WarningMessage::FormatArgCount(l, expected, actual) => Diagnostic::warning()
+                .with_message("printf arguments do not match the format")
+                .with_labels(option_to_vec(primary(l).map(|v| {
+                    v.with_message(format!(
+                        "format has {} placeholder(s) but {} argument(s) were given",
+                        expected, actual
+                    ))
+                }))),
+            WarningMessage::UnknownFunction(l, name, similar) => Diagnostic::warning()
+                .with_message(format!("function '{}' is not declared", name))
+                .with_labels(option_to_vec(primary(l).map(|v| {
+                    v.with_message(format!("did you mean {}?", quote_names(similar)))
+                }))),
+            WarningMessage::IgnoredHeader(l, reason) => Diagnostic::warning()
+                .with_message("precompiled header was ignored")
+                .with_labels(option_to_vec(
+                    primary(l).map(|v| v.with_message("parsing this include instead")),
+                ))
+                .with_notes(vec![reason.clone()]),
         }
     }
 }
@@ -542,3 +1154,18 @@ fn option_to_vec<T>(option: Option<T>) -> Vec<T> {
         Some(elem) => vec![elem],
     }
 }
+
+fn file_name(path: &Path) -> Cow<'_, str> {
+    path.file_name()
+        .unwrap_or(path.as_os_str())
+        .to_string_lossy()
+}
+
+/// Format names as a list of alternatives (ex. 'a', 'b' or 'c').
+fn quote_names(names: &[String]) -> String {
+    let quoted: Vec<String> = names.iter().map(|name| format!("'{}'", name)).collect();
+    match quoted.split_last() {
+        Some((last, rest)) if !rest.is_empty() => format!("{} or {}", rest.join(", "), last),
+        _ => quoted.join(""),
+    }
+}
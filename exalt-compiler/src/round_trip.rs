@@ -0,0 +1,408 @@
+use std::fmt::Display;
+use std::path::PathBuf;
+
+use exalt_assembler::{AssembleOptions, CodeGenTextData};
+use exalt_decompiler::DecompilerOptions;
+use exalt_disassembler::{CmbHeader, DisassemblyError};
+use exalt_lir::{Function, Game, Opcode, RawScript, TextOptions};
+
+use crate::{CompileRequest, MemoryIncludes, MessageFormat, OptimizationConfig};
+
+/// A place where a rebuilt script differs from the original.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Divergence {
+    /// The script could not be rebuilt at all.
+    Failed(String),
+    Header {
+        field: &'static str,
+        expected: String,
+        actual: String,
+    },
+    FunctionCount {
+        expected: usize,
+        actual: usize,
+    },
+    Function {
+        index: usize,
+        field: &'static str,
+    },
+    /// The first opcode that differs in a function. Labels are normalized before comparing.
+    /// An opcode is missing if one function's code is longer than the other's.
+    Opcode {
+        function: usize,
+        index: usize,
+        expected: Option<Opcode>,
+        actual: Option<Opcode>,
+    },
+    /// The first string that differs in the text data.
+    TextData {
+        index: usize,
+        expected: Option<String>,
+        actual: Option<String>,
+    },
+    /// The scripts differ somewhere that disassembly does not capture (ex. padding).
+    Bytes {
+        offset: usize,
+    },
+}
+
+impl Display for Divergence {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        fn show<T: std::fmt::Debug>(value: &Option<T>) -> String {
+            match value {
+                Some(value) => format!("{:?}", value),
+                None => "nothing".to_owned(),
+            }
+        }
+        match self {
+            Divergence::Failed(message) => write!(f, "failed: {}", message),
+            Divergence::Header {
+                field,
+                expected,
+                actual,
+            } => write!(f, "header {}: {} vs {}", field, expected, actual),
+            Divergence::FunctionCount { expected, actual } => {
+                write!(f, "function count: {} vs {}", expected, actual)
+            }
+            Divergence::Function { index, field } => {
+                write!(f, "function {}: different {}", index, field)
+            }
+            Divergence::Opcode {
+                function,
+                index,
+                expected,
+                actual,
+            } => write!(
+                f,
+                "function {}: opcode {} {} vs {}",
+                function,
+                index,
+                show(expected),
+                show(actual)
+            ),
+            Divergence::TextData {
+                index,
+                expected,
+                actual,
+            } => write!(
+                f,
+                "text data: string {} {} vs {}",
+                index,
+                show(expected),
+                show(actual)
+            ),
+            Divergence::Bytes { offset } => write!(f, "bytes differ at 0x{:X}", offset),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct RoundTripOptions {
+    /// Also decompile the script and compile the result.
+    pub recompile: bool,
+
+    /// How the script's strings are encoded.
+    pub text: TextOptions,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct RoundTripReport {
+    /// Differences between the original script and its disassembly assembled again.
+    pub reassembled: Vec<Divergence>,
+
+    /// Differences between the original script and its decompiled source compiled again.
+    /// None if recompiling was not requested.
+    pub recompiled: Option<Vec<Divergence>>,
+}
+
+impl RoundTripReport {
+    pub fn is_identical(&self) -> bool {
+        self.reassembled.is_empty()
+            && self
+                .recompiled
+                .as_ref()
+                .map(|divergences| divergences.is_empty())
+                .unwrap_or(true)
+    }
+}
+
+/// Older formats pack text data in an order the assembler can't reproduce, so rebuilt scripts reuse the original.
+fn uses_original_text_data(game: Game) -> bool {
+    matches!(game, Game::FE9 | Game::FE10 | Game::FE11 | Game::FE12)
+}
+
+fn original_text_data(
+    bytes: &[u8],
+    game: Game,
+    text: TextOptions,
+) -> anyhow::Result<Option<CodeGenTextData>> {
+    if uses_original_text_data(game) {
+        Ok(Some(CodeGenTextData::from_script(
+            bytes,
+            game,
+            text.encoding.unwrap_or(game.profile().encoding),
+        )?))
+    } else {
+        Ok(None)
+    }
+}
+
+fn compare_headers(expected: &CmbHeader, actual: &CmbHeader, divergences: &mut Vec<Divergence>) {
+    let fields = [
+        ("name", expected.name.clone(), actual.name.clone()),
+        (
+            "revision",
+            format!("0x{:X}", expected.revision),
+            format!("0x{:X}", actual.revision),
+        ),
+        (
+            "global frame size",
+            expected.global_frame_size.to_string(),
+            actual.global_frame_size.to_string(),
+        ),
+        (
+            "text data address",
+            format!("0x{:X}", expected.text_data_address),
+            format!("0x{:X}", actual.text_data_address),
+        ),
+        (
+            "function table address",
+            format!("0x{:X}", expected.function_table_address),
+            format!("0x{:X}", actual.function_table_address),
+        ),
+        (
+            "init function",
+            format!("{:?}", expected.init_function_index),
+            format!("{:?}", actual.init_function_index),
+        ),
+    ];
+    for (field, expected, actual) in fields {
+        if expected != actual {
+            divergences.push(Divergence::Header {
+                field,
+                expected,
+                actual,
+            });
+        }
+    }
+}
+
+fn compare_functions(
+    index: usize,
+    expected: &Function,
+    actual: &Function,
+    divergences: &mut Vec<Divergence>,
+) {
+    let fields = [
+        ("frame size", expected.frame_size != actual.frame_size),
+        ("event", expected.event != actual.event),
+        ("arity", expected.arity != actual.arity),
+        ("unknown", expected.unknown != actual.unknown),
+        ("prefix", expected.prefix != actual.prefix),
+        ("suffix", expected.suffix != actual.suffix),
+        ("name", expected.name != actual.name),
+        ("args", expected.args != actual.args),
+    ];
+    for (field, differs) in fields {
+        if differs {
+            divergences.push(Divergence::Function { index, field });
+        }
+    }
+    let expected_code = Opcode::normalize_labels(&expected.code);
+    let actual_code = Opcode::normalize_labels(&actual.code);
+    let length = expected_code.len().max(actual_code.len());
+    for i in 0..length {
+        let (e, a) = (expected_code.get(i), actual_code.get(i));
+        if e != a {
+            divergences.push(Divergence::Opcode {
+                function: index,
+                index: i,
+                expected: e.cloned(),
+                actual: a.cloned(),
+            });
+            break;
+        }
+    }
+}
+
+fn compare_scripts(expected: &RawScript, actual: &RawScript, divergences: &mut Vec<Divergence>) {
+    if expected.functions.len() != actual.functions.len() {
+        divergences.push(Divergence::FunctionCount {
+            expected: expected.functions.len(),
+            actual: actual.functions.len(),
+        });
+    }
+    for (i, (e, a)) in expected.functions.iter().zip(&actual.functions).enumerate() {
+        compare_functions(i, e, a, divergences);
+    }
+}
+
+fn compare_text_data(
+    expected: &[(String, usize)],
+    actual: &[(String, usize)],
+    divergences: &mut Vec<Divergence>,
+) {
+    let length = expected.len().max(actual.len());
+    for i in 0..length {
+        let (e, a) = (expected.get(i), actual.get(i));
+        if e.map(|(text, _)| text) != a.map(|(text, _)| text) {
+            divergences.push(Divergence::TextData {
+                index: i,
+                expected: e.map(|(text, _)| text.clone()),
+                actual: a.map(|(text, _)| text.clone()),
+            });
+            return;
+        }
+    }
+}
+
+/// Read the same section of both scripts and compare them. A section that can't be read is reported as a failure.
+fn compare_section<T>(
+    expected: &[u8],
+    actual: &[u8],
+    divergences: &mut Vec<Divergence>,
+    read: impl Fn(&[u8]) -> Result<T, DisassemblyError>,
+    compare: impl FnOnce(&T, &T, &mut Vec<Divergence>),
+) {
+    match read(expected).and_then(|e| Ok((e, read(actual)?))) {
+        Ok((expected, actual)) => compare(&expected, &actual, divergences),
+        Err(err) => divergences.push(Divergence::Failed(format!("{:#}", err))),
+    }
+}
+
+/// Compare each section on its own so one that can't be read doesn't hide differences in the others.
+fn compare_sections(
+    expected: &[u8],
+    actual: &[u8],
+    game: Game,
+    text: TextOptions,
+    divergences: &mut Vec<Divergence>,
+) {
+    compare_section(
+        expected,
+        actual,
+        divergences,
+        |script| exalt_disassembler::read_header(script, game, text),
+        compare_headers,
+    );
+    compare_section(
+        expected,
+        actual,
+        divergences,
+        |script| exalt_disassembler::disassemble_with_options(script, game, text),
+        compare_scripts,
+    );
+    compare_section(
+        expected,
+        actual,
+        divergences,
+        |script| exalt_disassembler::read_text_data(script, game, text),
+        |e, a, divergences| compare_text_data(e, a, divergences),
+    );
+}
+
+/// Find where two compiled scripts differ, from the header down to individual opcodes.
+pub fn diff_scripts(
+    expected: &[u8],
+    actual: &[u8],
+    game: Game,
+    text: TextOptions,
+) -> Vec<Divergence> {
+    if expected == actual {
+        return Vec::new();
+    }
+    let mut divergences = Vec::new();
+    compare_sections(expected, actual, game, text, &mut divergences);
+    if divergences.is_empty() {
+        let offset = expected
+            .iter()
+            .zip(actual)
+            .position(|(e, a)| e != a)
+            .unwrap_or_else(|| expected.len().min(actual.len()));
+        divergences.push(Divergence::Bytes { offset });
+    }
+    divergences
+}
+
+fn reassemble(
+    bytes: &[u8],
+    game: Game,
+    text: TextOptions,
+    header: &CmbHeader,
+) -> anyhow::Result<Vec<u8>> {
+    let script = exalt_disassembler::disassemble_with_options(bytes, game, text)?;
+    let options = AssembleOptions {
+        text_data: original_text_data(bytes, game, text)?,
+        revision: Some(header.revision),
+        text,
+    };
+    Ok(exalt_assembler::assemble_with_options(
+        &script,
+        &header.name,
+        game,
+        options,
+    )?)
+}
+
+fn recompile(
+    bytes: &[u8],
+    game: Game,
+    text: TextOptions,
+    header: &CmbHeader,
+) -> anyhow::Result<Vec<u8>> {
+    let script = exalt_disassembler::disassemble_with_options(bytes, game, text)?;
+    let source = exalt_decompiler::decompile(
+        &script,
+        None,
+        &DecompilerOptions::default(),
+        Vec::new(),
+        game,
+        true,
+        false,
+    )?;
+    let request = CompileRequest {
+        game,
+        target: PathBuf::from(format!("{}.exl", header.name)),
+        output: None,
+        text_data: original_text_data(bytes, game, text)?,
+        text,
+        additional_includes: vec![],
+        optimizations: OptimizationConfig::default(),
+        revision: Some(header.revision),
+        internal_name: Some(header.name.clone()),
+        symbols: None,
+        listing: false,
+        source_map: false,
+        message_format: MessageFormat::default(),
+        defines: Vec::new(),
+        aliases: Vec::new(),
+    };
+    Ok(crate::compile_request_source(
+        &request,
+        &source,
+        &MemoryIncludes::new(),
+    )?)
+}
+
+/// Rebuild a compiled script and report everywhere the result differs from the original.
+/// Fails only if the original script can't be read. Problems rebuilding it are reported as divergences.
+pub fn verify_round_trip(
+    bytes: &[u8],
+    game: Game,
+    options: &RoundTripOptions,
+) -> Result<RoundTripReport, DisassemblyError> {
+    let text = options.text;
+    let header = exalt_disassembler::read_header(bytes, game, text)?;
+    exalt_disassembler::disassemble_with_options(bytes, game, text)?;
+
+    let check = |rebuilt: anyhow::Result<Vec<u8>>| match rebuilt {
+        Ok(rebuilt) => diff_scripts(bytes, &rebuilt, game, text),
+        Err(err) => vec![Divergence::Failed(format!("{:#}", err))],
+    };
+    Ok(RoundTripReport {
+        reassembled: check(reassemble(bytes, game, text, &header)),
+        recompiled: options
+            .recompile
+            .then(|| check(recompile(bytes, game, text, &header))),
+    })
+}
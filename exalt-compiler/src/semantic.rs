@@ -1,24 +1,106 @@
 use indexmap::IndexMap;
 
-use crate::eval::{evaluate_const_expr, evaluate_enum_access};
-use crate::reporting::{CompilerLog, SemanticError, WarningMessage};
+use crate::asm;
+use crate::eval::{
+    evaluate_const_expr, evaluate_enum_access, evaluate_float_bits, fold_binary, undefined_variable,
+};
+use crate::reporting::{CompilerLog, SemanticError, Suggestion, WarningMessage};
 use crate::symbol::{SymbolTable, Variable};
 use exalt_ast::{
-    Annotation, Case, ConstSymbol, DataType, Decl, EnumSymbol, Expr, FunctionSymbol, LabelSymbol,
-    Literal, Location, Notation, Operator, Ref, Script, Shared, Stmt, VarSymbol,
+    Annotation, Case, ConstArraySymbol, ConstSymbol, DataType, Decl, EnumSymbol, Expr,
+    FunctionSymbol, LabelSymbol, Literal, Location, Notation, Operator, Ref, Script, Shared, Stmt,
+    VarSymbol,
 };
 
-use exalt_ast::surface::{self, EnumVariant, Identifier};
+use exalt_ast::surface::{self, EnumVariant, Identifier, Parameter};
 
 use std::cell::RefCell;
+use std::collections::{BTreeMap, HashMap};
 use std::rc::Rc;
 
 type Result<T> = std::result::Result<T, SemanticError>;
 
+const FUNCTION_ANNOTATIONS: &[&str] = &[
+    "NoDefaultReturn",
+    "Prefix",
+    "Suffix",
+    "Unknown",
+    "FrameSize",
+    "Index",
+];
+const GLOBAL_ANNOTATIONS: &[&str] = &["Global"];
+
+/// Most values a range case may cover.
+/// Ranges expand into one check per value, which keeps matches in the shape the decompiler recognizes.
+pub(crate) const MAX_RANGE_VALUES: i64 = 256;
+
+/// Error for an annotation that isn't valid here, suggesting any allowed one with a close name.
+fn undefined_annotation(identifier: &Identifier, allowed: &[&str]) -> SemanticError {
+    let suggestions = allowed
+        .iter()
+        .filter(|name| {
+            strsim::osa_distance(&identifier.value.to_lowercase(), &name.to_lowercase()) <= 2
+        })
+        .map(|name| Suggestion::new(identifier.location.clone(), name.to_string()))
+        .collect();
+    SemanticError::UndefinedAnnotation(identifier.clone(), suggestions)
+}
+
 fn make_shared<T>(value: T) -> Rc<RefCell<T>> {
     Rc::new(RefCell::new(value))
 }
 
+/// Best guess at the type an expression produces. Any if it can't be known statically.
+fn expr_type(expr: &Expr) -> DataType {
+    match expr {
+        Expr::Literal(l) => l.data_type(),
+        Expr::Grouped(e) => expr_type(e),
+        Expr::Unary(Operator::FloatNegate, _) => DataType::Float,
+        Expr::Unary(_, _) => DataType::Int,
+        Expr::Binary(_, op, _) => match op {
+            Operator::FloatAdd
+            | Operator::FloatSubtract
+            | Operator::FloatMultiply
+            | Operator::FloatDivide => DataType::Float,
+            _ => DataType::Int,
+        },
+        Expr::FunctionCall(symbol, _) => symbol.borrow().return_type.unwrap_or(DataType::Any),
+        Expr::Ref(Ref::Var(symbol)) => symbol.borrow().data_type.unwrap_or(DataType::Any),
+        Expr::Increment(_, _, _) => DataType::Int,
+        Expr::Conditional(_, then, otherwise) => {
+            let then = expr_type(then);
+            if then == expr_type(otherwise) {
+                then
+            } else {
+                DataType::Any
+            }
+        }
+        Expr::Array(_) | Expr::Ref(_) | Expr::AddressOf(_) => DataType::Any,
+    }
+}
+
+/// Check a value against a declared type. Any on either side always passes.
+fn check_type(expected: DataType, expr: &surface::Expr, value: &Expr) -> Result<()> {
+    let actual = expr_type(value);
+    if expected == DataType::Any || actual == DataType::Any || expected == actual {
+        Ok(())
+    } else {
+        Err(SemanticError::InvalidType(
+            expr.location().clone(),
+            expected.name(),
+            actual.name(),
+        ))
+    }
+}
+
+#[derive(Clone)]
+struct InlineFunction {
+    // Namespaces around the declaration, so names in the body resolve where they were written
+    namespaces: Vec<String>,
+    parameters: Vec<Parameter>,
+    body: surface::Stmt,
+}
+
 struct SemanticAnalyzer<'a> {
     symbol_table: SymbolTable,
     log: &'a mut CompilerLog,
@@ -29,35 +111,85 @@ struct SemanticAnalyzer<'a> {
     breaks: usize,
     continues: usize,
 
+    // Whether the innermost match case has another case or default to fall through to
+    fallthrough: bool,
+
     // Tracker for labels in a function
     // We need this because labels can be used before definition
     // and we need to validate that every referenced label is defined somewhere
     labels: Vec<Shared<LabelSymbol>>,
 
+    // Tracker for local variables in a function
+    // Checked at the end of the function to find variables that are never read
+    locals: Vec<Shared<VarSymbol>>,
+
+    // Inline functions are expanded at every call site, so we keep their parameters and bodies around
+    inline_functions: HashMap<String, InlineFunction>,
+
+    // Names of the inline functions currently being expanded, innermost last
+    inline_stack: Vec<String>,
+
+    // Declared return type of the function being analyzed, if any
+    return_type: Option<DataType>,
+
+    // Constant arrays used by the current function and the variables holding their values
+    // These are initialized at the start of the function
+    const_arrays: Vec<(Shared<ConstArraySymbol>, Shared<VarSymbol>)>,
+
     // Tracker for number of global variables declared
     globals: usize,
+
+    // Global frame slots claimed so far as (start, length, location)
+    // Extern and pinned globals bind to explicit indices, so we need this to catch overlaps
+    global_slots: Vec<(usize, usize, Location)>,
+
+    // Globals without an explicit index, placed around the claimed slots once every global is defined
+    pending_globals: Vec<(Shared<VarSymbol>, Identifier, usize)>,
 }
 
 impl<'a> SemanticAnalyzer<'a> {
-    fn new(log: &'a mut CompilerLog) -> Self {
+    fn new(log: &'a mut CompilerLog, symbol_table: SymbolTable) -> Self {
+        // Globals that came from precompiled headers still claim their slots
+        let global_slots = symbol_table
+            .global_variables()
+            .into_iter()
+            .filter_map(|v| match v {
+                Variable::Var(v) => {
+                    let v = v.borrow();
+                    let length = v.array_length.unwrap_or(1);
+                    v.frame_id.map(|id| (id, length, v.location.clone()))
+                }
+                _ => None,
+            })
+            .collect();
         SemanticAnalyzer {
-            symbol_table: SymbolTable::new(),
+            symbol_table,
             log,
             breaks: 0,
             continues: 0,
+            fallthrough: false,
             labels: Vec::new(),
+            locals: Vec::new(),
+            inline_functions: HashMap::new(),
+            inline_stack: Vec::new(),
+            return_type: None,
+            const_arrays: Vec::new(),
             globals: 0,
+            global_slots,
+            pending_globals: Vec::new(),
         }
     }
 
     pub fn analyze(
         log: &mut CompilerLog,
         script: &surface::Script,
+        symbol_table: SymbolTable,
     ) -> Option<(Script, SymbolTable)> {
-        let mut analyzer = SemanticAnalyzer::new(log);
+        let mut analyzer = SemanticAnalyzer::new(log, symbol_table);
 
         // Fill in type definitions and forward declare functions
-        analyzer.create_definitions(script);
+        analyzer.create_definitions(&script.0);
+        analyzer.allocate_globals();
         // End it here if there are errors since we don't handle bad types/functions gracefully yet.
         if !analyzer.log.has_errors() {
             let script = analyzer.transform_to_ast(script);
@@ -71,30 +203,39 @@ impl<'a> SemanticAnalyzer<'a> {
         }
     }
 
-    fn create_definitions(&mut self, script: &surface::Script) {
-        for decl in &script.0 {
+    fn create_definitions(&mut self, decls: &[surface::Decl]) {
+        for decl in decls {
             match decl {
                 surface::Decl::FunctionAlias {
                     location: _,
                     identifier,
                     alias,
+                    docs,
                 } => {
+                    let name = self.symbol_table.qualify(&identifier.value);
                     if let Err(err) = self.symbol_table.define_alias(
-                        identifier.value.clone(),
+                        name.clone(),
                         alias.value.clone(),
                         identifier.location.clone(),
                     ) {
                         self.log.log_error(err.into());
+                    } else if let Some(docs) = docs {
+                        self.symbol_table.document_alias(name, docs.clone());
                     }
                 }
-                surface::Decl::FunctionExtern { location: _, identifier, parameters } => {
-                    self.define_simple_function(identifier, parameters, true)
-                }
+                surface::Decl::FunctionExtern {
+                    location: _,
+                    identifier,
+                    parameters,
+                    return_type,
+                    docs,
+                } => self.define_simple_function(identifier, parameters, *return_type, docs, true),
                 surface::Decl::Constant {
                     location: _,
+                    annotations,
                     identifier,
                     value,
-                } => self.define_constant(identifier, value),
+                } => self.define_constant(identifier, annotations, value),
                 surface::Decl::Enum {
                     location: _,
                     identifier,
@@ -105,25 +246,74 @@ impl<'a> SemanticAnalyzer<'a> {
                     annotations: _,
                     identifier,
                     parameters,
+                    return_type,
                     body: _,
-                } => self.define_simple_function(identifier, parameters, false),
-                surface::Decl::Global(_, identifier, count) => {
-                    self.define_global(identifier, count.as_ref())
+                    docs,
+                } => self.define_simple_function(identifier, parameters, *return_type, docs, false),
+                surface::Decl::Inline {
+                    location: _,
+                    identifier,
+                    parameters,
+                    body,
+                    docs,
+                } => {
+                    self.define_simple_function(identifier, parameters, None, docs, false);
+                    self.inline_functions.insert(
+                        self.symbol_table.qualify(&identifier.value),
+                        InlineFunction {
+                            namespaces: self.symbol_table.current_namespaces().to_vec(),
+                            parameters: parameters.clone(),
+                            body: body.clone(),
+                        },
+                    );
+                }
+                surface::Decl::Global {
+                    location: _,
+                    identifier,
+                    count,
+                    index,
+                } => self.define_global(identifier, count.as_ref(), index.as_ref(), false),
+                surface::Decl::GlobalExtern {
+                    location: _,
+                    identifier,
+                    count,
+                    index,
+                } => self.define_global(identifier, count.as_ref(), Some(index), true),
+                surface::Decl::Namespace {
+                    location: _,
+                    identifier,
+                    decls,
+                } => {
+                    self.symbol_table.enter_namespace(identifier.value.clone());
+                    self.create_definitions(decls);
+                    self.symbol_table.exit_namespace();
                 }
                 _ => {}
             }
         }
     }
 
-    fn define_constant(&mut self, identifier: &Identifier, value: &surface::Expr) {
+    fn define_constant(
+        &mut self,
+        identifier: &Identifier,
+        annotations: &[surface::Annotation],
+        value: &surface::Expr,
+    ) {
+        if let surface::Expr::Array(_, elements) = value {
+            return self.define_const_array(identifier, annotations, elements);
+        }
+        for a in annotations {
+            self.log
+                .log_error(undefined_annotation(&a.identifier, &[]).into());
+        }
         match evaluate_const_expr(&self.symbol_table, value) {
             Ok(v) => {
-                let symbol =
-                    ConstSymbol::new(identifier.value.clone(), identifier.location.clone(), v);
-                if let Err(err) = self.symbol_table.define_variable(
-                    identifier.value.clone(),
-                    Variable::Const(make_shared(symbol)),
-                ) {
+                let name = self.symbol_table.qualify(&identifier.value);
+                let symbol = ConstSymbol::new(name.clone(), identifier.location.clone(), v);
+                if let Err(err) = self
+                    .symbol_table
+                    .define_variable(name, Variable::Const(make_shared(symbol)))
+                {
                     self.log.log_error(err.into());
                 }
             }
@@ -131,6 +321,64 @@ impl<'a> SemanticAnalyzer<'a> {
         }
     }
 
+    fn define_const_array(
+        &mut self,
+        identifier: &Identifier,
+        annotations: &[surface::Annotation],
+        elements: &[surface::Expr],
+    ) {
+        let mut values = Vec::new();
+        for e in elements {
+            match evaluate_const_expr(&self.symbol_table, e) {
+                Ok(v) => values.push(v),
+                Err(err) => {
+                    self.log.log_error(err.into());
+                    return;
+                }
+            }
+        }
+
+        let mut global = false;
+        for a in annotations {
+            if a.identifier.value != "Global" {
+                self.log
+                    .log_error(undefined_annotation(&a.identifier, GLOBAL_ANNOTATIONS).into());
+            } else if !a.args.is_empty() {
+                self.log.log_error(
+                    SemanticError::SignatureDisagreement(
+                        a.args[0].location().clone(),
+                        "annotation takes no arguments".to_owned(),
+                    )
+                    .into(),
+                );
+            } else {
+                global = true;
+            }
+        }
+
+        let name = self.symbol_table.qualify(&identifier.value);
+        let global = if global {
+            let mut symbol = VarSymbol::new(name.clone(), identifier.location.clone(), true);
+            symbol.array_length = Some(values.len());
+            symbol.assigned = true;
+            symbol.used = true;
+            let symbol = make_shared(symbol);
+            self.pending_globals
+                .push((symbol.clone(), identifier.clone(), values.len()));
+            Some(symbol)
+        } else {
+            None
+        };
+        let symbol =
+            ConstArraySymbol::new(name.clone(), identifier.location.clone(), values, global);
+        if let Err(err) = self
+            .symbol_table
+            .define_variable(name, Variable::ConstArray(make_shared(symbol)))
+        {
+            self.log.log_error(err.into());
+        }
+    }
+
     fn define_enum(&mut self, ident: &Identifier, variants: &[EnumVariant]) {
         let mut evaluated_variants = IndexMap::new();
         for v in variants {
@@ -167,92 +415,257 @@ impl<'a> SemanticAnalyzer<'a> {
                 Err(err) => self.log.log_error(err.into()),
             }
         }
+        let name = self.symbol_table.qualify(&ident.value);
         let symbol = make_shared(EnumSymbol::new(
-            ident.value.clone(),
+            name.clone(),
             ident.location.clone(),
             evaluated_variants,
         ));
-        if let Err(err) = self.symbol_table.define_enum(ident.value.clone(), symbol) {
+        if let Err(err) = self.symbol_table.define_enum(name, symbol) {
             self.log.log_error(err.into());
         }
     }
 
-    fn define_global(&mut self, identifier: &Identifier, count: Option<&surface::Expr>) {
-        let mut symbol =
-            VarSymbol::new(identifier.value.clone(), identifier.location.clone(), true);
-        symbol.frame_id = Some(self.globals);
+    fn define_global(
+        &mut self,
+        identifier: &Identifier,
+        count: Option<&surface::Expr>,
+        index: Option<&surface::Expr>,
+        external: bool,
+    ) {
+        let name = self.symbol_table.qualify(&identifier.value);
+        let mut symbol = VarSymbol::new(name.clone(), identifier.location.clone(), true);
+        let mut length = 1;
         if let Some(count) = count {
-            match evaluate_const_expr(&self.symbol_table, count) {
-                Ok(length) => {
-                    if let Literal::Int(i) = length {
-                        if i < 0 {
-                            self.log.log_error(
-                                SemanticError::NegativeArrayLength(count.location().clone()).into(),
-                            );
-                        }
-                        symbol.array_length = Some(i as usize);
-                        self.globals += i as usize;
-                    } else {
-                        self.log.log_error(
-                            SemanticError::InvalidType(
-                                count.location().clone(),
-                                DataType::Int.name(),
-                                length.data_type().name(),
-                            )
-                            .into(),
-                        );
-                    }
-                }
-                Err(err) => self.log.log_error(err.into()),
+            if let Some(i) = self.evaluate_global_int(count, SemanticError::NegativeArrayLength) {
+                symbol.array_length = Some(i);
+                length = i;
             }
-        } else {
-            self.globals += 1;
         }
+
+        // Externs bind to a fixed slot that some other script owns, so they don't grow the frame
+        if let Some(index) = index {
+            let frame_id =
+                match self.evaluate_global_int(index, SemanticError::NegativeGlobalIndex) {
+                    Some(i) => i,
+                    None => return,
+                };
+            if !external {
+                self.globals = self.globals.max(frame_id + length);
+            }
+            symbol.frame_id = Some(frame_id);
+            self.claim_global_slots(identifier, frame_id, length);
+        }
+
         let symbol = make_shared(symbol);
+        if index.is_none() {
+            self.pending_globals
+                .push((symbol.clone(), identifier.clone(), length));
+        }
         let variable = Variable::Var(symbol);
-        if let Err(err) = self
-            .symbol_table
-            .define_variable(identifier.value.clone(), variable)
-        {
+        if let Err(err) = self.symbol_table.define_variable(name, variable) {
             self.log.log_error(err.into());
         }
     }
 
-    fn define_simple_function(&mut self, identifier: &Identifier, params: &[Identifier], allow_redefinition: bool) {
-        let symbol = make_shared(FunctionSymbol::new(
-            identifier.value.clone(),
+    /// Give globals without an index the first free slots in declaration order.
+    /// Slots claimed by pinned and extern globals are skipped, so pinning one doesn't move the rest.
+    fn allocate_globals(&mut self) {
+        let mut next = 0;
+        for (symbol, identifier, length) in std::mem::take(&mut self.pending_globals) {
+            let mut frame_id = next;
+            while let Some((start, len, _)) = self
+                .global_slots
+                .iter()
+                .find(|(start, len, _)| frame_id < start + len && *start < frame_id + length)
+            {
+                frame_id = start + len;
+            }
+            symbol.borrow_mut().frame_id = Some(frame_id);
+            self.claim_global_slots(&identifier, frame_id, length);
+            self.globals = self.globals.max(frame_id + length);
+            next = frame_id + length;
+        }
+    }
+
+    fn claim_global_slots(&mut self, identifier: &Identifier, frame_id: usize, length: usize) {
+        let overlap = self
+            .global_slots
+            .iter()
+            .find(|(start, len, _)| frame_id < start + len && *start < frame_id + length);
+        if let Some((_, _, original)) = overlap {
+            self.log.log_error(
+                SemanticError::OverlappingGlobal(
+                    original.clone(),
+                    identifier.location.clone(),
+                    identifier.value.clone(),
+                )
+                .into(),
+            );
+        }
+        self.global_slots
+            .push((frame_id, length, identifier.location.clone()));
+    }
+
+    fn evaluate_global_int(
+        &mut self,
+        expr: &surface::Expr,
+        on_negative: fn(Location) -> SemanticError,
+    ) -> Option<usize> {
+        match evaluate_const_expr(&self.symbol_table, expr) {
+            Ok(Literal::Int(i)) => {
+                if i < 0 {
+                    self.log
+                        .log_error(on_negative(expr.location().clone()).into());
+                    None
+                } else {
+                    Some(i as usize)
+                }
+            }
+            Ok(value) => {
+                self.log.log_error(
+                    SemanticError::InvalidType(
+                        expr.location().clone(),
+                        DataType::Int.name(),
+                        value.data_type().name(),
+                    )
+                    .into(),
+                );
+                None
+            }
+            Err(err) => {
+                self.log.log_error(err.into());
+                None
+            }
+        }
+    }
+
+    fn define_simple_function(
+        &mut self,
+        identifier: &Identifier,
+        params: &[Parameter],
+        return_type: Option<DataType>,
+        docs: &Option<String>,
+        allow_redefinition: bool,
+    ) {
+        let name = self.symbol_table.qualify(&identifier.value);
+        let mut symbol = FunctionSymbol::new(
+            name.clone(),
             identifier.location.clone(),
             params.len(),
             None,
             allow_redefinition,
-        ));
-        if let Err(err) = self
-            .symbol_table
-            .define_function(identifier.value.clone(), symbol)
-        {
+        );
+        symbol.parameter_types = params
+            .iter()
+            .map(|p| p.data_type.unwrap_or(DataType::Any))
+            .collect();
+        symbol.return_type = return_type;
+        symbol.docs = docs.clone();
+        let symbol = make_shared(symbol);
+        if let Err(err) = self.symbol_table.define_function(name, symbol) {
             self.log.log_error(err.into());
         }
     }
 
     fn transform_to_ast(&mut self, script: &surface::Script) -> Script {
         let mut decls = Vec::new();
-        for decl in &script.0 {
+        self.transform_decls(&script.0, &mut decls);
+        let decls = self.arrange_function_table(decls);
+        Script::new(decls, self.globals)
+    }
+
+    /// Move functions and callbacks with an @Index annotation to that position in the function table.
+    /// Everything else fills the remaining positions in source order.
+    /// A callback with several events takes up consecutive entries starting at its index.
+    fn arrange_function_table(&mut self, decls: Vec<Decl>) -> Vec<Decl> {
+        let count: usize = decls.iter().map(Decl::table_entries).sum();
+        let mut claimed: Vec<Option<Location>> = vec![None; count];
+        let mut pinned: BTreeMap<usize, (Decl, Location)> = BTreeMap::new();
+        let mut unpinned = Vec::new();
+        for decl in decls {
+            let annotations = match &decl {
+                Decl::Function { annotations, .. } | Decl::Callback { annotations, .. } => {
+                    annotations
+                }
+            };
+            let index = annotations.iter().find_map(|a| match a {
+                Annotation::Index(index, location) => Some((*index, location.clone())),
+                _ => None,
+            });
+            let entries = decl.table_entries();
+            match index {
+                Some((index, location)) if index + entries > count => {
+                    self.log.log_error(
+                        SemanticError::FunctionIndexOutOfRange(location, index, count).into(),
+                    );
+                    unpinned.push(decl);
+                }
+                Some((index, location)) => {
+                    let slots = &mut claimed[index..index + entries];
+                    if let Some(original) = slots.iter().flatten().next() {
+                        self.log.log_error(
+                            SemanticError::DuplicateFunctionIndex(original.clone(), location, index)
+                                .into(),
+                        );
+                        unpinned.push(decl);
+                    } else {
+                        slots.fill(Some(location.clone()));
+                        pinned.insert(index, (decl, location));
+                    }
+                }
+                None => unpinned.push(decl),
+            }
+        }
+
+        let mut arranged = Vec::new();
+        let mut unpinned = unpinned.into_iter();
+        let mut position = 0;
+        loop {
+            let decl = match pinned.remove(&position) {
+                Some((decl, _)) => decl,
+                None => match unpinned.next() {
+                    Some(decl) => decl,
+                    None => break,
+                },
+            };
+            position += decl.table_entries();
+            arranged.push(decl);
+        }
+        // Only left over when an unpinned callback with several events ran past their index
+        for (index, (decl, location)) in pinned {
+            self.log
+                .log_error(SemanticError::FunctionIndexOverlap(location, index).into());
+            arranged.push(decl);
+        }
+        arranged
+    }
+
+    fn transform_decls(&mut self, surface_decls: &[surface::Decl], decls: &mut Vec<Decl>) {
+        for decl in surface_decls {
             self.breaks = 0;
             self.continues = 0;
+            self.fallthrough = false;
             self.labels.clear();
+            self.locals.clear();
+            self.return_type = None;
+            self.const_arrays.clear();
             match decl {
                 surface::Decl::Function {
                     location: _,
                     annotations,
                     identifier,
                     parameters,
+                    return_type,
                     body,
+                    docs: _,
                 } => {
                     let annotations = self.transform_annotations(annotations);
                     let symbol = self
                         .symbol_table
                         .lookup_function(&identifier.value)
                         .unwrap();
+                    self.return_type = *return_type;
                     self.symbol_table.open_scope();
                     let parameters = self.set_up_function_environment(parameters);
                     let body = match self.evaluate_stmt(body) {
@@ -262,6 +675,7 @@ impl<'a> SemanticAnalyzer<'a> {
                             Stmt::Block(Vec::new())
                         }
                     };
+                    let body = self.initialize_const_arrays(body);
                     self.symbol_table.close_scope();
                     decls.push(Decl::Function {
                         annotations,
@@ -273,31 +687,36 @@ impl<'a> SemanticAnalyzer<'a> {
                 surface::Decl::Callback {
                     location: _,
                     annotations,
-                    event_type,
+                    event_types,
                     args,
                     body,
                 } => {
                     let annotations = self.transform_annotations(annotations);
-                    let event_type = match evaluate_const_expr(&self.symbol_table, event_type) {
-                        Ok(v) => match v {
-                            Literal::Int(v) => v as usize, // TODO: Warn about narrowing conversion?
-                            l => {
-                                self.log.log_error(
-                                    SemanticError::InvalidType(
-                                        event_type.location().clone(),
-                                        DataType::Int.name(),
-                                        l.data_type().name(),
-                                    )
-                                    .into(),
-                                );
-                                0
+                    let event_types = event_types
+                        .iter()
+                        .map(|event_type| {
+                            match evaluate_const_expr(&self.symbol_table, event_type) {
+                                Ok(v) => match v {
+                                    Literal::Int(v) => v as usize, // TODO: Warn about narrowing conversion?
+                                    l => {
+                                        self.log.log_error(
+                                            SemanticError::InvalidType(
+                                                event_type.location().clone(),
+                                                DataType::Int.name(),
+                                                l.data_type().name(),
+                                            )
+                                            .into(),
+                                        );
+                                        0
+                                    }
+                                },
+                                Err(err) => {
+                                    self.log.log_error(err.into());
+                                    0 // Placeholder since we want to continue evaluating
+                                }
                             }
-                        },
-                        Err(err) => {
-                            self.log.log_error(err.into());
-                            0 // Placeholder since we want to continue evaluating
-                        }
-                    };
+                        })
+                        .collect();
                     let mut evaluated_args = Vec::new();
                     for arg in args {
                         match evaluate_const_expr(&self.symbol_table, arg) {
@@ -313,28 +732,48 @@ impl<'a> SemanticAnalyzer<'a> {
                             Stmt::Block(Vec::new())
                         }
                     };
+                    let body = self.initialize_const_arrays(body);
                     self.symbol_table.close_scope();
                     decls.push(Decl::Callback {
                         annotations,
-                        event_type,
+                        event_types,
                         args: evaluated_args,
                         body,
                     })
                 }
+                surface::Decl::Namespace {
+                    location: _,
+                    identifier,
+                    decls: namespace_decls,
+                } => {
+                    self.symbol_table.enter_namespace(identifier.value.clone());
+                    self.transform_decls(namespace_decls, decls);
+                    self.symbol_table.exit_namespace();
+                }
                 _ => {}
             }
             self.validate_labels();
+            self.validate_locals();
         }
-        Script::new(decls, self.globals)
     }
 
-    fn set_up_function_environment(&mut self, params: &[Identifier]) -> Vec<Shared<VarSymbol>> {
+    fn set_up_function_environment(&mut self, params: &[Parameter]) -> Vec<Shared<VarSymbol>> {
         let mut parameters = Vec::new();
         for p in params {
-            let symbol = make_shared(VarSymbol::new(p.value.clone(), p.location.clone(), false));
+            let mut symbol = VarSymbol::new(
+                p.identifier.value.clone(),
+                p.identifier.location.clone(),
+                false,
+            );
+            symbol.assigned = true;
+            symbol.data_type = p.data_type;
+            let symbol = make_shared(symbol);
             parameters.push(symbol.clone());
             let variable = Variable::Var(symbol);
-            if let Err(err) = self.symbol_table.define_variable(p.value.clone(), variable) {
+            if let Err(err) = self
+                .symbol_table
+                .define_variable(p.identifier.value.clone(), variable)
+            {
                 self.log.log_error(err.into());
             }
         }
@@ -371,9 +810,17 @@ impl<'a> SemanticAnalyzer<'a> {
                     Ok(v) => transformed.push(Annotation::Unknown(v)),
                     Err(err) => self.log.log_error(err.into()),
                 },
+                "FrameSize" => match self.transform_single_int_argument(&ident.location, &a.args) {
+                    Ok(v) => transformed.push(Annotation::FrameSize(v)),
+                    Err(err) => self.log.log_error(err.into()),
+                },
+                "Index" => match self.transform_single_int_argument(&ident.location, &a.args) {
+                    Ok(v) => transformed.push(Annotation::Index(v, ident.location.clone())),
+                    Err(err) => self.log.log_error(err.into()),
+                },
                 _ => {
                     self.log
-                        .log_error(SemanticError::UndefinedAnnotation(ident.clone()).into());
+                        .log_error(undefined_annotation(ident, FUNCTION_ANNOTATIONS).into());
                 }
             }
         }
@@ -435,6 +882,25 @@ impl<'a> SemanticAnalyzer<'a> {
         }
     }
 
+    fn track_local(&mut self, symbol: &Shared<VarSymbol>) {
+        // Inline function bodies are analyzed once per call, so skip them to avoid repeating warnings
+        if self.inline_stack.is_empty() {
+            self.locals.push(symbol.clone());
+        }
+    }
+
+    fn validate_locals(&mut self) {
+        for local in &self.locals {
+            let local = local.borrow();
+            if !local.used {
+                self.log.log_warning(WarningMessage::UnusedVariable(
+                    local.location.clone(),
+                    local.name.clone(),
+                ));
+            }
+        }
+    }
+
     fn evaluate_stmt(&mut self, stmt: &surface::Stmt) -> Result<Stmt> {
         match stmt {
             surface::Stmt::Assignment {
@@ -458,6 +924,18 @@ impl<'a> SemanticAnalyzer<'a> {
                     Ok(Stmt::Continue)
                 }
             }
+            surface::Stmt::Fallthrough(loc) => {
+                if self.fallthrough {
+                    Ok(Stmt::Fallthrough)
+                } else {
+                    Err(SemanticError::BadFallthrough(loc.clone()))
+                }
+            }
+            surface::Stmt::ExprStmt(_, surface::Expr::FunctionCall(_, identifier, args))
+                if self.lookup_inline(&identifier.value).is_some() =>
+            {
+                self.expand_inline(identifier, args)
+            }
             surface::Stmt::ExprStmt(_, e) => Ok(Stmt::ExprStmt(self.evaluate_expr(e)?)),
             surface::Stmt::For {
                 location: _,
@@ -473,6 +951,13 @@ impl<'a> SemanticAnalyzer<'a> {
                 self.continues -= 1;
                 result
             }
+            surface::Stmt::Goto(loc, _)
+            | surface::Stmt::Label(loc, _)
+            | surface::Stmt::Return(loc, _)
+                if !self.inline_stack.is_empty() =>
+            {
+                Err(SemanticError::UnsupportedInInline(loc.clone()))
+            }
             surface::Stmt::Goto(loc, identifier) => self.evaluate_goto(loc.clone(), identifier),
             surface::Stmt::If {
                 location: _,
@@ -493,6 +978,7 @@ impl<'a> SemanticAnalyzer<'a> {
                 })
             }
             surface::Stmt::Label(loc, identifier) => self.evaluate_label(loc.clone(), identifier),
+            surface::Stmt::Asm(_, instructions) => self.evaluate_asm(instructions),
             surface::Stmt::Match {
                 location: _,
                 switch,
@@ -504,7 +990,17 @@ impl<'a> SemanticAnalyzer<'a> {
                 self.breaks -= 1;
                 result
             }
-            surface::Stmt::Printf(_, args) => {
+            surface::Stmt::Printf(location, args) => {
+                if let Some(surface::Expr::Literal(_, Literal::Str(format))) = args.first() {
+                    let expected = count_format_placeholders(format);
+                    if expected != args.len() - 1 {
+                        self.log.log_warning(WarningMessage::FormatArgCount(
+                            location.clone(),
+                            expected,
+                            args.len() - 1,
+                        ));
+                    }
+                }
                 let mut evaluated_args = Vec::new();
                 for arg in args {
                     evaluated_args.push(self.evaluate_expr(arg)?);
@@ -513,7 +1009,11 @@ impl<'a> SemanticAnalyzer<'a> {
             }
             surface::Stmt::Return(_, e) => {
                 if let Some(e) = e {
-                    Ok(Stmt::Return(Some(self.evaluate_expr(e)?)))
+                    let value = self.evaluate_expr(e)?;
+                    if let Some(expected) = self.return_type {
+                        check_type(expected, e, &value)?;
+                    }
+                    Ok(Stmt::Return(Some(value)))
                 } else {
                     Ok(Stmt::Return(None))
                 }
@@ -533,6 +1033,18 @@ impl<'a> SemanticAnalyzer<'a> {
                 self.continues -= 1;
                 result
             }
+            surface::Stmt::DoWhile {
+                location: _,
+                body,
+                condition,
+            } => {
+                self.breaks += 1;
+                self.continues += 1;
+                let result = self.evaluate_do_while_loop(body, condition);
+                self.breaks -= 1;
+                self.continues -= 1;
+                result
+            }
             surface::Stmt::Yield(_) => Ok(Stmt::Yield),
         }
     }
@@ -545,16 +1057,39 @@ impl<'a> SemanticAnalyzer<'a> {
     ) -> Result<Stmt> {
         let switch = self.evaluate_expr(switch)?;
         let mut evaluated_cases = Vec::new();
-        for case in cases {
+        let outer_fallthrough = self.fallthrough;
+        for (i, case) in cases.iter().enumerate() {
             let mut conditions = Vec::new();
             for cond in &case.conditions {
-                conditions.push(self.evaluate_expr(cond)?);
+                match cond {
+                    surface::CaseCondition::Value(e) => conditions.push(self.evaluate_expr(e)?),
+                    surface::CaseCondition::Range(location, start, end) => {
+                        let start = self.evaluate_range_bound(start)?;
+                        let end = self.evaluate_range_bound(end)?;
+                        if start > end {
+                            return Err(SemanticError::EmptyRange(location.clone(), start, end));
+                        }
+                        if end as i64 - start as i64 + 1 > MAX_RANGE_VALUES {
+                            return Err(SemanticError::RangeTooLarge(
+                                location.clone(),
+                                start,
+                                end,
+                            ));
+                        }
+                        conditions.extend((start..=end).map(|v| Expr::Literal(Literal::Int(v))));
+                    }
+                }
             }
-            let body = self.evaluate_stmt(&case.body)?;
-            evaluated_cases.push(Case::new(conditions, body));
+            self.fallthrough = i + 1 < cases.len() || default.is_some();
+            let body = self.evaluate_stmt(&case.body);
+            self.fallthrough = outer_fallthrough;
+            evaluated_cases.push(Case::new(conditions, body?));
         }
+        self.fallthrough = false;
+        let default = default.map(|s| self.evaluate_stmt(s));
+        self.fallthrough = outer_fallthrough;
         let default = match default {
-            Some(s) => Some(Box::new(self.evaluate_stmt(s)?)),
+            Some(s) => Some(Box::new(s?)),
             None => None,
         };
         Ok(Stmt::Match {
@@ -564,6 +1099,17 @@ impl<'a> SemanticAnalyzer<'a> {
         })
     }
 
+    fn evaluate_range_bound(&self, bound: &surface::Expr) -> Result<i32> {
+        match evaluate_const_expr(&self.symbol_table, bound)? {
+            Literal::Int(i) => Ok(i),
+            value => Err(SemanticError::InvalidType(
+                bound.location().clone(),
+                DataType::Int.name(),
+                value.data_type().name(),
+            )),
+        }
+    }
+
     fn evaluate_var_decl(
         &mut self,
         ident: &Identifier,
@@ -592,6 +1138,7 @@ impl<'a> SemanticAnalyzer<'a> {
         let var = Variable::Var(symbol.clone());
         self.symbol_table
             .define_variable(ident.value.clone(), var)?;
+        self.track_local(&symbol);
         Ok(Stmt::VarDecl(symbol, count))
     }
 
@@ -605,6 +1152,16 @@ impl<'a> SemanticAnalyzer<'a> {
         Ok(Stmt::While { condition, body })
     }
 
+    fn evaluate_do_while_loop(
+        &mut self,
+        body: &surface::Stmt,
+        condition: &surface::Expr,
+    ) -> Result<Stmt> {
+        let body = Box::new(self.evaluate_stmt(body)?);
+        let condition = self.evaluate_expr(condition)?;
+        Ok(Stmt::DoWhile { body, condition })
+    }
+
     fn evaluate_for_loop(
         &mut self,
         init: &surface::Stmt,
@@ -631,27 +1188,69 @@ impl<'a> SemanticAnalyzer<'a> {
         op: Operator,
         right: &surface::Expr,
     ) -> Result<Stmt> {
+        self.check_writable(location, reference)?;
         if op == Operator::Assign && matches!(reference, surface::Ref::Var(_)) {
-            self.declare_variable(
-                location,
-                reference,
-                match right {
-                    surface::Expr::Array(_, elements) => Some(elements.len()),
-                    _ => None,
-                },
-            )?;
+            let array_length = match right {
+                surface::Expr::Array(_, elements) => Some(elements.len()),
+                _ => None,
+            };
+            let symbol = self.declare_variable(location, reference, array_length)?;
+            if let Some(length) = array_length {
+                fit_array_init(location, &symbol, length)?;
+            }
         }
         let expr = self.evaluate_reference(reference)?;
         match expr {
-            Expr::Ref(reference) => Ok(Stmt::Assignment {
-                left: reference,
-                op,
-                right: self.evaluate_expr(right)?,
-            }),
+            Expr::Ref(reference) => {
+                let right = self.evaluate_expr(right)?;
+                if op != Operator::Assign {
+                    self.mark_read(location, &reference);
+                }
+                self.mark_assigned(location, &reference);
+                Ok(Stmt::Assignment {
+                    left: reference,
+                    op,
+                    right,
+                })
+            }
             _ => Err(SemanticError::ExpectedReferenceOperand(location.clone())),
         }
     }
 
+    fn check_writable(&self, location: &Location, reference: &surface::Ref) -> Result<()> {
+        let identifier = match reference {
+            surface::Ref::Var(i) | surface::Ref::Index(i, _) | surface::Ref::Dereference(i, _) => i,
+        };
+        match self.symbol_table.lookup_variable(&identifier.value) {
+            Some(Variable::ConstArray(_)) => {
+                Err(SemanticError::ExpectedReferenceOperand(location.clone()))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    fn mark_read(&mut self, location: &Location, reference: &Ref) {
+        let mut symbol = reference_symbol(reference).borrow_mut();
+        // Arrays are allowed to be read before every element is assigned
+        if !symbol.assigned && !symbol.global && symbol.array_length.is_none() {
+            self.log.log_warning(WarningMessage::UseBeforeAssignment(
+                location.clone(),
+                symbol.name.clone(),
+            ));
+            // Only report the first use
+            symbol.assigned = true;
+        }
+        symbol.used = true;
+    }
+
+    fn mark_assigned(&mut self, location: &Location, reference: &Ref) {
+        match reference {
+            Ref::Var(symbol) | Ref::Index(symbol, _) => symbol.borrow_mut().assigned = true,
+            // Writing through a pointer reads the pointer itself
+            Ref::Dereference(_, _) => self.mark_read(location, reference),
+        }
+    }
+
     fn declare_variable(
         &mut self,
         location: &Location,
@@ -671,9 +1270,11 @@ impl<'a> SemanticAnalyzer<'a> {
         } else {
             let mut symbol = VarSymbol::new(id.value.clone(), id.location.clone(), false);
             symbol.array_length = array_length;
+            symbol.implicit = true;
             let symbol = make_shared(symbol);
             self.symbol_table
                 .define_variable(id.value.clone(), Variable::Var(symbol.clone()))?;
+            self.track_local(&symbol);
             Ok(symbol)
         }
     }
@@ -685,15 +1286,49 @@ impl<'a> SemanticAnalyzer<'a> {
     // as much of the script as possible
     fn evaluate_block(&mut self, stmts: &[surface::Stmt]) -> Stmt {
         let mut evaluated = Vec::new();
+        let mut reachable = true;
         for stmt in stmts {
+            match stmt {
+                // Labels (including ones in asm) can be jumped to, making what follows reachable
+                surface::Stmt::Label(_, _) | surface::Stmt::Asm(_, _) => reachable = true,
+                _ if !reachable => {
+                    // Only report the first unreachable statement in a run
+                    self.log
+                        .log_warning(WarningMessage::DeadCode(stmt.location().clone()));
+                    reachable = true;
+                }
+                _ => {}
+            }
+            if always_jumps(stmt) {
+                reachable = false;
+            }
             match self.evaluate_stmt(stmt) {
-                Ok(s) => evaluated.push(s),
+                Ok(s) => {
+                    evaluated.push(Stmt::SourceLocation(stmt.location().clone()));
+                    evaluated.push(s);
+                }
                 Err(err) => self.log.log_error(err.into()),
             }
         }
         Stmt::Block(evaluated)
     }
 
+    fn evaluate_asm(&mut self, instructions: &[surface::AsmInstruction]) -> Result<Stmt> {
+        let mut opcodes = Vec::new();
+        for instruction in instructions {
+            let mut args = Vec::new();
+            for arg in &instruction.args {
+                args.push(evaluate_const_expr(&self.symbol_table, arg)?);
+            }
+            let opcode =
+                asm::to_opcode(&instruction.identifier.value, &args).map_err(|reason| {
+                    SemanticError::BadAsmInstruction(instruction.location.clone(), reason)
+                })?;
+            opcodes.push(opcode);
+        }
+        Ok(Stmt::Asm(opcodes))
+    }
+
     fn evaluate_goto(&mut self, location: Location, identifier: &Identifier) -> Result<Stmt> {
         let symbol = if let Some(symbol) = self.symbol_table.lookup_label(&identifier.value) {
             symbol.borrow_mut().references.push(location);
@@ -763,15 +1398,16 @@ impl<'a> SemanticAnalyzer<'a> {
                 variant,
             )?)),
             surface::Expr::Unary(_, operand, op) => {
-                if let surface::Expr::Literal(_, _) = operand.as_ref() {
-                    if *op != Operator::FloatNegate {
-                        Ok(Expr::Literal(evaluate_const_expr(
-                            &self.symbol_table,
-                            expr,
-                        )?))
-                    } else {
-                        Ok(Expr::Unary(*op, Box::new(self.evaluate_expr(operand)?)))
-                    }
+                let is_const = match operand.as_ref() {
+                    surface::Expr::Literal(_, _) => true,
+                    surface::Expr::FunctionCall(_, identifier, _) => identifier.value == "float_bits",
+                    _ => false,
+                };
+                if is_const {
+                    Ok(Expr::Literal(evaluate_const_expr(
+                        &self.symbol_table,
+                        expr,
+                    )?))
                 } else {
                     Ok(Expr::Unary(*op, Box::new(self.evaluate_expr(operand)?)))
                 }
@@ -788,13 +1424,40 @@ impl<'a> SemanticAnalyzer<'a> {
                 } else {
                     let left = self.evaluate_expr(left)?;
                     let right = self.evaluate_expr(right)?;
+                    // Strings can't be combined at runtime, so fold them even when they come from constants
+                    if let (Expr::Literal(l), Expr::Literal(r)) = (&left, &right) {
+                        if matches!(l, Literal::Str(_)) || matches!(r, Literal::Str(_)) {
+                            return Ok(Expr::Literal(fold_binary(
+                                expr.location(),
+                                l.clone(),
+                                *op,
+                                r.clone(),
+                            )?));
+                        }
+                    }
                     Ok(Expr::Binary(Box::new(left), *op, Box::new(right)))
                 }
             }
             surface::Expr::FunctionCall(_, identifier, args) => {
                 self.evaluate_function_call(identifier, args)
             }
-            surface::Expr::Ref(_, reference) => self.evaluate_reference(reference),
+            surface::Expr::Ref(location, reference) => {
+                let expr = self.evaluate_reference(reference)?;
+                if let Expr::Ref(reference) = &expr {
+                    self.mark_read(location, reference);
+                    // Arrays have to be passed around by address
+                    if let Ref::Var(symbol) = reference {
+                        let symbol = symbol.borrow();
+                        if symbol.array_length.is_some() {
+                            self.log.log_warning(WarningMessage::ArrayAsScalar(
+                                location.clone(),
+                                symbol.name.clone(),
+                            ));
+                        }
+                    }
+                }
+                Ok(expr)
+            }
             surface::Expr::Grouped(_, e) => self.evaluate_expr(e),
             surface::Expr::Increment(location, reference, op, notation) => {
                 self.evaluate_increment(location, reference, *op, *notation)
@@ -802,6 +1465,21 @@ impl<'a> SemanticAnalyzer<'a> {
             surface::Expr::AddressOf(location, reference) => {
                 self.evaluate_address_of(location, reference)
             }
+            surface::Expr::Conditional(_, condition, then, otherwise) => {
+                let condition = self.evaluate_expr(condition)?;
+                let then_value = self.evaluate_expr(then)?;
+                let otherwise_value = self.evaluate_expr(otherwise)?;
+                // Both branches leave their value in the same place, so they need to agree on its type
+                let expected = expr_type(&then_value);
+                if expected != DataType::Any {
+                    check_type(expected, otherwise, &otherwise_value)?;
+                }
+                Ok(Expr::Conditional(
+                    Box::new(condition),
+                    Box::new(then_value),
+                    Box::new(otherwise_value),
+                ))
+            }
         }
     }
 
@@ -810,9 +1488,29 @@ impl<'a> SemanticAnalyzer<'a> {
         ident: &Identifier,
         args: &[surface::Expr],
     ) -> Result<Expr> {
+        if ident.value == "exlcall" {
+            return self.evaluate_exlcall(ident, args);
+        }
+        if ident.value == "float_bits" {
+            let value = evaluate_float_bits(&self.symbol_table, &ident.location, args)?;
+            return Ok(Expr::Literal(value));
+        }
+        if self.lookup_inline(&ident.value).is_some() {
+            return Err(SemanticError::InlineAsExpression(ident.location.clone()));
+        }
         let symbol = if let Some(symbol) = self.symbol_table.lookup_function(&ident.value) {
             symbol
         } else {
+            if self.symbol_table.lookup_alias(&ident.value).is_none() {
+                let similar = self.symbol_table.similar_function_names(&ident.value);
+                if !similar.is_empty() {
+                    self.log.log_warning(WarningMessage::UnknownFunction(
+                        ident.location.clone(),
+                        ident.value.clone(),
+                        similar,
+                    ));
+                }
+            }
             let symbol = make_shared(FunctionSymbol::new(
                 ident.value.clone(),
                 Location::External,
@@ -837,30 +1535,162 @@ impl<'a> SemanticAnalyzer<'a> {
             for arg in args {
                 evaluated_args.push(self.evaluate_expr(arg)?);
             }
+            for ((expected, arg), value) in symbol
+                .borrow()
+                .parameter_types
+                .iter()
+                .zip(args)
+                .zip(&evaluated_args)
+            {
+                check_type(*expected, arg, value)?;
+            }
         }
 
         Ok(Expr::FunctionCall(symbol, evaluated_args))
     }
 
+    /// Full name of the inline function that a call refers to, if any.
+    fn lookup_inline(&self, name: &str) -> Option<String> {
+        let symbol = self.symbol_table.lookup_function(name)?;
+        let name = symbol.borrow().name.clone();
+        self.inline_functions.contains_key(&name).then_some(name)
+    }
+
+    fn expand_inline(&mut self, ident: &Identifier, args: &[surface::Expr]) -> Result<Stmt> {
+        let name = self.lookup_inline(&ident.value).unwrap();
+        let InlineFunction {
+            namespaces,
+            parameters,
+            body,
+        } = self.inline_functions[&name].clone();
+        if args.len() != parameters.len() {
+            return Err(SemanticError::BadArgCount(
+                ident.location.clone(),
+                parameters.len(),
+                args.len(),
+            ));
+        }
+        if self.inline_stack.contains(&name) {
+            return Err(SemanticError::RecursiveInline(
+                ident.location.clone(),
+                ident.value.clone(),
+            ));
+        }
+
+        // Arguments are evaluated in the caller's scope, then bound to fresh variables
+        // so the body can't see or clobber anything from the caller
+        let mut evaluated_args = Vec::new();
+        for (param, arg) in parameters.iter().zip(args) {
+            let value = self.evaluate_expr(arg)?;
+            if let Some(expected) = param.data_type {
+                check_type(expected, arg, &value)?;
+            }
+            evaluated_args.push(value);
+        }
+        let previous_scope = self.symbol_table.open_isolated_scope();
+        let previous_namespaces = self.symbol_table.swap_namespaces(namespaces);
+        let breaks = std::mem::take(&mut self.breaks);
+        let continues = std::mem::take(&mut self.continues);
+        let fallthrough = std::mem::take(&mut self.fallthrough);
+        self.inline_stack.push(name);
+        let result = self.bind_inline_parameters(&parameters, evaluated_args, &body);
+        self.inline_stack.pop();
+        self.breaks = breaks;
+        self.continues = continues;
+        self.fallthrough = fallthrough;
+        self.symbol_table.swap_namespaces(previous_namespaces);
+        self.symbol_table.close_isolated_scope(previous_scope);
+        result
+    }
+
+    fn bind_inline_parameters(
+        &mut self,
+        parameters: &[Parameter],
+        args: Vec<Expr>,
+        body: &surface::Stmt,
+    ) -> Result<Stmt> {
+        let mut stmts = Vec::new();
+        for (param, arg) in parameters.iter().zip(args) {
+            let param_ident = &param.identifier;
+            let mut symbol = VarSymbol::new(
+                param_ident.value.clone(),
+                param_ident.location.clone(),
+                false,
+            );
+            symbol.assigned = true;
+            symbol.data_type = param.data_type;
+            let symbol = make_shared(symbol);
+            self.symbol_table
+                .define_variable(param_ident.value.clone(), Variable::Var(symbol.clone()))?;
+            stmts.push(Stmt::Assignment {
+                left: Ref::Var(symbol),
+                op: Operator::Assign,
+                right: arg,
+            });
+        }
+        stmts.push(self.evaluate_stmt(body)?);
+        Ok(Stmt::Block(stmts))
+    }
+
+    fn evaluate_exlcall(&mut self, ident: &Identifier, args: &[surface::Expr]) -> Result<Expr> {
+        let id = match args.first() {
+            Some(arg) => match evaluate_const_expr(&self.symbol_table, arg) {
+                Ok(Literal::Int(id)) => id,
+                _ => return Err(SemanticError::BadExlCall(arg.location().clone())),
+            },
+            None => return Err(SemanticError::BadExlCall(ident.location.clone())),
+        };
+        // Exlcall is variadic, so each call gets its own symbol
+        let symbol = make_shared(FunctionSymbol::new(
+            ident.value.clone(),
+            Location::Generated,
+            args.len(),
+            None,
+            false,
+        ));
+        let mut evaluated_args = vec![Expr::Literal(Literal::Int(id))];
+        for arg in &args[1..] {
+            evaluated_args.push(self.evaluate_expr(arg)?);
+        }
+        Ok(Expr::FunctionCall(symbol, evaluated_args))
+    }
+
     fn evaluate_reference(&mut self, reference: &surface::Ref) -> Result<Expr> {
         match reference {
             surface::Ref::Var(identifier) => match self.find_var(identifier)? {
                 Variable::Const(c) => Ok(Expr::Literal(c.borrow().value.clone())),
+                Variable::ConstArray(c) => Ok(Expr::Ref(Ref::Var(self.const_array_variable(&c)))),
                 Variable::Var(symbol) => Ok(Expr::Ref(Ref::Var(symbol))),
             },
             surface::Ref::Index(identifier, index) => match self.find_var(identifier)? {
                 Variable::Const(_) => Err(SemanticError::ExpectedReferenceOperand(
                     identifier.location.clone(),
                 )),
+                Variable::ConstArray(c) => {
+                    let evaluated_index = self.evaluate_expr(index)?;
+                    // Constant indexes can skip the array entirely
+                    if let Expr::Literal(Literal::Int(i)) = evaluated_index {
+                        if let Some(value) = usize::try_from(i)
+                            .ok()
+                            .and_then(|i| c.borrow().values.get(i).cloned())
+                        {
+                            return Ok(Expr::Literal(value));
+                        }
+                    }
+                    let symbol = self.const_array_variable(&c);
+                    self.validate_index(identifier, &symbol, index, &evaluated_index);
+                    Ok(Expr::Ref(Ref::Index(symbol, Box::new(evaluated_index))))
+                }
                 Variable::Var(symbol) => {
                     let evaluated_index = self.evaluate_expr(index)?;
+                    self.validate_index(identifier, &symbol, index, &evaluated_index);
                     Ok(Expr::Ref(Ref::Index(symbol, Box::new(evaluated_index))))
                 }
             },
             surface::Ref::Dereference(identifier, index) => match self.find_var(identifier)? {
-                Variable::Const(_) => Err(SemanticError::ExpectedReferenceOperand(
-                    identifier.location.clone(),
-                )),
+                Variable::Const(_) | Variable::ConstArray(_) => Err(
+                    SemanticError::ExpectedReferenceOperand(identifier.location.clone()),
+                ),
                 Variable::Var(symbol) => {
                     let evaluated_index = if let Some(index) = index {
                         Some(Box::new(self.evaluate_expr(index)?))
@@ -873,10 +1703,91 @@ impl<'a> SemanticAnalyzer<'a> {
         }
     }
 
+    fn validate_index(
+        &mut self,
+        identifier: &Identifier,
+        symbol: &Shared<VarSymbol>,
+        index: &surface::Expr,
+        evaluated_index: &Expr,
+    ) {
+        let length = match symbol.borrow().array_length {
+            Some(length) => length,
+            None => {
+                self.log.log_warning(WarningMessage::IndexOfScalar(
+                    identifier.location.clone(),
+                    identifier.value.clone(),
+                ));
+                return;
+            }
+        };
+        if let Expr::Literal(Literal::Int(i)) = evaluated_index {
+            if *i < 0 || *i as usize >= length {
+                self.log.log_warning(WarningMessage::IndexOutOfBounds(
+                    index.location().clone(),
+                    *i,
+                    length,
+                ));
+            }
+        }
+    }
+
+    /// Variable holding a constant array's values in the current function.
+    fn const_array_variable(&mut self, constant: &Shared<ConstArraySymbol>) -> Shared<VarSymbol> {
+        if let Some((_, symbol)) = self
+            .const_arrays
+            .iter()
+            .find(|(c, _)| Rc::ptr_eq(c, constant))
+        {
+            return symbol.clone();
+        }
+        let symbol = match &constant.borrow().global {
+            Some(symbol) => symbol.clone(),
+            None => {
+                let c = constant.borrow();
+                let mut symbol = VarSymbol::new(c.name.clone(), c.location.clone(), false);
+                symbol.array_length = Some(c.values.len());
+                symbol.assigned = true;
+                symbol.used = true;
+                make_shared(symbol)
+            }
+        };
+        self.const_arrays.push((constant.clone(), symbol.clone()));
+        symbol
+    }
+
+    /// Prepend initializers for every constant array the function used.
+    fn initialize_const_arrays(&mut self, body: Stmt) -> Stmt {
+        let mut stmts: Vec<Stmt> = self
+            .const_arrays
+            .drain(..)
+            .map(|(constant, symbol)| Stmt::Assignment {
+                left: Ref::Var(symbol),
+                op: Operator::Assign,
+                right: Expr::Array(
+                    constant
+                        .borrow()
+                        .values
+                        .iter()
+                        .cloned()
+                        .map(Expr::Literal)
+                        .collect(),
+                ),
+            })
+            .collect();
+        if stmts.is_empty() {
+            return body;
+        }
+        match body {
+            Stmt::Block(body) => stmts.extend(body),
+            body => stmts.push(body),
+        }
+        Stmt::Block(stmts)
+    }
+
     fn find_var(&self, identifier: &Identifier) -> Result<Variable> {
         self.symbol_table
             .lookup_variable(&identifier.value)
-            .ok_or_else(|| SemanticError::UndefinedVariable(identifier.clone()))
+            .ok_or_else(|| undefined_variable(&self.symbol_table, identifier))
     }
 
     fn evaluate_increment(
@@ -886,7 +1797,10 @@ impl<'a> SemanticAnalyzer<'a> {
         op: Operator,
         notation: Notation,
     ) -> Result<Expr> {
+        self.check_writable(location, reference)?;
         if let Expr::Ref(reference) = self.evaluate_reference(reference)? {
+            self.mark_read(location, &reference);
+            self.mark_assigned(location, &reference);
             Ok(Expr::Increment(reference, op, notation))
         } else {
             Err(SemanticError::ExpectedReferenceOperand(location.clone()))
@@ -899,6 +1813,12 @@ impl<'a> SemanticAnalyzer<'a> {
         reference: &surface::Ref,
     ) -> Result<Expr> {
         if let Expr::Ref(reference) = self.evaluate_reference(reference)? {
+            {
+                // The variable may be read or initialized through the pointer
+                let mut symbol = reference_symbol(&reference).borrow_mut();
+                symbol.assigned = true;
+                symbol.used = true;
+            }
             Ok(Expr::AddressOf(reference))
         } else {
             Err(SemanticError::ExpectedReferenceOperand(location.clone()))
@@ -906,6 +1826,91 @@ impl<'a> SemanticAnalyzer<'a> {
     }
 }
 
-pub fn analyze(script: &surface::Script, log: &mut CompilerLog) -> Option<(Script, SymbolTable)> {
-    SemanticAnalyzer::analyze(log, script)
+/// Count the placeholders in a printf format, skipping escaped percent signs
+fn count_format_placeholders(format: &str) -> usize {
+    let mut count = 0;
+    let mut chars = format.chars();
+    while let Some(c) = chars.next() {
+        if c == '%' && chars.next() != Some('%') {
+            count += 1;
+        }
+    }
+    count
+}
+
+/// Check if control never continues past a statement
+fn always_jumps(stmt: &surface::Stmt) -> bool {
+    match stmt {
+        surface::Stmt::Return(_, _)
+        | surface::Stmt::Goto(_, _)
+        | surface::Stmt::Break(_)
+        | surface::Stmt::Continue(_)
+        | surface::Stmt::Fallthrough(_) => true,
+        surface::Stmt::Block(_, stmts) => stmts.last().map(always_jumps).unwrap_or_default(),
+        _ => false,
+    }
+}
+
+/// Make sure a static array init fits in the variable it assigns to.
+/// Implicitly declared variables grow to fit since their frame isn't fixed until code generation.
+fn fit_array_init(location: &Location, symbol: &Shared<VarSymbol>, length: usize) -> Result<()> {
+    let mut symbol = symbol.borrow_mut();
+    let capacity = symbol.array_length.unwrap_or(1);
+    if length <= capacity {
+        Ok(())
+    } else if symbol.implicit {
+        symbol.array_length = Some(length);
+        Ok(())
+    } else {
+        Err(SemanticError::ArrayInitTooLong(
+            location.clone(),
+            symbol.name.clone(),
+            capacity,
+            length,
+        ))
+    }
+}
+
+fn reference_symbol(reference: &Ref) -> &Shared<VarSymbol> {
+    match reference {
+        Ref::Var(symbol) | Ref::Index(symbol, _) | Ref::Dereference(symbol, _) => symbol,
+    }
+}
+
+/// Analyze a script on top of the declarations already in a symbol table (ex. from precompiled headers).
+/// Define constants that come from outside the source (ex. a build manifest's defines).
+/// A script declaring a constant with the same name is reported as a redefinition.
+pub fn define_constants(
+    symbol_table: &mut SymbolTable,
+    constants: &[(String, Literal)],
+    log: &mut CompilerLog,
+) {
+    for (name, value) in constants {
+        let symbol = ConstSymbol::new(name.clone(), Location::External, value.clone());
+        if let Err(err) =
+            symbol_table.define_variable(name.clone(), Variable::Const(make_shared(symbol)))
+        {
+            log.log_error(err.into());
+        }
+    }
+}
+
+/// Define aliases that come from outside the source (ex. an alias file).
+/// Names the script or its headers already alias keep their own definition,
+/// and any the script declares later replace these.
+pub fn define_aliases(symbol_table: &mut SymbolTable, aliases: &[(String, String)]) {
+    for (name, internal) in aliases {
+        if symbol_table.lookup_alias(name).is_none() {
+            // Can't fail since the name isn't taken
+            let _ = symbol_table.define_alias(name.clone(), internal.clone(), Location::External);
+        }
+    }
+}
+
+pub fn analyze(
+    script: &surface::Script,
+    symbol_table: SymbolTable,
+    log: &mut CompilerLog,
+) -> Option<(Script, SymbolTable)> {
+    SemanticAnalyzer::analyze(log, script, symbol_table)
 }
@@ -0,0 +1,237 @@
+use std::collections::{HashMap, HashSet};
+
+use exalt_lir::{simulate_stack, Opcode, StackAnalysis, StackError};
+
+/// A block of local frame slots handed out by the code generator.
+#[derive(Debug, Clone)]
+pub struct FrameAllocation {
+    pub frame_id: usize,
+    pub count: usize,
+
+    /// Parameters, variables placed by the symbol map, and variables whose address is taken
+    /// have to stay where they are.
+    pub pinned: bool,
+}
+
+fn local_frame_id(opcode: &Opcode) -> Option<usize> {
+    match opcode {
+        Opcode::VarLoad(id)
+        | Opcode::VarAddr(id)
+        | Opcode::ArrLoad(id)
+        | Opcode::ArrAddr(id)
+        | Opcode::PtrLoad(id)
+        | Opcode::PtrAddr(id) => Some(*id as usize),
+        _ => None,
+    }
+}
+
+fn with_local_frame_id(opcode: &Opcode, id: usize) -> Opcode {
+    let id = id as u16;
+    match opcode {
+        Opcode::VarLoad(_) => Opcode::VarLoad(id),
+        Opcode::VarAddr(_) => Opcode::VarAddr(id),
+        Opcode::ArrLoad(_) => Opcode::ArrLoad(id),
+        Opcode::ArrAddr(_) => Opcode::ArrAddr(id),
+        Opcode::PtrLoad(_) => Opcode::PtrLoad(id),
+        Opcode::PtrAddr(_) => Opcode::PtrAddr(id),
+        _ => opcode.clone(),
+    }
+}
+
+/// Find the assignment that consumes the address pushed by the opcode at `index`.
+/// Returns None if the address is used for anything else (shorthand assignments, increments, pointers).
+fn find_assignment(code: &[Opcode], analysis: &StackAnalysis, index: usize) -> Option<usize> {
+    let base = analysis.depth(index)?;
+    if matches!(
+        code.get(index + 1),
+        Some(Opcode::Dereference | Opcode::Inc | Opcode::Dec)
+    ) {
+        return None;
+    }
+    // The value is generated right after the address, so the first assignment with both on top
+    // of the stack is the one that consumes it
+    for (i, opcode) in code.iter().enumerate().skip(index + 1) {
+        let depth = analysis.depth(i)?;
+        if depth <= base {
+            return None;
+        }
+        if matches!(opcode, Opcode::Assign | Opcode::CompleteAssign) && depth == base + 2 {
+            return Some(i);
+        }
+    }
+    None
+}
+
+fn successors(code: &[Opcode], labels: &HashMap<&str, usize>, index: usize) -> Vec<usize> {
+    let mut successors = Vec::new();
+    if code[index].falls_through() && index + 1 < code.len() {
+        successors.push(index + 1);
+    }
+    if let Some(target) = code[index].label_target().and_then(|l| labels.get(l)) {
+        successors.push(*target);
+    }
+    successors
+}
+
+/// Give local variables with disjoint lifetimes the same frame slot.
+///
+/// Only single slot variables move. Arrays and pinned allocations keep their index and their slots are never shared.
+/// Returns the new frame size, or None if nothing could be moved.
+pub fn reallocate<F>(
+    code: &mut [Opcode],
+    allocations: &[FrameAllocation],
+    arity: F,
+) -> Option<usize>
+where
+    F: Fn(usize) -> Option<usize>,
+{
+    let mut owners: HashMap<usize, &FrameAllocation> = HashMap::new();
+    for allocation in allocations {
+        for slot in allocation.frame_id..allocation.frame_id + allocation.count {
+            owners.insert(slot, allocation);
+        }
+    }
+    // Anything indexed or not handed out by the code generator stays put
+    let mut pinned_slots: HashSet<usize> = allocations
+        .iter()
+        .filter(|a| a.pinned || a.count > 1)
+        .flat_map(|a| a.frame_id..a.frame_id + a.count)
+        .collect();
+    for opcode in code.iter() {
+        if let Some(id) = local_frame_id(opcode) {
+            if matches!(opcode, Opcode::ArrLoad(_) | Opcode::ArrAddr(_))
+                || !owners.contains_key(&id)
+            {
+                pinned_slots.insert(id);
+            }
+        }
+    }
+    let mut variables: Vec<usize> = allocations
+        .iter()
+        .map(|a| a.frame_id)
+        .filter(|id| !pinned_slots.contains(id))
+        .collect();
+    variables.sort_unstable();
+    variables.dedup();
+    if variables.is_empty() {
+        return None;
+    }
+    let variable_index: HashMap<usize, usize> = variables
+        .iter()
+        .enumerate()
+        .map(|(i, id)| (*id, i))
+        .collect();
+
+    // Reads and writes of every variable
+    let analysis = simulate_stack(code, arity);
+    if analysis
+        .errors
+        .iter()
+        .any(|e| matches!(e, StackError::Underflow { .. }))
+    {
+        return None;
+    }
+    let mut uses = vec![Vec::new(); code.len()];
+    let mut defs = vec![Vec::new(); code.len()];
+    for (i, opcode) in code.iter().enumerate() {
+        let variable = match local_frame_id(opcode).and_then(|id| variable_index.get(&id)) {
+            Some(variable) => *variable,
+            None => continue,
+        };
+        match opcode {
+            Opcode::VarAddr(_) => match find_assignment(code, &analysis, i) {
+                Some(assignment) => defs[assignment].push(variable),
+                None => uses[i].push(variable),
+            },
+            _ => uses[i].push(variable),
+        }
+    }
+
+    // Backwards liveness until nothing changes
+    let labels: HashMap<&str, usize> = code
+        .iter()
+        .enumerate()
+        .filter_map(|(i, opcode)| match opcode {
+            Opcode::Label(l) => Some((l.as_str(), i)),
+            _ => None,
+        })
+        .collect();
+    let successors: Vec<Vec<usize>> = (0..code.len())
+        .map(|i| successors(code, &labels, i))
+        .collect();
+    let mut live_in = vec![vec![false; variables.len()]; code.len()];
+    let mut live_out = vec![vec![false; variables.len()]; code.len()];
+    let mut changed = true;
+    while changed {
+        changed = false;
+        for i in (0..code.len()).rev() {
+            let mut out = vec![false; variables.len()];
+            for successor in &successors[i] {
+                for (v, live) in live_in[*successor].iter().enumerate() {
+                    out[v] |= *live;
+                }
+            }
+            let mut inn = out.clone();
+            for v in &defs[i] {
+                inn[*v] = false;
+            }
+            for v in &uses[i] {
+                inn[*v] = true;
+            }
+            if inn != live_in[i] || out != live_out[i] {
+                live_in[i] = inn;
+                live_out[i] = out;
+                changed = true;
+            }
+        }
+    }
+
+    // Variables interfere if they are live at the same time or one is written while the other is live
+    let mut interferes = vec![vec![false; variables.len()]; variables.len()];
+    for i in 0..code.len() {
+        let live: Vec<usize> = (0..variables.len()).filter(|v| live_in[i][*v]).collect();
+        for a in &live {
+            for b in &live {
+                interferes[*a][*b] = true;
+            }
+        }
+        for a in &defs[i] {
+            for b in (0..variables.len()).filter(|v| live_out[i][*v]) {
+                interferes[*a][b] = true;
+                interferes[b][*a] = true;
+            }
+        }
+    }
+
+    // Hand out the lowest free slot in declaration order
+    let mut slots: Vec<usize> = Vec::with_capacity(variables.len());
+    for conflicts in &interferes {
+        let taken: HashSet<usize> = slots
+            .iter()
+            .enumerate()
+            .filter(|(other, _)| conflicts[*other])
+            .map(|(_, slot)| *slot)
+            .collect();
+        let slot = (0..)
+            .find(|slot| !pinned_slots.contains(slot) && !taken.contains(slot))
+            .unwrap();
+        slots.push(slot);
+    }
+    let new_ids: HashMap<usize, usize> = variables
+        .iter()
+        .copied()
+        .zip(slots.iter().copied())
+        .collect();
+    for opcode in code.iter_mut() {
+        if let Some(new_id) = local_frame_id(opcode).and_then(|id| new_ids.get(&id)) {
+            *opcode = with_local_frame_id(opcode, *new_id);
+        }
+    }
+    let frame_size = pinned_slots
+        .iter()
+        .chain(slots.iter())
+        .map(|slot| slot + 1)
+        .max()
+        .unwrap_or_default();
+    Some(frame_size)
+}
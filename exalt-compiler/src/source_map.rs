@@ -0,0 +1,78 @@
+use codespan_reporting::files::{Files, SimpleFiles};
+use exalt_assembler::FunctionLayout;
+use exalt_ast::Location;
+use serde::Serialize;
+
+use crate::codegen::SourceLocations;
+
+#[derive(Serialize)]
+struct SourceMap<'a> {
+    script: &'a str,
+    entries: Vec<SourceMapEntry<'a>>,
+}
+
+/// Where the opcode at an address was generated from.
+/// Lines and columns start at 1. Start and end are byte offsets in the source file.
+#[derive(Serialize)]
+struct SourceMapEntry<'a> {
+    function: usize,
+    opcode: usize,
+    address: usize,
+    file: &'a str,
+    line: usize,
+    column: usize,
+    start: usize,
+    end: usize,
+}
+
+fn make_entry<'a>(
+    function: usize,
+    opcode: usize,
+    address: usize,
+    location: &Location,
+    files: &'a SimpleFiles<String, String>,
+) -> Option<SourceMapEntry<'a>> {
+    let (file_id, range) = match location {
+        Location::Source(file_id, range) => (*file_id, range),
+        _ => return None,
+    };
+    let file = files.get(file_id).ok()?;
+    let position = files.location(file_id, range.start).ok()?;
+    Some(SourceMapEntry {
+        function,
+        opcode,
+        address,
+        file: file.name(),
+        line: position.line_number,
+        column: position.column_number,
+        start: range.start,
+        end: range.end,
+    })
+}
+
+/// Build a JSON map from the first opcode of each statement back to where it was written.
+/// Addresses are byte offsets in the assembled script.
+pub fn build(
+    script_name: &str,
+    locations: &[SourceLocations],
+    layout: &[FunctionLayout],
+    files: &SimpleFiles<String, String>,
+) -> Result<String, std::io::Error> {
+    let mut entries = Vec::new();
+    for (function, (locations, layout)) in locations.iter().zip(layout).enumerate() {
+        for (opcode, location) in locations {
+            // Statements that generate no code are marked past the end of the function
+            let offset = match layout.opcode_offsets.get(*opcode) {
+                Some(offset) => *offset,
+                None => continue,
+            };
+            let address = layout.code_address + offset;
+            entries.extend(make_entry(function, *opcode, address, location, files));
+        }
+    }
+    let map = SourceMap {
+        script: script_name,
+        entries,
+    };
+    Ok(serde_json::to_string_pretty(&map)?)
+}
@@ -0,0 +1,152 @@
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+
+use exalt_lir::Game;
+
+use crate::MemoryIncludes;
+
+/// Environment variable naming the directory the standard library's `std` folder lives in.
+pub const EXALT_STD: &str = "EXALT_STD";
+
+/// Root of the paths given to embedded files. Nothing is ever read from here on disk.
+const EMBEDDED_ROOT: &str = "<embedded>";
+
+macro_rules! embed {
+    ($($path:literal),* $(,)?) => {
+        &[$(($path, include_str!(concat!("../../", $path)))),*]
+    };
+}
+
+/// Copies of the standard library compiled in so an installed exe works without the repository.
+const EMBEDDED: &[(&str, &str)] = embed![
+    "std/fe9/enums.exl",
+    "std/fe9/prelude.exl",
+    "std/fe10/enums.exl",
+    "std/fe10/prelude.exl",
+    "std/fe13/bev.exl",
+    "std/fe13/enums.exl",
+    "std/fe13/functions.exl",
+    "std/fe13/prelude.exl",
+    "std/fe14/bev.exl",
+    "std/fe14/constants.exl",
+    "std/fe14/enums.exl",
+    "std/fe14/prelude.exl",
+    "std/fe15/bev.exl",
+    "std/fe15/enums.exl",
+    "std/fe15/functions.exl",
+    "std/fe15/prelude.exl",
+];
+
+fn embedded_source(path: &Path) -> Option<&'static str> {
+    let relative = path.strip_prefix(EMBEDDED_ROOT).ok()?;
+    EMBEDDED
+        .iter()
+        .find(|(name, _)| Path::new(name) == relative)
+        .map(|(_, source)| *source)
+}
+
+/// Whether a path refers to an embedded copy of a standard library file.
+pub(crate) fn is_embedded(path: &Path) -> bool {
+    embedded_source(path).is_some()
+}
+
+/// Where the embedded copy of a standard library directory on disk would be (ex. "/x/std/fe14" -> "<embedded>/std/fe14"),
+/// so a library that only overrides some files can still include the rest.
+pub(crate) fn embedded_counterpart(dir: &Path) -> Option<PathBuf> {
+    let components: Vec<_> = dir.components().collect();
+    let start = components.iter().rposition(|c| c.as_os_str() == "std")?;
+    let mut path = PathBuf::from(EMBEDDED_ROOT);
+    path.extend(&components[start..]);
+    Some(path)
+}
+
+/// Read a script from disk, or from the embedded standard library if the path points there.
+pub fn read_source(path: &Path) -> std::io::Result<String> {
+    match embedded_source(path) {
+        Some(source) => Ok(source.to_owned()),
+        None => std::fs::read_to_string(path),
+    }
+}
+
+/// Name of a game's folder under `std`, if the standard library covers it.
+fn game_dir(game: Game) -> Option<&'static str> {
+    match game {
+        Game::FE9 => Some("fe9"),
+        Game::FE10 => Some("fe10"),
+        Game::FE13 => Some("fe13"),
+        Game::FE14 => Some("fe14"),
+        Game::FE15 => Some("fe15"),
+        _ => None,
+    }
+}
+
+/// Include path of a game's prelude (ex. "std:fe14:prelude"), if the game has one.
+pub fn prelude_include(game: Game) -> Option<String> {
+    game_dir(game).map(|name| format!("std:{}:prelude", name))
+}
+
+/// Where the standard library is loaded from.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum StdLibrary {
+    /// A directory containing `std` (ex. the repository or an install next to the exe).
+    Directory(PathBuf),
+
+    /// The copy compiled into exalt.
+    Embedded,
+}
+
+impl StdLibrary {
+    /// Find the standard library, checking in order:
+    /// 1. The directory in EXALT_STD
+    /// 2. The directory of the running exe, if it has a `std` folder
+    /// 3. The embedded copy
+    ///
+    /// Includes missing from the directory still resolve to the embedded copy.
+    pub fn locate() -> Self {
+        if let Some(dir) = std::env::var_os(EXALT_STD).filter(|dir| !dir.is_empty()) {
+            return StdLibrary::Directory(PathBuf::from(dir));
+        }
+        let exe_dir = std::env::current_exe()
+            .ok()
+            .and_then(|exe| exe.parent().map(Path::to_path_buf));
+        match exe_dir {
+            Some(dir) if dir.join("std").is_dir() => StdLibrary::Directory(dir),
+            _ => StdLibrary::Embedded,
+        }
+    }
+
+    /// Directories includes like `std:fe14:prelude` are searched for in, in order.
+    /// The embedded copy always comes last so anything missing from a directory still resolves.
+    pub fn include_dirs(&self) -> Vec<PathBuf> {
+        let mut dirs = Vec::new();
+        if let StdLibrary::Directory(dir) = self {
+            dirs.push(dir.clone());
+        }
+        dirs.push(PathBuf::from(EMBEDDED_ROOT));
+        dirs
+    }
+
+    /// Path of a game's prelude, if the game has one.
+    /// The library's directory is preferred, falling back to the embedded prelude.
+    pub fn prelude(&self, game: Game) -> Option<PathBuf> {
+        let name = game_dir(game)?;
+        let relative = format!("std/{}/prelude.exl", name);
+        self.include_dirs()
+            .into_iter()
+            .map(|dir| dir.join(&relative))
+            .find(|path| path.is_file() || is_embedded(path))
+    }
+}
+
+/// The embedded standard library under the names [`MemoryIncludes`] uses (ex. "std/fe14/prelude.exl"),
+/// for compiles without a filesystem whose includes don't supply it.
+pub(crate) fn embedded_includes() -> &'static MemoryIncludes {
+    static INCLUDES: OnceLock<MemoryIncludes> = OnceLock::new();
+    INCLUDES.get_or_init(|| {
+        let mut includes = MemoryIncludes::new();
+        for (name, source) in EMBEDDED {
+            includes.insert(*name, *source);
+        }
+        includes
+    })
+}
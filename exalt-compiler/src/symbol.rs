@@ -2,8 +2,10 @@ use std::collections::HashMap;
 
 use crate::reporting::SemanticError;
 use exalt_ast::{
-    ConstSymbol, EnumSymbol, FunctionSymbol, LabelSymbol, Location, Shared, VarSymbol,
+    ConstArraySymbol, ConstSymbol, DataType, EnumSymbol, FunctionSymbol, LabelSymbol, Location,
+    Shared, VarSymbol,
 };
+use exalt_lir::FunctionDatabase;
 use itertools::Itertools;
 
 type Result<T> = std::result::Result<T, SemanticError>;
@@ -12,6 +14,7 @@ type Result<T> = std::result::Result<T, SemanticError>;
 #[derive(Debug, Clone)]
 pub enum Variable {
     Const(Shared<ConstSymbol>),
+    ConstArray(Shared<ConstArraySymbol>),
     Var(Shared<VarSymbol>),
 }
 
@@ -19,6 +22,7 @@ impl Variable {
     pub fn definition_location(&self) -> Location {
         match self {
             Variable::Const(c) => c.borrow().location.clone(),
+            Variable::ConstArray(c) => c.borrow().location.clone(),
             Variable::Var(v) => v.borrow().location.clone(),
         }
     }
@@ -85,6 +89,10 @@ impl Scope {
                 let s = s.borrow();
                 (s.location.range().map(|r| r.start), s.name.clone())
             }
+            Variable::ConstArray(s) => {
+                let s = s.borrow();
+                (s.location.range().map(|r| r.start), s.name.clone())
+            }
             Variable::Var(s) => {
                 let s = s.borrow();
                 (s.location.range().map(|r| r.start), s.name.clone())
@@ -100,43 +108,53 @@ const FUNCTION_SCOPE: usize = 1;
 /// Data structure for all symbols in the current context
 pub struct SymbolTable {
     scopes: Vec<Scope>,
+    // Scopes below this index (other than the global scope) are hidden from variable lookups
+    first_visible_scope: usize,
+    // Namespaces around the declarations currently being analyzed, outermost first
+    namespaces: Vec<String>,
     completed_function_scopes: Vec<Scope>,
     enums: HashMap<String, Shared<EnumSymbol>>,
     functions: HashMap<String, Shared<FunctionSymbol>>,
     aliases: HashMap<String, (Location, String)>,
+    // Doc comments for aliases, which have no symbol to hold them
+    alias_docs: HashMap<String, String>,
 }
 
 impl SymbolTable {
     pub fn new() -> Self {
         // Set up built in functions
+        // Only return types are declared so decompiled scripts that mix types still compile
         let mut functions = HashMap::new();
+        let builtins = [
+            ("negate", 1, DataType::Int),
+            ("negatef", 1, DataType::Float),
+            ("fix", 1, DataType::Int),
+            ("float", 1, DataType::Float),
+            ("float_bits", 1, DataType::Float),
+            ("streq", 2, DataType::Int),
+            ("strne", 2, DataType::Int),
+        ];
+        for (name, arity, return_type) in builtins {
+            let symbol =
+                FunctionSymbol::shared(name.to_owned(), Location::Generated, arity, None, false);
+            symbol.borrow_mut().return_type = Some(return_type);
+            functions.insert(name.to_owned(), symbol);
+        }
+        // Takes a call id followed by any number of arguments. Arity is checked separately.
         functions.insert(
-            "negate".to_owned(),
-            FunctionSymbol::shared("negate".to_owned(), Location::Generated, 1, None, false),
-        );
-        functions.insert(
-            "fix".to_owned(),
-            FunctionSymbol::shared("fix".to_owned(), Location::Generated, 1, None, false),
-        );
-        functions.insert(
-            "float".to_owned(),
-            FunctionSymbol::shared("float".to_owned(), Location::Generated, 1, None, false),
-        );
-        functions.insert(
-            "streq".to_owned(),
-            FunctionSymbol::shared("streq".to_owned(), Location::Generated, 2, None, false),
-        );
-        functions.insert(
-            "strne".to_owned(),
-            FunctionSymbol::shared("strne".to_owned(), Location::Generated, 2, None, false),
+            "exlcall".to_owned(),
+            FunctionSymbol::shared("exlcall".to_owned(), Location::Generated, 1, None, false),
         );
 
         SymbolTable {
             scopes: vec![Scope::new()],
+            first_visible_scope: 0,
+            namespaces: Vec::new(),
             completed_function_scopes: Default::default(),
             enums: HashMap::new(),
             functions,
             aliases: HashMap::new(),
+            alias_docs: HashMap::new(),
         }
     }
 
@@ -150,6 +168,57 @@ impl SymbolTable {
         }
     }
 
+    /// Open a scope that can only see globals, ex. for the body of an inline function.
+    /// Returns the previous visibility which should be passed to [`SymbolTable::close_isolated_scope`].
+    pub fn open_isolated_scope(&mut self) -> usize {
+        let previous = self.first_visible_scope;
+        self.first_visible_scope = self.scopes.len();
+        self.open_scope();
+        previous
+    }
+
+    pub fn close_isolated_scope(&mut self, previous: usize) {
+        self.close_scope();
+        self.first_visible_scope = previous;
+    }
+
+    pub fn enter_namespace(&mut self, name: String) {
+        self.namespaces.push(name);
+    }
+
+    pub fn exit_namespace(&mut self) {
+        self.namespaces.pop();
+    }
+
+    pub fn current_namespaces(&self) -> &[String] {
+        &self.namespaces
+    }
+
+    /// Replace the current namespaces, returning the previous ones so they can be restored.
+    pub fn swap_namespaces(&mut self, namespaces: Vec<String>) -> Vec<String> {
+        std::mem::replace(&mut self.namespaces, namespaces)
+    }
+
+    /// Full name of a symbol declared in the current namespace (ex. MAX in namespace foo is foo::MAX).
+    pub fn qualify(&self, name: &str) -> String {
+        if self.namespaces.is_empty() {
+            name.to_owned()
+        } else {
+            format!("{}::{}", self.namespaces.join("::"), name)
+        }
+    }
+
+    /// Names to try when resolving a symbol, starting from the innermost namespace.
+    fn candidate_names<'a>(&'a self, name: &'a str) -> impl Iterator<Item = String> + 'a {
+        (0..=self.namespaces.len()).rev().map(move |depth| {
+            if depth == 0 {
+                name.to_owned()
+            } else {
+                format!("{}::{}", self.namespaces[..depth].join("::"), name)
+            }
+        })
+    }
+
     pub fn define_enum(&mut self, name: String, symbol: Shared<EnumSymbol>) -> Result<()> {
         match self.enums.get(&name) {
             Some(original) => Err(SemanticError::SymbolRedefinition(
@@ -194,17 +263,18 @@ impl SymbolTable {
         self.scopes[FUNCTION_SCOPE].define_label(name, symbol)
     }
 
+    /// Aliases from outside the source (ex. an alias file) give way to ones a script declares.
     pub fn define_alias(&mut self, name: String, alias: String, location: Location) -> Result<()> {
         match self.aliases.get(&name) {
+            Some((Location::External, _)) | None => {
+                self.aliases.insert(name, (location, alias));
+                Ok(())
+            }
             Some((original_location, _)) => Err(SemanticError::SymbolRedefinition(
                 original_location.clone(),
                 location,
                 name,
             )),
-            None => {
-                self.aliases.insert(name, (location, alias));
-                Ok(())
-            }
         }
     }
 
@@ -212,21 +282,34 @@ impl SymbolTable {
         self.aliases.get(name)
     }
 
+    pub fn document_alias(&mut self, name: String, docs: String) {
+        self.alias_docs.insert(name, docs);
+    }
+
+    pub fn alias_docs(&self, name: &str) -> Option<&str> {
+        self.alias_docs.get(name).map(|d| d.as_str())
+    }
+
     pub fn lookup_enum(&self, name: &str) -> Option<Shared<EnumSymbol>> {
-        self.enums.get(name).cloned()
+        self.candidate_names(name)
+            .find_map(|name| self.enums.get(&name).cloned())
     }
 
     pub fn lookup_function(&self, name: &str) -> Option<Shared<FunctionSymbol>> {
-        self.functions.get(name).map(|f| f.to_owned())
+        self.candidate_names(name)
+            .find_map(|name| self.functions.get(&name).cloned())
     }
 
     pub fn lookup_variable(&self, name: &str) -> Option<Variable> {
-        for scope in self.scopes.iter().rev() {
+        // Locals are never namespaced, so only globals need the full name
+        let first_local_scope = self.first_visible_scope.max(1).min(self.scopes.len());
+        for scope in self.scopes[first_local_scope..].iter().rev() {
             if let Some(v) = scope.lookup_variable(name) {
                 return Some(v);
             }
         }
-        None
+        self.candidate_names(name)
+            .find_map(|name| self.scopes[0].lookup_variable(&name))
     }
 
     pub fn lookup_label(&self, name: &str) -> Option<Shared<LabelSymbol>> {
@@ -253,6 +336,19 @@ impl SymbolTable {
             .collect()
     }
 
+    /// Constants, constant arrays, and globals declared outside of any function.
+    pub fn global_variables(&self) -> Vec<Variable> {
+        self.scopes[0].variables.values().cloned().collect()
+    }
+
+    /// Aliases as (friendly name, location, internal name).
+    pub fn alias_declarations(&self) -> Vec<(String, Location, String)> {
+        self.aliases
+            .iter()
+            .map(|(name, (location, alias))| (name.clone(), location.clone(), alias.clone()))
+            .collect()
+    }
+
     pub fn enums(&self) -> Vec<Shared<EnumSymbol>> {
         self.enums.values().cloned().collect()
     }
@@ -261,6 +357,89 @@ impl SymbolTable {
         self.functions.values().cloned().collect()
     }
 
+    /// Every function declared in the table under both its alias and its name in compiled scripts.
+    pub fn function_database(&self) -> FunctionDatabase {
+        let mut database = FunctionDatabase::default();
+        for (name, symbol) in &self.functions {
+            if !matches!(
+                symbol.borrow().location,
+                Location::External | Location::Generated
+            ) {
+                database.insert(name.clone());
+            }
+        }
+        for (name, (_, alias)) in &self.aliases {
+            database.insert(name.clone());
+            database.insert(alias.clone());
+        }
+        database
+    }
+
+    /// Declared functions and aliases whose names are close to a name that could not be found.
+    /// The closest names come first.
+    pub fn similar_function_names(&self, name: &str) -> Vec<String> {
+        let declared = self
+            .functions
+            .iter()
+            .filter(|(_, symbol)| {
+                !matches!(
+                    symbol.borrow().location,
+                    Location::External | Location::Generated
+                )
+            })
+            .map(|(name, _)| name)
+            .chain(self.aliases.keys());
+        // Engine functions often differ by a single word (ex. GetFlag and SetFlag), so stay strict
+        let max_distance = if name.len() < 12 { 1 } else { 2 };
+        declared
+            .filter_map(|candidate| {
+                let distance = self
+                    .candidate_names(name)
+                    .map(|name| {
+                        strsim::osa_distance(&name.to_lowercase(), &candidate.to_lowercase())
+                    })
+                    .min()?;
+                (distance <= max_distance).then(|| (distance, candidate.clone()))
+            })
+            .sorted()
+            .dedup()
+            .take(3)
+            .map(|(_, candidate)| candidate)
+            .collect()
+    }
+
+    /// Visible variables whose names are close to a name that could not be found.
+    /// The closest names come first.
+    pub fn similar_variable_names(&self, name: &str) -> Vec<String> {
+        let first_local_scope = self.first_visible_scope.max(1).min(self.scopes.len());
+        let visible = self.scopes[first_local_scope..]
+            .iter()
+            .chain(std::iter::once(&self.scopes[0]))
+            .flat_map(|scope| scope.variables.keys());
+        let max_distance = if name.len() < 6 { 1 } else { 2 };
+        visible
+            .filter_map(|candidate| {
+                let distance = self
+                    .candidate_names(name)
+                    .map(|name| strsim::osa_distance(&name, candidate))
+                    .min()?;
+                (distance <= max_distance).then(|| (distance, candidate.clone()))
+            })
+            .sorted()
+            .dedup()
+            .take(3)
+            .map(|(_, candidate)| candidate)
+            .collect()
+    }
+
+    /// Labels from every function analyzed so far.
+    pub fn labels(&self) -> Vec<Shared<LabelSymbol>> {
+        self.completed_function_scopes
+            .iter()
+            .flat_map(|scope| scope.labels.values().cloned())
+            .collect()
+    }
+
     pub fn completed_scopes(&self) -> &[Scope] {
         &self.completed_function_scopes
     }
@@ -0,0 +1,74 @@
+use std::collections::BTreeMap;
+
+use exalt_assembler::{AssembleOptions, CodeGenTextData};
+use exalt_compiler::Divergence;
+use exalt_lir::{Function, Game, Opcode, RawScript, ScriptQuirks, TextOptions, SCHEMA_VERSION};
+
+fn script(value: i32) -> RawScript {
+    RawScript {
+        schema_version: SCHEMA_VERSION,
+        global_frame_size: 0,
+        functions: vec![Function {
+            frame_size: 1,
+            event: 0,
+            arity: 0,
+            unknown: 0,
+            prefix: Vec::new(),
+            suffix: Vec::new(),
+            name: Some("f".to_owned()),
+            args: Vec::new(),
+            code: vec![Opcode::IntLoad(value), Opcode::Consume, Opcode::Return],
+            comments: BTreeMap::new(),
+            offsets: Vec::new(),
+        }],
+        quirks: ScriptQuirks::default(),
+    }
+}
+
+/// Assemble with text data that isn't valid Shift-JIS. Nothing in the code refers to it.
+fn assemble_with_bad_text(script: &RawScript, game: Game) -> Vec<u8> {
+    let options = AssembleOptions {
+        text_data: Some(CodeGenTextData::hard_coded(
+            vec![0x81, 0xFF, 0],
+            Default::default(),
+        )),
+        ..Default::default()
+    };
+    exalt_assembler::assemble_with_options(script, "t", game, options).unwrap()
+}
+
+#[test]
+fn string_less_scripts_diff_by_opcode() {
+    for game in [Game::FE9, Game::FE10, Game::FE12] {
+        let expected = exalt_assembler::assemble(&script(1), "t", game).unwrap();
+        let actual = exalt_assembler::assemble(&script(2), "t", game).unwrap();
+        let divergences =
+            exalt_compiler::diff_scripts(&expected, &actual, game, TextOptions::default());
+        assert_eq!(
+            divergences,
+            vec![Divergence::Opcode {
+                function: 0,
+                index: 0,
+                expected: Some(Opcode::IntLoad(1)),
+                actual: Some(Opcode::IntLoad(2)),
+            }],
+            "{:?}",
+            game
+        );
+    }
+}
+
+#[test]
+fn unreadable_text_data_keeps_other_differences() {
+    let game = Game::FE10;
+    let expected = assemble_with_bad_text(&script(1), game);
+    let actual = assemble_with_bad_text(&script(2), game);
+    let divergences =
+        exalt_compiler::diff_scripts(&expected, &actual, game, TextOptions::default());
+    assert_eq!(divergences.len(), 2, "{:?}", divergences);
+    assert!(matches!(
+        divergences[0],
+        Divergence::Opcode { index: 0, .. }
+    ));
+    assert!(matches!(divergences[1], Divergence::Failed(_)));
+}
@@ -1,35 +1,472 @@
+mod navigation;
+
+use std::collections::HashMap;
+use std::ops::Range;
+
+use exalt_ast::surface::{Decl, Parameter, Script};
+use exalt_ast::{DataType, Literal, Location};
 use exalt_compiler::SymbolTable;
+pub use navigation::SymbolIndex;
+
+/// The script itself is always the first file in the log.
+const MAIN_FILE_ID: usize = 0;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CompletionKind {
+    Constant,
+    Enum,
+    EnumVariant,
+    Function,
+    Label,
+}
+
+#[derive(Debug, Clone)]
+pub struct Completion {
+    pub label: String,
+    pub kind: CompletionKind,
+    pub detail: Option<String>,
+}
+
+impl Completion {
+    fn new(label: String, kind: CompletionKind, detail: Option<String>) -> Self {
+        Completion {
+            label,
+            kind,
+            detail,
+        }
+    }
+}
+
+/// Signature of the function whose argument list the cursor is in.
+#[derive(Debug, Clone)]
+pub struct ParameterHint {
+    pub label: String,
+    /// Where each parameter is in the label, in bytes.
+    pub parameters: Vec<Range<usize>>,
+    pub active_parameter: usize,
+    pub docs: Option<String>,
+}
+
+/// What to show when hovering over a function.
+#[derive(Debug, Clone)]
+pub struct Hover {
+    pub signature: String,
+    /// Unknown for aliases, which only rename engine functions.
+    pub arity: Option<usize>,
+    pub docs: Option<String>,
+}
+
+struct FunctionInfo {
+    name: String,
+    parameters: Vec<String>,
+    return_type: Option<DataType>,
+    docs: Option<String>,
+}
+
+impl FunctionInfo {
+    fn signature(&self) -> (String, Vec<Range<usize>>) {
+        let mut label = format!("{}(", self.name);
+        let mut ranges = Vec::new();
+        for (i, parameter) in self.parameters.iter().enumerate() {
+            if i > 0 {
+                label.push_str(", ");
+            }
+            ranges.push(label.len()..label.len() + parameter.len());
+            label.push_str(parameter);
+        }
+        label.push(')');
+        if let Some(return_type) = &self.return_type {
+            label.push_str(" -> ");
+            label.push_str(data_type(return_type));
+        }
+        (label, ranges)
+    }
+}
+
+struct EnumInfo {
+    name: String,
+    variants: Vec<Completion>,
+}
+
+/// Where the cursor is, as far as completions are concerned.
+enum Context<'a> {
+    /// After `Enum.`
+    EnumVariant(&'a str),
+    /// After `goto`
+    Label,
+    Anywhere,
+}
 
 /// Completion server for a single script file.
 /// Provides suggestions using cached data from a successful run of the parser.
 #[derive(Default)]
 pub struct CompletionServer {
-    symbols: Vec<String>,
+    symbols: Vec<Completion>,
+    enums: Vec<EnumInfo>,
+    functions: Vec<FunctionInfo>,
+    // Friendly names for engine functions as (name, internal name, docs)
+    aliases: Vec<(String, String, Option<String>)>,
+    // Labels in the script along with where they are defined
+    labels: Vec<(Completion, usize)>,
+    // Functions and callbacks in the script, used to find the labels in scope
+    function_ranges: Vec<Range<usize>>,
 }
 
 impl CompletionServer {
-    pub fn from_symbol_table(symbol_table: &SymbolTable) -> Self {
+    pub fn from_parse_result(parse_tree: &Script, symbol_table: &SymbolTable) -> Self {
+        let mut parameter_names = HashMap::new();
+        let mut function_ranges = Vec::new();
+        collect_functions(
+            &parse_tree.0,
+            &mut Vec::new(),
+            &mut parameter_names,
+            &mut function_ranges,
+        );
+
+        let mut symbols = Vec::new();
+        for c in symbol_table.constants() {
+            let c = c.borrow();
+            symbols.push(Completion::new(
+                c.name.clone(),
+                CompletionKind::Constant,
+                Some(literal(&c.value)),
+            ));
+        }
+        let mut enums = Vec::new();
+        for e in symbol_table.enums() {
+            let e = e.borrow();
+            symbols.push(Completion::new(e.name.clone(), CompletionKind::Enum, None));
+            enums.push(EnumInfo {
+                name: e.name.clone(),
+                variants: e
+                    .variants
+                    .values()
+                    .map(|v| {
+                        Completion::new(
+                            v.name.clone(),
+                            CompletionKind::EnumVariant,
+                            Some(literal(&v.value)),
+                        )
+                    })
+                    .collect(),
+            });
+        }
+        let mut functions = Vec::new();
+        for f in symbol_table.functions() {
+            let f = f.borrow();
+            // Prefer the names from the declaration, falling back on types for functions we only know the shape of
+            let parameters = match parameter_names.remove(&f.name) {
+                Some(names) => names,
+                None => (0..f.arity)
+                    .map(|i| match f.parameter_types.get(i) {
+                        Some(t) => format!("arg{}: {}", i, data_type(t)),
+                        None => format!("arg{}", i),
+                    })
+                    .collect(),
+            };
+            let info = FunctionInfo {
+                name: f.name.clone(),
+                parameters,
+                return_type: f.return_type,
+                docs: f.docs.clone(),
+            };
+            symbols.push(Completion::new(
+                f.name.clone(),
+                CompletionKind::Function,
+                Some(info.signature().0),
+            ));
+            functions.push(info);
+        }
+        let aliases = symbol_table
+            .aliases()
+            .into_iter()
+            .map(|(name, alias)| {
+                let docs = symbol_table.alias_docs(&name).map(|d| d.to_owned());
+                (name, alias, docs)
+            })
+            .collect();
+        let labels = symbol_table
+            .labels()
+            .into_iter()
+            .filter_map(|l| {
+                let l = l.borrow();
+                match &l.location {
+                    Location::Source(MAIN_FILE_ID, range) => Some((
+                        Completion::new(l.name.clone(), CompletionKind::Label, None),
+                        range.start,
+                    )),
+                    _ => None,
+                }
+            })
+            .collect();
         Self {
-            symbols: symbol_table
-                .constants()
-                .iter()
-                .map(|c| c.borrow().name.clone())
-                .chain(symbol_table.enums().iter().map(|s| s.borrow().name.clone()))
-                .chain(
-                    symbol_table
-                        .functions()
+            symbols,
+            enums,
+            functions,
+            aliases,
+            labels,
+            function_ranges,
+        }
+    }
+
+    /// Also suggest names that the script does not declare (ex. known engine functions).
+    pub fn add_names<'a>(&mut self, names: impl IntoIterator<Item = &'a str>) {
+        for name in names {
+            if !self.symbols.iter().any(|s| s.label == name) {
+                self.symbols.push(Completion::new(
+                    name.to_owned(),
+                    CompletionKind::Function,
+                    None,
+                ));
+            }
+        }
+    }
+
+    /// Suggestions for the identifier being typed at the end of `before` (the document up to the cursor).
+    pub fn suggest_completions(&self, before: &str) -> Vec<&Completion> {
+        let prefix = identifier_before(before);
+        let rest = &before[..before.len() - prefix.len()];
+        let candidates: Vec<&Completion> = match context(rest) {
+            Context::EnumVariant(name) => self
+                .lookup_enum(name)
+                .map(|e| e.variants.iter().collect())
+                .unwrap_or_default(),
+            Context::Label => {
+                let offset = before.len();
+                match self.function_ranges.iter().find(|r| r.contains(&offset)) {
+                    Some(function) => self
+                        .labels
                         .iter()
-                        .map(|c| c.borrow().name.clone()),
-                )
-                .collect(),
+                        .filter(|(_, start)| function.contains(start))
+                        .map(|(label, _)| label)
+                        .collect(),
+                    None => Vec::new(),
+                }
+            }
+            Context::Anywhere => self.symbols.iter().collect(),
+        };
+        candidates
+            .into_iter()
+            .filter(|c| c.label.starts_with(prefix))
+            .collect()
+    }
+
+    /// Signature of the innermost known function whose argument list is still open at the end of `before`.
+    pub fn parameter_hint(&self, before: &str) -> Option<ParameterHint> {
+        open_calls(before)
+            .into_iter()
+            .rev()
+            .find_map(|(paren, argument)| {
+                let name = identifier_before(before[..paren].trim_end());
+                let function = self.functions.iter().find(|f| f.name == name)?;
+                let (label, parameters) = function.signature();
+                Some(ParameterHint {
+                    label,
+                    parameters,
+                    active_parameter: argument,
+                    docs: function.docs.clone(),
+                })
+            })
+    }
+
+    /// Signature, arity, and doc comment of a function or alias.
+    pub fn hover(&self, name: &str) -> Option<Hover> {
+        if let Some(function) = self.functions.iter().find(|f| f.name == name) {
+            return Some(Hover {
+                signature: function.signature().0,
+                arity: Some(function.parameters.len()),
+                docs: function.docs.clone(),
+            });
         }
+        self.aliases
+            .iter()
+            .find(|(alias_name, _, _)| alias_name == name)
+            .map(|(name, alias, docs)| Hover {
+                signature: format!("alias def {} -> {}", name, alias),
+                arity: None,
+                docs: docs.clone(),
+            })
     }
 
-    pub fn suggest_completions(&self, prefix: &str) -> Vec<&str> {
-        self.symbols
+    fn lookup_enum(&self, name: &str) -> Option<&EnumInfo> {
+        // Enums declared in a namespace can be referred to by their short name from inside it
+        let suffix = format!("::{}", name);
+        self.enums
             .iter()
-            .filter(|s| s.starts_with(prefix))
-            .map(|s| s.as_str())
-            .collect()
+            .find(|e| e.name == name)
+            .or_else(|| self.enums.iter().find(|e| e.name.ends_with(&suffix)))
+    }
+}
+
+fn collect_functions(
+    decls: &[Decl],
+    namespaces: &mut Vec<String>,
+    parameter_names: &mut HashMap<String, Vec<String>>,
+    function_ranges: &mut Vec<Range<usize>>,
+) {
+    let qualify = |namespaces: &[String], name: &str| {
+        namespaces
+            .iter()
+            .map(|n| n.as_str())
+            .chain(std::iter::once(name))
+            .collect::<Vec<_>>()
+            .join("::")
+    };
+    for decl in decls {
+        match decl {
+            Decl::Function {
+                location,
+                identifier,
+                parameters,
+                body,
+                ..
+            }
+            | Decl::Inline {
+                location,
+                identifier,
+                parameters,
+                body,
+                ..
+            } => {
+                parameter_names.insert(
+                    qualify(namespaces, &identifier.value),
+                    parameters.iter().map(parameter).collect(),
+                );
+                if let Some(range) = main_file_range(location, body.location()) {
+                    function_ranges.push(range);
+                }
+            }
+            Decl::FunctionExtern {
+                identifier,
+                parameters,
+                ..
+            } => {
+                parameter_names.insert(
+                    qualify(namespaces, &identifier.value),
+                    parameters.iter().map(parameter).collect(),
+                );
+            }
+            Decl::Callback { location, body, .. } => {
+                if let Some(range) = main_file_range(location, body.location()) {
+                    function_ranges.push(range);
+                }
+            }
+            Decl::Namespace {
+                identifier, decls, ..
+            } => {
+                namespaces.push(identifier.value.clone());
+                collect_functions(decls, namespaces, parameter_names, function_ranges);
+                namespaces.pop();
+            }
+            _ => {}
+        }
+    }
+}
+
+fn main_file_range(signature: &Location, body: &Location) -> Option<Range<usize>> {
+    match (signature, body) {
+        (Location::Source(MAIN_FILE_ID, start), Location::Source(MAIN_FILE_ID, end)) => {
+            Some(start.start..end.end)
+        }
+        _ => None,
+    }
+}
+
+fn parameter(parameter: &Parameter) -> String {
+    match &parameter.data_type {
+        Some(t) => format!("{}: {}", parameter.identifier.value, data_type(t)),
+        None => parameter.identifier.value.clone(),
+    }
+}
+
+fn data_type(data_type: &DataType) -> &'static str {
+    match data_type {
+        DataType::Int => "int",
+        DataType::Float => "float",
+        DataType::Str => "string",
+        DataType::Any => "any",
+    }
+}
+
+fn literal(literal: &Literal) -> String {
+    match literal {
+        Literal::Int(v) => v.to_string(),
+        Literal::Str(v) => format!("\"{}\"", v),
+        Literal::Float(v) => v.to_string(),
+    }
+}
+
+fn is_identifier_char(c: char) -> bool {
+    c.is_alphanumeric() || c == '_' || c == ':'
+}
+
+/// Identifier characters at the end of some text.
+fn identifier_before(text: &str) -> &str {
+    let start = text
+        .char_indices()
+        .rev()
+        .take_while(|(_, c)| is_identifier_char(*c))
+        .last()
+        .map(|(i, _)| i)
+        .unwrap_or(text.len());
+    &text[start..]
+}
+
+fn context(rest: &str) -> Context<'_> {
+    if let Some(before_dot) = rest.strip_suffix('.') {
+        let name = identifier_before(before_dot);
+        if name.starts_with(|c: char| c.is_alphabetic() || c == '_') {
+            return Context::EnumVariant(name);
+        }
+    }
+    let trimmed = rest.trim_end();
+    if trimmed.len() < rest.len() {
+        if let Some(before_goto) = trimmed.strip_suffix("goto") {
+            if identifier_before(before_goto).is_empty() {
+                return Context::Label;
+            }
+        }
+    }
+    Context::Anywhere
+}
+
+/// Argument lists still open at the end of some text as (offset of the paren, index of the current argument).
+/// Statements and blocks close anything left open, so a typo earlier in the script doesn't leak into later hints.
+fn open_calls(text: &str) -> Vec<(usize, usize)> {
+    let mut calls: Vec<(usize, usize)> = Vec::new();
+    let mut chars = text.char_indices();
+    let mut in_string = false;
+    while let Some((i, c)) = chars.next() {
+        if in_string {
+            match c {
+                '\\' => {
+                    chars.next();
+                }
+                '"' => in_string = false,
+                _ => {}
+            }
+            continue;
+        }
+        match c {
+            '"' => in_string = true,
+            '#' => {
+                chars.by_ref().find(|(_, c)| *c == '\n');
+            }
+            '/' if text[i + 1..].starts_with('/') => {
+                chars.by_ref().find(|(_, c)| *c == '\n');
+            }
+            '(' => calls.push((i, 0)),
+            ')' => {
+                calls.pop();
+            }
+            ',' => {
+                if let Some((_, argument)) = calls.last_mut() {
+                    *argument += 1;
+                }
+            }
+            ';' | '{' | '}' => calls.clear(),
+            _ => {}
+        }
     }
+    calls
 }
@@ -0,0 +1,462 @@
+use std::collections::HashMap;
+
+use exalt_ast::surface::{CaseCondition, Decl, Expr, Identifier, Parameter, Ref, Script, Stmt};
+use exalt_ast::{FileId, Location};
+use exalt_compiler::SymbolTable;
+
+/// Every identifier in a script paired with the definition it refers to.
+/// Built from a successful run of the parser so editors can jump between definitions and uses.
+#[derive(Default)]
+pub struct SymbolIndex {
+    // (identifier, definition)
+    occurrences: Vec<(Location, Location)>,
+}
+
+impl SymbolIndex {
+    pub fn from_parse_result(parse_tree: &Script, symbol_table: &SymbolTable) -> Self {
+        let mut indexer = Indexer {
+            symbol_table,
+            namespaces: Vec::new(),
+            locals: HashMap::new(),
+            labels: HashMap::new(),
+            occurrences: Vec::new(),
+        };
+        indexer.visit_decls(&parse_tree.0);
+        SymbolIndex {
+            occurrences: indexer.occurrences,
+        }
+    }
+
+    /// Where the symbol named at an offset is defined.
+    pub fn definition(&self, file: FileId, offset: usize) -> Option<Location> {
+        self.occurrence_at(file, offset)
+            .map(|(_, definition)| definition.clone())
+    }
+
+    /// Every place the symbol named at an offset is used, starting with its definition.
+    pub fn references(&self, file: FileId, offset: usize) -> Vec<Location> {
+        let definition = match self.definition(file, offset) {
+            Some(definition) => definition,
+            None => return Vec::new(),
+        };
+        let mut references = vec![definition.clone()];
+        for (location, target) in &self.occurrences {
+            if same_location(target, &definition) && !same_location(location, &definition) {
+                references.push(location.clone());
+            }
+        }
+        references
+    }
+
+    fn occurrence_at(&self, file: FileId, offset: usize) -> Option<&(Location, Location)> {
+        // The end is inclusive so the cursor can sit right after the identifier
+        self.occurrences
+            .iter()
+            .find(|(location, _)| match location {
+                Location::Source(f, range) => {
+                    *f == file && range.start <= offset && offset <= range.end
+                }
+                _ => false,
+            })
+    }
+}
+
+fn same_location(a: &Location, b: &Location) -> bool {
+    match (a, b) {
+        (Location::Source(f1, r1), Location::Source(f2, r2)) => f1 == f2 && r1 == r2,
+        _ => false,
+    }
+}
+
+struct Indexer<'a> {
+    symbol_table: &'a SymbolTable,
+    namespaces: Vec<String>,
+    // Locals and labels are visible everywhere in the function that declares them
+    locals: HashMap<String, Location>,
+    labels: HashMap<String, Location>,
+    occurrences: Vec<(Location, Location)>,
+}
+
+impl<'a> Indexer<'a> {
+    fn record(&mut self, identifier: &Identifier, definition: Option<Location>) {
+        if let Some(definition @ Location::Source(_, _)) = definition {
+            if let Location::Source(_, _) = identifier.location {
+                self.occurrences
+                    .push((identifier.location.clone(), definition));
+            }
+        }
+    }
+
+    /// Names to try for a global, starting from the innermost namespace.
+    fn candidate_names(&self, name: &str) -> Vec<String> {
+        (0..=self.namespaces.len())
+            .rev()
+            .map(|depth| {
+                if depth == 0 {
+                    name.to_owned()
+                } else {
+                    format!("{}::{}", self.namespaces[..depth].join("::"), name)
+                }
+            })
+            .collect()
+    }
+
+    fn lookup_variable(&self, name: &str) -> Option<Location> {
+        if let Some(location) = self.locals.get(name) {
+            return Some(location.clone());
+        }
+        self.candidate_names(name).into_iter().find_map(|name| {
+            self.symbol_table
+                .lookup_variable(&name)
+                .map(|v| v.definition_location())
+        })
+    }
+
+    fn lookup_function(&self, name: &str) -> Option<Location> {
+        self.candidate_names(name).into_iter().find_map(|name| {
+            match self.symbol_table.lookup_function(&name) {
+                Some(f) => Some(f.borrow().location.clone()),
+                None => self
+                    .symbol_table
+                    .lookup_alias(&name)
+                    .map(|(location, _)| location.clone()),
+            }
+        })
+    }
+
+    fn visit_decls(&mut self, decls: &[Decl]) {
+        for decl in decls {
+            self.visit_decl(decl);
+        }
+    }
+
+    fn visit_decl(&mut self, decl: &Decl) {
+        match decl {
+            Decl::Constant {
+                identifier, value, ..
+            } => {
+                self.record(identifier, self.lookup_variable(&identifier.value));
+                self.visit_expr(value);
+            }
+            Decl::Enum {
+                identifier,
+                variants,
+                ..
+            } => {
+                let symbol = self
+                    .candidate_names(&identifier.value)
+                    .into_iter()
+                    .find_map(|name| self.symbol_table.lookup_enum(&name));
+                self.record(
+                    identifier,
+                    symbol.as_ref().map(|e| e.borrow().location.clone()),
+                );
+                for v in variants {
+                    let location = symbol.as_ref().and_then(|e| {
+                        e.borrow()
+                            .variants
+                            .get(&v.identifier.value)
+                            .map(|c| c.location.clone())
+                    });
+                    self.record(&v.identifier, location);
+                    self.visit_expr(&v.value);
+                }
+            }
+            Decl::Function {
+                identifier,
+                parameters,
+                body,
+                ..
+            }
+            | Decl::Inline {
+                identifier,
+                parameters,
+                body,
+                ..
+            } => {
+                self.record(identifier, self.lookup_function(&identifier.value));
+                self.visit_function(parameters, body);
+            }
+            Decl::Callback {
+                event_types,
+                args,
+                body,
+                ..
+            } => {
+                for event_type in event_types {
+                    self.visit_expr(event_type);
+                }
+                for arg in args {
+                    self.visit_expr(arg);
+                }
+                self.visit_function(&[], body);
+            }
+            Decl::Global {
+                identifier,
+                count,
+                index,
+                ..
+            } => {
+                self.record(identifier, self.lookup_variable(&identifier.value));
+                if let Some(count) = count {
+                    self.visit_expr(count);
+                }
+                if let Some(index) = index {
+                    self.visit_expr(index);
+                }
+            }
+            Decl::FunctionAlias { identifier, .. } | Decl::FunctionExtern { identifier, .. } => {
+                self.record(identifier, self.lookup_function(&identifier.value));
+            }
+            Decl::GlobalExtern {
+                identifier,
+                count,
+                index,
+                ..
+            } => {
+                self.record(identifier, self.lookup_variable(&identifier.value));
+                if let Some(count) = count {
+                    self.visit_expr(count);
+                }
+                self.visit_expr(index);
+            }
+            Decl::Namespace {
+                identifier, decls, ..
+            } => {
+                self.namespaces.push(identifier.value.clone());
+                self.visit_decls(decls);
+                self.namespaces.pop();
+            }
+            Decl::Include { .. } => {}
+        }
+    }
+
+    fn visit_function(&mut self, parameters: &[Parameter], body: &Stmt) {
+        self.locals.clear();
+        self.labels.clear();
+        for p in parameters {
+            self.locals
+                .insert(p.identifier.value.clone(), p.identifier.location.clone());
+            self.record(&p.identifier, Some(p.identifier.location.clone()));
+        }
+        // Labels can be jumped to before they appear
+        collect_labels(body, &mut self.labels);
+        self.visit_stmt(body);
+        self.locals.clear();
+        self.labels.clear();
+    }
+
+    fn visit_stmt(&mut self, stmt: &Stmt) {
+        match stmt {
+            Stmt::Assignment { left, right, .. } => {
+                self.visit_expr(right);
+                self.visit_ref(left, true);
+            }
+            Stmt::Block(_, stmts) => {
+                for stmt in stmts {
+                    self.visit_stmt(stmt);
+                }
+            }
+            Stmt::DoWhile {
+                body, condition, ..
+            } => {
+                self.visit_stmt(body);
+                self.visit_expr(condition);
+            }
+            Stmt::ExprStmt(_, expr) => self.visit_expr(expr),
+            Stmt::For {
+                init,
+                check,
+                step,
+                body,
+                ..
+            } => {
+                self.visit_stmt(init);
+                self.visit_expr(check);
+                self.visit_stmt(step);
+                self.visit_stmt(body);
+            }
+            Stmt::Goto(_, identifier) | Stmt::Label(_, identifier) => {
+                let label = self.labels.get(&identifier.value).cloned();
+                self.record(identifier, label);
+            }
+            Stmt::If {
+                condition,
+                then_part,
+                else_part,
+                ..
+            } => {
+                self.visit_expr(condition);
+                self.visit_stmt(then_part);
+                if let Some(else_part) = else_part {
+                    self.visit_stmt(else_part);
+                }
+            }
+            Stmt::Asm(_, instructions) => {
+                for arg in instructions.iter().flat_map(|i| &i.args) {
+                    self.visit_expr(arg);
+                }
+            }
+            Stmt::Match {
+                switch,
+                cases,
+                default,
+                ..
+            } => {
+                self.visit_expr(switch);
+                for case in cases {
+                    for condition in &case.conditions {
+                        match condition {
+                            CaseCondition::Value(value) => self.visit_expr(value),
+                            CaseCondition::Range(_, start, end) => {
+                                self.visit_expr(start);
+                                self.visit_expr(end);
+                            }
+                        }
+                    }
+                    self.visit_stmt(&case.body);
+                }
+                if let Some(default) = default {
+                    self.visit_stmt(default);
+                }
+            }
+            Stmt::Printf(_, args) => {
+                for arg in args {
+                    self.visit_expr(arg);
+                }
+            }
+            Stmt::Return(_, value) => {
+                if let Some(value) = value {
+                    self.visit_expr(value);
+                }
+            }
+            Stmt::VarDecl(_, identifier, value) => {
+                if let Some(value) = value {
+                    self.visit_expr(value);
+                }
+                self.locals
+                    .entry(identifier.value.clone())
+                    .or_insert_with(|| identifier.location.clone());
+                self.record(identifier, self.lookup_variable(&identifier.value));
+            }
+            Stmt::While {
+                condition, body, ..
+            } => {
+                self.visit_expr(condition);
+                self.visit_stmt(body);
+            }
+            Stmt::Break(_) | Stmt::Continue(_) | Stmt::Fallthrough(_) | Stmt::Yield(_) => {}
+        }
+    }
+
+    fn visit_expr(&mut self, expr: &Expr) {
+        match expr {
+            Expr::Array(_, values) => {
+                for value in values {
+                    self.visit_expr(value);
+                }
+            }
+            Expr::Literal(_, _) => {}
+            Expr::EnumAccess(_, name, variant) => {
+                let symbol = self
+                    .candidate_names(&name.value)
+                    .into_iter()
+                    .find_map(|name| self.symbol_table.lookup_enum(&name));
+                let variant_location = symbol.as_ref().and_then(|e| {
+                    e.borrow()
+                        .variants
+                        .get(&variant.value)
+                        .map(|c| c.location.clone())
+                });
+                self.record(name, symbol.map(|e| e.borrow().location.clone()));
+                self.record(variant, variant_location);
+            }
+            Expr::Unary(_, operand, _) => self.visit_expr(operand),
+            Expr::Binary(_, left, _, right) => {
+                self.visit_expr(left);
+                self.visit_expr(right);
+            }
+            Expr::FunctionCall(_, identifier, args) => {
+                self.record(identifier, self.lookup_function(&identifier.value));
+                for arg in args {
+                    self.visit_expr(arg);
+                }
+            }
+            Expr::Grouped(_, inner) => self.visit_expr(inner),
+            Expr::Conditional(_, condition, then, otherwise) => {
+                self.visit_expr(condition);
+                self.visit_expr(then);
+                self.visit_expr(otherwise);
+            }
+            Expr::Ref(_, reference)
+            | Expr::Increment(_, reference, _, _)
+            | Expr::AddressOf(_, reference) => self.visit_ref(reference, false),
+        }
+    }
+
+    fn visit_ref(&mut self, reference: &Ref, assigned: bool) {
+        let identifier = match reference {
+            Ref::Var(identifier) => identifier,
+            Ref::Index(identifier, index) => {
+                self.visit_expr(index);
+                identifier
+            }
+            Ref::Dereference(identifier, index) => {
+                if let Some(index) = index {
+                    self.visit_expr(index);
+                }
+                identifier
+            }
+        };
+        let mut definition = self.lookup_variable(&identifier.value);
+        // Assigning to a name that doesn't exist yet declares it
+        if definition.is_none() && assigned {
+            self.locals
+                .insert(identifier.value.clone(), identifier.location.clone());
+            definition = Some(identifier.location.clone());
+        }
+        self.record(identifier, definition);
+    }
+}
+
+fn collect_labels(stmt: &Stmt, labels: &mut HashMap<String, Location>) {
+    match stmt {
+        Stmt::Label(_, identifier) => {
+            labels
+                .entry(identifier.value.clone())
+                .or_insert_with(|| identifier.location.clone());
+        }
+        Stmt::Block(_, stmts) => {
+            for stmt in stmts {
+                collect_labels(stmt, labels);
+            }
+        }
+        Stmt::DoWhile { body, .. } | Stmt::While { body, .. } => collect_labels(body, labels),
+        Stmt::For {
+            init, step, body, ..
+        } => {
+            collect_labels(init, labels);
+            collect_labels(step, labels);
+            collect_labels(body, labels);
+        }
+        Stmt::If {
+            then_part,
+            else_part,
+            ..
+        } => {
+            collect_labels(then_part, labels);
+            if let Some(else_part) = else_part {
+                collect_labels(else_part, labels);
+            }
+        }
+        Stmt::Match { cases, default, .. } => {
+            for case in cases {
+                collect_labels(&case.body, labels);
+            }
+            if let Some(default) = default {
+                collect_labels(default, labels);
+            }
+        }
+        _ => {}
+    }
+}
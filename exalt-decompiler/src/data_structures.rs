@@ -7,7 +7,7 @@ pub struct ExprStack<'a> {
 }
 
 impl<'a> ExprStack<'a> {
-    pub fn top(&self) -> Option<&'a Expr> {
+    pub fn top(&self) -> Option<&Expr<'a>> {
         self.stack.last()
     }
 
@@ -1,9 +1,13 @@
 use derive_more::Unwrap;
-use exalt_ast::{Notation, Operator};
+use exalt_ast::{escape_str, Notation, Operator};
+use exalt_lir::{Opcode, SymbolMap};
 
 use anyhow::{bail, Result};
 use itertools::Itertools;
+use serde::Deserialize;
 use std::borrow::Cow;
+use std::cell::RefCell;
+use std::collections::{BTreeMap, HashMap};
 use std::fmt::Write;
 
 use crate::IrTransform;
@@ -15,7 +19,7 @@ pub enum Literal<'a> {
     Str(Cow<'a, str>),
 }
 
-#[derive(Copy, Clone, PartialEq, Eq)]
+#[derive(Copy, Clone, PartialEq, Eq, Hash)]
 pub struct FrameId(pub usize, pub bool);
 
 #[derive(Clone, PartialEq)]
@@ -46,6 +50,8 @@ pub enum Expr<'a> {
     Inc(Operator, Notation, Reference<'a>),
     Grouped(Box<Expr<'a>>),
     StaticArrayInit(Vec<Expr<'a>>),
+    /// Condition, then the values for when it's true and false
+    Conditional(Box<Expr<'a>>, Box<Expr<'a>>, Box<Expr<'a>>),
 }
 
 pub struct Case<'a> {
@@ -53,23 +59,31 @@ pub struct Case<'a> {
     pub body: Stmt<'a>,
 }
 
+/// Addresses of the first and last opcode a statement was decompiled from.
+/// Only tracked in debug mode.
+pub type Origin = Option<(usize, usize)>;
+
 #[derive(Unwrap)]
 pub enum Stmt<'a> {
-    Assign(Operator, Reference<'a>, Expr<'a>),
+    /// Opcodes along with their addresses in the original script, if they should be shown.
+    Asm(&'a [Opcode], &'a [usize]),
+    Assign(Operator, Reference<'a>, Expr<'a>, Origin),
     Block(Vec<Stmt<'a>>),
     Break,
     Continue,
-    Expr(Expr<'a>),
+    DoWhile(Box<Stmt<'a>>, Expr<'a>),
+    Expr(Expr<'a>, Origin),
+    Fallthrough,
     For(Box<Stmt<'a>>, Expr<'a>, Box<Stmt<'a>>, Box<Stmt<'a>>),
     Goto(&'a str),
     If(Expr<'a>, Box<Stmt<'a>>, Option<Box<Stmt<'a>>>, &'a str),
     Label(&'a str),
     Match(Expr<'a>, Vec<Case<'a>>, Option<Box<Stmt<'a>>>, &'a str),
-    Printf(Vec<Expr<'a>>),
-    Return(Option<Expr<'a>>),
+    Printf(Vec<Expr<'a>>, Origin),
+    Return(Option<Expr<'a>>, Origin),
     VarDecl(usize, Option<usize>),
     While(Expr<'a>, Box<Stmt<'a>>),
-    Yield,
+    Yield(Origin),
 }
 
 pub enum Annotation<'a> {
@@ -77,10 +91,17 @@ pub enum Annotation<'a> {
     Prefix(&'a [u8]),
     Suffix(&'a [u8]),
     Unknown(u8),
+    FrameSize(usize),
+    /// Position in the function table, for declarations printed somewhere else.
+    Index(usize),
+    /// Where the function's code starts in the original script. Written as a comment.
+    Address(usize),
 }
 
 pub enum Decl<'a> {
-    Callback(Vec<Annotation<'a>>, u8, Vec<Literal<'a>>, Stmt<'a>),
+    /// Every event that shares the body, each with its own entry in the function table.
+    /// The id is the table index of the first entry.
+    Callback(Vec<Annotation<'a>>, Vec<u8>, Vec<Literal<'a>>, Stmt<'a>, usize),
     Function(Vec<Annotation<'a>>, String, usize, Stmt<'a>),
     GlobalVarDecl(usize, Option<usize>),
 }
@@ -88,7 +109,7 @@ pub enum Decl<'a> {
 impl<'a> Decl<'a> {
     pub fn append_annotation(&mut self, annotation: Annotation<'a>) {
         match self {
-            Decl::Callback(annotations, _, _, _) => annotations.push(annotation),
+            Decl::Callback(annotations, _, _, _, _) => annotations.push(annotation),
             Decl::Function(annotations, _, _, _) => annotations.push(annotation),
             _ => panic!("bug - this decl does not accept annotations"),
         }
@@ -97,7 +118,125 @@ impl<'a> Decl<'a> {
 
 pub struct Script<'a>(pub Vec<Decl<'a>>);
 
-pub fn pretty_print(script: &Script, transform: &IrTransform, includes: &[String]) -> Result<String> {
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum BraceStyle {
+    /// Opening braces end the line of the statement they belong to.
+    SameLine,
+    /// Opening braces go on a line of their own.
+    NextLine,
+}
+
+/// How functions without a name in the compiled script are named.
+/// The compiler leaves any function whose name starts with "anonfn" unnamed again.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AnonymousNames {
+    /// Position in the function table (ex. anonfn3).
+    /// Adding or removing a function renames every unnamed function after it.
+    Index,
+    /// Hash of the function's code (ex. anonfn_1f2e3d4c), which stays the same when functions move.
+    /// Functions with identical code are told apart by their order (ex. anonfn_1f2e3d4c_2).
+    Hash,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum IntStyle {
+    Decimal,
+    /// Non-negative ints are written in hex. Negative ints stay in decimal.
+    Hex,
+    /// Hex for operands of bitwise operators (ex. flag masks) and ints of at least the hex threshold.
+    Auto,
+}
+
+/// Layout of decompiled scripts.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct DecompilerOptions {
+    pub indent_width: usize,
+    pub brace_style: BraceStyle,
+    /// Call arguments are written one per line when they would run past this column.
+    pub max_line_length: Option<usize>,
+    pub int_style: IntStyle,
+    /// Smallest int written in hex by [`IntStyle::Auto`] outside of bitwise expressions.
+    pub hex_threshold: i32,
+    /// Name unnamed variables after their frame index (ex. v3) instead of
+    /// numbering them in the order they appear (ex. var_0).
+    pub frame_id_names: bool,
+    /// Write a comment naming the event of each callback along with its position
+    /// among callbacks for the same event (ex. // Event.Turn #2).
+    pub callback_headers: bool,
+    /// Sort callbacks by event. Functions keep their place.
+    /// Callbacks that move are marked with @Index so compiling again keeps the original table order.
+    pub group_callbacks: bool,
+    pub anonymous_names: AnonymousNames,
+    /// Write neighboring callbacks with identical arguments and code as one callback
+    /// with several events (ex. callback[Event.Turn, Event.TurnAfter]).
+    pub merge_callbacks: bool,
+    /// Write ifs nested without elses as one if (ex. `if (a && b)` for `if (a) { if (b) ... }`).
+    /// Recompiling behaves the same but won't match the original opcodes.
+    pub merge_nested_ifs: bool,
+    /// Write ifs whose branches only assign the same variable as one assignment
+    /// (ex. `x = a ? 1 : 2;`). Recompiling behaves the same but won't match the original opcodes.
+    pub conditional_assignments: bool,
+}
+
+impl Default for DecompilerOptions {
+    fn default() -> Self {
+        DecompilerOptions {
+            indent_width: 4,
+            brace_style: BraceStyle::SameLine,
+            max_line_length: None,
+            int_style: IntStyle::Decimal,
+            hex_threshold: 0x10000,
+            frame_id_names: true,
+            callback_headers: false,
+            group_callbacks: false,
+            anonymous_names: AnonymousNames::Index,
+            merge_callbacks: false,
+            merge_nested_ifs: false,
+            conditional_assignments: false,
+        }
+    }
+}
+
+/// State for printing the declaration currently being printed.
+struct Context<'t> {
+    transform: &'t IrTransform,
+    options: &'t DecompilerOptions,
+    locals: Option<&'t BTreeMap<usize, String>>,
+    local_names: RefCell<HashMap<usize, usize>>,
+    global_names: &'t RefCell<HashMap<usize, usize>>,
+    /// Name of the function being printed, used to look up enums for its parameters.
+    function: Option<&'t str>,
+}
+
+impl<'t> Context<'t> {
+    fn new(
+        transform: &'t IrTransform,
+        options: &'t DecompilerOptions,
+        locals: Option<&'t BTreeMap<usize, String>>,
+        global_names: &'t RefCell<HashMap<usize, usize>>,
+    ) -> Self {
+        Context {
+            transform,
+            options,
+            locals,
+            local_names: RefCell::new(HashMap::new()),
+            global_names,
+            function: None,
+        }
+    }
+}
+
+pub fn pretty_print(
+    script: &Script,
+    transform: &IrTransform,
+    options: &DecompilerOptions,
+    includes: &[String],
+) -> Result<String> {
+    let global_names = RefCell::new(HashMap::new());
     let mut sb = String::new();
     for inc in includes {
         writeln!(sb, "include {};", inc)?;
@@ -105,62 +244,125 @@ pub fn pretty_print(script: &Script, transform: &IrTransform, includes: &[String
     if !includes.is_empty() {
         sb.push('\n');
     }
-    let (vars, functions): (Vec<&Decl>, Vec<&Decl>) = script.0.iter().partition(|d| matches!(d, Decl::GlobalVarDecl(_, _)));
+    let (mut vars, mut functions): (Vec<&Decl>, Vec<&Decl>) = script.0.iter().partition(|d| matches!(d, Decl::GlobalVarDecl(_, _)));
+    // Globals from a layout file are already declared by its include
+    vars.retain(|d| !matches!(d, Decl::GlobalVarDecl(base, _) if transform.is_shared_global(*base)));
+    if options.group_callbacks {
+        group_callbacks(&mut functions);
+    }
     for decl in &vars {
-        pretty_print_decl(&mut sb, decl, transform)?;
+        pretty_print_decl(&mut sb, decl, transform, options, &global_names)?;
         sb.push('\n');
     }
     if !vars.is_empty() {
         sb.push('\n');
     }
+    let mut callback_counts: HashMap<u8, usize> = HashMap::new();
+    // Declarations other than globals are in table order until callbacks are grouped
+    let mut position = 0;
     for decl in functions {
-        pretty_print_decl(&mut sb, decl, transform)?;
+        if let Decl::Callback(_, events, _, _, id) = decl {
+            if options.callback_headers {
+                for event in events {
+                    let count = callback_counts.entry(*event).or_default();
+                    *count += 1;
+                    match transform.transform_event((*event).into()) {
+                        Some(name) => writeln!(sb, "// {} #{}", name, count)?,
+                        None => writeln!(sb, "// Event 0x{:X} #{}", event, count)?,
+                    }
+                }
+            }
+            if *id != position {
+                pretty_print_annotation(&mut sb, &Annotation::Index(*id))?;
+                sb.push('\n');
+            }
+            position += events.len();
+        } else {
+            position += 1;
+        }
+        pretty_print_decl(&mut sb, decl, transform, options, &global_names)?;
         sb.push_str("\n\n");
     }
     Ok(sb)
 }
 
-fn pretty_print_decl(sb: &mut String, decl: &Decl, transform: &IrTransform) -> Result<()> {
+/// Sort callbacks by event in the slots that callbacks already occupy.
+fn group_callbacks(decls: &mut [&Decl]) {
+    let slots: Vec<usize> = decls
+        .iter()
+        .positions(|decl| matches!(decl, Decl::Callback(..)))
+        .collect();
+    let callbacks: Vec<&Decl> = slots
+        .iter()
+        .map(|i| decls[*i])
+        .sorted_by_key(|decl| match decl {
+            Decl::Callback(_, events, _, _, _) => events[0],
+            _ => unreachable!(),
+        })
+        .collect();
+    for (slot, callback) in slots.into_iter().zip(callbacks) {
+        decls[slot] = callback;
+    }
+}
+
+fn pretty_print_decl(
+    sb: &mut String,
+    decl: &Decl,
+    transform: &IrTransform,
+    options: &DecompilerOptions,
+    global_names: &RefCell<HashMap<usize, usize>>,
+) -> Result<()> {
     match decl {
-        Decl::Callback(annotations, event, args, body) => {
+        Decl::Callback(annotations, events, args, body, id) => {
+            let locals = transform.symbols.locals(&SymbolMap::callback_key(*id));
+            let ctx = Context::new(transform, options, locals, global_names);
             for annotation in annotations {
                 pretty_print_annotation(sb, annotation)?;
                 sb.push('\n');
             }
             sb.push_str("callback[");
-            if let Some(name) = transform.transform_event((*event).into()) {
-                write!(sb, "{}", name)?;
-            } else {
-                write!(sb, "0x{:X}", event)?;
+            for (i, event) in events.iter().enumerate() {
+                if i > 0 {
+                    sb.push_str(", ");
+                }
+                if let Some(name) = transform.transform_event((*event).into()) {
+                    write!(sb, "{}", name)?;
+                } else {
+                    write!(sb, "0x{:X}", event)?;
+                }
             }
             sb.push_str("](");
             for (i, arg) in args.iter().enumerate() {
-                pretty_print_literal(sb, arg, transform)?;
+                pretty_print_literal(sb, arg, &ctx)?;
                 if i + 1 < args.len() {
                     sb.push_str(", ");
                 }
             }
             sb.push_str(") ");
-            pretty_print_stmt(sb, body, 0, transform)?;
+            pretty_print_stmt(sb, body, 0, &ctx)?;
         }
         Decl::Function(annotations, name, arity, body) => {
+            let locals = transform.symbols.locals(name);
+            let mut ctx = Context::new(transform, options, locals, global_names);
+            ctx.function = Some(name);
             for annotation in annotations {
                 pretty_print_annotation(sb, annotation)?;
                 sb.push('\n');
             }
             write!(sb, "def {}(", transform.transform_function_name(name).unwrap_or(name))?;
             for i in 0..*arity {
-                pretty_print_var(sb, FrameId(i, false))?;
+                pretty_print_var(sb, FrameId(i, false), &ctx)?;
                 if i + 1 < *arity {
                     sb.push_str(", ");
                 }
             }
             sb.push_str(") ");
-            pretty_print_stmt(sb, body, 0, transform)?;
+            pretty_print_stmt(sb, body, 0, &ctx)?;
         }
         Decl::GlobalVarDecl(base, count) => {
+            let ctx = Context::new(transform, options, None, global_names);
             sb.push_str("let ");
-            pretty_print_var(sb, FrameId(*base, true))?;
+            pretty_print_var(sb, FrameId(*base, true), &ctx)?;
             if let Some(count) = count {
                 write!(sb, "[{}]", count)?;
             }
@@ -171,121 +373,152 @@ fn pretty_print_decl(sb: &mut String, decl: &Decl, transform: &IrTransform) -> R
 }
 
 fn pretty_print_annotation(sb: &mut String, annotation: &Annotation) -> Result<()> {
-    sb.push('@');
     match annotation {
-        Annotation::NoDefaultReturn => sb.push_str("NoDefaultReturn"),
+        Annotation::NoDefaultReturn => sb.push_str("@NoDefaultReturn"),
         Annotation::Prefix(v) => write!(
             sb,
-            "Prefix({})",
+            "@Prefix({})",
             v.iter().map(|v| format!("0x{:X}", v)).join(", ")
         )?,
         Annotation::Suffix(v) => write!(
             sb,
-            "Suffix({})",
+            "@Suffix({})",
             v.iter().map(|v| format!("0x{:X}", v)).join(", ")
         )?,
-        Annotation::Unknown(v) => write!(sb, "Unknown(0x{:X})", v)?,
+        Annotation::Unknown(v) => write!(sb, "@Unknown(0x{:X})", v)?,
+        Annotation::FrameSize(v) => write!(sb, "@FrameSize({})", v)?,
+        Annotation::Index(v) => write!(sb, "@Index({})", v)?,
+        Annotation::Address(v) => write!(sb, "// Code at 0x{:X}", v)?,
     }
     Ok(())
 }
 
-fn pretty_print_stmt(sb: &mut String, stmt: &Stmt, indent: usize, transform: &IrTransform) -> Result<()> {
+fn pretty_print_stmt(sb: &mut String, stmt: &Stmt, indent: usize, ctx: &Context) -> Result<()> {
     match stmt {
-        Stmt::Assign(op, left, right) => {
-            pretty_print_ref(sb, left, indent, transform)?;
+        Stmt::Asm(code, offsets) => {
+            sb.push_str("asm ");
+            open_brace(sb, indent, ctx);
+            sb.push('\n');
+            for (i, opcode) in code.iter().enumerate() {
+                add_indent(sb, indent + 1, ctx);
+                pretty_print_opcode(sb, opcode)?;
+                sb.push(';');
+                if let Some(offset) = offsets.get(i) {
+                    write!(sb, " // 0x{:X}", offset)?;
+                }
+                sb.push('\n');
+            }
+            add_indent(sb, indent, ctx);
+            sb.push('}');
+        }
+        Stmt::Assign(op, left, right, _) => {
+            pretty_print_ref(sb, left, indent, ctx)?;
             write!(sb, " {} ", op)?;
-            pretty_print_expr(sb, right, indent, transform)?;
+            pretty_print_operand(sb, *op, right, indent, ctx)?;
             sb.push(';');
         }
         Stmt::Block(lines) => {
+            open_brace(sb, indent, ctx);
             if lines.is_empty() {
-                sb.push_str("{}");
+                sb.push('}');
             } else {
-                sb.push_str("{\n");
+                sb.push('\n');
                 for line in lines {
-                    add_indent(sb, indent + 1);
-                    pretty_print_stmt(sb, line, indent + 1, transform)?;
+                    add_indent(sb, indent + 1, ctx);
+                    pretty_print_stmt(sb, line, indent + 1, ctx)?;
+                    pretty_print_origin(sb, line)?;
                     sb.push('\n');
                 }
-                add_indent(sb, indent);
+                add_indent(sb, indent, ctx);
                 sb.push('}');
             }
         }
         Stmt::Break => sb.push_str("break;"),
         Stmt::Continue => sb.push_str("continue;"),
-        Stmt::Expr(expr) => {
-            pretty_print_expr(sb, expr, indent, transform)?;
+        Stmt::DoWhile(body, check) => {
+            sb.push_str("do ");
+            pretty_print_stmt(sb, body, indent, ctx)?;
+            continue_after_brace(sb, "while (", indent, ctx);
+            pretty_print_expr(sb, check, indent, ctx)?;
+            sb.push_str(");");
+        }
+        Stmt::Expr(expr, _) => {
+            pretty_print_expr(sb, expr, indent, ctx)?;
             sb.push(';');
         }
+        Stmt::Fallthrough => sb.push_str("fallthrough;"),
         Stmt::For(init, check, step, body) => {
             sb.push_str("for (");
-            pretty_print_stmt(sb, init, indent, transform)?;
+            pretty_print_stmt(sb, init, indent, ctx)?;
             sb.push(' ');
-            pretty_print_expr(sb, check, indent, transform)?;
+            pretty_print_expr(sb, check, indent, ctx)?;
             sb.push_str("; ");
             match step.as_ref() {
-                Stmt::Assign(op, left, right) => {
-                    pretty_print_ref(sb, left, indent, transform)?;
+                Stmt::Assign(op, left, right, _) => {
+                    pretty_print_ref(sb, left, indent, ctx)?;
                     write!(sb, " {} ", op)?;
-                    pretty_print_expr(sb, right, indent, transform)?;
+                    pretty_print_operand(sb, *op, right, indent, ctx)?;
                 }
-                Stmt::Expr(e) => pretty_print_expr(sb, e, indent, transform)?,
+                Stmt::Expr(e, _) => pretty_print_expr(sb, e, indent, ctx)?,
                 _ => bail!("unexpected step part in for loop"),
             }
             sb.push_str(") ");
-            pretty_print_stmt(sb, body, indent, transform)?;
+            pretty_print_stmt(sb, body, indent, ctx)?;
         }
         Stmt::Goto(label) => write!(sb, "goto {};", label)?,
         Stmt::If(check, then_part, else_part, _) => {
             sb.push_str("if (");
-            pretty_print_expr(sb, check, indent, transform)?;
+            pretty_print_expr(sb, check, indent, ctx)?;
             sb.push_str(") ");
-            pretty_print_stmt(sb, then_part, indent, transform)?;
+            pretty_print_stmt(sb, then_part, indent, ctx)?;
             if let Some(stmt) = else_part {
-                sb.push_str(" else ");
-                pretty_print_stmt(sb, stmt, indent, transform)?;
+                continue_after_brace(sb, "else ", indent, ctx);
+                match stmt.as_ref() {
+                    // Print chains as else if instead of nesting each check in another block
+                    Stmt::Block(contents) if matches!(contents.as_slice(), [Stmt::If(..)]) => {
+                        pretty_print_stmt(sb, &contents[0], indent, ctx)?
+                    }
+                    _ => pretty_print_stmt(sb, stmt, indent, ctx)?,
+                }
             }
         }
         Stmt::Label(label) => write!(sb, "label {};", label)?,
         Stmt::Match(switch, cases, default, _) => {
             sb.push_str("match (");
-            pretty_print_expr(sb, switch, indent, transform)?;
-            sb.push_str(") {\n");
+            pretty_print_expr(sb, switch, indent, ctx)?;
+            sb.push_str(") ");
+            open_brace(sb, indent, ctx);
+            sb.push('\n');
             for case in cases {
-                add_indent(sb, indent + 1);
+                add_indent(sb, indent + 1, ctx);
                 for (i, check) in case.conditions.iter().enumerate() {
-                    pretty_print_expr(sb, check, indent, transform)?;
+                    pretty_print_comparand(sb, Operator::Equal, check, switch, indent, ctx)?;
                     if i + 1 < case.conditions.len() {
                         sb.push_str(", ");
                     }
                 }
                 sb.push_str(" -> ");
-                pretty_print_stmt(sb, &case.body, indent + 1, transform)?;
+                pretty_print_stmt(sb, &case.body, indent + 1, ctx)?;
                 sb.push('\n');
             }
             if let Some(default) = default {
-                add_indent(sb, indent + 1);
+                add_indent(sb, indent + 1, ctx);
                 sb.push_str("else -> ");
-                pretty_print_stmt(sb, default, indent + 1, transform)?;
+                pretty_print_stmt(sb, default, indent + 1, ctx)?;
                 sb.push('\n');
             }
-            add_indent(sb, indent);
+            add_indent(sb, indent, ctx);
             sb.push('}');
         }
-        Stmt::Printf(args) => {
-            sb.push_str("printf(");
-            for i in 0..args.len() {
-                pretty_print_expr(sb, &args[i], indent, transform)?;
-                if i + 1 < args.len() {
-                    sb.push_str(", ");
-                }
-            }
-            sb.push_str(");");
+        Stmt::Printf(args, _) => {
+            sb.push_str("printf");
+            pretty_print_args(sb, None, args, indent, ctx)?;
+            sb.push(';');
         }
-        Stmt::Return(value) => {
+        Stmt::Return(value, _) => {
             if let Some(value) = value {
                 sb.push_str("return ");
-                pretty_print_expr(sb, value, indent, transform)?;
+                pretty_print_expr(sb, value, indent, ctx)?;
                 sb.push(';');
             } else {
                 sb.push_str("return;");
@@ -293,7 +526,7 @@ fn pretty_print_stmt(sb: &mut String, stmt: &Stmt, indent: usize, transform: &Ir
         }
         Stmt::VarDecl(frame_id, count) => {
             sb.push_str("let ");
-            pretty_print_var(sb, FrameId(*frame_id, false))?;
+            pretty_print_var(sb, FrameId(*frame_id, false), ctx)?;
             if let Some(count) = count {
                 write!(sb, "[{}]", count)?;
             }
@@ -301,68 +534,286 @@ fn pretty_print_stmt(sb: &mut String, stmt: &Stmt, indent: usize, transform: &Ir
         }
         Stmt::While(check, body) => {
             sb.push_str("while (");
-            pretty_print_expr(sb, check, indent, transform)?;
+            pretty_print_expr(sb, check, indent, ctx)?;
             sb.push_str(") ");
-            pretty_print_stmt(sb, body, indent, transform)?;
+            pretty_print_stmt(sb, body, indent, ctx)?;
+        }
+        Stmt::Yield(_) => sb.push_str("yield;"),
+    }
+    Ok(())
+}
+
+/// Write a comment with the addresses a statement came from and the frame indices it references.
+/// Only statements decompiled in debug mode have an origin.
+fn pretty_print_origin(sb: &mut String, stmt: &Stmt) -> Result<()> {
+    let mut frames = Vec::new();
+    let origin = match stmt {
+        Stmt::Assign(_, left, right, origin) => {
+            collect_ref_frames(left, &mut frames);
+            collect_expr_frames(right, &mut frames);
+            origin
+        }
+        Stmt::Expr(expr, origin) => {
+            collect_expr_frames(expr, &mut frames);
+            origin
+        }
+        Stmt::Printf(args, origin) => {
+            args.iter().for_each(|arg| collect_expr_frames(arg, &mut frames));
+            origin
+        }
+        Stmt::Return(value, origin) => {
+            if let Some(value) = value {
+                collect_expr_frames(value, &mut frames);
+            }
+            origin
         }
-        Stmt::Yield => sb.push_str("yield;"),
+        Stmt::Yield(origin) => origin,
+        _ => return Ok(()),
+    };
+    let (start, end) = match origin {
+        Some(origin) => *origin,
+        None => return Ok(()),
+    };
+    if start == end {
+        write!(sb, " // 0x{:X}", start)?;
+    } else {
+        write!(sb, " // 0x{:X}-0x{:X}", start, end)?;
+    }
+    let frames = frames
+        .into_iter()
+        .unique()
+        .map(|FrameId(index, global)| {
+            if global {
+                format!("global {}", index)
+            } else {
+                format!("local {}", index)
+            }
+        })
+        .join(", ");
+    if !frames.is_empty() {
+        write!(sb, " ({})", frames)?;
     }
     Ok(())
 }
 
-fn add_indent(sb: &mut String, indent: usize) {
-    for _ in 0..indent {
-        sb.push_str("    ");
+fn collect_ref_frames(reference: &Reference, frames: &mut Vec<FrameId>) {
+    frames.push(reference.frame_id());
+    match reference {
+        Reference::Var(_) => {}
+        Reference::Index(_, index) | Reference::Dereference(_, index) => {
+            collect_expr_frames(index, frames)
+        }
     }
 }
 
-fn pretty_print_expr(sb: &mut String, expr: &Expr, indent: usize, transform: &IrTransform) -> Result<()> {
+fn collect_expr_frames(expr: &Expr, frames: &mut Vec<FrameId>) {
     match expr {
-        Expr::Literal(l) => pretty_print_literal(sb, l, transform)?,
+        Expr::Literal(_) => {}
+        Expr::Unary(_, operand) | Expr::Grouped(operand) => collect_expr_frames(operand, frames),
+        Expr::Binary(_, left, right) => {
+            collect_expr_frames(left, frames);
+            collect_expr_frames(right, frames);
+        }
+        Expr::Conditional(condition, then, otherwise) => {
+            collect_expr_frames(condition, frames);
+            collect_expr_frames(then, frames);
+            collect_expr_frames(otherwise, frames);
+        }
+        Expr::Call(_, args) | Expr::StaticArrayInit(args) => {
+            args.iter().for_each(|arg| collect_expr_frames(arg, frames))
+        }
+        Expr::Ref(reference) | Expr::Addr(reference) | Expr::Inc(_, _, reference) => {
+            collect_ref_frames(reference, frames)
+        }
+    }
+}
+
+fn pretty_print_opcode(sb: &mut String, opcode: &Opcode) -> Result<()> {
+    match opcode {
+        Opcode::StrLoad(v) => write!(sb, "StrLoad(\"{}\")", escape_str(v))?,
+        Opcode::FloatLoad(v) => {
+            sb.push_str("FloatLoad(");
+            pretty_print_float(sb, *v)?;
+            sb.push(')');
+        }
+        Opcode::CallByName(name, arity) => {
+            write!(sb, "CallByName(\"{}\", {})", escape_str(name), arity)?
+        }
+        Opcode::Jump(l) => write!(sb, "Jump(\"{}\")", l)?,
+        Opcode::JumpNotZero(l) => write!(sb, "JumpNotZero(\"{}\")", l)?,
+        Opcode::Or(l) => write!(sb, "Or(\"{}\")", l)?,
+        Opcode::JumpZero(l) => write!(sb, "JumpZero(\"{}\")", l)?,
+        Opcode::And(l) => write!(sb, "And(\"{}\")", l)?,
+        Opcode::Label(l) => write!(sb, "Label(\"{}\")", l)?,
+        Opcode::Unknown(op, bytes) => write!(
+            sb,
+            "Unknown({})",
+            std::iter::once(op)
+                .chain(bytes)
+                .map(|v| format!("0x{:X}", v))
+                .join(", ")
+        )?,
+        // Everything else only has numbers for arguments, which debug formatting already handles
+        _ => write!(sb, "{:?}", opcode)?,
+    }
+    Ok(())
+}
+
+fn add_indent(sb: &mut String, indent: usize, ctx: &Context) {
+    for _ in 0..indent * ctx.options.indent_width {
+        sb.push(' ');
+    }
+}
+
+/// Write the opening brace of a block whose header (ex. "if (x) ") has already been written.
+fn open_brace(sb: &mut String, indent: usize, ctx: &Context) {
+    if let BraceStyle::NextLine = ctx.options.brace_style {
+        sb.truncate(sb.trim_end_matches(' ').len());
+        // Blocks that start their own statement are already on a new line
+        if !sb.is_empty() && !sb.ends_with('\n') {
+            sb.push('\n');
+        }
+        add_indent(sb, indent, ctx);
+    }
+    sb.push('{');
+}
+
+/// Write the part of a statement that follows a closing brace (ex. "else").
+fn continue_after_brace(sb: &mut String, text: &str, indent: usize, ctx: &Context) {
+    match ctx.options.brace_style {
+        BraceStyle::SameLine => sb.push(' '),
+        BraceStyle::NextLine => {
+            sb.push('\n');
+            add_indent(sb, indent, ctx);
+        }
+    }
+    sb.push_str(text);
+}
+
+/// Print an argument to a function, or to printf if there is no function.
+fn pretty_print_arg(
+    sb: &mut String,
+    function: Option<&str>,
+    args: &[Expr],
+    i: usize,
+    indent: usize,
+    ctx: &Context,
+) -> Result<()> {
+    match &args[i] {
+        // Braces in a format printed on its own would be read back as interpolation placeholders
+        Expr::Literal(Literal::Str(v))
+            if function.is_none()
+                && args.len() == 1
+                && v.contains(['{', '}'])
+                && ctx.transform.transform_string(v).is_none() =>
+        {
+            write!(sb, "\"{}\"", escape_str(v).replace('{', "{{").replace('}', "}}"))?
+        }
+        Expr::Literal(Literal::Int(v)) => {
+            let variant = function.and_then(|f| ctx.transform.transform_argument(f, i, *v));
+            match variant {
+                Some(variant) => sb.push_str(&variant),
+                None => pretty_print_literal(sb, &Literal::Int(*v), ctx)?,
+            }
+        }
+        arg => pretty_print_expr(sb, arg, indent, ctx)?,
+    }
+    Ok(())
+}
+
+/// Write a parenthesized argument list, putting each argument on its own line
+/// if the list would run past the max line length.
+fn pretty_print_args(
+    sb: &mut String,
+    function: Option<&str>,
+    args: &[Expr],
+    indent: usize,
+    ctx: &Context,
+) -> Result<()> {
+    let mut flat = String::new();
+    for i in 0..args.len() {
+        pretty_print_arg(&mut flat, function, args, i, indent, ctx)?;
+        if i + 1 < args.len() {
+            flat.push_str(", ");
+        }
+    }
+    let column = sb
+        .rsplit('\n')
+        .next()
+        .map(|line| line.chars().count())
+        .unwrap_or_default();
+    match ctx.options.max_line_length {
+        Some(max)
+            if !args.is_empty()
+                && !flat.contains('\n')
+                && column + flat.chars().count() + 2 > max =>
+        {
+            sb.push_str("(\n");
+            for i in 0..args.len() {
+                add_indent(sb, indent + 1, ctx);
+                pretty_print_arg(sb, function, args, i, indent + 1, ctx)?;
+                sb.push_str(",\n");
+            }
+            add_indent(sb, indent, ctx);
+            sb.push(')');
+        }
+        _ => write!(sb, "({})", flat)?,
+    }
+    Ok(())
+}
+
+fn pretty_print_expr(sb: &mut String, expr: &Expr, indent: usize, ctx: &Context) -> Result<()> {
+    match expr {
+        Expr::Literal(l) => pretty_print_literal(sb, l, ctx)?,
         Expr::Unary(op, operand) => {
             write!(sb, "{}", op)?;
-            pretty_print_expr(sb, operand, indent, transform)?;
+            pretty_print_operand(sb, *op, operand, indent, ctx)?;
+        }
+        Expr::Binary(op @ (Operator::Equal | Operator::NotEqual), left, right) => {
+            pretty_print_comparand(sb, *op, left, right, indent, ctx)?;
+            write!(sb, " {} ", op)?;
+            pretty_print_comparand(sb, *op, right, left, indent, ctx)?;
         }
         Expr::Binary(op, left, right) => {
-            pretty_print_expr(sb, left, indent, transform)?;
+            pretty_print_operand(sb, *op, left, indent, ctx)?;
             write!(sb, " {} ", op)?;
-            pretty_print_expr(sb, right, indent, transform)?;
+            pretty_print_operand(sb, *op, right, indent, ctx)?;
         }
         Expr::Call(name, args) => {
-            sb.push_str(transform.transform_function_name(name).unwrap_or(name));
-            sb.push('(');
-            for i in 0..args.len() {
-                pretty_print_expr(sb, &args[i], indent, transform)?;
-                if i + 1 < args.len() {
-                    sb.push_str(", ");
-                }
-            }
-            sb.push(')');
+            sb.push_str(ctx.transform.transform_function_name(name).unwrap_or(name));
+            pretty_print_args(sb, Some(name), args, indent, ctx)?;
         }
-        Expr::Ref(r) => pretty_print_ref(sb, r, indent, transform)?,
+        Expr::Ref(r) => pretty_print_ref(sb, r, indent, ctx)?,
         Expr::Addr(r) => {
             sb.push('&');
-            pretty_print_ref(sb, r, indent, transform)?;
+            pretty_print_ref(sb, r, indent, ctx)?;
         }
         Expr::Inc(op, notation, operand) => {
             if let Notation::Prefix = notation {
                 write!(sb, "{}", op)?;
             }
-            pretty_print_ref(sb, operand, indent, transform)?;
+            pretty_print_ref(sb, operand, indent, ctx)?;
             if let Notation::Postfix = notation {
                 write!(sb, "{}", op)?;
             }
         }
         Expr::Grouped(e) => {
             sb.push('(');
-            pretty_print_expr(sb, e, indent, transform)?;
+            pretty_print_expr(sb, e, indent, ctx)?;
             sb.push(')');
         }
+        Expr::Conditional(condition, then, otherwise) => {
+            pretty_print_expr(sb, condition, indent, ctx)?;
+            sb.push_str(" ? ");
+            pretty_print_expr(sb, then, indent, ctx)?;
+            sb.push_str(" : ");
+            pretty_print_expr(sb, otherwise, indent, ctx)?;
+        }
         Expr::StaticArrayInit(entries) => {
             sb.push('[');
             if entries.len() < 5 {
                 for (i, entry) in entries.iter().enumerate() {
-                    pretty_print_expr(sb, entry, indent, transform)?;
+                    pretty_print_expr(sb, entry, indent, ctx)?;
                     if i + 1 < entries.len() {
                         sb.push_str(", ");
                     }
@@ -370,14 +821,14 @@ fn pretty_print_expr(sb: &mut String, expr: &Expr, indent: usize, transform: &Ir
             } else {
                 for i in (0..entries.len()).step_by(4) {
                     sb.push('\n');
-                    add_indent(sb, indent + 1);
+                    add_indent(sb, indent + 1, ctx);
                     for j in 0..(4.min(entries.len() - i)) {
-                        pretty_print_expr(sb, &entries[i + j], indent + 1, transform)?;
+                        pretty_print_expr(sb, &entries[i + j], indent + 1, ctx)?;
                         sb.push_str(", ");
                     }
                 }
                 sb.push('\n');
-                add_indent(sb, indent);
+                add_indent(sb, indent, ctx);
             }
             sb.push(']');
         }
@@ -385,40 +836,126 @@ fn pretty_print_expr(sb: &mut String, expr: &Expr, indent: usize, transform: &Ir
     Ok(())
 }
 
-fn pretty_print_literal(sb: &mut String, literal: &Literal, transform: &IrTransform) -> Result<()> {
+/// Write one side of an equality check, as an enum access if the other side is tagged with an enum.
+fn pretty_print_comparand(
+    sb: &mut String,
+    op: Operator,
+    operand: &Expr,
+    other: &Expr,
+    indent: usize,
+    ctx: &Context,
+) -> Result<()> {
+    if let Expr::Literal(Literal::Int(v)) = operand {
+        let variant = value_enum(other, ctx).and_then(|e| ctx.transform.transform_enum(e, *v));
+        if let Some(variant) = variant {
+            sb.push_str(&variant);
+            return Ok(());
+        }
+    }
+    pretty_print_operand(sb, op, operand, indent, ctx)
+}
+
+/// Find the enum an expression takes its values from, if the transform tags it with one.
+fn value_enum<'a>(expr: &Expr, ctx: &Context<'a>) -> Option<&'a str> {
+    match expr {
+        Expr::Call(name, _) => ctx.transform.result_enum(name),
+        Expr::Ref(Reference::Var(FrameId(frame_id, false))) => ctx
+            .function
+            .and_then(|function| ctx.transform.parameter_enum(function, *frame_id)),
+        Expr::Grouped(e) => value_enum(e, ctx),
+        _ => None,
+    }
+}
+
+/// Print an operand of an operator, writing ints in hex if the operator works on bits.
+fn pretty_print_operand(
+    sb: &mut String,
+    op: Operator,
+    operand: &Expr,
+    indent: usize,
+    ctx: &Context,
+) -> Result<()> {
+    let is_bitwise = matches!(
+        op,
+        Operator::BitwiseAnd
+            | Operator::Xor
+            | Operator::BitwiseOr
+            | Operator::BitwiseNot
+            | Operator::AssignBitwiseAnd
+            | Operator::AssignXor
+            | Operator::AssignBitwiseOr
+    );
+    match operand {
+        Expr::Literal(Literal::Int(v))
+            if *v >= 0 && is_bitwise && ctx.options.int_style == IntStyle::Auto =>
+        {
+            write!(sb, "0x{:X}", v)?
+        }
+        _ => pretty_print_expr(sb, operand, indent, ctx)?,
+    }
+    Ok(())
+}
+
+fn pretty_print_literal(sb: &mut String, literal: &Literal, ctx: &Context) -> Result<()> {
     match literal {
+        Literal::Int(v) if *v >= 0 && use_hex(*v, ctx) => write!(sb, "0x{:X}", v)?,
         Literal::Int(v) => write!(sb, "{}", v)?,
-        Literal::Float(v) => if v.fract() == 0.0 {
-            write!(sb, "{:.1}", v)?
+        Literal::Float(v) => pretty_print_float(sb, *v)?,
+        Literal::Str(v) => if let Some(transformed_value) = ctx.transform.transform_string(v) {
+            sb.push_str(transformed_value)
         } else {
-            write!(sb, "{:.}", v)?
-        },
-        Literal::Str(v) => if let Some(transformed_value) = transform.transform_string(v) {
-            sb.push_str(transformed_value) // TODO: Unescape?
-        } else {
-            write!(sb, "\"{}\"", v)? // TODO: Unescape?
+            write!(sb, "\"{}\"", escape_str(v))?
         }
     }
     Ok(())
 }
 
-fn pretty_print_ref(sb: &mut String, reference: &Reference, indent: usize, transform: &IrTransform) -> Result<()> {
+/// Write a float as a decimal, or as its raw bits if the decimal wouldn't compile back to the same value (ex. NaN).
+fn pretty_print_float(sb: &mut String, value: f32) -> Result<()> {
+    // "-" only applies to ints, so negative floats are written with the float negation operator
+    if value.is_sign_negative() {
+        sb.push_str("-f ");
+        return pretty_print_float(sb, -value);
+    }
+    let decimal = if value.fract() == 0.0 {
+        format!("{:.1}", value)
+    } else {
+        format!("{}", value)
+    };
+    let exact = decimal.parse::<f32>().map(f32::to_bits) == Ok(value.to_bits());
+    if value.is_finite() && exact {
+        sb.push_str(&decimal);
+    } else {
+        write!(sb, "float_bits(0x{:08X})", value.to_bits())?;
+    }
+    Ok(())
+}
+
+fn use_hex(value: i32, ctx: &Context) -> bool {
+    match ctx.options.int_style {
+        IntStyle::Decimal => false,
+        IntStyle::Hex => true,
+        IntStyle::Auto => value >= ctx.options.hex_threshold,
+    }
+}
+
+fn pretty_print_ref(sb: &mut String, reference: &Reference, indent: usize, ctx: &Context) -> Result<()> {
     match reference {
-        Reference::Var(frame_id) => pretty_print_var(sb, *frame_id)?,
+        Reference::Var(frame_id) => pretty_print_var(sb, *frame_id, ctx)?,
         Reference::Index(frame_id, index) => {
-            pretty_print_var(sb, *frame_id)?;
+            pretty_print_var(sb, *frame_id, ctx)?;
             sb.push('[');
-            pretty_print_expr(sb, index, indent, transform)?;
+            pretty_print_expr(sb, index, indent, ctx)?;
             sb.push(']');
         }
         Reference::Dereference(frame_id, index) => if is_useless_index(index) {
             sb.push('*');
-            pretty_print_var(sb, *frame_id)?;
+            pretty_print_var(sb, *frame_id, ctx)?;
         } else {
             sb.push('*');
-            pretty_print_var(sb, *frame_id)?;
+            pretty_print_var(sb, *frame_id, ctx)?;
             sb.push('[');
-            pretty_print_expr(sb, index, indent, transform)?;
+            pretty_print_expr(sb, index, indent, ctx)?;
             sb.push(']');
         }
     }
@@ -432,9 +969,24 @@ fn is_useless_index(index: &Expr) -> bool {
     false
 }
 
-// TODO: Set up a better system for picking var names?
-fn pretty_print_var(sb: &mut String, frame_id: FrameId) -> Result<()> {
-    if frame_id.1 {
+fn pretty_print_var(sb: &mut String, frame_id: FrameId, ctx: &Context) -> Result<()> {
+    let name = if frame_id.1 {
+        ctx.transform.symbols.global(frame_id.0)
+    } else {
+        ctx.locals.and_then(|locals| locals.get(&frame_id.0)).map(|v| v.as_str())
+    };
+    if let Some(name) = name {
+        sb.push_str(name);
+    } else if !ctx.options.frame_id_names {
+        let (prefix, names) = if frame_id.1 {
+            ("g_", ctx.global_names)
+        } else {
+            ("", &ctx.local_names)
+        };
+        let mut names = names.borrow_mut();
+        let next = names.len();
+        write!(sb, "{}var_{}", prefix, names.entry(frame_id.0).or_insert(next))?;
+    } else if frame_id.1 {
         write!(sb, "g_v{}", frame_id.0)?;
     } else {
         write!(sb, "v{}", frame_id.0)?;
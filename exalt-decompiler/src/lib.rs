@@ -5,18 +5,21 @@ use std::slice::Iter;
 
 use data_structures::{BlockStack, DeclarationRequest, ExprStack, VarTracker};
 use exalt_ast::{Notation, Operator, Precedence};
-use exalt_lir::{CallbackArg, Function, Game, Opcode, RawScript};
+use exalt_lir::{CallbackArg, Function, Game, Opcode, RawScript, StackError};
 
 mod data_structures;
 pub mod ir;
 mod refining;
+mod report;
 mod transform;
 
 use anyhow::{anyhow, bail, Result};
-use ir::{Annotation, Case, Decl, Expr, FrameId, Literal, Reference, Script, Stmt};
+use ir::{Annotation, Case, Decl, Expr, FrameId, Literal, Origin, Reference, Script, Stmt};
+pub use ir::{AnonymousNames, BraceStyle, DecompilerOptions, IntStyle};
 
 use itertools::Itertools;
-pub use transform::IrTransform;
+pub use report::text_data_report;
+pub use transform::{IrTransform, ValueTags};
 
 #[derive(Clone, Copy)]
 enum AssignState {
@@ -24,20 +27,27 @@ enum AssignState {
     Shorthand,
 }
 
-pub struct DecompilerState<'a> {
+pub struct DecompilerState<'a, 'f> {
     game: Game,
     opcodes: Peekable<Iter<'a, Opcode>>,
-    functions: &'a HashMap<usize, (String, usize)>,
+    functions: &'f HashMap<usize, (String, usize)>,
     expr_stack: ExprStack<'a>,
     block_stack: BlockStack<'a>,
     assign_state: AssignState,
+    /// Address of each opcode, or nothing if statements shouldn't be traced back to them.
+    offsets: &'a [usize],
+    /// Index of the next opcode.
+    position: usize,
+    /// Index of the first opcode of the statement being built.
+    statement_start: usize,
 }
 
-impl<'a> DecompilerState<'a> {
+impl<'a, 'f> DecompilerState<'a, 'f> {
     pub fn new(
         game: Game,
         opcodes: Peekable<Iter<'a, Opcode>>,
-        functions: &'a HashMap<usize, (String, usize)>,
+        functions: &'f HashMap<usize, (String, usize)>,
+        offsets: &'a [usize],
     ) -> Self {
         Self {
             game,
@@ -46,58 +56,193 @@ impl<'a> DecompilerState<'a> {
             expr_stack: ExprStack::default(),
             block_stack: BlockStack::default(),
             assign_state: AssignState::Normal,
+            offsets,
+            position: 0,
+            statement_start: 0,
         }
     }
+
+    fn next_opcode(&mut self) -> Option<&'a Opcode> {
+        let opcode = self.opcodes.next();
+        if opcode.is_some() {
+            self.position += 1;
+        }
+        opcode
+    }
+
+    /// Addresses of the opcodes from the start of the current statement to the last one read.
+    fn origin(&self) -> Origin {
+        let start = *self.offsets.get(self.statement_start)?;
+        let end = *self.offsets.get(self.position.checked_sub(1)?)?;
+        Some((start, end))
+    }
 }
 
 pub fn decompile(
     script: &RawScript,
     transform: Option<IrTransform>,
+    options: &DecompilerOptions,
     includes: Vec<String>,
     game: Game,
     debug: bool,
+    lenient: bool,
 ) -> Result<String> {
     let ir_transform = transform.unwrap_or_default();
-    let mut functions = HashMap::new();
+    let script = decompile_to_ir(script, game, options, debug, lenient)?;
+    ir::pretty_print(&script, &ir_transform, options, &includes)
+}
+
+/// Decompile a script without printing it.
+/// The result can be modified and then printed with [`ir::pretty_print`].
+/// In lenient mode, functions that can't be decompiled are emitted as asm blocks instead of failing.
+/// Functions containing opcodes the disassembler didn't recognize are always emitted as asm blocks.
+/// Only the naming and merging options are used here. The rest apply when printing.
+pub fn decompile_to_ir<'a>(
+    script: &'a RawScript,
+    game: Game,
+    options: &DecompilerOptions,
+    debug: bool,
+    lenient: bool,
+) -> Result<Script<'a>> {
+    let functions = function_names(script, options.anonymous_names);
     let mut global_var_tracker = VarTracker::new(script.global_frame_size);
-    for (i, func) in script.functions.iter().enumerate() {
-        if func.event == 0 {
-            functions.insert(
+    let mut decls = Vec::new();
+    let mut i = 0;
+    while i < script.functions.len() {
+        let func = &script.functions[i];
+        let mut events = vec![func.event];
+        if options.merge_callbacks {
+            events.extend(
+                script.functions[i + 1..]
+                    .iter()
+                    .take_while(|next| same_callback(func, next))
+                    .map(|next| next.event),
+            );
+        }
+        let decl = if func.code.iter().any(|op| matches!(op, Opcode::Unknown(_, _))) {
+            asm_fallback(func, i, &functions, debug)
+        } else {
+            let decl = decompile_function(
+                game,
+                &mut global_var_tracker,
+                &functions,
+                func,
                 i,
-                (
-                    func.name.clone().unwrap_or_else(|| format!("anonfn{}", i)),
-                    func.arity as usize,
-                ),
+                debug,
             );
+            match decl {
+                Ok(decl) => decl,
+                Err(_) if lenient => asm_fallback(func, i, &functions, debug),
+                Err(err) => return Err(err),
+            }
+        };
+        let mut decl = with_address(decl, func, debug);
+        if let Decl::Callback(_, decl_events, _, _, _) = &mut decl {
+            *decl_events = events.clone();
         }
-    }
-    let mut decls = Vec::new();
-    for (i, func) in script.functions.iter().enumerate() {
-        decls.push(decompile_function(
-            game,
-            &mut global_var_tracker,
-            &functions,
-            func,
-            i,
-            debug,
-        )?);
+        if let Decl::Callback(_, _, _, body, _) | Decl::Function(_, _, _, body) = &mut decl {
+            if options.conditional_assignments {
+                refining::collapse_conditional_assignments(body);
+            }
+            if options.merge_nested_ifs {
+                refining::merge_nested_ifs(body);
+            }
+        }
+        decls.push(decl);
+        i += events.len();
     }
     let mut script = Script(decls);
     global_var_tracker.find_empty_array_inits()?;
     let extra_declarations = global_var_tracker.build_declaration_requests(true);
     refining::inject_global_var_declarations(&mut script, &extra_declarations);
-    ir::pretty_print(&script, &ir_transform, &includes)
+    Ok(script)
+}
+
+/// Whether two callbacks differ in nothing but their event, so they can be written as one.
+fn same_callback(a: &Function, b: &Function) -> bool {
+    a.event != 0
+        && b.event != 0
+        && a.frame_size == b.frame_size
+        && a.arity == b.arity
+        && a.unknown == b.unknown
+        && a.prefix == b.prefix
+        && a.suffix == b.suffix
+        && a.name == b.name
+        && a.args == b.args
+        && a.code == b.code
+}
+
+/// FNV-1a, since names built from it have to come out the same in every build.
+fn fnv1a(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf29ce484222325, |hash, byte| {
+        (hash ^ *byte as u64).wrapping_mul(0x100000001b3)
+    })
+}
+
+/// Name and arity of every function (not callback) by position in the function table.
+fn function_names(script: &RawScript, scheme: AnonymousNames) -> HashMap<usize, (String, usize)> {
+    let mut functions = HashMap::new();
+    let mut seen: HashMap<String, usize> = HashMap::new();
+    for (i, func) in script.functions.iter().enumerate() {
+        if func.event != 0 {
+            continue;
+        }
+        let name = match (&func.name, scheme) {
+            (Some(name), _) => name.clone(),
+            (None, AnonymousNames::Index) => format!("anonfn{}", i),
+            (None, AnonymousNames::Hash) => {
+                // Calls to other unnamed functions are left out since their positions can change
+                let mut contents = format!("{}", func.arity);
+                for opcode in &func.code {
+                    match opcode {
+                        Opcode::CallById(id) => match script.functions.get(*id) {
+                            Some(Function { name: Some(name), .. }) => {
+                                contents.push_str(&format!("CallByName({:?})", name))
+                            }
+                            _ => contents.push_str("CallById"),
+                        },
+                        _ => contents.push_str(&format!("{:?}", opcode)),
+                    }
+                }
+                let hash = fnv1a(contents.as_bytes());
+                let name = format!("anonfn_{:08x}", (hash ^ (hash >> 32)) as u32);
+                let count = seen.entry(name.clone()).or_default();
+                *count += 1;
+                if *count > 1 {
+                    format!("{}_{}", name, count)
+                } else {
+                    name
+                }
+            }
+        };
+        functions.insert(i, (name, func.arity as usize));
+    }
+    functions
 }
 
 fn decompile_function<'a>(
     game: Game,
     global_var_tracker: &mut VarTracker,
-    functions: &'a HashMap<usize, (String, usize)>,
+    functions: &HashMap<usize, (String, usize)>,
     function: &'a Function,
     id: usize,
     debug: bool,
 ) -> Result<Decl<'a>> {
-    let mut state = DecompilerState::new(game, function.code.iter().peekable(), functions);
+    // Catch bad stack usage up front instead of partway through building expressions
+    let analysis = exalt_lir::simulate_stack(&function.code, |id| {
+        functions.get(&id).map(|(_, arity)| *arity)
+    });
+    if let Some(error) = analysis
+        .errors
+        .iter()
+        .find(|e| matches!(e, StackError::Underflow { .. }))
+    {
+        bail!("function {}: {}", id, error);
+    }
+    // Statements are only traced back to their opcodes in debug mode
+    let offsets: &[usize] = if debug { &function.offsets } else { &[] };
+    let mut state =
+        DecompilerState::new(game, function.code.iter().peekable(), functions, offsets);
     state.block_stack.push();
     while state.opcodes.peek().is_some() {
         decompile_opcode(&mut state)?;
@@ -105,11 +250,14 @@ fn decompile_function<'a>(
     let mut body = state.block_stack.pop()?;
     let has_default_return = refining::strip_default_return(&mut body);
     let mut block = Stmt::Block(body);
+    // Else branches first. Their end label may double as the continue label of a following loop.
+    refining::collapse_else_branches(&mut block);
     // Collapse loops
     refining::collapse_for_loops(&mut block);
     refining::collapse_while_loops(&mut block);
+    refining::collapse_do_while_loops(&mut block);
     refining::add_match_breaks(&mut block);
-    // Loops use jumps/labels which are no longer required, so get rid of them
+    // Loops and else branches use jumps/labels which are no longer required, so get rid of them
     refining::prune_unused_labels(&mut block);
     // Analyze variable usage to identify arrays. Specifically looking for:
     // - Static array initializations. Shows up as a chain of assignments where only the first var is used/indexed.
@@ -136,20 +284,63 @@ fn decompile_function<'a>(
         // Under normal conditions, var declarations are noisy and don't add anything to the source.
         // Variables may receive different frame indices during compiling, but the resulting
         // script is functionally identical.
+        // Variables that are never plainly assigned (ex. only targeted by `+=`) still need one
+        // since the compiler only declares variables implicitly on their first assignment.
         var_info
             .build_declaration_requests(false)
             .into_iter()
-            .filter(|r| !matches!(r, DeclarationRequest::Var(_)))
+            .filter(|r| match r {
+                DeclarationRequest::Var(frame_id) => {
+                    !var_info.is_initialized(*frame_id).unwrap_or(true)
+                }
+                _ => true,
+            })
             .collect_vec()
     };
     refining::inject_var_declarations(&mut block, &local_var_declarations);
     // TODO: Inject global vars at the top level
 
+    let mut decl = build_decl(function, id, functions, block);
+    if !has_default_return {
+        decl.append_annotation(Annotation::NoDefaultReturn);
+    }
+    Ok(decl)
+}
+
+/// Emit a function's code as-is in an asm block.
+/// Used in lenient mode when structural analysis fails.
+/// In debug mode, every opcode is marked with its address in the original script.
+fn asm_fallback<'a>(
+    function: &'a Function,
+    id: usize,
+    functions: &HashMap<usize, (String, usize)>,
+    debug: bool,
+) -> Decl<'a> {
+    let offsets: &[usize] = if debug { &function.offsets } else { &[] };
+    let block = Stmt::Block(vec![Stmt::Asm(&function.code, offsets)]);
+    let mut decl = build_decl(function, id, functions, block);
+    // The code already contains its own return and we can't tell which locals it touches
+    decl.append_annotation(Annotation::NoDefaultReturn);
+    decl.append_annotation(Annotation::FrameSize(function.frame_size));
+    decl
+}
+
+/// In debug mode, mark a function with where its code starts in the original script.
+fn with_address<'a>(mut decl: Decl<'a>, function: &Function, debug: bool) -> Decl<'a> {
+    if let (true, Some(address)) = (debug, function.offsets.first()) {
+        decl.append_annotation(Annotation::Address(*address));
+    }
+    decl
+}
+
+fn build_decl<'a>(
+    function: &'a Function,
+    id: usize,
+    functions: &HashMap<usize, (String, usize)>,
+    block: Stmt<'a>,
+) -> Decl<'a> {
     let mut decl = if function.event == 0 {
-        let name = function
-            .name
-            .clone()
-            .unwrap_or_else(|| format!("anonfn{}", id));
+        let name = functions[&id].0.clone();
         Decl::Function(Vec::new(), name, function.arity.into(), block)
     } else {
         let mut args = Vec::new();
@@ -160,7 +351,7 @@ fn decompile_function<'a>(
                 CallbackArg::Float(v) => Literal::Float(*v),
             });
         }
-        Decl::Callback(Vec::new(), function.event, args, block)
+        Decl::Callback(Vec::new(), vec![function.event], args, block, id)
     };
     if !function.prefix.is_empty() {
         decl.append_annotation(Annotation::Prefix(&function.prefix));
@@ -171,10 +362,7 @@ fn decompile_function<'a>(
     if function.unknown != 0 {
         decl.append_annotation(Annotation::Unknown(function.unknown));
     }
-    if !has_default_return {
-        decl.append_annotation(Annotation::NoDefaultReturn);
-    }
-    Ok(decl)
+    decl
 }
 
 fn decompile_until(state: &mut DecompilerState, label: &str) -> Result<()> {
@@ -190,11 +378,15 @@ fn decompile_until(state: &mut DecompilerState, label: &str) -> Result<()> {
 }
 
 fn decompile_opcode(state: &mut DecompilerState) -> Result<()> {
-    let opcode = if let Some(opcode) = state.opcodes.next() {
+    let opcode = if let Some(opcode) = state.next_opcode() {
         opcode
     } else {
         return Ok(());
     };
+    // Every statement starts with an empty stack
+    if state.expr_stack.stack.is_empty() {
+        state.statement_start = state.position - 1;
+    }
     match opcode {
         Opcode::Done => {}
         Opcode::VarLoad(id) => {
@@ -277,7 +469,8 @@ fn decompile_opcode(state: &mut DecompilerState) -> Result<()> {
         Opcode::Dereference => state.assign_state = AssignState::Shorthand,
         Opcode::Consume => {
             let expr = state.expr_stack.pop()?;
-            state.block_stack.line(Stmt::Expr(expr))?;
+            let origin = state.origin();
+            state.block_stack.line(Stmt::Expr(expr, origin))?;
         }
         Opcode::CompleteAssign | Opcode::Assign => decompile_assignment(state)?,
         Opcode::Fix => {
@@ -312,7 +505,17 @@ fn decompile_opcode(state: &mut DecompilerState) -> Result<()> {
         Opcode::RightShift => decompile_binary_expr(state, Operator::RightShift)?,
         Opcode::Equal => decompile_binary_expr(state, Operator::Equal)?,
         Opcode::FloatEqual => decompile_binary_expr(state, Operator::FloatEqual)?,
-        Opcode::Exlcall => todo!(),
+        Opcode::Exlcall => {
+            let count = match state.expr_stack.pop()? {
+                Expr::Literal(Literal::Int(count)) if count >= 0 => count as usize,
+                _ => bail!("expected a constant argument count for exlcall"),
+            };
+            // The call id comes before the arguments
+            let args = state.expr_stack.pop_args(count + 1)?;
+            state
+                .expr_stack
+                .push(Expr::Call(Cow::Borrowed("exlcall"), args))
+        }
         Opcode::NotEqual => decompile_binary_expr(state, Operator::NotEqual)?,
         Opcode::FloatNotEqual => decompile_binary_expr(state, Operator::FloatNotEqual)?,
         Opcode::Nop0x3D => {}
@@ -334,7 +537,7 @@ fn decompile_opcode(state: &mut DecompilerState) -> Result<()> {
                 .get(id)
                 .ok_or_else(|| anyhow!("bad function id {}", id))?;
             let args = state.expr_stack.pop_args(*arity)?;
-            state.expr_stack.push(Expr::Call(Cow::Borrowed(name), args));
+            state.expr_stack.push(Expr::Call(Cow::Owned(name.clone()), args));
         }
         Opcode::CallByName(name, arity) => {
             let args = state.expr_stack.pop_args((*arity) as usize)?;
@@ -342,7 +545,8 @@ fn decompile_opcode(state: &mut DecompilerState) -> Result<()> {
         }
         Opcode::Return => {
             let value = state.expr_stack.pop()?;
-            state.block_stack.line(Stmt::Return(Some(value)))?;
+            let origin = state.origin();
+            state.block_stack.line(Stmt::Return(Some(value), origin))?;
         }
         Opcode::Jump(label) => state.block_stack.line(Stmt::Goto(label))?,
         Opcode::JumpNotZero(_) => bail!("found opcode for match stmt outside of match context"),
@@ -353,20 +557,30 @@ fn decompile_opcode(state: &mut DecompilerState) -> Result<()> {
         Opcode::And(label) => {
             decompile_short_circuited_binary_expr(state, label, Operator::LogicalAnd)?
         }
-        Opcode::Yield => state.block_stack.line(Stmt::Yield)?,
+        Opcode::Yield => {
+            let origin = state.origin();
+            state.block_stack.line(Stmt::Yield(origin))?
+        }
         Opcode::Format(arity) => {
             let args = state.expr_stack.pop_args((*arity) as usize)?;
-            state.block_stack.line(Stmt::Printf(args))?;
+            let origin = state.origin();
+            state.block_stack.line(Stmt::Printf(args, origin))?;
         }
         Opcode::Inc => decompile_inc(state, Operator::Increment)?,
         Opcode::Dec => decompile_inc(state, Operator::Decrement)?,
         Opcode::Copy => decompile_match(state)?,
-        Opcode::ReturnFalse => state
-            .block_stack
-            .line(Stmt::Return(Some(Expr::Literal(Literal::Int(0)))))?,
-        Opcode::ReturnTrue => state
-            .block_stack
-            .line(Stmt::Return(Some(Expr::Literal(Literal::Int(1)))))?,
+        Opcode::ReturnFalse => {
+            let origin = state.origin();
+            state
+                .block_stack
+                .line(Stmt::Return(Some(Expr::Literal(Literal::Int(0))), origin))?
+        }
+        Opcode::ReturnTrue => {
+            let origin = state.origin();
+            state
+                .block_stack
+                .line(Stmt::Return(Some(Expr::Literal(Literal::Int(1))), origin))?
+        }
         Opcode::Label(label) => state.block_stack.line(Stmt::Label(label))?,
         Opcode::StringEquals => {
             let args = state.expr_stack.pop_args(2)?;
@@ -381,18 +595,20 @@ fn decompile_opcode(state: &mut DecompilerState) -> Result<()> {
                 .push(Expr::Call(Cow::Borrowed("strne"), args))
         }
         Opcode::Nop0x40 => {}
+        Opcode::Unknown(op, _) => bail!("can't decompile unknown opcode 0x{:X}", op),
     }
     Ok(())
 }
 
 fn decompile_assignment(state: &mut DecompilerState) -> Result<()> {
+    let origin = state.origin();
     if let AssignState::Normal = state.assign_state {
         let right = state.expr_stack.pop()?;
         let left = state.expr_stack.pop()?;
         if let Expr::Addr(left) = left {
             state
                 .block_stack
-                .line(Stmt::Assign(Operator::Assign, left, right))?;
+                .line(Stmt::Assign(Operator::Assign, left, right, origin))?;
         } else {
             bail!("malformed assignment - left hand side is not a variable address");
         }
@@ -400,8 +616,13 @@ fn decompile_assignment(state: &mut DecompilerState) -> Result<()> {
         let op = op
             .to_shorthand()
             .ok_or_else(|| anyhow!("malformed shorthand assignment - bad operator"))?;
+        // The right hand side is a single operand, so grouping for precedence is redundant.
+        let right = match *right {
+            Expr::Grouped(inner) => *inner,
+            right => right,
+        };
         if let Expr::Addr(left) = *left {
-            state.block_stack.line(Stmt::Assign(op, left, *right))?;
+            state.block_stack.line(Stmt::Assign(op, left, right, origin))?;
         } else {
             bail!("malformed assignment - left hand side is not a variable address");
         }
@@ -410,9 +631,9 @@ fn decompile_assignment(state: &mut DecompilerState) -> Result<()> {
     }
     // In FE9, assignments are expressions. This is the only game where this is the case.
     // It isn't actually used, but it leads to inconsistencies in a couple places which we deal with here.
-    if let Game::FE9 = state.game {
+    if state.game.profile().assignment_expressions {
         if let Some(Opcode::Consume) = state.opcodes.peek() {
-            state.opcodes.next();
+            state.next_opcode();
         }
     }
     state.assign_state = AssignState::Normal;
@@ -491,6 +712,10 @@ fn decompile_unary_expr(state: &mut DecompilerState, op: Operator) -> Result<()>
         state
             .expr_stack
             .push(Expr::Call(Cow::Borrowed("negate"), vec![operand]));
+    } else if let (Operator::FloatNegate, Expr::Literal(Literal::Float(_))) = (op, &operand) {
+        state
+            .expr_stack
+            .push(Expr::Call(Cow::Borrowed("negatef"), vec![operand]));
     } else {
         state.expr_stack.push(Expr::Unary(op, Box::new(operand)));
     }
@@ -499,6 +724,7 @@ fn decompile_unary_expr(state: &mut DecompilerState, op: Operator) -> Result<()>
 
 fn preserve_precedence(operand: Expr, op: Operator) -> Expr {
     match operand {
+        Expr::Conditional(_, _, _) => Expr::Grouped(Box::new(operand)),
         Expr::Binary(other_op, _, _) => {
             let op_precedence: Precedence = op.into();
             let operand_precedence: Precedence = other_op.into();
@@ -512,8 +738,18 @@ fn preserve_precedence(operand: Expr, op: Operator) -> Expr {
     }
 }
 
+/// A conditional used as an operand needs parentheses, unlike the ones the parser takes on its own.
+fn preserve_conditional(operand: Expr) -> Expr {
+    match operand {
+        Expr::Conditional(_, _, _) => Expr::Grouped(Box::new(operand)),
+        _ => operand,
+    }
+}
+
 fn decompile_if(state: &mut DecompilerState, label: &str) -> Result<()> {
     let check = state.expr_stack.pop()?;
+    let depth = state.expr_stack.stack.len();
+    let statement_start = state.statement_start;
     state.block_stack.push();
     decompile_until(state, label)?;
     let terminating_label = if let Some(Opcode::Label(label)) = state.opcodes.peek() {
@@ -521,18 +757,56 @@ fn decompile_if(state: &mut DecompilerState, label: &str) -> Result<()> {
     } else {
         bail!("expected label after if");
     };
-    let then_part = Box::new(Stmt::Block(state.block_stack.pop()?));
+    let then_part = state.block_stack.pop()?;
+    // A then part that leaves a value behind and jumps past the else is a conditional expression.
+    // Labels inside it end nested conditionals and aren't referenced by anything else.
+    if state.expr_stack.stack.len() == depth + 1 {
+        let mut lines = then_part.iter().filter(|s| !matches!(s, Stmt::Label(_)));
+        if let (Some(Stmt::Goto(end_label)), None) = (lines.next(), lines.next()) {
+            state.statement_start = statement_start;
+            return decompile_conditional(state, check, end_label);
+        }
+    }
+    let then_part = Box::new(Stmt::Block(then_part));
     state
         .block_stack
         .line(Stmt::If(check, then_part, None, terminating_label))?;
     Ok(())
 }
 
+fn decompile_conditional<'a>(
+    state: &mut DecompilerState<'a, '_>,
+    check: Expr<'a>,
+    end_label: &str,
+) -> Result<()> {
+    state.next_opcode(); // Discard the else label
+    // The then value stays on the stack so the else branch doesn't look like a new statement
+    state.block_stack.push();
+    decompile_until(state, end_label)?;
+    let else_part = state.block_stack.pop()?;
+    if else_part.iter().any(|s| !matches!(s, Stmt::Label(_))) {
+        bail!("malformed conditional - else branch has statements");
+    }
+    // The end label stays, since an enclosing conditional or short circuit may end at the same address.
+    // Nothing jumps to it afterwards, so it's pruned with the other unused labels.
+    let otherwise = state.expr_stack.pop()?;
+    let then = state.expr_stack.pop()?;
+    state.expr_stack.push(Expr::Conditional(
+        Box::new(preserve_conditional(check)),
+        Box::new(then),
+        Box::new(otherwise),
+    ));
+    Ok(())
+}
+
 fn decompile_match(state: &mut DecompilerState) -> Result<()> {
     let switch = state.expr_stack.pop()?;
     let mut cases = Vec::new();
     let mut default = None;
     let mut done_label: Option<&str>;
+    // Where each case's body starts, so jumps between bodies can become fallthroughs
+    let mut block_labels = Vec::new();
+    let mut default_label = None;
     loop {
         // Read conditions for the current case
         let mut conditions = Vec::new();
@@ -541,8 +815,11 @@ fn decompile_match(state: &mut DecompilerState) -> Result<()> {
             // Push the switch expr on to the stack so we can build this equality
             state.expr_stack.push(switch.clone());
             while let Some(opcode) = state.opcodes.peek() {
-                if let Opcode::JumpNotZero(_) = opcode {
-                    state.opcodes.next(); // Discard the jump
+                if let Opcode::JumpNotZero(label) = opcode {
+                    if conditions.is_empty() {
+                        block_labels.push(label.as_str());
+                    }
+                    state.next_opcode(); // Discard the jump
                     break;
                 }
                 decompile_opcode(state)?;
@@ -554,20 +831,20 @@ fn decompile_match(state: &mut DecompilerState) -> Result<()> {
             }
             if let Some(Opcode::Copy) = state.opcodes.peek() {
                 // Need to read another condition.
-                state.opcodes.next();
+                state.next_opcode();
             } else {
                 break;
             }
         }
         // Read the case body
-        let next_case_label = if let Some(Opcode::Jump(label)) = state.opcodes.next() {
+        let next_case_label = if let Some(Opcode::Jump(label)) = state.next_opcode() {
             label
         } else {
             bail!("malformed match - no jump to next case stmt")
         };
         state.block_stack.push();
         decompile_until(state, next_case_label)?;
-        state.opcodes.next(); // Discard the label
+        state.next_opcode(); // Discard the label
         let mut body = state.block_stack.pop()?;
         let end_label = if let Some(Stmt::Goto(label)) = body.pop() {
             label
@@ -583,26 +860,33 @@ fn decompile_match(state: &mut DecompilerState) -> Result<()> {
         match state.opcodes.peek() {
             Some(Opcode::Consume) => {
                 // Done!
-                state.opcodes.next();
+                state.next_opcode();
                 break;
             }
             Some(Opcode::Copy) => {
                 // This is the start of another case
-                state.opcodes.next();
+                state.next_opcode();
             }
             Some(_) => {
+                default_label = Some(next_case_label.as_str());
                 state.block_stack.push();
                 decompile_until(state, end_label)?;
                 let mut body = state.block_stack.pop()?;
                 body.pop();
-                state.opcodes.next(); // Consume the end label
+                state.next_opcode(); // Consume the end label
                 default = Some(Stmt::Block(body));
-                state.opcodes.next(); // Consume the consume opcode
+                state.next_opcode(); // Consume the consume opcode
                 break;
             }
             _ => bail!("unexpected end of match"),
         }
     }
+    for (i, case) in cases.iter_mut().enumerate() {
+        let fallthrough_label = block_labels.get(i + 1).copied().or(default_label);
+        if let Some(label) = fallthrough_label {
+            refining::replace_jumps_with_fallthrough(&mut case.body, label);
+        }
+    }
     state.block_stack.line(Stmt::Match(
         switch,
         cases,
@@ -4,11 +4,11 @@ use exalt_ast::Operator;
 use itertools::Itertools;
 
 use crate::data_structures::{DeclarationRequest, VarTracker};
-use crate::ir::{Expr, FrameId, Literal, Reference, Script, Stmt, Decl};
+use crate::ir::{Decl, Expr, FrameId, Literal, Reference, Script, Stmt};
 use anyhow::Result;
 
 pub fn strip_default_return(block: &mut Vec<Stmt>) -> bool {
-    if let Some(Stmt::Return(Some(Expr::Literal(Literal::Int(i))))) = block.last() {
+    if let Some(Stmt::Return(Some(Expr::Literal(Literal::Int(i))), _)) = block.last() {
         if *i == 0 {
             return block.pop().is_some();
         }
@@ -39,6 +39,7 @@ fn prune_labels_recursive(stmt: &mut Stmt, counts: &HashMap<String, usize>) {
                 }
             }
         }
+        Stmt::DoWhile(body, _) => prune_labels_recursive(body, counts),
         Stmt::For(_, _, _, body) => prune_labels_recursive(body, counts),
         Stmt::If(_, then_part, else_part, _) => {
             prune_labels_recursive(then_part, counts);
@@ -66,6 +67,7 @@ fn count_label_references_recursive(stmt: &Stmt, counts: &mut HashMap<String, us
                 count_label_references_recursive(stmt, counts);
             }
         }
+        Stmt::DoWhile(body, _) => count_label_references_recursive(body, counts),
         Stmt::For(_, _, _, body) => count_label_references_recursive(body, counts),
         Stmt::Goto(label) => {
             let count = counts.entry(label.to_string()).or_insert(0);
@@ -114,6 +116,7 @@ pub fn collapse_while_loops(stmt: &mut Stmt) {
                 collapse_while_loops(stmt);
             }
         }
+        Stmt::DoWhile(body, _) => collapse_while_loops(body),
         Stmt::For(_, _, _, body) => collapse_while_loops(body),
         Stmt::If(_, then_part, else_part, _) => {
             collapse_while_loops(then_part);
@@ -172,6 +175,7 @@ pub fn collapse_for_loops(stmt: &mut Stmt) {
                 collapse_for_loops(stmt);
             }
         }
+        Stmt::DoWhile(body, _) => collapse_for_loops(body),
         Stmt::For(_, _, _, body) => collapse_for_loops(body),
         Stmt::If(_, then_part, else_part, _) => {
             collapse_for_loops(then_part);
@@ -194,7 +198,7 @@ pub fn collapse_for_loops(stmt: &mut Stmt) {
 
 fn is_for_loop_sequence(stmts: &[Stmt]) -> bool {
     if let (
-        Stmt::Assign(_, _, _),
+        Stmt::Assign(..),
         Stmt::Goto(maybe_check_label),
         Stmt::Label(step_label),
         Stmt::Label(check_label),
@@ -218,6 +222,7 @@ pub fn add_match_breaks(stmt: &mut Stmt) {
                 add_match_breaks(line);
             }
         }
+        Stmt::DoWhile(body, _) => add_match_breaks(body),
         Stmt::For(_, _, _, body) => add_match_breaks(body),
         Stmt::If(_, then_part, else_part, _) => {
             add_match_breaks(then_part);
@@ -240,6 +245,330 @@ pub fn add_match_breaks(stmt: &mut Stmt) {
     }
 }
 
+/// Rewrite `if (a) { if (b) { X } }` as `if (a && b) { X }` when neither if has an else
+/// and the inner if ends right where the outer one does, so both checks skip to the same place like && would.
+/// If something still jumps to the inner if's end label, the label is kept at the end of the merged body.
+/// The result behaves the same, but && compiles to a short-circuit And instead of a second jump,
+/// so recompiling won't reproduce the original opcodes.
+pub fn merge_nested_ifs(stmt: &mut Stmt) {
+    match stmt {
+        Stmt::Block(contents) => {
+            for line in contents {
+                merge_nested_ifs(line);
+            }
+        }
+        Stmt::DoWhile(body, _) => merge_nested_ifs(body),
+        Stmt::For(_, _, _, body) => merge_nested_ifs(body),
+        Stmt::If(check, then_part, else_part, _) => {
+            if else_part.is_none() {
+                while let Some((inner_check, inner_then)) = take_nested_if(then_part) {
+                    let outer_check = std::mem::replace(check, Expr::Literal(Literal::Int(0)));
+                    *check = Expr::Binary(
+                        Operator::LogicalAnd,
+                        Box::new(crate::preserve_precedence(outer_check, Operator::LogicalAnd)),
+                        Box::new(crate::preserve_precedence(inner_check, Operator::LogicalAnd)),
+                    );
+                    *then_part = inner_then;
+                }
+            }
+            merge_nested_ifs(then_part);
+            if let Some(stmt) = else_part {
+                merge_nested_ifs(stmt);
+            }
+        }
+        Stmt::Match(_, cases, default, _) => {
+            for case in cases {
+                merge_nested_ifs(&mut case.body);
+            }
+            if let Some(stmt) = default {
+                merge_nested_ifs(stmt);
+            }
+        }
+        Stmt::While(_, body) => merge_nested_ifs(body),
+        _ => {}
+    }
+}
+
+/// Rewrite `if (c) { x = a; } else { x = b; }` as `x = c ? a : b;`.
+/// Else if chains that assign the same variable in every branch become nested conditionals.
+/// Like [`merge_nested_ifs`], this changes the opcodes a recompile produces.
+pub fn collapse_conditional_assignments(stmt: &mut Stmt) {
+    match stmt {
+        Stmt::Block(contents) => {
+            for line in contents {
+                collapse_conditional_assignments(line);
+            }
+        }
+        Stmt::DoWhile(body, _) => collapse_conditional_assignments(body),
+        Stmt::For(_, _, _, body) => collapse_conditional_assignments(body),
+        Stmt::If(_, then_part, else_part, _) => {
+            collapse_conditional_assignments(then_part);
+            if let Some(else_part) = else_part {
+                collapse_conditional_assignments(else_part);
+                // Else if chains keep the inner if in a block of its own
+                if let Stmt::Block(contents) = else_part.as_mut() {
+                    if let [Stmt::Assign(..)] = contents.as_slice() {
+                        **else_part = contents.pop().unwrap();
+                    }
+                }
+            }
+            if is_conditional_assignment(stmt) {
+                let (check, then_part, else_part, _) =
+                    std::mem::replace(stmt, Stmt::Block(Vec::new())).unwrap_if();
+                let (_, left, then, then_origin) = single_statement(*then_part).unwrap_assign();
+                let (_, _, otherwise, else_origin) =
+                    single_statement(*else_part.unwrap()).unwrap_assign();
+                let origin = match (then_origin, else_origin) {
+                    (Some((start, _)), Some((_, end))) => Some((start, end)),
+                    _ => None,
+                };
+                let value = Expr::Conditional(
+                    Box::new(crate::preserve_conditional(check)),
+                    Box::new(then),
+                    Box::new(otherwise),
+                );
+                *stmt = Stmt::Assign(Operator::Assign, left, value, origin);
+            }
+        }
+        Stmt::Match(_, cases, default, _) => {
+            for case in cases {
+                collapse_conditional_assignments(&mut case.body);
+            }
+            if let Some(stmt) = default {
+                collapse_conditional_assignments(stmt);
+            }
+        }
+        Stmt::While(_, body) => collapse_conditional_assignments(body),
+        _ => {}
+    }
+}
+
+fn is_conditional_assignment(stmt: &Stmt) -> bool {
+    fn assignment<'s, 'a>(stmt: &'s Stmt<'a>) -> Option<&'s Reference<'a>> {
+        let stmt = match stmt {
+            Stmt::Block(contents) if contents.len() == 1 => &contents[0],
+            stmt => stmt,
+        };
+        match stmt {
+            Stmt::Assign(Operator::Assign, left, _, _) => Some(left),
+            _ => None,
+        }
+    }
+    match stmt {
+        Stmt::If(_, then_part, Some(else_part), _) => {
+            match (assignment(then_part), assignment(else_part)) {
+                (Some(left), Some(right)) => left == right,
+                _ => false,
+            }
+        }
+        _ => false,
+    }
+}
+
+fn single_statement(stmt: Stmt) -> Stmt {
+    match stmt {
+        Stmt::Block(mut contents) if contents.len() == 1 => contents.pop().unwrap(),
+        stmt => stmt,
+    }
+}
+
+/// Take the check and body of an if without an else if it's the only statement in a block.
+/// The if may be followed by its own end label, which then moves to the end of the body.
+fn take_nested_if<'a>(block: &mut Stmt<'a>) -> Option<(Expr<'a>, Box<Stmt<'a>>)> {
+    let contents = match block {
+        Stmt::Block(contents) => contents,
+        _ => return None,
+    };
+    let end_label = match contents.as_slice() {
+        [Stmt::If(_, _, None, _)] => None,
+        [Stmt::If(_, _, None, label), Stmt::Label(end)] if label == end => contents.pop(),
+        _ => return None,
+    };
+    let (check, mut then_part, _, _) = contents.pop()?.unwrap_if();
+    if let Some(label) = end_label {
+        match then_part.as_mut() {
+            Stmt::Block(body) => body.push(label),
+            _ => then_part = Box::new(Stmt::Block(vec![*then_part, label])),
+        }
+    }
+    Some((check, then_part))
+}
+
+pub fn collapse_else_branches(stmt: &mut Stmt) {
+    let mut counts = HashMap::new();
+    count_label_references_recursive(stmt, &mut counts);
+    collapse_else_branches_recursive(stmt, &mut counts);
+}
+
+fn collapse_else_branches_recursive(stmt: &mut Stmt, counts: &mut HashMap<String, usize>) {
+    match stmt {
+        Stmt::Block(contents) => {
+            for line in contents.iter_mut() {
+                collapse_else_branches_recursive(line, counts);
+            }
+            // Work backwards so chains collapse from the innermost else if outwards
+            let mut i = contents.len();
+            while i > 0 {
+                i -= 1;
+                if let Some(done_index) = find_else_sequence(&contents[i..], counts) {
+                    let end = i + done_index;
+                    let else_part = contents.drain(i + 2..end).collect_vec();
+                    contents.remove(i + 1); // The else label is implicit in the source
+                    if let Stmt::If(_, then_part, else_slot, _) = &mut contents[i] {
+                        if let Stmt::Block(then_contents) = then_part.as_mut() {
+                            if let Some(Stmt::Goto(done_label)) = then_contents.pop() {
+                                if let Some(count) = counts.get_mut(done_label) {
+                                    *count -= 1;
+                                }
+                            }
+                        }
+                        *else_slot = Some(Box::new(Stmt::Block(else_part)));
+                    }
+                }
+            }
+        }
+        Stmt::DoWhile(body, _) => collapse_else_branches_recursive(body, counts),
+        Stmt::For(_, _, _, body) => collapse_else_branches_recursive(body, counts),
+        Stmt::If(_, then_part, else_part, _) => {
+            collapse_else_branches_recursive(then_part, counts);
+            if let Some(stmt) = else_part {
+                collapse_else_branches_recursive(stmt, counts);
+            }
+        }
+        Stmt::Match(_, cases, default, _) => {
+            for case in cases {
+                collapse_else_branches_recursive(&mut case.body, counts);
+            }
+            if let Some(stmt) = default {
+                collapse_else_branches_recursive(stmt, counts);
+            }
+        }
+        Stmt::While(_, body) => collapse_else_branches_recursive(body, counts),
+        _ => {}
+    }
+}
+
+/// Look for the pattern the compiler generates for an else branch:
+/// an if whose body ends by jumping over the statements following its terminating label.
+/// Returns the index of the label that ends the else branch, relative to the start of the sequence.
+fn find_else_sequence(stmts: &[Stmt], counts: &HashMap<String, usize>) -> Option<usize> {
+    if let (Some(Stmt::If(_, then_part, None, else_label)), Some(Stmt::Label(label))) =
+        (stmts.first(), stmts.get(1))
+    {
+        // The else label has to be reachable only by falling through the check
+        if else_label != label || counts.get(*label).copied().unwrap_or(0) != 0 {
+            return None;
+        }
+        let done_label = match then_part.as_ref() {
+            Stmt::Block(contents) => match contents.last() {
+                Some(Stmt::Goto(done_label)) => *done_label,
+                _ => return None,
+            },
+            _ => return None,
+        };
+        for (i, stmt) in stmts.iter().enumerate().skip(2) {
+            if let Stmt::Label(label) = stmt {
+                if *label == done_label {
+                    // An empty else would just produce the same code as a plain if
+                    return if i > 2 { Some(i) } else { None };
+                }
+                // Moving a label that something else jumps to into a nested block would change meaning
+                if counts.get(*label).copied().unwrap_or(0) != 0 {
+                    return None;
+                }
+            }
+        }
+    }
+    None
+}
+
+pub fn collapse_do_while_loops(stmt: &mut Stmt) {
+    let mut counts = HashMap::new();
+    count_label_references_recursive(stmt, &mut counts);
+    collapse_do_while_loops_recursive(stmt, &mut counts);
+}
+
+fn collapse_do_while_loops_recursive(stmt: &mut Stmt, counts: &mut HashMap<String, usize>) {
+    match stmt {
+        Stmt::Block(contents) => {
+            for line in contents.iter_mut() {
+                collapse_do_while_loops_recursive(line, counts);
+            }
+            let mut i = 0;
+            while i < contents.len() {
+                if let Some(start) = find_do_while_start(&contents[..=i], counts) {
+                    let (check, _, _, break_label) = contents.remove(i).unwrap_if();
+                    let mut body = contents.drain(start + 1..i).collect_vec();
+                    contents.remove(start); // The loop start label is implicit in the source
+                    let continue_label = match body.last() {
+                        Some(Stmt::Label(label)) => *label,
+                        _ => "",
+                    };
+                    if !continue_label.is_empty() {
+                        body.pop();
+                    }
+                    let mut body = Stmt::Block(body);
+                    // Breaks may target a label that starts a later loop, so stop counting them
+                    let mut body_counts = HashMap::new();
+                    count_label_references_recursive(&body, &mut body_counts);
+                    for label in [break_label, continue_label] {
+                        if let (Some(count), Some(removed)) =
+                            (counts.get_mut(label), body_counts.get(label))
+                        {
+                            *count -= removed;
+                        }
+                    }
+                    replace_jumps_with_break_and_continue(&mut body, break_label, continue_label);
+                    contents.insert(start, Stmt::DoWhile(Box::new(body), check));
+                    i = start;
+                }
+                i += 1;
+            }
+        }
+        Stmt::DoWhile(body, _) => collapse_do_while_loops_recursive(body, counts),
+        Stmt::For(_, _, _, body) => collapse_do_while_loops_recursive(body, counts),
+        Stmt::If(_, then_part, else_part, _) => {
+            collapse_do_while_loops_recursive(then_part, counts);
+            if let Some(stmt) = else_part {
+                collapse_do_while_loops_recursive(stmt, counts);
+            }
+        }
+        Stmt::Match(_, cases, default, _) => {
+            for case in cases {
+                collapse_do_while_loops_recursive(&mut case.body, counts);
+            }
+            if let Some(stmt) = default {
+                collapse_do_while_loops_recursive(stmt, counts);
+            }
+        }
+        Stmt::While(_, body) => collapse_do_while_loops_recursive(body, counts),
+        _ => {}
+    }
+}
+
+/// Look for the pattern the compiler generates for a do-while loop:
+/// a label followed later by an if that only jumps back to it.
+/// Returns the index of the label that starts the loop.
+fn find_do_while_start(stmts: &[Stmt], counts: &HashMap<String, usize>) -> Option<usize> {
+    if let Some(Stmt::If(_, then_part, None, _)) = stmts.last() {
+        let start_label = match then_part.as_ref() {
+            Stmt::Block(contents) => match contents.as_slice() {
+                [Stmt::Goto(label)] => *label,
+                _ => return None,
+            },
+            _ => return None,
+        };
+        // Anything else jumping to the start would not be part of the loop
+        if counts.get(start_label).copied().unwrap_or(0) != 1 {
+            return None;
+        }
+        return stmts
+            .iter()
+            .rposition(|stmt| matches!(stmt, Stmt::Label(label) if *label == start_label));
+    }
+    None
+}
+
 fn replace_jumps_with_break_and_continue<'a>(
     stmt: &mut Stmt<'a>,
     break_label: &'a str,
@@ -265,6 +594,9 @@ fn replace_jumps_with_break_and_continue<'a>(
                 i += 1;
             }
         }
+        Stmt::DoWhile(body, _) => {
+            replace_jumps_with_break_and_continue(body, break_label, continue_label)
+        }
         Stmt::For(_, _, _, body) => {
             replace_jumps_with_break_and_continue(body, break_label, continue_label)
         }
@@ -289,6 +621,33 @@ fn replace_jumps_with_break_and_continue<'a>(
     }
 }
 
+pub fn replace_jumps_with_fallthrough<'a>(stmt: &mut Stmt<'a>, fallthrough_label: &'a str) {
+    match stmt {
+        Stmt::Block(contents) => {
+            for line in contents {
+                if let Stmt::Goto(label) = line {
+                    if *label == fallthrough_label {
+                        *line = Stmt::Fallthrough;
+                    }
+                } else {
+                    replace_jumps_with_fallthrough(line, fallthrough_label);
+                }
+            }
+        }
+        Stmt::DoWhile(body, _) => replace_jumps_with_fallthrough(body, fallthrough_label),
+        Stmt::For(_, _, _, body) => replace_jumps_with_fallthrough(body, fallthrough_label),
+        Stmt::If(_, then_part, else_part, _) => {
+            replace_jumps_with_fallthrough(then_part, fallthrough_label);
+            if let Some(stmt) = else_part {
+                replace_jumps_with_fallthrough(stmt, fallthrough_label);
+            }
+        }
+        Stmt::While(_, body) => replace_jumps_with_fallthrough(body, fallthrough_label),
+        // A fallthrough inside a nested match would belong to that match, so its jumps stay gotos
+        _ => {}
+    }
+}
+
 pub fn collect_var_details(
     stmt: &Stmt,
     arity: usize,
@@ -311,7 +670,7 @@ fn collect_var_details_recursive(
     global_var_tracker: &mut VarTracker,
 ) -> Result<()> {
     match stmt {
-        Stmt::Assign(op, left, right) => {
+        Stmt::Assign(op, left, right, _) => {
             collect_var_details_in_expr_recursive(right, var_tracker, global_var_tracker)?;
             if let Reference::Var(FrameId(frame_id, global)) = left {
                 if *global {
@@ -328,7 +687,13 @@ fn collect_var_details_recursive(
                 collect_var_details_recursive(line, var_tracker, global_var_tracker)?;
             }
         }
-        Stmt::Expr(e) => collect_var_details_in_expr_recursive(e, var_tracker, global_var_tracker)?,
+        Stmt::DoWhile(body, check) => {
+            collect_var_details_recursive(body, var_tracker, global_var_tracker)?;
+            collect_var_details_in_expr_recursive(check, var_tracker, global_var_tracker)?;
+        }
+        Stmt::Expr(e, _) => {
+            collect_var_details_in_expr_recursive(e, var_tracker, global_var_tracker)?
+        }
         Stmt::For(init, check, step, body) => {
             collect_var_details_recursive(init, var_tracker, global_var_tracker)?;
             collect_var_details_in_expr_recursive(check, var_tracker, global_var_tracker)?;
@@ -354,12 +719,12 @@ fn collect_var_details_recursive(
                 collect_var_details_recursive(default, var_tracker, global_var_tracker)?;
             }
         }
-        Stmt::Printf(args) => {
+        Stmt::Printf(args, _) => {
             for arg in args {
                 collect_var_details_in_expr_recursive(arg, var_tracker, global_var_tracker)?;
             }
         }
-        Stmt::Return(Some(value)) => {
+        Stmt::Return(Some(value), _) => {
             collect_var_details_in_expr_recursive(value, var_tracker, global_var_tracker)?
         }
         Stmt::While(check, body) => {
@@ -382,6 +747,9 @@ fn collect_var_details_in_assign_lhs(
         } else {
             var_tracker.mark_initialized(frame_id)?;
         }
+    } else {
+        // Shorthand assignments read the variable before writing it back.
+        var_tracker.mark_used(frame_id)?;
     }
     Ok(())
 }
@@ -412,6 +780,11 @@ fn collect_var_details_in_expr_recursive(
         Expr::Grouped(e) => {
             collect_var_details_in_expr_recursive(e, var_tracker, global_var_tracker)?
         }
+        Expr::Conditional(condition, then, otherwise) => {
+            collect_var_details_in_expr_recursive(condition, var_tracker, global_var_tracker)?;
+            collect_var_details_in_expr_recursive(then, var_tracker, global_var_tracker)?;
+            collect_var_details_in_expr_recursive(otherwise, var_tracker, global_var_tracker)?;
+        }
         _ => {}
     }
     Ok(())
@@ -468,6 +841,7 @@ pub fn collapse_static_array_inits(stmt: &mut Stmt, vars: &mut VarTracker) -> Re
                     Operator::Assign,
                     Reference::Var(FrameId(frame_id, global)),
                     _,
+                    _,
                 ) = &contents[i]
                 {
                     i += 1;
@@ -478,7 +852,7 @@ pub fn collapse_static_array_inits(stmt: &mut Stmt, vars: &mut VarTracker) -> Re
                     }
                     let mut target_frame_id = FrameId(frame_id + 1, *global);
                     while i < contents.len() {
-                        if let Stmt::Assign(Operator::Assign, Reference::Var(frame_id), _) =
+                        if let Stmt::Assign(Operator::Assign, Reference::Var(frame_id), _, _) =
                             &contents[i]
                         {
                             if target_frame_id == *frame_id {
@@ -513,6 +887,7 @@ pub fn collapse_static_array_inits(stmt: &mut Stmt, vars: &mut VarTracker) -> Re
                 }
             }
         }
+        Stmt::DoWhile(body, _) => collapse_static_array_inits(body, vars)?,
         Stmt::For(_, _, _, body) => collapse_static_array_inits(body, vars)?,
         Stmt::If(_, then_part, else_part, _) => {
             collapse_static_array_inits(then_part, vars)?;
@@ -538,19 +913,24 @@ fn create_static_array_init<'a>(
     mut assigns: Vec<Stmt<'a>>,
     vars: &mut VarTracker,
 ) -> Result<Stmt<'a>> {
-    let (_, left, right) = assigns.remove(0).unwrap_assign();
+    let (_, left, right, mut origin) = assigns.remove(0).unwrap_assign();
     let frame_id = left.frame_id();
     vars.set_array_length(frame_id.0, assigns.len() + 1)?;
     vars.mark_static_array(frame_id.0)?;
     let mut elements = vec![right];
     for element in assigns.into_iter() {
-        let (_, _, right) = element.unwrap_assign();
+        let (_, _, right, element_origin) = element.unwrap_assign();
         elements.push(right);
+        // The combined statement covers every assignment it replaced
+        if let (Some((_, end)), Some((_, element_end))) = (&mut origin, element_origin) {
+            *end = element_end;
+        }
     }
     Ok(Stmt::Assign(
         Operator::Assign,
         left,
         Expr::StaticArrayInit(elements),
+        origin,
     ))
 }
 
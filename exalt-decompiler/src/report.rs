@@ -0,0 +1,41 @@
+use std::fmt::Write;
+
+use anyhow::Result;
+use indexmap::IndexMap;
+
+/// Summarize duplicate strings in a script's text data as a comment block.
+/// Takes each string alongside its encoded size, in the order they appear in the text data.
+pub fn text_data_report(strings: &[(String, usize)]) -> Result<String> {
+    let mut counts: IndexMap<&str, (usize, usize)> = IndexMap::new();
+    for (text, size) in strings {
+        let entry = counts.entry(text.as_str()).or_insert((0, *size));
+        entry.0 += 1;
+    }
+    let total_bytes: usize = strings.iter().map(|(_, size)| size).sum();
+    let savings: usize = counts
+        .values()
+        .map(|(count, size)| (count - 1) * size)
+        .sum();
+
+    let mut sb = String::new();
+    writeln!(
+        sb,
+        "// Text data: {} strings, {} bytes",
+        strings.len(),
+        total_bytes
+    )?;
+    if savings == 0 {
+        writeln!(sb, "// No duplicate strings")?;
+    } else {
+        writeln!(
+            sb,
+            "// Duplicate strings (deduplication saves {} bytes):",
+            savings
+        )?;
+        for (text, (count, _)) in counts.iter().filter(|(_, (count, _))| *count > 1) {
+            writeln!(sb, "//   {:?} x{}", text, count)?;
+        }
+    }
+    sb.push('\n');
+    Ok(sb)
+}
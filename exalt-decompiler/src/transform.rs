@@ -1,5 +1,6 @@
 use std::collections::HashMap;
 
+use exalt_lir::SymbolMap;
 use serde::Deserialize;
 
 #[derive(Debug, Default, Deserialize)]
@@ -7,6 +8,47 @@ pub struct IrTransform {
     pub strings: HashMap<String, String>,
     pub functions: HashMap<String, String>,
     pub events: HashMap<usize, String>,
+
+    /// Variant names of each enum by value.
+    #[serde(default)]
+    pub enums: HashMap<String, HashMap<i32, String>>,
+
+    /// Enums that call arguments take their values from, keyed by function name and argument index.
+    #[serde(default)]
+    pub arguments: HashMap<String, HashMap<usize, String>>,
+
+    /// Enums that call results and parameters take their values from.
+    #[serde(default)]
+    pub value_tags: ValueTags,
+
+    /// Names for local and global variables.
+    #[serde(default)]
+    pub symbols: SymbolMap,
+
+    /// Globals declared by an included layout file as (frame index, slot count).
+    /// Slots they cover aren't declared again in the output.
+    #[serde(default)]
+    pub shared_globals: Vec<(usize, usize)>,
+}
+
+/// Tags values with the enum they come from so comparisons against them can name variants.
+#[derive(Debug, Default, Deserialize)]
+pub struct ValueTags {
+    /// Enums that function results take their values from, keyed by function name.
+    #[serde(default)]
+    pub results: HashMap<String, String>,
+
+    /// Enums that script function parameters take their values from, keyed by function name and parameter index.
+    #[serde(default)]
+    pub parameters: HashMap<String, HashMap<usize, String>>,
+}
+
+impl ValueTags {
+    pub fn enums(&self) -> impl Iterator<Item = &String> {
+        self.results
+            .values()
+            .chain(self.parameters.values().flat_map(|p| p.values()))
+    }
 }
 
 impl IrTransform {
@@ -21,4 +63,48 @@ impl IrTransform {
     pub fn transform_event(&self, value: usize) -> Option<&str> {
         self.events.get(&value).map(|v| v.as_str())
     }
+
+    pub fn is_shared_global(&self, frame_id: usize) -> bool {
+        self.shared_globals
+            .iter()
+            .any(|(start, length)| (*start..start + length).contains(&frame_id))
+    }
+
+    /// Find the enum access (ex. Flag.Chapter5Cleared) for an argument of a function.
+    /// Functions can be named by either their alias or their internal name.
+    pub fn transform_argument(&self, function: &str, index: usize, value: i32) -> Option<String> {
+        let enum_name = lookup_function(&self.arguments, function, self)?.get(&index)?;
+        self.transform_enum(enum_name, value)
+    }
+
+    /// Find the enum that a function's result takes its values from.
+    pub fn result_enum(&self, function: &str) -> Option<&str> {
+        lookup_function(&self.value_tags.results, function, self).map(|v| v.as_str())
+    }
+
+    /// Find the enum that a parameter of a script function takes its values from.
+    pub fn parameter_enum(&self, function: &str, index: usize) -> Option<&str> {
+        lookup_function(&self.value_tags.parameters, function, self)?
+            .get(&index)
+            .map(|v| v.as_str())
+    }
+
+    /// Find the enum access (ex. Difficulty.Lunatic) for a value of an enum.
+    pub fn transform_enum(&self, enum_name: &str, value: i32) -> Option<String> {
+        let variant = self.enums.get(enum_name)?.get(&value)?;
+        Some(format!("{}.{}", enum_name, variant))
+    }
+}
+
+/// Look up a rule for a function by either its alias or its internal name.
+fn lookup_function<'a, T>(
+    rules: &'a HashMap<String, T>,
+    function: &str,
+    transform: &IrTransform,
+) -> Option<&'a T> {
+    rules.get(function).or_else(|| {
+        transform
+            .transform_function_name(function)
+            .and_then(|alias| rules.get(alias))
+    })
 }
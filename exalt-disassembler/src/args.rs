@@ -1,9 +1,8 @@
 use std::io::Cursor;
 
-use crate::util::read_shift_jis;
-use anyhow::{bail, Result};
-use byteorder::{LittleEndian, ReadBytesExt};
-use exalt_lir::{CallbackArg, Game};
+use crate::error::{DisassemblyError, Result};
+use crate::util::{read_text, ReadEndian};
+use exalt_lir::{CallbackArg, Game, Platform, TextCodec};
 use lazy_static::lazy_static;
 use maplit::hashmap;
 use std::collections::HashMap;
@@ -439,71 +438,49 @@ lazy_static! {
     };
 }
 
-fn read_gcn_args(
-    cursor: &mut Cursor<&[u8]>,
-    text_data: &[u8],
-    signature: Option<&Vec<CallbackArgType>>,
-    count: usize,
-) -> Result<Vec<CallbackArg>> {
-    let mut args = Vec::new();
-    if let Some(sig) = signature {
-        if sig.len() != count {
-            bail!(
-                "expected '{}' args but actual count is '{}'",
-                sig.len(),
-                count
-            );
-        }
-        for arg in sig {
-            match arg {
-                CallbackArgType::Str => {
-                    let raw = cursor.read_u16::<LittleEndian>()?;
-                    let text = read_shift_jis(text_data, raw as u64)?;
-                    args.push(CallbackArg::Str(text));
-                }
-                CallbackArgType::Int => {
-                    args.push(CallbackArg::Int(cursor.read_i16::<LittleEndian>()? as i32));
-                }
-            }
-        }
-    } else {
-        for _ in 0..count {
-            args.push(CallbackArg::Int(cursor.read_i16::<LittleEndian>()? as i32));
-        }
-    }
-    Ok(args)
+fn read_arg(cursor: &mut Cursor<&[u8]>, platform: Platform) -> Result<u32> {
+    let endian = platform.header_endian;
+    Ok(match platform.arg_size {
+        2 => cursor.read_i16_as(endian)? as u32,
+        _ => cursor.read_u32_as(endian)?,
+    })
 }
 
-fn read_three_ds_args(
+fn read_callback_args(
     cursor: &mut Cursor<&[u8]>,
     text_data: &[u8],
+    codec: TextCodec,
+    platform: Platform,
+    event: u32,
     signature: Option<&Vec<CallbackArgType>>,
     count: usize,
 ) -> Result<Vec<CallbackArg>> {
     let mut args = Vec::new();
     if let Some(sig) = signature {
         if sig.len() != count {
-            bail!(
-                "expected '{}' args but actual count is '{}'",
-                sig.len(),
-                count
-            );
+            return Err(DisassemblyError::ArgCountMismatch {
+                event,
+                expected: sig.len(),
+                actual: count,
+            });
         }
         for arg in sig {
+            let raw = read_arg(cursor, platform)?;
             match arg {
                 CallbackArgType::Str => {
-                    let offset = cursor.read_u32::<LittleEndian>()? as usize;
-                    let text = read_shift_jis(text_data, offset as u64)?;
-                    args.push(CallbackArg::Str(text));
-                }
-                CallbackArgType::Int => {
-                    args.push(CallbackArg::Int(cursor.read_i32::<LittleEndian>()?));
+                    // Offsets are unsigned even when arguments are narrow
+                    let offset = match platform.arg_size {
+                        2 => raw as u16 as u64,
+                        _ => raw as u64,
+                    };
+                    args.push(CallbackArg::Str(read_text(text_data, offset, codec)?));
                 }
+                CallbackArgType::Int => args.push(CallbackArg::Int(raw as i32)),
             }
         }
     } else {
         for _ in 0..count {
-            args.push(CallbackArg::Int(cursor.read_i32::<LittleEndian>()?));
+            args.push(CallbackArg::Int(read_arg(cursor, platform)? as i32));
         }
     }
     Ok(args)
@@ -512,6 +489,7 @@ fn read_three_ds_args(
 pub fn read_args(
     cursor: &mut Cursor<&[u8]>,
     text_data: &[u8],
+    codec: TextCodec,
     game: Game,
     event: u32,
     count: usize,
@@ -525,12 +503,13 @@ pub fn read_args(
         Game::FE14 => FE14_EVENTS.get(&event),
         Game::FE15 => FE15_EVENTS.get(&event),
     };
-    match game {
-        Game::FE9 | Game::FE10 | Game::FE11 | Game::FE12 => {
-            read_gcn_args(cursor, text_data, signature, count)
-        }
-        Game::FE13 | Game::FE14 | Game::FE15 => {
-            read_three_ds_args(cursor, text_data, signature, count)
-        }
-    }
+    read_callback_args(
+        cursor,
+        text_data,
+        codec,
+        game.platform(),
+        event,
+        signature,
+        count,
+    )
 }
@@ -1,22 +1,26 @@
-use anyhow::{Result, Context, bail};
-use byteorder::{BigEndian, ReadBytesExt};
-use exalt_lir::{Opcode, Game};
+use byteorder::ReadBytesExt;
+use exalt_lir::{Endian, Game, Opcode, OpcodeSet, TextCodec};
 use std::io::Cursor;
 use rustc_hash::{FxHashMap, FxHashSet};
 
-use crate::util::read_shift_jis;
+use crate::error::{DisassemblyError, Result};
+use crate::util::{read_text, ReadEndian};
 
 struct ResolveState<'a> {
     pub text_data: &'a [u8],
+    pub codec: TextCodec,
     pub labels: FxHashMap<u64, String>,
+    pub endian: Endian,
     next_label: usize,
 }
 
 impl<'a> ResolveState<'a> {
-    pub fn new(text_data: &'a [u8]) -> Self {
+    pub fn new(text_data: &'a [u8], codec: TextCodec, endian: Endian) -> Self {
         ResolveState {
             text_data,
+            codec,
             labels: FxHashMap::default(),
+            endian,
             next_label: 0,
         }
     }
@@ -33,8 +37,8 @@ impl<'a> ResolveState<'a> {
         }
     }
 
-    pub fn text(&self, offset: u64) -> anyhow::Result<String> {
-        read_shift_jis(self.text_data, offset)
+    pub fn text(&self, offset: u64) -> Result<String> {
+        read_text(self.text_data, offset, self.codec)
     }
 }
 
@@ -51,46 +55,46 @@ fn read_gcn_opcode(
     match opcode {
         0x0 => Ok((addr, Opcode::Done)),
         0x1 => Ok((addr, Opcode::VarLoad(cursor.read_u8()? as u16))),
-        0x2 => Ok((addr, Opcode::VarLoad(cursor.read_u16::<BigEndian>()?))),
+        0x2 => Ok((addr, Opcode::VarLoad(cursor.read_u16_as(state.endian)?))),
         0x3 => Ok((addr, Opcode::ArrLoad(cursor.read_u8()? as u16))),
-        0x4 => Ok((addr, Opcode::ArrLoad(cursor.read_u16::<BigEndian>()?))),
+        0x4 => Ok((addr, Opcode::ArrLoad(cursor.read_u16_as(state.endian)?))),
         0x5 => Ok((addr, Opcode::PtrLoad(cursor.read_u8()? as u16))),
-        0x6 => Ok((addr, Opcode::PtrLoad(cursor.read_u16::<BigEndian>()?))),
+        0x6 => Ok((addr, Opcode::PtrLoad(cursor.read_u16_as(state.endian)?))),
         0x7 => Ok((addr, Opcode::VarAddr(cursor.read_u8()? as u16))),
-        0x8 => Ok((addr, Opcode::VarAddr(cursor.read_u16::<BigEndian>()?))),
+        0x8 => Ok((addr, Opcode::VarAddr(cursor.read_u16_as(state.endian)?))),
         0x9 => Ok((addr, Opcode::ArrAddr(cursor.read_u8()? as u16))),
-        0xA => Ok((addr, Opcode::ArrAddr(cursor.read_u16::<BigEndian>()?))),
+        0xA => Ok((addr, Opcode::ArrAddr(cursor.read_u16_as(state.endian)?))),
         0xB => Ok((addr, Opcode::PtrAddr(cursor.read_u8()? as u16))),
-        0xC => Ok((addr, Opcode::PtrAddr(cursor.read_u16::<BigEndian>()?))),
+        0xC => Ok((addr, Opcode::PtrAddr(cursor.read_u16_as(state.endian)?))),
         0xD => Ok((addr, Opcode::GlobalVarLoad(cursor.read_u8()? as u16))),
-        0xE => Ok((addr, Opcode::GlobalVarLoad(cursor.read_u16::<BigEndian>()?))),
+        0xE => Ok((addr, Opcode::GlobalVarLoad(cursor.read_u16_as(state.endian)?))),
         0xF => Ok((addr, Opcode::GlobalArrLoad(cursor.read_u8()? as u16))),
-        0x10 => Ok((addr, Opcode::GlobalArrLoad(cursor.read_u16::<BigEndian>()?))),
+        0x10 => Ok((addr, Opcode::GlobalArrLoad(cursor.read_u16_as(state.endian)?))),
         0x11 => Ok((addr, Opcode::GlobalPtrLoad(cursor.read_u8()? as u16))),
-        0x12 => Ok((addr, Opcode::GlobalPtrLoad(cursor.read_u16::<BigEndian>()?))),
+        0x12 => Ok((addr, Opcode::GlobalPtrLoad(cursor.read_u16_as(state.endian)?))),
         0x13 => Ok((addr, Opcode::GlobalVarAddr(cursor.read_u8()? as u16))),
-        0x14 => Ok((addr, Opcode::GlobalVarAddr(cursor.read_u16::<BigEndian>()?))),
+        0x14 => Ok((addr, Opcode::GlobalVarAddr(cursor.read_u16_as(state.endian)?))),
         0x15 => Ok((addr, Opcode::GlobalArrAddr(cursor.read_u8()? as u16))),
-        0x16 => Ok((addr, Opcode::GlobalArrAddr(cursor.read_u16::<BigEndian>()?))),
+        0x16 => Ok((addr, Opcode::GlobalArrAddr(cursor.read_u16_as(state.endian)?))),
         0x17 => Ok((addr, Opcode::GlobalPtrAddr(cursor.read_u8()? as u16))),
-        0x18 => Ok((addr, Opcode::GlobalPtrAddr(cursor.read_u16::<BigEndian>()?))),
+        0x18 => Ok((addr, Opcode::GlobalPtrAddr(cursor.read_u16_as(state.endian)?))),
         0x19 => Ok((addr, Opcode::IntLoad(cursor.read_i8()? as i32))),
         0x1A => Ok((
             addr,
-            Opcode::IntLoad(cursor.read_i16::<BigEndian>()? as i32),
+            Opcode::IntLoad(cursor.read_i16_as(state.endian)? as i32),
         )),
-        0x1B => Ok((addr, Opcode::IntLoad(cursor.read_i32::<BigEndian>()?))),
+        0x1B => Ok((addr, Opcode::IntLoad(cursor.read_i32_as(state.endian)?))),
         0x1C => Ok((
             addr,
             Opcode::StrLoad(state.text(cursor.read_u8()? as u64)?),
         )),
         0x1D => Ok((
             addr,
-            Opcode::StrLoad(state.text(cursor.read_u16::<BigEndian>()? as u64)?),
+            Opcode::StrLoad(state.text(cursor.read_u16_as(state.endian)? as u64)?),
         )),
         0x1E => Ok((
             addr,
-            Opcode::StrLoad(state.text(cursor.read_u32::<BigEndian>()? as u64)?),
+            Opcode::StrLoad(state.text(cursor.read_u32_as(state.endian)? as u64)?),
         )),
         0x1F => Ok((addr, Opcode::Dereference)),
         0x20 => Ok((addr, Opcode::Consume)),
@@ -120,7 +124,7 @@ fn read_gcn_opcode(
         0x38 => Ok((
             addr,
             Opcode::CallByName(
-                state.text(cursor.read_u16::<BigEndian>()? as u64)?,
+                state.text(cursor.read_u16_as(state.endian)? as u64)?,
                 cursor.read_u8()?,
             ),
         )),
@@ -129,41 +133,44 @@ fn read_gcn_opcode(
             addr,
             Opcode::Jump(state.label(calculate_jump_address(
                 addr,
-                cursor.read_i16::<BigEndian>()?,
+                cursor.read_i16_as(state.endian)?,
             ))),
         )),
         0x3B => Ok((
             addr,
             Opcode::JumpNotZero(state.label(calculate_jump_address(
                 addr,
-                cursor.read_i16::<BigEndian>()?,
+                cursor.read_i16_as(state.endian)?,
             ))),
         )),
         0x3C => Ok((
             addr,
             Opcode::Or(state.label(calculate_jump_address(
                 addr,
-                cursor.read_i16::<BigEndian>()?,
+                cursor.read_i16_as(state.endian)?,
             ))),
         )),
         0x3D => Ok((
             addr,
             Opcode::JumpZero(state.label(calculate_jump_address(
                 addr,
-                cursor.read_i16::<BigEndian>()?,
+                cursor.read_i16_as(state.endian)?,
             ))),
         )),
         0x3E => Ok((
             addr,
             Opcode::And(state.label(calculate_jump_address(
                 addr,
-                cursor.read_i16::<BigEndian>()?,
+                cursor.read_i16_as(state.endian)?,
             ))),
         )),
         0x3F => Ok((addr, Opcode::Yield)),
         0x40 => Ok((addr, Opcode::Nop0x40)),
         0x41 => Ok((addr, Opcode::Format(cursor.read_u8()?))),
-        _ => bail!("unrecognized opcode 0x{:X}", opcode),
+        _ => Err(DisassemblyError::InvalidOpcode {
+            opcode,
+            address: addr,
+        }),
     }
 }
 
@@ -176,46 +183,46 @@ fn read_wii_opcode(
     match opcode {
         0x0 => Ok((addr, Opcode::Done)),
         0x1 => Ok((addr, Opcode::VarLoad(cursor.read_u8()? as u16))),
-        0x2 => Ok((addr, Opcode::VarLoad(cursor.read_u16::<BigEndian>()?))),
+        0x2 => Ok((addr, Opcode::VarLoad(cursor.read_u16_as(state.endian)?))),
         0x3 => Ok((addr, Opcode::ArrLoad(cursor.read_u8()? as u16))),
-        0x4 => Ok((addr, Opcode::ArrLoad(cursor.read_u16::<BigEndian>()?))),
+        0x4 => Ok((addr, Opcode::ArrLoad(cursor.read_u16_as(state.endian)?))),
         0x5 => Ok((addr, Opcode::PtrLoad(cursor.read_u8()? as u16))),
-        0x6 => Ok((addr, Opcode::PtrLoad(cursor.read_u16::<BigEndian>()?))),
+        0x6 => Ok((addr, Opcode::PtrLoad(cursor.read_u16_as(state.endian)?))),
         0x7 => Ok((addr, Opcode::VarAddr(cursor.read_u8()? as u16))),
-        0x8 => Ok((addr, Opcode::VarAddr(cursor.read_u16::<BigEndian>()?))),
+        0x8 => Ok((addr, Opcode::VarAddr(cursor.read_u16_as(state.endian)?))),
         0x9 => Ok((addr, Opcode::ArrAddr(cursor.read_u8()? as u16))),
-        0xA => Ok((addr, Opcode::ArrAddr(cursor.read_u16::<BigEndian>()?))),
+        0xA => Ok((addr, Opcode::ArrAddr(cursor.read_u16_as(state.endian)?))),
         0xB => Ok((addr, Opcode::PtrAddr(cursor.read_u8()? as u16))),
-        0xC => Ok((addr, Opcode::PtrAddr(cursor.read_u16::<BigEndian>()?))),
+        0xC => Ok((addr, Opcode::PtrAddr(cursor.read_u16_as(state.endian)?))),
         0xD => Ok((addr, Opcode::GlobalVarLoad(cursor.read_u8()? as u16))),
-        0xE => Ok((addr, Opcode::GlobalVarLoad(cursor.read_u16::<BigEndian>()?))),
+        0xE => Ok((addr, Opcode::GlobalVarLoad(cursor.read_u16_as(state.endian)?))),
         0xF => Ok((addr, Opcode::GlobalArrLoad(cursor.read_u8()? as u16))),
-        0x10 => Ok((addr, Opcode::GlobalArrLoad(cursor.read_u16::<BigEndian>()?))),
+        0x10 => Ok((addr, Opcode::GlobalArrLoad(cursor.read_u16_as(state.endian)?))),
         0x11 => Ok((addr, Opcode::GlobalPtrLoad(cursor.read_u8()? as u16))),
-        0x12 => Ok((addr, Opcode::GlobalPtrLoad(cursor.read_u16::<BigEndian>()?))),
+        0x12 => Ok((addr, Opcode::GlobalPtrLoad(cursor.read_u16_as(state.endian)?))),
         0x13 => Ok((addr, Opcode::GlobalVarAddr(cursor.read_u8()? as u16))),
-        0x14 => Ok((addr, Opcode::GlobalVarAddr(cursor.read_u16::<BigEndian>()?))),
+        0x14 => Ok((addr, Opcode::GlobalVarAddr(cursor.read_u16_as(state.endian)?))),
         0x15 => Ok((addr, Opcode::GlobalArrAddr(cursor.read_u8()? as u16))),
-        0x16 => Ok((addr, Opcode::GlobalArrAddr(cursor.read_u16::<BigEndian>()?))),
+        0x16 => Ok((addr, Opcode::GlobalArrAddr(cursor.read_u16_as(state.endian)?))),
         0x17 => Ok((addr, Opcode::GlobalPtrAddr(cursor.read_u8()? as u16))),
-        0x18 => Ok((addr, Opcode::GlobalPtrAddr(cursor.read_u16::<BigEndian>()?))),
+        0x18 => Ok((addr, Opcode::GlobalPtrAddr(cursor.read_u16_as(state.endian)?))),
         0x19 => Ok((addr, Opcode::IntLoad(cursor.read_i8()? as i32))),
         0x1A => Ok((
             addr,
-            Opcode::IntLoad(cursor.read_i16::<BigEndian>()? as i32),
+            Opcode::IntLoad(cursor.read_i16_as(state.endian)? as i32),
         )),
-        0x1B => Ok((addr, Opcode::IntLoad(cursor.read_i32::<BigEndian>()?))),
+        0x1B => Ok((addr, Opcode::IntLoad(cursor.read_i32_as(state.endian)?))),
         0x1C => Ok((
             addr,
             Opcode::StrLoad(state.text(cursor.read_u8()? as u64)?),
         )),
         0x1D => Ok((
             addr,
-            Opcode::StrLoad(state.text(cursor.read_u16::<BigEndian>()? as u64)?),
+            Opcode::StrLoad(state.text(cursor.read_u16_as(state.endian)? as u64)?),
         )),
         0x1E => Ok((
             addr,
-            Opcode::StrLoad(state.text(cursor.read_u32::<BigEndian>()? as u64)?),
+            Opcode::StrLoad(state.text(cursor.read_u32_as(state.endian)? as u64)?),
         )),
         0x1F => Ok((addr, Opcode::Dereference)),
         0x20 => Ok((addr, Opcode::Consume)),
@@ -253,7 +260,7 @@ fn read_wii_opcode(
         0x38 => Ok((
             addr,
             Opcode::CallByName(
-                state.text(cursor.read_u16::<BigEndian>()? as u64)?,
+                state.text(cursor.read_u16_as(state.endian)? as u64)?,
                 cursor.read_u8()?,
             ),
         )),
@@ -262,35 +269,35 @@ fn read_wii_opcode(
             addr,
             Opcode::Jump(state.label(calculate_jump_address(
                 addr,
-                cursor.read_i16::<BigEndian>()?,
+                cursor.read_i16_as(state.endian)?,
             ))),
         )),
         0x3B => Ok((
             addr,
             Opcode::JumpNotZero(state.label(calculate_jump_address(
                 addr,
-                cursor.read_i16::<BigEndian>()?,
+                cursor.read_i16_as(state.endian)?,
             ))),
         )),
         0x3C => Ok((
             addr,
             Opcode::Or(state.label(calculate_jump_address(
                 addr,
-                cursor.read_i16::<BigEndian>()?,
+                cursor.read_i16_as(state.endian)?,
             ))),
         )),
         0x3D => Ok((
             addr,
             Opcode::JumpZero(state.label(calculate_jump_address(
                 addr,
-                cursor.read_i16::<BigEndian>()?,
+                cursor.read_i16_as(state.endian)?,
             ))),
         )),
         0x3E => Ok((
             addr,
             Opcode::And(state.label(calculate_jump_address(
                 addr,
-                cursor.read_i16::<BigEndian>()?,
+                cursor.read_i16_as(state.endian)?,
             ))),
         )),
         0x3F => Ok((addr, Opcode::Yield)),
@@ -302,7 +309,10 @@ fn read_wii_opcode(
         0x45 => Ok((addr, Opcode::ReturnFalse)),
         0x46 => Ok((addr, Opcode::ReturnTrue)),
         0x47 => Ok((addr, Opcode::Assign)),
-        _ => bail!("unrecognized opcode 0x{:X}", opcode),
+        _ => Err(DisassemblyError::InvalidOpcode {
+            opcode,
+            address: addr,
+        }),
     }
 }
 
@@ -315,36 +325,36 @@ fn read_three_ds_opcode(
     match opcode {
         0x0 => Ok((addr, Opcode::Done)),
         0x1 => Ok((addr, Opcode::VarLoad(cursor.read_u8()? as u16))),
-        0x2 => Ok((addr, Opcode::VarLoad(cursor.read_u16::<BigEndian>()?))),
+        0x2 => Ok((addr, Opcode::VarLoad(cursor.read_u16_as(state.endian)?))),
         0x3 => Ok((addr, Opcode::ArrLoad(cursor.read_u8()? as u16))),
-        0x4 => Ok((addr, Opcode::ArrLoad(cursor.read_u16::<BigEndian>()?))),
+        0x4 => Ok((addr, Opcode::ArrLoad(cursor.read_u16_as(state.endian)?))),
         0x5 => Ok((addr, Opcode::PtrLoad(cursor.read_u8()? as u16))),
-        0x6 => Ok((addr, Opcode::PtrLoad(cursor.read_u16::<BigEndian>()?))),
+        0x6 => Ok((addr, Opcode::PtrLoad(cursor.read_u16_as(state.endian)?))),
         0x7 => Ok((addr, Opcode::VarAddr(cursor.read_u8()? as u16))),
-        0x8 => Ok((addr, Opcode::VarAddr(cursor.read_u16::<BigEndian>()?))),
+        0x8 => Ok((addr, Opcode::VarAddr(cursor.read_u16_as(state.endian)?))),
         0x9 => Ok((addr, Opcode::ArrAddr(cursor.read_u8()? as u16))),
-        0xA => Ok((addr, Opcode::ArrAddr(cursor.read_u16::<BigEndian>()?))),
+        0xA => Ok((addr, Opcode::ArrAddr(cursor.read_u16_as(state.endian)?))),
         0xB => Ok((addr, Opcode::PtrAddr(cursor.read_u8()? as u16))),
-        0xC => Ok((addr, Opcode::PtrAddr(cursor.read_u16::<BigEndian>()?))),
+        0xC => Ok((addr, Opcode::PtrAddr(cursor.read_u16_as(state.endian)?))),
         0x19 => Ok((addr, Opcode::IntLoad(cursor.read_i8()? as i32))),
         0x1A => Ok((
             addr,
-            Opcode::IntLoad(cursor.read_i16::<BigEndian>()? as i32),
+            Opcode::IntLoad(cursor.read_i16_as(state.endian)? as i32),
         )),
-        0x1B => Ok((addr, Opcode::IntLoad(cursor.read_i32::<BigEndian>()?))),
+        0x1B => Ok((addr, Opcode::IntLoad(cursor.read_i32_as(state.endian)?))),
         0x1C => Ok((
             addr,
             Opcode::StrLoad(state.text(cursor.read_u8()? as u64)?),
         )),
         0x1D => Ok((
             addr,
-            Opcode::StrLoad(state.text(cursor.read_u16::<BigEndian>()? as u64)?),
+            Opcode::StrLoad(state.text(cursor.read_u16_as(state.endian)? as u64)?),
         )),
         0x1E => Ok((
             addr,
-            Opcode::StrLoad(state.text(cursor.read_u32::<BigEndian>()? as u64)?),
+            Opcode::StrLoad(state.text(cursor.read_u32_as(state.endian)? as u64)?),
         )),
-        0x1F => Ok((addr, Opcode::FloatLoad(cursor.read_f32::<BigEndian>()?))),
+        0x1F => Ok((addr, Opcode::FloatLoad(cursor.read_f32_as(state.endian)?))),
         0x20 => Ok((addr, Opcode::Dereference)),
         0x21 => Ok((addr, Opcode::Consume)),
         0x23 => Ok((addr, Opcode::CompleteAssign)),
@@ -370,6 +380,7 @@ fn read_three_ds_opcode(
         0x37 => Ok((addr, Opcode::RightShift)),
         0x38 => Ok((addr, Opcode::Equal)),
         0x39 => Ok((addr, Opcode::FloatEqual)),
+        0x3A => Ok((addr, Opcode::Exlcall)),
         0x3B => Ok((addr, Opcode::NotEqual)),
         0x3C => Ok((addr, Opcode::FloatNotEqual)),
         0x3D => Ok((addr, Opcode::Nop0x3D)),
@@ -393,7 +404,7 @@ fn read_three_ds_opcode(
         0x47 => Ok((
             addr,
             Opcode::CallByName(
-                state.text(cursor.read_u16::<BigEndian>()? as u64)?,
+                state.text(cursor.read_u16_as(state.endian)? as u64)?,
                 cursor.read_u8()?,
             ),
         )),
@@ -402,35 +413,35 @@ fn read_three_ds_opcode(
             addr,
             Opcode::Jump(state.label(calculate_jump_address(
                 addr,
-                cursor.read_i16::<BigEndian>()?,
+                cursor.read_i16_as(state.endian)?,
             ))),
         )),
         0x4A => Ok((
             addr,
             Opcode::JumpNotZero(state.label(calculate_jump_address(
                 addr,
-                cursor.read_i16::<BigEndian>()?,
+                cursor.read_i16_as(state.endian)?,
             ))),
         )),
         0x4B => Ok((
             addr,
             Opcode::Or(state.label(calculate_jump_address(
                 addr,
-                cursor.read_i16::<BigEndian>()?,
+                cursor.read_i16_as(state.endian)?,
             ))),
         )),
         0x4C => Ok((
             addr,
             Opcode::JumpZero(state.label(calculate_jump_address(
                 addr,
-                cursor.read_i16::<BigEndian>()?,
+                cursor.read_i16_as(state.endian)?,
             ))),
         )),
         0x4D => Ok((
             addr,
             Opcode::And(state.label(calculate_jump_address(
                 addr,
-                cursor.read_i16::<BigEndian>()?,
+                cursor.read_i16_as(state.endian)?,
             ))),
         )),
         0x4E => Ok((addr, Opcode::Yield)),
@@ -440,23 +451,58 @@ fn read_three_ds_opcode(
         0x53 => Ok((addr, Opcode::Copy)),
         0x54 => Ok((addr, Opcode::ReturnFalse)),
         0x55 => Ok((addr, Opcode::ReturnTrue)),
-        _ => bail!("unrecognized opcode 0x{:X}", opcode),
+        _ => Err(DisassemblyError::InvalidOpcode {
+            opcode,
+            address: addr,
+        }),
     }
 }
 
-pub fn disassemble(cursor: &mut Cursor<&[u8]>, text_data: &[u8], game: Game) -> Result<Vec<Opcode>> {
-    let disassembler = match game {
-        Game::FE9 => read_gcn_opcode,
-        Game::FE10 | Game::FE11 | Game::FE12 => read_wii_opcode,
-        Game::FE13 | Game::FE14 | Game::FE15 => read_three_ds_opcode,
+/// Read everything from the cursor to `end` as the operands of an unrecognized opcode.
+/// The last zero is taken to be the terminating opcode, which the assembler writes back on its own.
+fn read_unknown(cursor: &mut Cursor<&[u8]>, opcode: u8, end: u64) -> Opcode {
+    let start = cursor.position().min(cursor.get_ref().len() as u64);
+    let end = end.clamp(start, cursor.get_ref().len() as u64);
+    let mut rest = cursor.get_ref()[start as usize..end as usize].to_vec();
+    if rest.last() == Some(&0) {
+        rest.pop();
+    }
+    cursor.set_position(end);
+    Opcode::Unknown(opcode, rest)
+}
+
+/// Read code until the terminating opcode. `end` is the furthest the code can go,
+/// which bounds an unrecognized opcode since its length can't be known.
+/// Returns the opcodes along with the address of each one.
+pub fn disassemble(
+    cursor: &mut Cursor<&[u8]>,
+    end: u64,
+    text_data: &[u8],
+    codec: TextCodec,
+    game: Game,
+) -> Result<(Vec<Opcode>, Vec<usize>)> {
+    let disassembler = match game.profile().opcodes {
+        OpcodeSet::Gcn => read_gcn_opcode,
+        OpcodeSet::Wii => read_wii_opcode,
+        OpcodeSet::ThreeDs => read_three_ds_opcode,
     };
 
     // First pass: just read the opcodes
-    let mut state = ResolveState::new(text_data);
+    let mut state = ResolveState::new(text_data, codec, game.platform().code_endian);
     let mut opcodes = Vec::new();
     loop {
-        let (real_addr, raw_op) = disassembler(cursor, &mut state)
-            .with_context(|| format!("failed to read opcode at '0x{:X}'", cursor.position()))?;
+        let address = cursor.position();
+        let (real_addr, raw_op) = match disassembler(cursor, &mut state) {
+            Ok(op) => op,
+            Err(DisassemblyError::InvalidOpcode { opcode, address }) => {
+                opcodes.push((address, read_unknown(cursor, opcode, end)));
+                break;
+            }
+            Err(DisassemblyError::Truncated) => {
+                return Err(DisassemblyError::TruncatedOpcode(address))
+            }
+            Err(err) => return Err(err),
+        };
         match raw_op {
             Opcode::Done => break,
             _ => opcodes.push((real_addr, raw_op)),
@@ -465,28 +511,28 @@ pub fn disassemble(cursor: &mut Cursor<&[u8]>, text_data: &[u8], game: Game) ->
 
     // Second pass: place labels
     let mut resolved_opcodes = Vec::new();
+    let mut offsets = Vec::new();
     let mut placed_labels = FxHashSet::default();
     for (addr, op) in opcodes {
         if let Some(label) = state.labels.get(&addr) {
             resolved_opcodes.push(Opcode::Label(label.to_owned()));
+            offsets.push(addr as usize);
             placed_labels.insert(label);
         }
         resolved_opcodes.push(op);
+        offsets.push(addr as usize);
     }
 
     // Sanity check: Did we place every label?
-    let unplaced_labels: Vec<&str> = state
+    let unplaced_labels: Vec<String> = state
         .labels
         .values()
         .filter(|l| !placed_labels.contains(*l))
-        .map(|l| l.as_str())
+        .cloned()
         .collect();
     if !unplaced_labels.is_empty() {
-        bail!(
-            "Failed to resolve the following jump positions: {}",
-            unplaced_labels.join(", ")
-        );
+        return Err(DisassemblyError::UnresolvedJumps(unplaced_labels));
     }
     
-    Ok(resolved_opcodes)
+    Ok((resolved_opcodes, offsets))
 }
@@ -0,0 +1,64 @@
+use exalt_lir::Game;
+use thiserror::Error;
+
+pub(crate) type Result<T> = std::result::Result<T, DisassemblyError>;
+
+/// Why a script couldn't be disassembled. Addresses are offsets into the script
+/// except for text pointers, which are offsets into the text data.
+#[derive(Debug, Error)]
+pub enum DisassemblyError {
+    #[error("invalid magic number 0x{0:X}")]
+    BadMagic(u32),
+
+    #[error("invalid revision '0x{revision:X}' for {game:?}")]
+    BadRevision { game: Game, revision: u32 },
+
+    #[error("{what} address 0x{address:X} is out of bounds (limit 0x{limit:X})")]
+    OutOfBounds {
+        what: &'static str,
+        address: usize,
+        limit: usize,
+    },
+
+    #[error("unexpected end of data")]
+    Truncated,
+
+    #[error("opcode at 0x{0:X} runs past the end of the script")]
+    TruncatedOpcode(u64),
+
+    #[error("unrecognized opcode 0x{opcode:X} at 0x{address:X}")]
+    InvalidOpcode { opcode: u8, address: u64 },
+
+    #[error("malformed text at 0x{0:X} for the script's encoding")]
+    MalformedText(u64),
+
+    #[error("expected '{expected}' args for event {event} but actual count is '{actual}'")]
+    ArgCountMismatch {
+        event: u32,
+        expected: usize,
+        actual: usize,
+    },
+
+    #[error("failed to resolve the following jump positions: {}", .0.join(", "))]
+    UnresolvedJumps(Vec<String>),
+
+    #[error("failed to read function {index} at 0x{address:X}: {error}")]
+    Function {
+        index: usize,
+        address: usize,
+        error: Box<DisassemblyError>,
+    },
+
+    #[error("function index {index} is out of bounds (the script has {count} functions)")]
+    NoSuchFunctionIndex { index: usize, count: usize },
+
+    #[error("no function named '{0}'")]
+    NoSuchFunction(String),
+}
+
+// Every read goes through a cursor over the script, so the only IO error is running off the end
+impl From<std::io::Error> for DisassemblyError {
+    fn from(_: std::io::Error) -> Self {
+        DisassemblyError::Truncated
+    }
+}
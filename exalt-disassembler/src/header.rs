@@ -1,19 +1,26 @@
 use std::io::Cursor;
 use crate::types::CmbHeader;
-use anyhow::Result;
-use byteorder::{LittleEndian, ReadBytesExt};
-use exalt_lir::Game;
+use crate::error::Result;
+use crate::util::{self, ReadEndian};
+use exalt_lir::{Endian, Game, HeaderLayout, TextCodec};
 
-fn read_gcn_header(cursor: &mut Cursor<&[u8]>) -> Result<CmbHeader> {
-    let magic_number = cursor.read_u32::<LittleEndian>()?;
+fn read_gcn_header(
+    cursor: &mut Cursor<&[u8]>,
+    endian: Endian,
+    codec: TextCodec,
+) -> Result<CmbHeader> {
+    let magic_number = cursor.read_u32_as(endian)?;
+    // Nothing else depends on the name, so a malformed one shouldn't stop disassembly
+    let name = util::read_text(cursor.get_ref(), 4, codec).unwrap_or_default();
     cursor.set_position(0x18);
-    let revision = cursor.read_u32::<LittleEndian>()?;
+    let revision = cursor.read_u32_as(endian)?;
     cursor.set_position(0x22);
-    let global_frame_size = cursor.read_u16::<LittleEndian>()? as u32;
-    let text_data_address = cursor.read_u32::<LittleEndian>()?;
-    let function_table_address = cursor.read_u32::<LittleEndian>()?;
+    let global_frame_size = cursor.read_u16_as(endian)? as u32;
+    let text_data_address = cursor.read_u32_as(endian)?;
+    let function_table_address = cursor.read_u32_as(endian)?;
     Ok(CmbHeader {
         magic_number,
+        name,
         revision,
         text_data_address,
         function_table_address,
@@ -22,17 +29,25 @@ fn read_gcn_header(cursor: &mut Cursor<&[u8]>) -> Result<CmbHeader> {
     })
 }
 
-fn read_three_ds_header(cursor: &mut Cursor<&[u8]>) -> Result<CmbHeader> {
-    let magic_number = cursor.read_u32::<LittleEndian>()?;
-    let revision = cursor.read_u32::<LittleEndian>()?;
+fn read_three_ds_header(
+    cursor: &mut Cursor<&[u8]>,
+    endian: Endian,
+    codec: TextCodec,
+) -> Result<CmbHeader> {
+    let magic_number = cursor.read_u32_as(endian)?;
+    let revision = cursor.read_u32_as(endian)?;
+    cursor.set_position(0xC);
+    let name_address = cursor.read_u32_as(endian)?;
+    let name = util::read_text(cursor.get_ref(), name_address as u64, codec).unwrap_or_default();
     cursor.set_position(0x18);
-    let global_frame_size = cursor.read_u32::<LittleEndian>()?;
-    let function_table_address = cursor.read_u32::<LittleEndian>()?;
-    let text_data_address = cursor.read_u32::<LittleEndian>()?;
+    let global_frame_size = cursor.read_u32_as(endian)?;
+    let function_table_address = cursor.read_u32_as(endian)?;
+    let text_data_address = cursor.read_u32_as(endian)?;
     cursor.set_position(0x26);
-    let init_function_index = cursor.read_u16::<LittleEndian>()?;
+    let init_function_index = cursor.read_u16_as(endian)?;
     let header = CmbHeader {
         magic_number,
+        name,
         revision,
         text_data_address,
         function_table_address,
@@ -46,9 +61,10 @@ fn read_three_ds_header(cursor: &mut Cursor<&[u8]>) -> Result<CmbHeader> {
     Ok(header)
 }
 
-pub fn read_header(cursor: &mut Cursor<&[u8]>, game: Game) -> Result<CmbHeader> {
-    match game {
-        Game::FE9 | Game::FE10 | Game::FE11 | Game::FE12 => read_gcn_header(cursor),
-        Game::FE13 | Game::FE14 | Game::FE15 => read_three_ds_header(cursor),
+pub fn read_header(cursor: &mut Cursor<&[u8]>, game: Game, codec: TextCodec) -> Result<CmbHeader> {
+    let endian = game.platform().header_endian;
+    match game.profile().header {
+        HeaderLayout::Gcn => read_gcn_header(cursor, endian, codec),
+        HeaderLayout::ThreeDs => read_three_ds_header(cursor, endian, codec),
     }
 }
@@ -1,27 +1,39 @@
 mod args;
 mod code;
+mod error;
 mod function;
 mod header;
 mod types;
 mod util;
 
+use std::collections::BTreeMap;
+use std::convert::Infallible;
 use std::io::Cursor;
+use std::str::FromStr;
 
-use anyhow::{bail, Context, Result};
-use byteorder::{LittleEndian, ReadBytesExt};
-use exalt_lir::{Function, Game, RawScript};
-use types::CmbHeader;
+use byteorder::ReadBytesExt;
+use exalt_lir::{
+    profiles, Endian, Function, Game, GameProfile, RawScript, ScriptQuirks, TextCodec, TextOptions,
+    SCHEMA_VERSION,
+};
+pub use error::DisassemblyError;
+use error::Result;
+pub use types::CmbHeader;
+use types::CommonFunctionHeader;
+use util::ReadEndian;
 
 // The FE9/FE10 compiler seems to leave junk between null terminators and the next word boundary.
 // Ex. Name ends at 0x4, we expect 0x5 until 0x8 to be all zeroes, but there is actually non-zero values for some reason.
 // There are no pointers or offsets to this data and loading it is a hassle, so chances are this is unused.
 // We hold on to this data anyways just to be safe.
 fn read_junk_until_word_boundary(cursor: &mut Cursor<&[u8]>, game: Game) -> Result<Vec<u8>> {
-    match game {
-        Game::FE9 | Game::FE10 | Game::FE11 | Game::FE12 => {
+    match game.profile().word_padding_junk {
+        true => {
             let mut buffer = Vec::new();
             let mut has_non_zero_values = false;
-            while cursor.position() % 4 != 0 {
+            // The last function in some DLC scripts isn't padded, so the end of the script counts as a boundary
+            let len = cursor.get_ref().len() as u64;
+            while !cursor.position().is_multiple_of(4) && cursor.position() < len {
                 let b = cursor.read_u8()?;
                 has_non_zero_values = has_non_zero_values || b != 0;
                 buffer.push(b);
@@ -31,103 +43,462 @@ fn read_junk_until_word_boundary(cursor: &mut Cursor<&[u8]>, game: Game) -> Resu
             }
             Ok(buffer)
         }
-        _ => Ok(vec![]),
+        false => Ok(vec![]),
     }
 }
 
-fn read_function_table(cursor: &mut Cursor<&[u8]>) -> Result<Vec<usize>> {
+fn read_function_table(cursor: &mut Cursor<&[u8]>, endian: Endian) -> Result<Vec<usize>> {
     let mut addresses: Vec<usize> = Vec::new();
-    let mut next = cursor.read_u32::<LittleEndian>()?;
+    let mut next = cursor.read_u32_as(endian)?;
     while next != 0 {
         addresses.push(next as usize);
-        next = cursor.read_u32::<LittleEndian>()?;
+        next = cursor.read_u32_as(endian)?;
     }
     Ok(addresses)
 }
 
+/// Revisions are build dates written as hex digits (ex. 0x20110819).
+fn is_build_date(revision: u32) -> bool {
+    let digits = format!("{:08X}", revision);
+    if !digits.bytes().all(|d| d.is_ascii_digit()) {
+        return false;
+    }
+    let month: u32 = digits[4..6].parse().unwrap_or(0);
+    let day: u32 = digits[6..8].parse().unwrap_or(0);
+    (1..=12).contains(&month) && (1..=31).contains(&day)
+}
+
 fn validate_header(header: &CmbHeader, game: Game) -> Result<()> {
     if header.magic_number != 0x626D63 {
-        bail!("invalid magic number");
+        return Err(DisassemblyError::BadMagic(header.magic_number));
     }
-    let valid_revision = match game {
-        Game::FE9 => header.revision == 0x20041125,
-        Game::FE10 | Game::FE11 | Game::FE12 => header.revision == 0x20061024,
-        Game::FE13 | Game::FE14 | Game::FE15 => header.revision >= 0x20080801,
+    // Revisions nobody has seen are kept as quirks, but one that belongs to another game
+    // or isn't a build date almost certainly means the wrong game was picked
+    let accepts = |profile: &GameProfile| {
+        let (min, max) = profile.revisions;
+        (min..=max).contains(&header.revision)
     };
-    if !valid_revision {
-        bail!("invalid revision '0x{:X}'", header.revision);
+    let known = accepts(game.profile());
+    if !known && (profiles().iter().any(accepts) || !is_build_date(header.revision)) {
+        return Err(DisassemblyError::BadRevision {
+            game,
+            revision: header.revision,
+        });
     }
     Ok(())
 }
 
-/// Disassemble a script for the target game
-pub fn disassemble(script: &[u8], game: Game) -> Result<RawScript> {
+/// Read the header of a script without disassembling the rest of it.
+pub fn read_header(script: &[u8], game: Game, text: TextOptions) -> Result<CmbHeader> {
     let mut cursor = Cursor::new(script);
-    let header =
-        header::read_header(&mut cursor, game).with_context(|| "failed to read CMB header")?;
+    let header = header::read_header(&mut cursor, game, text.codec(game.profile().encoding))?;
     validate_header(&header, game)?;
+    Ok(header)
+}
+
+/// Picks out one function of a script, either by its position in the function table or by name.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FunctionSelector {
+    Index(usize),
+    Name(String),
+}
+
+impl FromStr for FunctionSelector {
+    type Err = Infallible;
+
+    /// Numbers select by index and anything else selects by name.
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        Ok(match s.parse() {
+            Ok(index) => FunctionSelector::Index(index),
+            Err(_) => FunctionSelector::Name(s.to_owned()),
+        })
+    }
+}
 
-    // Load text data.
+/// Everything needed to find the functions in a script.
+struct ScriptLayout<'a> {
+    cursor: Cursor<&'a [u8]>,
+    header: CmbHeader,
+    text_data: &'a [u8],
+    text: TextCodec,
+    addresses: Vec<usize>,
+
+    /// Sorted addresses that code can't run past: functions, the function table, text data, and the end of the script.
+    boundaries: Vec<usize>,
+}
+
+impl ScriptLayout<'_> {
+    /// Where the code starting at an address has to end.
+    fn code_end(&self, code_address: usize) -> usize {
+        self.boundaries
+            .iter()
+            .copied()
+            .find(|b| *b > code_address)
+            .unwrap_or(self.cursor.get_ref().len())
+    }
+}
+
+fn check_bounds(what: &'static str, address: usize, script: &[u8]) -> Result<()> {
+    if address >= script.len() {
+        return Err(DisassemblyError::OutOfBounds {
+            what,
+            address,
+            limit: script.len(),
+        });
+    }
+    Ok(())
+}
+
+fn read_layout(script: &[u8], game: Game, text: TextCodec) -> Result<ScriptLayout<'_>> {
+    let mut cursor = Cursor::new(script);
+    let header = header::read_header(&mut cursor, game, text)?;
+    validate_header(&header, game)?;
+
+    // Load text data. Scripts without any strings may point it at the very end.
     let text_data_address = header.text_data_address as usize;
     if text_data_address > script.len() {
-        bail!("text data address is out of bounds.");
+        return Err(DisassemblyError::OutOfBounds {
+            what: "text data",
+            address: text_data_address,
+            limit: script.len(),
+        });
     }
     let text_data = &script[text_data_address..];
 
     // Load function addresses.
     let function_table_address = header.function_table_address as usize;
-    if function_table_address >= script.len() {
-        return Err(anyhow::anyhow!("function table address is out of bounds"));
-    }
+    check_bounds("function table", function_table_address, script)?;
     cursor.set_position(function_table_address as u64);
-    let addresses = read_function_table(&mut cursor).context("failed to read function table")?;
+    let addresses = read_function_table(&mut cursor, game.platform().header_endian)?;
+    let mut boundaries = addresses.clone();
+    boundaries.push(function_table_address);
+    boundaries.push(text_data_address);
+    boundaries.push(script.len());
+    boundaries.sort_unstable();
+    Ok(ScriptLayout {
+        cursor,
+        header,
+        text_data,
+        text,
+        addresses,
+        boundaries,
+    })
+}
 
-    // Parse individual functions.
-    let mut functions = Vec::new();
-    for address in addresses {
-        // Read the function header.
-        cursor.set_position(address as u64);
-        let raw_function = function::read_function(&mut cursor, text_data, game)
-            .with_context(|| format!("failed to read function at address '0x{:X}'", address))?;
-
-        // Hack to deal with "junk" data after the name/args in FE9/FE10.
-        // Doesn't seem like it's referenced anywhere, but we preserve it just in case.
-        let prefix = read_junk_until_word_boundary(&mut cursor, game)?;
-
-        // Read the code.
-        if raw_function.code as usize >= script.len() {
-            return Err(anyhow::anyhow!(
-                "Code address is out of bounds, RawFunctionHeader={:?}",
-                raw_function
-            ));
-        }
-        cursor.set_position(raw_function.code.into());
-        let code = code::disassemble(&mut cursor, text_data, game).with_context(|| {
-            format!(
-                "function disassembly failed, RawFunctionHeader={:?}",
-                raw_function
-            )
-        })?;
-
-        // Hack to deal with "junk" data after the terminating opcode in FE9/FE10.
-        // Doesn't seem like it's referenced anywhere, but we preserve it just in case.
-        let suffix = read_junk_until_word_boundary(&mut cursor, game)?;
-
-        functions.push(Function {
+/// Compare a script's layout to what its profile would produce.
+/// `end` is where the last function's code (and any suffix) ends.
+fn read_quirks(layout: &ScriptLayout, end: Option<usize>, game: Game) -> ScriptQuirks {
+    let profile = game.profile();
+    let revision = Some(layout.header.revision).filter(|r| *r != profile.revision);
+
+    // Padding only shows when the last function ends off a word boundary
+    let boundary = if profile.text_first {
+        layout.cursor.get_ref().len()
+    } else {
+        layout.header.text_data_address as usize
+    };
+    let pad_last_event = end
+        .filter(|end| !end.is_multiple_of(4) && *end <= boundary)
+        .map(|end| end < boundary)
+        .filter(|padded| *padded != profile.pad_last_event);
+    let encoding = Some(layout.text.encoding).filter(|e| *e != profile.encoding);
+    ScriptQuirks {
+        revision,
+        pad_last_event,
+        encoding,
+    }
+}
+
+fn in_function(index: usize, address: usize, error: DisassemblyError) -> DisassemblyError {
+    DisassemblyError::Function {
+        index,
+        address,
+        error: Box::new(error),
+    }
+}
+
+fn read_function_header_at(
+    layout: &mut ScriptLayout,
+    index: usize,
+    address: usize,
+    game: Game,
+) -> Result<CommonFunctionHeader> {
+    let read = |layout: &mut ScriptLayout| {
+        check_bounds("function", address, layout.cursor.get_ref())?;
+        layout.cursor.set_position(address as u64);
+        function::read_function(&mut layout.cursor, layout.text_data, layout.text, game)
+    };
+    read(layout).map_err(|err| in_function(index, address, err))
+}
+
+fn read_function_body(
+    layout: &mut ScriptLayout,
+    raw_function: CommonFunctionHeader,
+    game: Game,
+) -> Result<Function> {
+    let end = layout.code_end(raw_function.code as usize);
+    let cursor = &mut layout.cursor;
+
+    // Hack to deal with "junk" data after the name/args in FE9/FE10.
+    // Doesn't seem like it's referenced anywhere, but we preserve it just in case.
+    let prefix = read_junk_until_word_boundary(cursor, game)?;
+
+    // Read the code.
+    check_bounds("code", raw_function.code as usize, cursor.get_ref())?;
+    cursor.set_position(raw_function.code.into());
+    let (code, offsets) =
+        code::disassemble(cursor, end as u64, layout.text_data, layout.text, game)?;
+
+    // Hack to deal with "junk" data after the terminating opcode in FE9/FE10.
+    // Doesn't seem like it's referenced anywhere, but we preserve it just in case.
+    let suffix = read_junk_until_word_boundary(cursor, game)?;
+
+    Ok(Function {
+        event: raw_function.event,
+        arity: raw_function.arity,
+        frame_size: raw_function.frame_size as usize,
+        name: raw_function.name,
+        args: raw_function.args,
+        code,
+        unknown: raw_function.unknown,
+        prefix,
+        suffix,
+        comments: BTreeMap::new(),
+        offsets,
+    })
+}
+
+fn read_function_at(
+    layout: &mut ScriptLayout,
+    index: usize,
+    address: usize,
+    game: Game,
+) -> Result<Function> {
+    let raw_function = read_function_header_at(layout, index, address, game)?;
+    read_function_body(layout, raw_function, game).map_err(|err| in_function(index, address, err))
+}
+
+/// A function table entry read without decoding the function's code.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FunctionSummary {
+    pub address: usize,
+    pub name: Option<String>,
+    pub event: u8,
+    pub arity: u8,
+    pub frame_size: usize,
+    pub code_address: usize,
+
+    /// Bytes from the start of the code to the next function, table, or text data.
+    /// This includes any padding after the terminating opcode.
+    pub code_size: usize,
+}
+
+/// Read the header of every function in a script without decoding any code.
+pub fn summarize_functions(
+    script: &[u8],
+    game: Game,
+    text: TextOptions,
+) -> Result<Vec<FunctionSummary>> {
+    let mut layout = read_layout(script, game, text.codec(game.profile().encoding))?;
+    let mut summaries = Vec::new();
+    for (index, address) in layout.addresses.clone().into_iter().enumerate() {
+        let raw_function = read_function_header_at(&mut layout, index, address, game)?;
+        let code_address = raw_function.code as usize;
+        let end = layout.code_end(code_address);
+        summaries.push(FunctionSummary {
+            address,
+            name: raw_function.name,
             event: raw_function.event,
             arity: raw_function.arity,
             frame_size: raw_function.frame_size as usize,
-            name: raw_function.name,
-            args: raw_function.args,
-            code,
-            unknown: raw_function.unknown,
-            prefix,
-            suffix,
+            code_address,
+            code_size: end.saturating_sub(code_address),
         });
     }
+    Ok(summaries)
+}
+
+/// Disassemble a script for the target game
+pub fn disassemble(script: &[u8], game: Game) -> Result<RawScript> {
+    disassemble_with_options(script, game, TextOptions::default())
+}
+
+/// Disassemble a script, reading its strings with something other than the game's usual encoding.
+/// An encoding that differs from the game's is recorded in the script's quirks.
+pub fn disassemble_with_options(script: &[u8], game: Game, text: TextOptions) -> Result<RawScript> {
+    let mut layout = read_layout(script, game, text.codec(game.profile().encoding))?;
+
+    // Parse individual functions.
+    let mut functions = Vec::new();
+    let mut end = None;
+    for (index, address) in layout.addresses.clone().into_iter().enumerate() {
+        functions.push(read_function_at(&mut layout, index, address, game)?);
+        end = Some(layout.cursor.position() as usize);
+    }
 
     Ok(RawScript {
+        schema_version: SCHEMA_VERSION,
         functions,
-        global_frame_size: header.global_frame_size as usize,
+        global_frame_size: layout.header.global_frame_size as usize,
+        quirks: read_quirks(&layout, end, game),
+    })
+}
+
+/// A script that was only partly readable and what went wrong with the rest.
+#[derive(Debug)]
+pub struct PartialDisassembly {
+    pub script: RawScript,
+    pub errors: Vec<DisassemblyError>,
+}
+
+/// Disassemble as much of a script as possible instead of stopping at the first bad function.
+///
+/// Functions that can't be read keep their place in the script so indices stay the same.
+/// They keep whatever header fields could be read and have no code,
+/// so the result won't assemble back into the original script.
+/// Fails outright if the header or function table is unreadable.
+pub fn disassemble_best_effort(
+    script: &[u8],
+    game: Game,
+    text: TextOptions,
+) -> Result<PartialDisassembly> {
+    let mut layout = read_layout(script, game, text.codec(game.profile().encoding))?;
+    let mut functions = Vec::new();
+    let mut errors = Vec::new();
+    let mut end = None;
+    for (index, address) in layout.addresses.clone().into_iter().enumerate() {
+        end = None;
+        let raw_function = match read_function_header_at(&mut layout, index, address, game) {
+            Ok(raw_function) => raw_function,
+            Err(err) => {
+                errors.push(err);
+                functions.push(placeholder_function(None));
+                continue;
+            }
+        };
+        let header = raw_function.clone();
+        match read_function_body(&mut layout, raw_function, game) {
+            Ok(function) => {
+                functions.push(function);
+                end = Some(layout.cursor.position() as usize);
+            }
+            Err(err) => {
+                errors.push(in_function(index, address, err));
+                functions.push(placeholder_function(Some(header)));
+            }
+        }
+    }
+    Ok(PartialDisassembly {
+        script: RawScript {
+            schema_version: SCHEMA_VERSION,
+            functions,
+            global_frame_size: layout.header.global_frame_size as usize,
+            quirks: read_quirks(&layout, end, game),
+        },
+        errors,
     })
 }
+
+fn placeholder_function(header: Option<CommonFunctionHeader>) -> Function {
+    let header = header.unwrap_or(CommonFunctionHeader {
+        name: None,
+        args: Vec::new(),
+        code: 0,
+        frame_size: 0,
+        event: 0,
+        arity: 0,
+        unknown: 0,
+    });
+    Function {
+        event: header.event,
+        arity: header.arity,
+        frame_size: header.frame_size as usize,
+        name: header.name,
+        args: header.args,
+        code: Vec::new(),
+        unknown: header.unknown,
+        prefix: Vec::new(),
+        suffix: Vec::new(),
+        comments: BTreeMap::new(),
+        offsets: Vec::new(),
+    }
+}
+
+/// Disassemble one function of a script, returning it along with its index.
+/// Only the selected function's code is decoded.
+pub fn disassemble_function(
+    script: &[u8],
+    game: Game,
+    selector: &FunctionSelector,
+    text: TextOptions,
+) -> Result<(usize, Function)> {
+    let mut layout = read_layout(script, game, text.codec(game.profile().encoding))?;
+    let index = match selector {
+        FunctionSelector::Index(index) => {
+            if *index >= layout.addresses.len() {
+                return Err(DisassemblyError::NoSuchFunctionIndex {
+                    index: *index,
+                    count: layout.addresses.len(),
+                });
+            }
+            *index
+        }
+        FunctionSelector::Name(name) => {
+            let mut found = None;
+            for (index, address) in layout.addresses.clone().into_iter().enumerate() {
+                let raw_function = read_function_header_at(&mut layout, index, address, game)?;
+                if raw_function.name.as_deref() == Some(name.as_str()) {
+                    found = Some(index);
+                    break;
+                }
+            }
+            found.ok_or_else(|| DisassemblyError::NoSuchFunction(name.clone()))?
+        }
+    };
+    let address = layout.addresses[index];
+    Ok((index, read_function_at(&mut layout, index, address, game)?))
+}
+
+/// Read every string in the script's text data along with its encoded size (including the terminator).
+/// Padding at the end of the text data is skipped.
+pub fn read_text_data(
+    script: &[u8],
+    game: Game,
+    text: TextOptions,
+) -> Result<Vec<(String, usize)>> {
+    let header = read_header(script, game, text)?;
+
+    // Some formats place text data right before the function table rather than at the end.
+    // A script without strings has an empty text section there, starting at the function table.
+    let end = if game.profile().text_first {
+        header.function_table_address as usize
+    } else {
+        script.len()
+    };
+    if end > script.len() {
+        return Err(DisassemblyError::OutOfBounds {
+            what: "function table",
+            address: end,
+            limit: script.len(),
+        });
+    }
+    let text_data_address = header.text_data_address as usize;
+    if text_data_address > end {
+        return Err(DisassemblyError::OutOfBounds {
+            what: "text data",
+            address: text_data_address,
+            limit: end,
+        });
+    }
+    let codec = text.codec(game.profile().encoding);
+    let mut cursor = Cursor::new(&script[text_data_address..end]);
+    let mut strings = Vec::new();
+    while (cursor.position() as usize) < cursor.get_ref().len() {
+        let start = cursor.position();
+        let text = util::read_text_from_cursor(&mut cursor, codec)?;
+        if !text.is_empty() {
+            strings.push((text, (cursor.position() - start) as usize));
+        }
+    }
+    Ok(strings)
+}
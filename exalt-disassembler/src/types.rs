@@ -1,8 +1,10 @@
 use exalt_lir::CallbackArg;
 
-#[derive(Debug)]
+/// Fields from the start of a compiled script.
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct CmbHeader {
     pub magic_number: u32,
+    pub name: String,
     pub revision: u32,
     pub function_table_address: u32,
     pub text_data_address: u32,
@@ -10,12 +12,11 @@ pub struct CmbHeader {
     pub init_function_index: Option<u16>,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct CommonFunctionHeader {
     pub name: Option<String>,
     pub args: Vec<CallbackArg>,
     pub code: u32,
-    pub id: u32,
     pub frame_size: u16,
     pub event: u8,
     pub arity: u8,
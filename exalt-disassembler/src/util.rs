@@ -1,7 +1,9 @@
-use std::io::{Cursor, BufRead};
+use std::io::{self, BufRead, Cursor};
 
-use anyhow::{bail, Result};
-use encoding_rs::SHIFT_JIS;
+use byteorder::{BigEndian, LittleEndian, ReadBytesExt};
+use exalt_lir::{Endian, TextCodec};
+
+use crate::error::{DisassemblyError, Result};
 
 pub fn address_or_none(address: u32) -> Option<u32> {
     if address != 0 {
@@ -11,24 +13,65 @@ pub fn address_or_none(address: u32) -> Option<u32> {
     }
 }
 
-pub fn read_shift_jis(data: &[u8], start: u64) -> anyhow::Result<String> {
-    if start > data.len().try_into()? {
-        bail!("Out of bounds text pointer.");
+pub fn read_text(data: &[u8], start: u64, codec: TextCodec) -> Result<String> {
+    if start > data.len() as u64 {
+        return Err(DisassemblyError::OutOfBounds {
+            what: "text",
+            address: start as usize,
+            limit: data.len(),
+        });
     }
     let mut cursor = Cursor::new(data);
     cursor.set_position(start);
-    read_shift_jis_from_cursor(&mut cursor)
+    read_text_from_cursor(&mut cursor, codec)
 }
 
-pub fn read_shift_jis_from_cursor(cursor: &mut Cursor<&[u8]>) -> Result<String> {
+pub fn read_text_from_cursor(cursor: &mut Cursor<&[u8]>, codec: TextCodec) -> Result<String> {
     let start = cursor.position();
     let mut buffer = Vec::new();
     cursor.read_until(0, &mut buffer)?;
     buffer.pop(); // Get rid of the null terminator
-    let (v, _, failure) = SHIFT_JIS.decode(&buffer);
-    if failure {
-        bail!("Malformed shift-jis sequence addr={:X}", start)
-    } else {
-        Ok(v.to_string())
+    codec
+        .decode(&buffer)
+        .ok_or(DisassemblyError::MalformedText(start))
+}
+
+/// Reads that follow a platform's byte order instead of a fixed one.
+pub trait ReadEndian: ReadBytesExt {
+    fn read_u16_as(&mut self, endian: Endian) -> io::Result<u16> {
+        match endian {
+            Endian::Little => self.read_u16::<LittleEndian>(),
+            Endian::Big => self.read_u16::<BigEndian>(),
+        }
+    }
+
+    fn read_i16_as(&mut self, endian: Endian) -> io::Result<i16> {
+        match endian {
+            Endian::Little => self.read_i16::<LittleEndian>(),
+            Endian::Big => self.read_i16::<BigEndian>(),
+        }
+    }
+
+    fn read_u32_as(&mut self, endian: Endian) -> io::Result<u32> {
+        match endian {
+            Endian::Little => self.read_u32::<LittleEndian>(),
+            Endian::Big => self.read_u32::<BigEndian>(),
+        }
+    }
+
+    fn read_i32_as(&mut self, endian: Endian) -> io::Result<i32> {
+        match endian {
+            Endian::Little => self.read_i32::<LittleEndian>(),
+            Endian::Big => self.read_i32::<BigEndian>(),
+        }
+    }
+
+    fn read_f32_as(&mut self, endian: Endian) -> io::Result<f32> {
+        match endian {
+            Endian::Little => self.read_f32::<LittleEndian>(),
+            Endian::Big => self.read_f32::<BigEndian>(),
+        }
     }
 }
+
+impl<R: ReadBytesExt + ?Sized> ReadEndian for R {}
@@ -0,0 +1,51 @@
+use std::collections::BTreeMap;
+
+use exalt_lir::{Function, Game, Opcode, RawScript, ScriptQuirks, TextOptions, SCHEMA_VERSION};
+
+fn script(code: Vec<Opcode>) -> RawScript {
+    RawScript {
+        schema_version: SCHEMA_VERSION,
+        global_frame_size: 0,
+        functions: vec![Function {
+            frame_size: 0,
+            event: 0,
+            arity: 0,
+            unknown: 0,
+            prefix: Vec::new(),
+            suffix: Vec::new(),
+            name: Some("f".to_owned()),
+            args: Vec::new(),
+            code,
+            comments: BTreeMap::new(),
+            offsets: Vec::new(),
+        }],
+        quirks: ScriptQuirks::default(),
+    }
+}
+
+#[test]
+fn text_first_script_without_strings_has_no_text_data() {
+    for game in [Game::FE9, Game::FE10, Game::FE12] {
+        let raw = exalt_assembler::assemble(&script(vec![Opcode::Return]), "t", game).unwrap();
+        let header = exalt_disassembler::read_header(&raw, game, TextOptions::default()).unwrap();
+        assert_eq!(header.text_data_address, header.function_table_address);
+        let strings =
+            exalt_disassembler::read_text_data(&raw, game, TextOptions::default()).unwrap();
+        assert!(strings.is_empty(), "{:?}: {:?}", game, strings);
+    }
+}
+
+#[test]
+fn text_data_stops_at_the_function_table() {
+    let code = vec![
+        Opcode::StrLoad("hi".to_owned()),
+        Opcode::Consume,
+        Opcode::Return,
+    ];
+    for game in [Game::FE10, Game::FE14] {
+        let raw = exalt_assembler::assemble(&script(code.clone()), "t", game).unwrap();
+        let strings =
+            exalt_disassembler::read_text_data(&raw, game, TextOptions::default()).unwrap();
+        assert_eq!(strings, vec![("hi".to_owned(), 3)], "{:?}", game);
+    }
+}
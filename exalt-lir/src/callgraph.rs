@@ -0,0 +1,122 @@
+use std::collections::HashMap;
+
+use crate::{Opcode, RawScript};
+
+/// Which functions call which in a script.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct CallGraph {
+    /// Functions called from each function, in order of first call.
+    pub calls: Vec<Vec<usize>>,
+
+    /// Names called from each function that aren't defined in the script (usually engine functions).
+    pub external: Vec<Vec<String>>,
+}
+
+impl CallGraph {
+    /// Functions that call the given one.
+    pub fn callers(&self, function: usize) -> Vec<usize> {
+        self.calls
+            .iter()
+            .enumerate()
+            .filter(|(_, calls)| calls.contains(&function))
+            .map(|(i, _)| i)
+            .collect()
+    }
+
+    /// For every function, whether it can be reached from one of the roots.
+    pub fn reachable_from(&self, roots: &[usize]) -> Vec<bool> {
+        let mut reachable = vec![false; self.calls.len()];
+        let mut pending = roots.to_vec();
+        while let Some(function) = pending.pop() {
+            if function < reachable.len() && !reachable[function] {
+                reachable[function] = true;
+                pending.extend_from_slice(&self.calls[function]);
+            }
+        }
+        reachable
+    }
+}
+
+/// Build the call graph of a script.
+/// Calls by name count as edges when the name matches a function in the same script.
+pub fn build(script: &RawScript) -> CallGraph {
+    let names: HashMap<&str, usize> = script
+        .functions
+        .iter()
+        .enumerate()
+        .filter_map(|(i, f)| f.name.as_deref().map(|name| (name, i)))
+        .collect();
+    let mut graph = CallGraph::default();
+    for function in &script.functions {
+        let mut calls = Vec::new();
+        let mut external = Vec::new();
+        for opcode in &function.code {
+            let callee = match opcode {
+                Opcode::CallById(id) => Some(*id),
+                Opcode::CallByName(name, _) => match names.get(name.as_str()) {
+                    Some(id) => Some(*id),
+                    None => {
+                        if !external.contains(name) {
+                            external.push(name.clone());
+                        }
+                        None
+                    }
+                },
+                _ => None,
+            };
+            if let Some(callee) = callee {
+                if !calls.contains(&callee) {
+                    calls.push(callee);
+                }
+            }
+        }
+        graph.calls.push(calls);
+        graph.external.push(external);
+    }
+    graph
+}
+
+/// Functions the engine can start: every callback, plus any named function listed in `keep`.
+pub fn entry_points(script: &RawScript, keep: &[String]) -> Vec<usize> {
+    script
+        .functions
+        .iter()
+        .enumerate()
+        .filter(|(_, f)| f.event != 0 || f.name.as_ref().is_some_and(|n| keep.contains(n)))
+        .map(|(i, _)| i)
+        .collect()
+}
+
+/// Remove functions that can't be reached from any entry point and renumber calls to match.
+/// Returns the original indices of the removed functions.
+pub fn strip_unreachable(script: &mut RawScript, keep: &[String]) -> Vec<usize> {
+    let graph = build(script);
+    let reachable = graph.reachable_from(&entry_points(script, keep));
+    let mut new_ids = Vec::with_capacity(reachable.len());
+    let mut next = 0;
+    for is_reachable in &reachable {
+        new_ids.push(next);
+        if *is_reachable {
+            next += 1;
+        }
+    }
+
+    let functions = std::mem::take(&mut script.functions);
+    let mut removed = Vec::new();
+    for (i, mut function) in functions.into_iter().enumerate() {
+        if !reachable[i] {
+            removed.push(i);
+            continue;
+        }
+        // Reachable functions only ever call other reachable functions
+        for opcode in &mut function.code {
+            if let Opcode::CallById(id) = opcode {
+                if let Some(new_id) = new_ids.get(*id) {
+                    *id = *new_id;
+                }
+            }
+        }
+        script.functions.push(function);
+    }
+    removed
+}
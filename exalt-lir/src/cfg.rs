@@ -0,0 +1,109 @@
+use std::collections::HashMap;
+
+use crate::{Function, Opcode};
+
+/// A run of opcodes that is only entered at the top and only left at the bottom.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BasicBlock {
+    /// Index of the first opcode in the block.
+    pub start: usize,
+
+    /// Index one past the last opcode in the block.
+    pub end: usize,
+
+    /// Blocks that execution can continue to, fallthrough first.
+    pub successors: Vec<usize>,
+
+    pub predecessors: Vec<usize>,
+}
+
+impl BasicBlock {
+    /// Opcode indices covered by the block.
+    pub fn range(&self) -> std::ops::Range<usize> {
+        self.start..self.end
+    }
+}
+
+/// Control flow graph of a function. The entry block is always the first one.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Cfg {
+    pub blocks: Vec<BasicBlock>,
+}
+
+impl Cfg {
+    /// Index of the block that contains an opcode.
+    pub fn block_of(&self, opcode: usize) -> Option<usize> {
+        let index = self.blocks.partition_point(|b| b.end <= opcode);
+        self.blocks
+            .get(index)
+            .filter(|b| b.start <= opcode)
+            .map(|_| index)
+    }
+
+    /// For every block, whether it can be reached from the entry block.
+    pub fn reachable(&self) -> Vec<bool> {
+        let mut reachable = vec![false; self.blocks.len()];
+        let mut pending = Vec::new();
+        if !self.blocks.is_empty() {
+            pending.push(0);
+        }
+        while let Some(block) = pending.pop() {
+            if !reachable[block] {
+                reachable[block] = true;
+                pending.extend_from_slice(&self.blocks[block].successors);
+            }
+        }
+        reachable
+    }
+}
+
+/// Split a function's code into basic blocks.
+/// Blocks begin at labels and after any opcode that can jump or return.
+/// Jumps to labels that don't exist get no edge.
+pub fn build(function: &Function) -> Cfg {
+    let code = &function.code;
+    let mut starts = Vec::new();
+    for (i, opcode) in code.iter().enumerate() {
+        let after_branch =
+            i > 0 && (code[i - 1].label_target().is_some() || !code[i - 1].falls_through());
+        if i == 0 || after_branch || matches!(opcode, Opcode::Label(_)) {
+            starts.push(i);
+        }
+    }
+    let mut blocks: Vec<BasicBlock> = starts
+        .iter()
+        .enumerate()
+        .map(|(i, start)| BasicBlock {
+            start: *start,
+            end: starts.get(i + 1).copied().unwrap_or(code.len()),
+            successors: Vec::new(),
+            predecessors: Vec::new(),
+        })
+        .collect();
+
+    let label_blocks: HashMap<&str, usize> = blocks
+        .iter()
+        .enumerate()
+        .filter_map(|(i, block)| match &code[block.start] {
+            Opcode::Label(l) => Some((l.as_str(), i)),
+            _ => None,
+        })
+        .collect();
+    for i in 0..blocks.len() {
+        let last = &code[blocks[i].end - 1];
+        let mut successors = Vec::new();
+        if last.falls_through() && i + 1 < blocks.len() {
+            successors.push(i + 1);
+        }
+        if let Some(target) = last.label_target().and_then(|l| label_blocks.get(l)) {
+            if !successors.contains(target) {
+                successors.push(*target);
+            }
+        }
+        for successor in &successors {
+            blocks[*successor].predecessors.push(i);
+        }
+        blocks[i].successors = successors;
+    }
+    Cfg { blocks }
+}
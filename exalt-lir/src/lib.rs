@@ -1,7 +1,24 @@
-use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+
+use serde::de::Error as _;
+use serde::{Deserialize, Deserializer, Serialize};
 use strum_macros::EnumString;
 
-#[derive(Debug, Clone, Copy, EnumString, Deserialize, Serialize, PartialEq)]
+pub mod callgraph;
+pub mod cfg;
+mod platform;
+mod profile;
+mod stack;
+mod text;
+mod validate;
+
+pub use platform::{Endian, Platform};
+pub use profile::{profiles, GameProfile, HeaderLayout, OpcodeSet};
+pub use stack::{simulate_stack, StackAnalysis, StackError, ValueKind};
+pub use text::{TextCodec, TextEncoding, TextOptions};
+pub use validate::ValidationError;
+
+#[derive(Debug, Clone, Copy, EnumString, Deserialize, Serialize, PartialEq, Eq, Hash)]
 pub enum Game {
     FE9,
     FE10,
@@ -12,14 +29,75 @@ pub enum Game {
     FE15,
 }
 
-#[derive(Debug, Deserialize, Serialize, PartialEq)]
+/// Version of the layout RawScript is serialized with (JSON, YAML, RON, and MessagePack).
+///
+/// People keep disassembled scripts around and edit them by hand, so old files must keep loading:
+/// - Serialized names never change. If a Rust name changes, keep the old one with `#[serde(rename = "...")]`.
+/// - If a serialized name really has to change, keep accepting the old one with `#[serde(alias = "...")]`.
+/// - New fields need `#[serde(default)]` and new variants go at the end of their enum.
+/// - Anything that can't follow these rules (ex. changing what a variant holds) bumps the version.
+///
+/// The files in `tests/fixtures` were written by earlier versions and must keep loading.
+pub const SCHEMA_VERSION: u32 = 1;
+
+/// Scripts saved before the version was written out have the same layout as version 1.
+fn unversioned_schema() -> u32 {
+    1
+}
+
+fn deserialize_schema_version<'de, D: Deserializer<'de>>(deserializer: D) -> Result<u32, D::Error> {
+    let version = u32::deserialize(deserializer)?;
+    if version > SCHEMA_VERSION {
+        return Err(D::Error::custom(format!(
+            "script schema version {} is newer than this version of exalt supports ({})",
+            version, SCHEMA_VERSION
+        )));
+    }
+    Ok(version)
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize, PartialEq)]
 pub struct RawScript {
+    #[serde(
+        default = "unversioned_schema",
+        deserialize_with = "deserialize_schema_version"
+    )]
+    pub schema_version: u32,
+
     #[serde(default)]
     pub global_frame_size: usize,
     pub functions: Vec<Function>,
+
+    #[serde(default, skip_serializing_if = "ScriptQuirks::is_empty")]
+    pub quirks: ScriptQuirks,
 }
 
-#[derive(Debug, Deserialize, Serialize, PartialEq)]
+/// Places where a script's binary layout departs from its game's profile.
+///
+/// Some DLC and mini-game scripts were built with other revisions or padding.
+/// The disassembler records the differences here so the assembler can rebuild them byte for byte.
+#[derive(Debug, Clone, Default, Deserialize, Serialize, PartialEq)]
+pub struct ScriptQuirks {
+    /// Revision written to the header instead of the profile's.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub revision: Option<u32>,
+
+    /// Whether the last function is padded to a word boundary, if the profile gets it wrong.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pad_last_event: Option<bool>,
+
+    /// Encoding of the script's text, if it isn't the profile's.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub encoding: Option<TextEncoding>,
+}
+
+impl ScriptQuirks {
+    pub fn is_empty(&self) -> bool {
+        self.revision.is_none() && self.pad_last_event.is_none() && self.encoding.is_none()
+    }
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize, PartialEq)]
 pub struct Function {
     pub frame_size: usize,
     pub event: u8,
@@ -30,6 +108,132 @@ pub struct Function {
     pub name: Option<String>,
     pub args: Vec<CallbackArg>,
     pub code: Vec<Opcode>,
+
+    /// Notes keyed by opcode index. These are never written to the compiled script.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub comments: BTreeMap<usize, String>,
+
+    /// Address of each opcode in the script it was disassembled from, lined up with `code`.
+    /// Labels share the address of the opcode after them. Empty when the code came from anywhere else.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub offsets: Vec<usize>,
+}
+
+/// Opcode comments for a whole script, keyed by function index.
+pub type ScriptComments = BTreeMap<usize, BTreeMap<usize, String>>;
+
+impl RawScript {
+    /// Serialize to MessagePack. Fields are written by name, so the binary form follows
+    /// the same compatibility rules as the text formats (see SCHEMA_VERSION).
+    pub fn to_binary(&self) -> Result<Vec<u8>, rmp_serde::encode::Error> {
+        rmp_serde::to_vec_named(self)
+    }
+
+    pub fn from_binary(bytes: &[u8]) -> Result<Self, rmp_serde::decode::Error> {
+        rmp_serde::from_slice(bytes)
+    }
+
+    /// Remove the comments from every function.
+    pub fn take_comments(&mut self) -> ScriptComments {
+        self.functions
+            .iter_mut()
+            .map(|function| std::mem::take(&mut function.comments))
+            .enumerate()
+            .filter(|(_, comments)| !comments.is_empty())
+            .collect()
+    }
+
+    /// Attach comments to their functions.
+    /// Comments for functions or opcodes that do not exist are dropped.
+    pub fn apply_comments(&mut self, comments: ScriptComments) {
+        for (index, comments) in comments {
+            if let Some(function) = self.functions.get_mut(index) {
+                let len = function.code.len();
+                function
+                    .comments
+                    .extend(comments.into_iter().filter(|(opcode, _)| *opcode < len));
+            }
+        }
+    }
+
+    /// Names of engine functions called by the script that are missing from a database.
+    pub fn unknown_calls(&self, database: &FunctionDatabase) -> BTreeSet<&str> {
+        self.functions
+            .iter()
+            .flat_map(|function| function.code.iter())
+            .filter_map(|opcode| match opcode {
+                Opcode::CallByName(name, _) if !database.contains(name) => Some(name.as_str()),
+                _ => None,
+            })
+            .collect()
+    }
+}
+
+/// Names of functions that a game's engine is known to provide.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct FunctionDatabase(BTreeSet<String>);
+
+impl FunctionDatabase {
+    /// Read a list with one name per line. Blank lines and lines starting with '#' are skipped.
+    pub fn parse(text: &str) -> Self {
+        FunctionDatabase(
+            text.lines()
+                .map(str::trim)
+                .filter(|line| !line.is_empty() && !line.starts_with('#'))
+                .map(str::to_owned)
+                .collect(),
+        )
+    }
+
+    pub fn insert(&mut self, name: String) {
+        self.0.insert(name);
+    }
+
+    pub fn extend(&mut self, other: FunctionDatabase) {
+        self.0.extend(other.0);
+    }
+
+    pub fn contains(&self, name: &str) -> bool {
+        self.0.contains(name)
+    }
+
+    pub fn names(&self) -> impl Iterator<Item = &str> {
+        self.0.iter().map(|name| name.as_str())
+    }
+}
+
+/// Whether a function name is one the decompiler made up for a function with no name in the
+/// compiled script (ex. anonfn3 or anonfn_1f2e3d4c). Those functions are compiled without a name again.
+pub fn is_anonymous_function_name(name: &str) -> bool {
+    name.starts_with("anonfn")
+}
+
+/// Names for variables in a script, keyed by frame index.
+///
+/// Functions are keyed by their name in the compiled script (ex. "anonfn3" for unnamed functions).
+/// Callbacks are keyed by their position in the function table (ex. "callback5").
+/// Local names should be unique within a function.
+#[derive(Debug, Clone, Default, Deserialize, Serialize, PartialEq, Eq, Hash)]
+pub struct SymbolMap {
+    #[serde(default)]
+    pub globals: BTreeMap<usize, String>,
+
+    #[serde(default)]
+    pub functions: BTreeMap<String, BTreeMap<usize, String>>,
+}
+
+impl SymbolMap {
+    pub fn callback_key(index: usize) -> String {
+        format!("callback{}", index)
+    }
+
+    pub fn global(&self, frame_id: usize) -> Option<&str> {
+        self.globals.get(&frame_id).map(|v| v.as_str())
+    }
+
+    pub fn locals(&self, key: &str) -> Option<&BTreeMap<usize, String>> {
+        self.functions.get(key)
+    }
 }
 
 #[derive(Debug, Clone, Deserialize, Serialize, PartialEq)]
@@ -40,7 +244,7 @@ pub enum CallbackArg {
     Float(f32),
 }
 
-#[derive(Debug, Deserialize, Serialize, PartialEq)]
+#[derive(Debug, Clone, Deserialize, Serialize, PartialEq)]
 pub enum Opcode {
     Done,
     VarLoad(u16),
@@ -83,6 +287,7 @@ pub enum Opcode {
     RightShift,
     Equal,
     FloatEqual,
+    #[serde(alias = "ExlCall")]
     Exlcall,
     NotEqual,
     FloatNotEqual,
@@ -115,4 +320,87 @@ pub enum Opcode {
     StringNotEquals,
     Nop0x40,
     Assign,
+    /// An opcode byte the disassembler doesn't recognize and the raw bytes after it, emitted verbatim.
+    /// Since its operands can't be told apart from the code that follows, it runs to the end of the function.
+    Unknown(u8, Vec<u8>),
+}
+
+impl Opcode {
+    /// Label that a jump goes to.
+    pub fn label_target(&self) -> Option<&str> {
+        match self {
+            Opcode::Jump(l)
+            | Opcode::JumpNotZero(l)
+            | Opcode::JumpZero(l)
+            | Opcode::Or(l)
+            | Opcode::And(l) => Some(l),
+            _ => None,
+        }
+    }
+
+    /// Whether execution can continue to the next opcode.
+    pub fn falls_through(&self) -> bool {
+        !matches!(
+            self,
+            Opcode::Jump(_) | Opcode::Return | Opcode::ReturnFalse | Opcode::ReturnTrue
+        )
+    }
+
+    fn with_label_target(&self, name: String) -> Opcode {
+        match self {
+            Opcode::Jump(_) => Opcode::Jump(name),
+            Opcode::JumpNotZero(_) => Opcode::JumpNotZero(name),
+            Opcode::JumpZero(_) => Opcode::JumpZero(name),
+            Opcode::Or(_) => Opcode::Or(name),
+            Opcode::And(_) => Opcode::And(name),
+            _ => self.clone(),
+        }
+    }
+
+    /// Rewrite labels so that code from different sources (ex. the compiler and the disassembler) can be compared directly.
+    /// Unreferenced labels are dropped, runs of labels are merged, and names are assigned by order of appearance.
+    pub fn normalize_labels(code: &[Opcode]) -> Vec<Opcode> {
+        let referenced: HashSet<&str> = code.iter().filter_map(Opcode::label_target).collect();
+
+        // Labels that share an address collapse into the first one
+        let mut canonical: HashMap<&str, &str> = HashMap::new();
+        let mut run_start: Option<&str> = None;
+        for opcode in code {
+            match opcode {
+                Opcode::Label(l) if referenced.contains(l.as_str()) => {
+                    let first = *run_start.get_or_insert(l);
+                    canonical.insert(l, first);
+                }
+                Opcode::Label(_) => {}
+                _ => run_start = None,
+            }
+        }
+
+        let mut names: HashMap<String, String> = HashMap::new();
+        let mut name_of = |label: &str| -> String {
+            let label = canonical.get(label).copied().unwrap_or(label);
+            let next = names.len();
+            names
+                .entry(label.to_owned())
+                .or_insert_with(|| format!("l{}", next))
+                .clone()
+        };
+        let mut normalized = Vec::new();
+        for opcode in code {
+            match opcode {
+                Opcode::Label(l) => {
+                    if referenced.contains(l.as_str())
+                        && canonical.get(l.as_str()) == Some(&l.as_str())
+                    {
+                        normalized.push(Opcode::Label(name_of(l)));
+                    }
+                }
+                _ => match opcode.label_target() {
+                    Some(l) => normalized.push(opcode.with_label_target(name_of(l))),
+                    None => normalized.push(opcode.clone()),
+                },
+            }
+        }
+        normalized
+    }
 }
@@ -0,0 +1,41 @@
+/// Byte order of multi-byte values in a compiled script.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Endian {
+    Little,
+    Big,
+}
+
+/// How a game's platform lays out values in compiled scripts.
+///
+/// The assembler and disassembler read everything that varies between platforms from here,
+/// so a port with a different byte order or argument width only needs a new entry.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Platform {
+    /// Byte order of the script header, function table, function headers, and callback arguments.
+    pub header_endian: Endian,
+
+    /// Byte order of opcode operands.
+    pub code_endian: Endian,
+
+    /// Size in bytes of each callback argument.
+    pub arg_size: usize,
+
+    /// Whether callback arguments can be floats.
+    pub float_args: bool,
+}
+
+/// GameCube, Wii, and DS.
+pub(crate) const NINTENDO_LEGACY: Platform = Platform {
+    header_endian: Endian::Little,
+    code_endian: Endian::Big,
+    arg_size: 2,
+    float_args: false,
+};
+
+/// 3DS.
+pub(crate) const THREE_DS: Platform = Platform {
+    header_endian: Endian::Little,
+    code_endian: Endian::Big,
+    arg_size: 4,
+    float_args: true,
+};
@@ -0,0 +1,151 @@
+use crate::platform::{NINTENDO_LEGACY, THREE_DS};
+use crate::{Game, Platform, TextEncoding};
+
+/// Layout of the script header and function headers.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HeaderLayout {
+    /// Name embedded in the header, function names and arguments inline. Used up to FE12.
+    Gcn,
+
+    /// Name stored after the header, arguments found through a pointer. Used from FE13 on.
+    ThreeDs,
+}
+
+/// Which numbering of opcodes a game uses.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OpcodeSet {
+    Gcn,
+    Wii,
+    ThreeDs,
+}
+
+/// Everything the toolchain needs to know about one game's scripts.
+///
+/// Code that behaves differently between games should read it from here instead of matching on `Game`,
+/// so supporting a new revision of the format is a matter of adding a profile.
+#[derive(Debug, Clone, PartialEq)]
+pub struct GameProfile {
+    pub game: Game,
+    pub header: HeaderLayout,
+    pub opcodes: OpcodeSet,
+
+    /// Byte order and callback argument encoding.
+    pub platform: Platform,
+
+    /// Revision written to the header when assembling.
+    pub revision: u32,
+
+    /// Range of header revisions the disassembler accepts.
+    pub revisions: (u32, u32),
+
+    /// Whether text data comes before the function table instead of after the last function.
+    pub text_first: bool,
+
+    /// Whether the last function is padded to a word boundary.
+    pub pad_last_event: bool,
+
+    /// Whether the bytes between a string's terminator and the next word may hold junk worth keeping.
+    pub word_padding_junk: bool,
+
+    /// Whether assignments leave their value on the stack (FE9 only).
+    pub assignment_expressions: bool,
+
+    /// Whether `ReturnTrue` and `ReturnFalse` exist.
+    pub bool_returns: bool,
+
+    /// Whether callbacks without a return end with `ReturnFalse` rather than returning 0.
+    pub callback_bool_return: bool,
+
+    /// Whether a callback's arity is its argument count. Older formats store the count separately.
+    pub callback_arity_is_arg_count: bool,
+
+    /// Whether every named function keeps its name. Otherwise only qualified names (ex. `Foo::bar`) are kept.
+    pub keep_function_names: bool,
+
+    /// Largest function index a call can encode.
+    pub max_call_id: usize,
+
+    /// Whether the `exlcall` intrinsic is available.
+    /// Only the 3DS opcode set has it. GCN and Wii scripts use its byte (0x3A) for jumps.
+    pub exlcall: bool,
+
+    /// Encoding of strings unless a script says otherwise.
+    pub encoding: TextEncoding,
+}
+
+const fn legacy(game: Game, opcodes: OpcodeSet, revision: u32) -> GameProfile {
+    GameProfile {
+        game,
+        header: HeaderLayout::Gcn,
+        opcodes,
+        platform: NINTENDO_LEGACY,
+        revision,
+        revisions: (revision, revision),
+        text_first: true,
+        pad_last_event: true,
+        word_padding_junk: true,
+        assignment_expressions: false,
+        bool_returns: true,
+        callback_bool_return: false,
+        callback_arity_is_arg_count: false,
+        keep_function_names: true,
+        max_call_id: 0x7FFF,
+        exlcall: false,
+        encoding: TextEncoding::ShiftJis,
+    }
+}
+
+const fn three_ds(game: Game) -> GameProfile {
+    GameProfile {
+        game,
+        header: HeaderLayout::ThreeDs,
+        opcodes: OpcodeSet::ThreeDs,
+        platform: THREE_DS,
+        revision: 0x20110819,
+        revisions: (0x20080801, u32::MAX),
+        text_first: false,
+        pad_last_event: false,
+        word_padding_junk: false,
+        assignment_expressions: false,
+        bool_returns: true,
+        callback_bool_return: true,
+        callback_arity_is_arg_count: true,
+        keep_function_names: false,
+        max_call_id: 0x7FFF,
+        exlcall: true,
+        encoding: TextEncoding::ShiftJis,
+    }
+}
+
+static PROFILES: [GameProfile; 7] = [
+    GameProfile {
+        assignment_expressions: true,
+        bool_returns: false,
+        max_call_id: 0xFF,
+        ..legacy(Game::FE9, OpcodeSet::Gcn, 0x20041125)
+    },
+    legacy(Game::FE10, OpcodeSet::Wii, 0x20061024),
+    legacy(Game::FE11, OpcodeSet::Wii, 0x20061024),
+    legacy(Game::FE12, OpcodeSet::Wii, 0x20061024),
+    three_ds(Game::FE13),
+    three_ds(Game::FE14),
+    three_ds(Game::FE15),
+];
+
+/// Every known game.
+pub fn profiles() -> &'static [GameProfile] {
+    &PROFILES
+}
+
+impl Game {
+    pub fn profile(self) -> &'static GameProfile {
+        PROFILES
+            .iter()
+            .find(|profile| profile.game == self)
+            .expect("every game has a profile")
+    }
+
+    pub fn platform(self) -> Platform {
+        self.profile().platform
+    }
+}
@@ -0,0 +1,336 @@
+use std::collections::HashMap;
+use std::fmt::Display;
+
+use crate::Opcode;
+
+/// What kind of value a stack slot holds, as far as it can be told from the opcodes alone.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ValueKind {
+    Int,
+    Float,
+    Str,
+    /// Address of a variable or array element, for assignments and increments.
+    Address,
+    /// Variable contents, call results, or a mix of kinds where paths meet.
+    Unknown,
+}
+
+impl ValueKind {
+    fn join(self, other: ValueKind) -> ValueKind {
+        if self == other {
+            self
+        } else {
+            ValueKind::Unknown
+        }
+    }
+}
+
+/// A problem found by [`simulate_stack`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum StackError {
+    /// An opcode needs more values than the stack holds.
+    Underflow {
+        opcode: usize,
+        needed: usize,
+        available: usize,
+    },
+
+    /// Two paths reach the same opcode with different stack depths.
+    Mismatch {
+        opcode: usize,
+        first: usize,
+        second: usize,
+    },
+}
+
+impl StackError {
+    /// Index of the opcode the error is reported at.
+    pub fn opcode(&self) -> usize {
+        match self {
+            StackError::Underflow { opcode, .. } | StackError::Mismatch { opcode, .. } => *opcode,
+        }
+    }
+}
+
+impl Display for StackError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            StackError::Underflow {
+                opcode,
+                needed,
+                available,
+            } => write!(
+                f,
+                "opcode {} pops {} value(s) but the stack only has {}",
+                opcode, needed, available
+            ),
+            StackError::Mismatch {
+                opcode,
+                first,
+                second,
+            } => write!(
+                f,
+                "opcode {} is reached with stack depths {} and {}",
+                opcode, first, second
+            ),
+        }
+    }
+}
+
+/// Result of [`simulate_stack`].
+#[derive(Debug, Clone, Default)]
+pub struct StackAnalysis {
+    /// Stack contents before each opcode, bottom first. None if the opcode is unreachable.
+    pub states: Vec<Option<Vec<ValueKind>>>,
+
+    /// Errors sorted by opcode index.
+    pub errors: Vec<StackError>,
+}
+
+impl StackAnalysis {
+    /// Stack depth before an opcode, or None if it is unreachable.
+    pub fn depth(&self, index: usize) -> Option<usize> {
+        self.states.get(index)?.as_ref().map(|s| s.len())
+    }
+}
+
+/// Number of values an opcode pops and pushes.
+/// `arity` looks up the argument count of a function called by id.
+fn stack_effect<F>(code: &[Opcode], index: usize, arity: &F) -> (usize, usize)
+where
+    F: Fn(usize) -> Option<usize>,
+{
+    match &code[index] {
+        Opcode::VarLoad(_)
+        | Opcode::VarAddr(_)
+        | Opcode::GlobalVarLoad(_)
+        | Opcode::GlobalVarAddr(_)
+        | Opcode::IntLoad(_)
+        | Opcode::StrLoad(_)
+        | Opcode::FloatLoad(_) => (0, 1),
+        Opcode::ArrLoad(_)
+        | Opcode::PtrLoad(_)
+        | Opcode::ArrAddr(_)
+        | Opcode::PtrAddr(_)
+        | Opcode::GlobalArrLoad(_)
+        | Opcode::GlobalPtrLoad(_)
+        | Opcode::GlobalArrAddr(_)
+        | Opcode::GlobalPtrAddr(_)
+        | Opcode::Fix
+        | Opcode::Float
+        | Opcode::IntNegate
+        | Opcode::FloatNegate
+        | Opcode::BinaryNot
+        | Opcode::LogicalNot => (1, 1),
+        Opcode::Add
+        | Opcode::FloatAdd
+        | Opcode::Subtract
+        | Opcode::FloatSubtract
+        | Opcode::Multiply
+        | Opcode::FloatMultiply
+        | Opcode::Divide
+        | Opcode::FloatDivide
+        | Opcode::Modulo
+        | Opcode::BinaryOr
+        | Opcode::BinaryAnd
+        | Opcode::Xor
+        | Opcode::LeftShift
+        | Opcode::RightShift
+        | Opcode::Equal
+        | Opcode::FloatEqual
+        | Opcode::NotEqual
+        | Opcode::FloatNotEqual
+        | Opcode::LessThan
+        | Opcode::FloatLessThan
+        | Opcode::LessThanEqualTo
+        | Opcode::FloatLessThanEqualTo
+        | Opcode::GreaterThan
+        | Opcode::FloatGreaterThan
+        | Opcode::GreaterThanEqualTo
+        | Opcode::FloatGreaterThanEqualTo
+        | Opcode::StringEquals
+        | Opcode::StringNotEquals => (2, 1),
+        // Both keep their operand. Dereference loads the value at an address for a shorthand assignment
+        Opcode::Dereference | Opcode::Copy => (1, 2),
+        Opcode::Consume
+        | Opcode::Return
+        | Opcode::JumpZero(_)
+        | Opcode::JumpNotZero(_)
+        | Opcode::Inc
+        | Opcode::Dec => (1, 0),
+        Opcode::CompleteAssign | Opcode::Assign => (2, 0),
+        Opcode::CallById(id) => (arity(*id).unwrap_or(0), 1),
+        Opcode::CallByName(_, arity) => (*arity as usize, 1),
+        Opcode::Format(arity) => (*arity as usize, 0),
+        // The argument count is pushed last, after the call id and the arguments
+        Opcode::Exlcall => match index.checked_sub(1).map(|i| &code[i]) {
+            Some(Opcode::IntLoad(count)) if *count >= 0 => (*count as usize + 2, 1),
+            _ => (1, 1),
+        },
+        // Short circuits only pop when they don't jump
+        Opcode::Or(_) | Opcode::And(_) => (1, 0),
+        Opcode::Done
+        | Opcode::Nop0x3D
+        | Opcode::Nop0x40
+        | Opcode::Jump(_)
+        | Opcode::Yield
+        | Opcode::ReturnFalse
+        | Opcode::ReturnTrue
+        | Opcode::Label(_)
+        | Opcode::Unknown(_, _) => (0, 0),
+    }
+}
+
+/// Kinds of the values an opcode pushes, given the values it popped.
+fn pushed_kinds(opcode: &Opcode, popped: &[ValueKind], pushes: usize) -> Vec<ValueKind> {
+    match opcode {
+        Opcode::IntLoad(_)
+        | Opcode::Fix
+        | Opcode::IntNegate
+        | Opcode::BinaryNot
+        | Opcode::LogicalNot
+        | Opcode::Add
+        | Opcode::Subtract
+        | Opcode::Multiply
+        | Opcode::Divide
+        | Opcode::Modulo
+        | Opcode::BinaryOr
+        | Opcode::BinaryAnd
+        | Opcode::Xor
+        | Opcode::LeftShift
+        | Opcode::RightShift
+        | Opcode::Equal
+        | Opcode::FloatEqual
+        | Opcode::NotEqual
+        | Opcode::FloatNotEqual
+        | Opcode::LessThan
+        | Opcode::FloatLessThan
+        | Opcode::LessThanEqualTo
+        | Opcode::FloatLessThanEqualTo
+        | Opcode::GreaterThan
+        | Opcode::FloatGreaterThan
+        | Opcode::GreaterThanEqualTo
+        | Opcode::FloatGreaterThanEqualTo
+        | Opcode::StringEquals
+        | Opcode::StringNotEquals => vec![ValueKind::Int],
+        Opcode::FloatLoad(_)
+        | Opcode::Float
+        | Opcode::FloatNegate
+        | Opcode::FloatAdd
+        | Opcode::FloatSubtract
+        | Opcode::FloatMultiply
+        | Opcode::FloatDivide => vec![ValueKind::Float],
+        Opcode::StrLoad(_) => vec![ValueKind::Str],
+        Opcode::VarAddr(_)
+        | Opcode::ArrAddr(_)
+        | Opcode::PtrAddr(_)
+        | Opcode::GlobalVarAddr(_)
+        | Opcode::GlobalArrAddr(_)
+        | Opcode::GlobalPtrAddr(_) => vec![ValueKind::Address],
+        Opcode::Copy => vec![popped[0], popped[0]],
+        Opcode::Dereference => vec![popped[0], ValueKind::Unknown],
+        _ => vec![ValueKind::Unknown; pushes],
+    }
+}
+
+struct Simulation {
+    states: Vec<Option<Vec<ValueKind>>>,
+    errors: Vec<StackError>,
+    pending: Vec<usize>,
+}
+
+impl Simulation {
+    fn report(&mut self, error: StackError) {
+        if !self.errors.contains(&error) {
+            self.errors.push(error);
+        }
+    }
+
+    /// Merge the stack coming from one path into the state of an opcode, queueing it if anything changed.
+    fn flow(&mut self, index: usize, stack: Vec<ValueKind>) {
+        let changed = match &mut self.states[index] {
+            None => {
+                self.states[index] = Some(stack);
+                true
+            }
+            Some(existing) if existing.len() != stack.len() => {
+                let error = StackError::Mismatch {
+                    opcode: index,
+                    first: existing.len(),
+                    second: stack.len(),
+                };
+                self.report(error);
+                return;
+            }
+            Some(existing) => {
+                let mut changed = false;
+                for (current, incoming) in existing.iter_mut().zip(stack) {
+                    let joined = current.join(incoming);
+                    changed |= joined != *current;
+                    *current = joined;
+                }
+                changed
+            }
+        };
+        if changed {
+            self.pending.push(index);
+        }
+    }
+}
+
+/// Run a sequence of opcodes symbolically, starting from an empty stack.
+///
+/// Every path through the code is followed, so the result describes the stack before each reachable opcode.
+/// `arity` looks up the argument count of a function called by id. Unknown ids are treated as taking no arguments.
+pub fn simulate_stack<F>(code: &[Opcode], arity: F) -> StackAnalysis
+where
+    F: Fn(usize) -> Option<usize>,
+{
+    let labels: HashMap<&str, usize> = code
+        .iter()
+        .enumerate()
+        .filter_map(|(i, opcode)| match opcode {
+            Opcode::Label(l) => Some((l.as_str(), i)),
+            _ => None,
+        })
+        .collect();
+    let mut simulation = Simulation {
+        states: vec![None; code.len()],
+        errors: Vec::new(),
+        pending: Vec::new(),
+    };
+    if !code.is_empty() {
+        simulation.flow(0, Vec::new());
+    }
+    while let Some(index) = simulation.pending.pop() {
+        let mut stack = simulation.states[index].clone().unwrap_or_default();
+        let opcode = &code[index];
+        let (pops, pushes) = stack_effect(code, index, &arity);
+        if stack.len() < pops {
+            simulation.report(StackError::Underflow {
+                opcode: index,
+                needed: pops,
+                available: stack.len(),
+            });
+            continue;
+        }
+        let popped = stack.split_off(stack.len() - pops);
+        if let Some(target) = opcode.label_target().and_then(|l| labels.get(l)) {
+            // Short circuits leave their operand on the stack when they jump
+            let mut jumped = stack.clone();
+            if matches!(opcode, Opcode::Or(_) | Opcode::And(_)) {
+                jumped.extend_from_slice(&popped);
+            }
+            simulation.flow(*target, jumped);
+        }
+        stack.extend(pushed_kinds(opcode, &popped, pushes));
+        if opcode.falls_through() && index + 1 < code.len() {
+            simulation.flow(index + 1, stack);
+        }
+    }
+    simulation.errors.sort_by_key(|e| e.opcode());
+    StackAnalysis {
+        states: simulation.states,
+        errors: simulation.errors,
+    }
+}
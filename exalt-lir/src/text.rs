@@ -0,0 +1,93 @@
+use encoding_rs::SHIFT_JIS;
+use serde::{Deserialize, Serialize};
+use strum_macros::{Display, EnumString};
+
+/// Encoding of the strings in a compiled script.
+#[derive(
+    Debug, Clone, Copy, Default, PartialEq, Eq, Display, EnumString, Deserialize, Serialize,
+)]
+#[strum(serialize_all = "kebab-case")]
+#[serde(rename_all = "kebab-case")]
+pub enum TextEncoding {
+    /// Used by every official script.
+    #[default]
+    ShiftJis,
+
+    /// Used by some localized or modified scripts. Holds text that Shift-JIS can't.
+    Utf8,
+}
+
+/// An encoding along with what to do about text that doesn't fit it.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct TextCodec {
+    pub encoding: TextEncoding,
+
+    /// Replace bad text instead of failing.
+    /// Malformed bytes decode to U+FFFD and characters the encoding lacks encode as '?'.
+    pub lossy: bool,
+}
+
+impl TextCodec {
+    /// Decode a string without its null terminator. Fails on malformed bytes unless lossy.
+    pub fn decode(&self, bytes: &[u8]) -> Option<String> {
+        match self.encoding {
+            TextEncoding::ShiftJis => {
+                let (text, _, malformed) = SHIFT_JIS.decode(bytes);
+                (!malformed || self.lossy).then(|| text.into_owned())
+            }
+            TextEncoding::Utf8 => match std::str::from_utf8(bytes) {
+                Ok(text) => Some(text.to_owned()),
+                Err(_) if self.lossy => Some(String::from_utf8_lossy(bytes).into_owned()),
+                Err(_) => None,
+            },
+        }
+    }
+
+    /// Encode a string without a null terminator. Fails on characters the encoding lacks unless lossy.
+    pub fn encode(&self, text: &str) -> Option<Vec<u8>> {
+        match self.encoding {
+            TextEncoding::ShiftJis => {
+                let (bytes, _, unmappable) = SHIFT_JIS.encode(text);
+                if !unmappable {
+                    return Some(bytes.into_owned());
+                }
+                if !self.lossy {
+                    return None;
+                }
+                // encoding_rs writes unmappable characters as HTML escapes, so replace them one at a time instead
+                let mut bytes = Vec::new();
+                let mut buffer = [0; 4];
+                for c in text.chars() {
+                    let (encoded, _, unmappable) = SHIFT_JIS.encode(c.encode_utf8(&mut buffer));
+                    if unmappable {
+                        bytes.push(b'?');
+                    } else {
+                        bytes.extend_from_slice(&encoded);
+                    }
+                }
+                Some(bytes)
+            }
+            TextEncoding::Utf8 => Some(text.as_bytes().to_vec()),
+        }
+    }
+}
+
+/// Text settings for reading or writing a script. The defaults use the script's own encoding strictly.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct TextOptions {
+    /// Encoding to use instead of the one the script or game calls for.
+    pub encoding: Option<TextEncoding>,
+
+    /// Replace text that doesn't fit the encoding instead of failing.
+    pub lossy: bool,
+}
+
+impl TextOptions {
+    /// Settle on a codec, falling back to the given encoding unless one was picked.
+    pub fn codec(&self, fallback: TextEncoding) -> TextCodec {
+        TextCodec {
+            encoding: self.encoding.unwrap_or(fallback),
+            lossy: self.lossy,
+        }
+    }
+}
@@ -0,0 +1,162 @@
+use std::collections::HashMap;
+use std::fmt::Display;
+
+use crate::stack::{simulate_stack, StackError};
+use crate::{Function, Game, Opcode, RawScript};
+
+/// A broken structural invariant found by [`RawScript::validate`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ValidationError {
+    /// Index of the function in the function table.
+    pub function: usize,
+
+    /// Index of the offending opcode, if the problem is with a specific one.
+    pub opcode: Option<usize>,
+
+    pub message: String,
+}
+
+impl Display for ValidationError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.opcode {
+            Some(opcode) => write!(
+                f,
+                "function {}, opcode {}: {}",
+                self.function, opcode, self.message
+            ),
+            None => write!(f, "function {}: {}", self.function, self.message),
+        }
+    }
+}
+
+// Largest frame index that fits in the short form of a variable opcode
+const MAX_FRAME_ID: u16 = 0x7FFF;
+
+fn frame_id(opcode: &Opcode) -> Option<u16> {
+    match opcode {
+        Opcode::VarLoad(id)
+        | Opcode::ArrLoad(id)
+        | Opcode::PtrLoad(id)
+        | Opcode::VarAddr(id)
+        | Opcode::ArrAddr(id)
+        | Opcode::PtrAddr(id)
+        | Opcode::GlobalVarLoad(id)
+        | Opcode::GlobalArrLoad(id)
+        | Opcode::GlobalPtrLoad(id)
+        | Opcode::GlobalVarAddr(id)
+        | Opcode::GlobalArrAddr(id)
+        | Opcode::GlobalPtrAddr(id) => Some(*id),
+        _ => None,
+    }
+}
+
+fn check_stack_depth(script: &RawScript, function: &Function, errors: &mut Vec<(usize, String)>) {
+    let analysis = simulate_stack(&function.code, |id| {
+        script.functions.get(id).map(|f| f.arity as usize)
+    });
+    // Compiled scripts can legitimately reach a label with different depths, e.g. a continue inside a match
+    for error in analysis.errors {
+        if let StackError::Underflow {
+            opcode,
+            needed,
+            available,
+        } = error
+        {
+            errors.push((
+                opcode,
+                format!(
+                    "{:?} pops {} value(s) but the stack only has {}",
+                    function.code[opcode], needed, available
+                ),
+            ));
+        }
+    }
+}
+
+fn check_labels(function: &Function, errors: &mut Vec<(usize, String)>) {
+    let mut definitions: HashMap<&str, usize> = HashMap::new();
+    for (i, opcode) in function.code.iter().enumerate() {
+        if let Opcode::Label(l) = opcode {
+            if definitions.insert(l, i).is_some() {
+                errors.push((i, format!("label '{}' is defined more than once", l)));
+            }
+        }
+    }
+    for (i, opcode) in function.code.iter().enumerate() {
+        if let Some(l) = opcode.label_target() {
+            if !definitions.contains_key(l) {
+                errors.push((i, format!("jump to undefined label '{}'", l)));
+            }
+        }
+    }
+}
+
+fn check_operands(
+    script: &RawScript,
+    function: &Function,
+    game: Game,
+    errors: &mut Vec<(usize, String)>,
+) {
+    for (i, opcode) in function.code.iter().enumerate() {
+        if let Some(id) = frame_id(opcode) {
+            if id > MAX_FRAME_ID {
+                errors.push((i, format!("frame index {} is too large", id)));
+            }
+        }
+        if let Opcode::CallById(id) = opcode {
+            if *id >= script.functions.len() {
+                errors.push((i, format!("call to function {} which does not exist", id)));
+            } else if *id > game.profile().max_call_id {
+                errors.push((i, format!("function id {} is too large for {:?}", id, game)));
+            }
+        }
+    }
+}
+
+impl RawScript {
+    /// Check structural invariants that the assembler relies on.
+    /// Returns every problem found. An empty list means the script is safe to assemble.
+    pub fn validate(&self, game: Game) -> Vec<ValidationError> {
+        let mut errors = Vec::new();
+        for (index, function) in self.functions.iter().enumerate() {
+            if function.event == 0 && !function.args.is_empty() {
+                errors.push(ValidationError {
+                    function: index,
+                    opcode: None,
+                    message: "functions with event 0 cannot have arguments".to_owned(),
+                });
+            }
+            // Newer formats read callback arguments using the arity as a count
+            if function.event != 0
+                && game.profile().callback_arity_is_arg_count
+                && function.arity as usize != function.args.len()
+            {
+                errors.push(ValidationError {
+                    function: index,
+                    opcode: None,
+                    message: format!(
+                        "arity is {} but there are {} arguments",
+                        function.arity,
+                        function.args.len()
+                    ),
+                });
+            }
+
+            let mut opcode_errors = Vec::new();
+            check_labels(function, &mut opcode_errors);
+            check_operands(self, function, game, &mut opcode_errors);
+            check_stack_depth(self, function, &mut opcode_errors);
+            opcode_errors.sort_by_key(|(opcode, _)| *opcode);
+            errors.extend(
+                opcode_errors
+                    .into_iter()
+                    .map(|(opcode, message)| ValidationError {
+                        function: index,
+                        opcode: Some(opcode),
+                        message,
+                    }),
+            );
+        }
+        errors
+    }
+}
@@ -0,0 +1,75 @@
+use exalt_lir::{CallbackArg, Opcode, RawScript, TextEncoding, SCHEMA_VERSION};
+
+const UNVERSIONED: &str = include_str!("fixtures/unversioned.yml");
+const VERSION_1: &str = include_str!("fixtures/v1.json");
+
+#[test]
+fn unversioned_yaml_loads_as_version_1() {
+    let script: RawScript = serde_yaml::from_str(UNVERSIONED).unwrap();
+    assert_eq!(script.schema_version, 1);
+    assert_eq!(script.global_frame_size, 1);
+    assert!(script.quirks.is_empty());
+    assert_eq!(script.functions.len(), 2);
+
+    let function = &script.functions[0];
+    assert_eq!(function.code[3], Opcode::JumpZero("l0".to_owned()));
+    assert_eq!(function.code[4], Opcode::StrLoad("hi".to_owned()));
+    assert!(function.comments.is_empty());
+    assert!(function.offsets.is_empty());
+
+    let callback = &script.functions[1];
+    assert_eq!(callback.event, 16);
+    assert_eq!(
+        callback.args,
+        vec![
+            CallbackArg::Int(1),
+            CallbackArg::Int(1),
+            CallbackArg::Int(0)
+        ]
+    );
+}
+
+#[test]
+fn version_1_json_loads() {
+    let script: RawScript = serde_json::from_str(VERSION_1).unwrap();
+    assert_eq!(script.schema_version, 1);
+    assert_eq!(script.quirks.revision, Some(0x20090819));
+    assert_eq!(script.quirks.encoding, Some(TextEncoding::Utf8));
+
+    let function = &script.functions[0];
+    assert_eq!(function.name.as_deref(), Some("ExlCallTest"));
+    assert_eq!(function.code[2], Opcode::Exlcall);
+    assert_eq!(function.code[4], Opcode::Unknown(0xFA, vec![1, 2]));
+    assert_eq!(function.comments[&2], "call into the exl plugin");
+    assert_eq!(function.offsets, vec![0, 2, 4, 5, 6]);
+}
+
+#[test]
+fn fixtures_survive_a_round_trip() {
+    for script in [
+        serde_yaml::from_str::<RawScript>(UNVERSIONED).unwrap(),
+        serde_json::from_str::<RawScript>(VERSION_1).unwrap(),
+    ] {
+        let yaml = serde_yaml::to_string(&script).unwrap();
+        assert_eq!(serde_yaml::from_str::<RawScript>(&yaml).unwrap(), script);
+        let json = serde_json::to_string(&script).unwrap();
+        assert_eq!(serde_json::from_str::<RawScript>(&json).unwrap(), script);
+        assert_eq!(
+            RawScript::from_binary(&script.to_binary().unwrap()).unwrap(),
+            script
+        );
+    }
+}
+
+#[test]
+fn newer_versions_are_rejected() {
+    let newer = VERSION_1.replacen(
+        "\"schema_version\": 1",
+        &format!("\"schema_version\": {}", SCHEMA_VERSION + 1),
+        1,
+    );
+    let err = serde_json::from_str::<RawScript>(&newer).unwrap_err();
+    assert!(err
+        .to_string()
+        .contains("newer than this version of exalt supports"));
+}
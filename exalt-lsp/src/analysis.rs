@@ -0,0 +1,404 @@
+use std::collections::HashMap;
+use std::ops::Range;
+use std::path::PathBuf;
+
+use exalt_ast::surface::Decl;
+use exalt_ast::{FileId, Location};
+use exalt_compiler::{
+    classify_tokens, CompilerError, CompilerLog, ParseRequest, ParseResult, TokenClass,
+};
+use exalt_completions::{CompletionServer, SymbolIndex};
+use exalt_lir::{FunctionDatabase, Game};
+use lsp_types::{
+    CodeAction, CodeActionKind, CodeActionOrCommand, Diagnostic, DiagnosticRelatedInformation,
+    DiagnosticSeverity, DocumentSymbol, Position, SemanticToken, SemanticTokenType, SymbolKind,
+    TextEdit, Url, WorkspaceEdit,
+};
+
+/// The main file is always the first one added to the log.
+pub const MAIN_FILE_ID: FileId = 0;
+
+/// Maps byte offsets in a document to LSP positions.
+pub struct LineIndex {
+    text: String,
+    line_starts: Vec<usize>,
+}
+
+impl LineIndex {
+    pub fn new(text: &str) -> Self {
+        let mut line_starts = vec![0];
+        line_starts.extend(text.match_indices('\n').map(|(i, _)| i + 1));
+        LineIndex {
+            text: text.to_owned(),
+            line_starts,
+        }
+    }
+
+    pub fn position(&self, offset: usize) -> Position {
+        let offset = offset.min(self.text.len());
+        let line = match self.line_starts.binary_search(&offset) {
+            Ok(line) => line,
+            Err(next) => next - 1,
+        };
+        let start = self.line_starts[line];
+        // LSP columns are measured in UTF-16 code units
+        let character = self.text[start..offset]
+            .chars()
+            .map(|c| c.len_utf16())
+            .sum::<usize>();
+        Position::new(line as u32, character as u32)
+    }
+
+    pub fn offset(&self, position: Position) -> usize {
+        let start = match self.line_starts.get(position.line as usize) {
+            Some(start) => *start,
+            None => return self.text.len(),
+        };
+        let mut units = 0;
+        for (i, c) in self.text[start..].char_indices() {
+            if units >= position.character as usize || c == '\n' {
+                return start + i;
+            }
+            units += c.len_utf16();
+        }
+        self.text.len()
+    }
+
+    pub fn range(&self, range: &Range<usize>) -> lsp_types::Range {
+        lsp_types::Range::new(self.position(range.start), self.position(range.end))
+    }
+
+    /// The identifier around an offset, if there is one.
+    pub fn identifier_at(&self, offset: usize) -> Option<&str> {
+        let offset = offset.min(self.text.len());
+        let is_identifier_char = |c: char| c.is_alphanumeric() || c == '_' || c == ':';
+        let start = self.text[..offset]
+            .char_indices()
+            .rev()
+            .take_while(|(_, c)| is_identifier_char(*c))
+            .last()
+            .map(|(i, _)| i)
+            .unwrap_or(offset);
+        let end = self.text[offset..]
+            .char_indices()
+            .find(|(_, c)| !is_identifier_char(*c))
+            .map(|(i, _)| offset + i)
+            .unwrap_or(self.text.len());
+        let identifier = &self.text[start..end];
+        (!identifier.is_empty()).then_some(identifier)
+    }
+
+    /// Everything in the document before an offset.
+    pub fn text_before(&self, offset: usize) -> &str {
+        &self.text[..offset.min(self.text.len())]
+    }
+}
+
+/// Token types reported for highlighting, indexed by [`semantic_tokens`].
+pub const TOKEN_TYPES: [SemanticTokenType; 6] = [
+    SemanticTokenType::KEYWORD,
+    SemanticTokenType::VARIABLE,
+    SemanticTokenType::NUMBER,
+    SemanticTokenType::STRING,
+    SemanticTokenType::COMMENT,
+    SemanticTokenType::OPERATOR,
+];
+
+/// Highlighting for a document, straight from the lexer so it works even if the document doesn't parse.
+pub fn semantic_tokens(index: &LineIndex) -> Vec<SemanticToken> {
+    let mut tokens = Vec::new();
+    let mut previous = Position::new(0, 0);
+    for token in classify_tokens(&index.text) {
+        let token_type = match token.class {
+            TokenClass::Keyword => 0,
+            TokenClass::Identifier => 1,
+            TokenClass::Number => 2,
+            TokenClass::String => 3,
+            TokenClass::Comment => 4,
+            TokenClass::Operator => 5,
+        };
+        // Tokens can't span lines, so anything that does is cut off at the end of its first line
+        let text = &index.text[token.span.clone()];
+        let text = text.split('\n').next().unwrap_or_default();
+        let start = index.position(token.span.start);
+        let delta_start = if start.line == previous.line {
+            start.character - previous.character
+        } else {
+            start.character
+        };
+        tokens.push(SemanticToken {
+            delta_line: start.line - previous.line,
+            delta_start,
+            length: text.encode_utf16().count() as u32,
+            token_type,
+            token_modifiers_bitset: 0,
+        });
+        previous = start;
+    }
+    tokens
+}
+
+/// Results of analyzing a document that are kept around between edits.
+#[derive(Default)]
+pub struct Analysis {
+    pub completions: CompletionServer,
+    pub navigation: SymbolIndex,
+    pub symbols: Vec<DocumentSymbol>,
+    // Every file in the parse by file ID, so locations in included files can be shown too
+    files: Vec<(Option<Url>, LineIndex)>,
+}
+
+impl Analysis {
+    /// Convert a location from the analyzed parse to one an editor can open.
+    /// Locations in the document itself are reported against its own URI.
+    pub fn lsp_location(&self, uri: &Url, location: &Location) -> Option<lsp_types::Location> {
+        let (file_id, range) = match location {
+            Location::Source(file_id, range) => (*file_id, range),
+            _ => return None,
+        };
+        let (file_uri, index) = self.files.get(file_id)?;
+        let file_uri = if file_id == MAIN_FILE_ID {
+            uri.clone()
+        } else {
+            file_uri.clone()?
+        };
+        Some(lsp_types::Location::new(file_uri, index.range(range)))
+    }
+}
+
+fn main_file_range(location: Option<&Location>) -> Option<&Range<usize>> {
+    match location {
+        Some(Location::Source(file_id, range)) if *file_id == MAIN_FILE_ID => Some(range),
+        _ => None,
+    }
+}
+
+/// Where a problem in an included file actually is, since it can only be shown at the top of the document.
+fn included_file_location(
+    log: &CompilerLog,
+    location: Option<&Location>,
+) -> Option<lsp_types::Location> {
+    let (file_id, range) = match location {
+        Some(Location::Source(file_id, range)) if *file_id != MAIN_FILE_ID => (*file_id, range),
+        _ => return None,
+    };
+    let uri = Url::from_file_path(log.file(file_id)?).ok()?;
+    let span = log.span(file_id, range)?;
+    let range = lsp_types::Range::new(
+        Position::new(span.line as u32 - 1, span.column as u32 - 1),
+        Position::new(span.end_line as u32 - 1, span.end_column as u32 - 1),
+    );
+    Some(lsp_types::Location::new(uri, range))
+}
+
+fn collect_diagnostics(log: &CompilerLog, index: &LineIndex) -> Vec<Diagnostic> {
+    let errors = log.errors.iter().map(|e| {
+        // Quick fixes ride along in the diagnostic so code actions don't need the log
+        let fixes: Vec<TextEdit> = e
+            .suggestions()
+            .iter()
+            .filter_map(|s| {
+                main_file_range(Some(&s.location))
+                    .map(|r| TextEdit::new(index.range(r), s.replacement.clone()))
+            })
+            .collect();
+        (
+            e.location(),
+            e.message().to_string(),
+            DiagnosticSeverity::ERROR,
+            fixes,
+        )
+    });
+    let warnings = log.warnings.iter().map(|w| {
+        (
+            Some(w.location()),
+            w.message().to_string(),
+            DiagnosticSeverity::WARNING,
+            Vec::new(),
+        )
+    });
+    errors
+        .chain(warnings)
+        .map(|(location, message, severity, fixes)| Diagnostic {
+            // Problems in included files are reported at the top of the document
+            range: main_file_range(location)
+                .map(|r| index.range(r))
+                .unwrap_or_default(),
+            severity: Some(severity),
+            source: Some("exalt".to_owned()),
+            related_information: included_file_location(log, location).map(|location| {
+                vec![DiagnosticRelatedInformation {
+                    location,
+                    message: message.clone(),
+                }]
+            }),
+            message,
+            data: (!fixes.is_empty())
+                .then(|| serde_json::to_value(fixes).ok())
+                .flatten(),
+            ..Default::default()
+        })
+        .collect()
+}
+
+/// Quick fix actions for the fixes stored in diagnostics by [`collect_diagnostics`].
+pub fn quick_fixes(uri: &Url, diagnostics: &[Diagnostic]) -> Vec<CodeActionOrCommand> {
+    let mut actions = Vec::new();
+    for diagnostic in diagnostics {
+        let fixes: Vec<TextEdit> = match &diagnostic.data {
+            Some(data) => serde_json::from_value(data.clone()).unwrap_or_default(),
+            None => continue,
+        };
+        let preferred = fixes.len() == 1;
+        for fix in fixes {
+            let title = if fix.range.start == fix.range.end {
+                format!("Insert '{}'", fix.new_text)
+            } else {
+                format!("Replace with '{}'", fix.new_text)
+            };
+            let changes = HashMap::from([(uri.clone(), vec![fix])]);
+            actions.push(CodeActionOrCommand::CodeAction(CodeAction {
+                title,
+                kind: Some(CodeActionKind::QUICKFIX),
+                diagnostics: Some(vec![diagnostic.clone()]),
+                edit: Some(WorkspaceEdit::new(changes)),
+                is_preferred: Some(preferred),
+                ..Default::default()
+            }));
+        }
+    }
+    actions
+}
+
+#[allow(deprecated)]
+fn make_symbol(
+    index: &LineIndex,
+    name: &str,
+    kind: SymbolKind,
+    range: &Range<usize>,
+    selection: Option<&Location>,
+) -> DocumentSymbol {
+    let range = index.range(range);
+    DocumentSymbol {
+        name: name.to_owned(),
+        detail: None,
+        kind,
+        tags: None,
+        deprecated: None,
+        range,
+        selection_range: main_file_range(selection)
+            .map(|r| index.range(r))
+            .unwrap_or(range),
+        children: None,
+    }
+}
+
+fn collect_symbols(decls: &[Decl], index: &LineIndex) -> Vec<DocumentSymbol> {
+    let mut symbols = Vec::new();
+    for decl in decls {
+        let range = match main_file_range(Some(decl.location())) {
+            Some(range) => range,
+            None => continue,
+        };
+        let (name, kind) = match decl {
+            Decl::Constant { identifier, .. } => (identifier, SymbolKind::CONSTANT),
+            Decl::Enum { identifier, .. } => (identifier, SymbolKind::ENUM),
+            Decl::Function { identifier, .. }
+            | Decl::Inline { identifier, .. }
+            | Decl::FunctionExtern { identifier, .. }
+            | Decl::FunctionAlias { identifier, .. } => (identifier, SymbolKind::FUNCTION),
+            Decl::Global { identifier, .. } | Decl::GlobalExtern { identifier, .. } => {
+                (identifier, SymbolKind::VARIABLE)
+            }
+            Decl::Callback { .. } => {
+                symbols.push(make_symbol(
+                    index,
+                    "callback",
+                    SymbolKind::EVENT,
+                    range,
+                    None,
+                ));
+                continue;
+            }
+            Decl::Namespace {
+                identifier, decls, ..
+            } => {
+                let mut symbol = make_symbol(
+                    index,
+                    &identifier.value,
+                    SymbolKind::NAMESPACE,
+                    range,
+                    Some(&identifier.location),
+                );
+                symbol.children = Some(collect_symbols(decls, index));
+                symbols.push(symbol);
+                continue;
+            }
+            Decl::Include { .. } => continue,
+        };
+        symbols.push(make_symbol(
+            index,
+            &name.value,
+            kind,
+            range,
+            Some(&name.location),
+        ));
+    }
+    symbols
+}
+
+/// Parse a document and report its diagnostics.
+/// Returns new analysis results only if the document parsed successfully.
+pub fn analyze(
+    game: Game,
+    path: PathBuf,
+    text: &str,
+    index: &LineIndex,
+    functions: &FunctionDatabase,
+) -> (Vec<Diagnostic>, Option<Analysis>) {
+    let request = ParseRequest {
+        game,
+        target: path,
+        source: Some(text.to_owned()),
+        additional_includes: vec![],
+    };
+    match exalt_compiler::parse(&request) {
+        Ok(ParseResult {
+            parse_tree,
+            symbol_table,
+            log,
+            ..
+        }) => {
+            let mut completions = CompletionServer::from_parse_result(&parse_tree, &symbol_table);
+            completions.add_names(functions.names());
+            let files = (0..log.peek_file_id())
+                .map(|file_id| {
+                    let uri = log
+                        .file(file_id)
+                        .and_then(|name| Url::from_file_path(name).ok());
+                    let source = log.source(file_id).unwrap_or_default();
+                    (uri, LineIndex::new(&source))
+                })
+                .collect();
+            (
+                collect_diagnostics(&log, index),
+                Some(Analysis {
+                    completions,
+                    navigation: SymbolIndex::from_parse_result(&parse_tree, &symbol_table),
+                    symbols: collect_symbols(&parse_tree.0, index),
+                    files,
+                }),
+            )
+        }
+        Err(CompilerError::ParseError(log)) => (collect_diagnostics(&log, index), None),
+        Err(err) => (
+            vec![Diagnostic {
+                severity: Some(DiagnosticSeverity::ERROR),
+                source: Some("exalt".to_owned()),
+                message: err.to_string(),
+                ..Default::default()
+            }],
+            None,
+        ),
+    }
+}
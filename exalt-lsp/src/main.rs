@@ -0,0 +1,359 @@
+mod analysis;
+
+use std::collections::HashMap;
+use std::path::PathBuf;
+
+use analysis::{Analysis, LineIndex, MAIN_FILE_ID};
+use anyhow::Context;
+use clap::Parser;
+use exalt_completions::{Completion as CompletionSuggestion, CompletionKind, ParameterHint};
+use exalt_lir::{FunctionDatabase, Game};
+use lsp_server::{Connection, Message, Notification, Request, RequestId, Response};
+use lsp_types::notification::{
+    DidChangeTextDocument, DidCloseTextDocument, DidOpenTextDocument, PublishDiagnostics,
+};
+use lsp_types::request::{
+    CodeActionRequest, Completion, DocumentSymbolRequest, GotoDefinition, HoverRequest, References,
+    SemanticTokensFullRequest, SignatureHelpRequest,
+};
+use lsp_types::{
+    CodeActionProviderCapability, CompletionItem, CompletionItemKind, CompletionOptions,
+    CompletionResponse, DocumentSymbolResponse, Documentation, GotoDefinitionResponse, Hover,
+    HoverContents, HoverProviderCapability, MarkupContent, MarkupKind, OneOf, ParameterInformation,
+    ParameterLabel, PublishDiagnosticsParams, SemanticTokens, SemanticTokensFullOptions,
+    SemanticTokensLegend, SemanticTokensOptions, SemanticTokensResult,
+    SemanticTokensServerCapabilities, ServerCapabilities, SignatureHelp, SignatureHelpOptions,
+    SignatureInformation, TextDocumentSyncCapability, TextDocumentSyncKind, Url,
+};
+
+#[derive(Parser)]
+struct Args {
+    #[clap(short, long, value_name = "GAME")]
+    game: Game,
+
+    /// Text file listing known engine functions to suggest, one per line
+    #[clap(long)]
+    functions: Option<PathBuf>,
+}
+
+struct Document {
+    index: LineIndex,
+    // Kept from the last successful parse so completions still work while the user is typing
+    analysis: Analysis,
+}
+
+struct Server {
+    game: Game,
+    functions: FunctionDatabase,
+    connection: Connection,
+    documents: HashMap<Url, Document>,
+}
+
+impl Server {
+    fn run(&mut self) -> anyhow::Result<()> {
+        while let Ok(message) = self.connection.receiver.recv() {
+            match message {
+                Message::Request(request) => {
+                    if self.connection.handle_shutdown(&request)? {
+                        return Ok(());
+                    }
+                    self.handle_request(request)?;
+                }
+                Message::Notification(notification) => self.handle_notification(notification)?,
+                Message::Response(_) => {}
+            }
+        }
+        Ok(())
+    }
+
+    fn handle_request(&mut self, request: Request) -> anyhow::Result<()> {
+        let response = match request.method.as_str() {
+            <Completion as lsp_types::request::Request>::METHOD => {
+                let (id, params) = extract_request::<Completion>(request)?;
+                let position = params.text_document_position;
+                let items = self
+                    .documents
+                    .get(&position.text_document.uri)
+                    .map(|doc| {
+                        let offset = doc.index.offset(position.position);
+                        doc.analysis
+                            .completions
+                            .suggest_completions(doc.index.text_before(offset))
+                            .into_iter()
+                            .map(completion_item)
+                            .collect()
+                    })
+                    .unwrap_or_default();
+                Response::new_ok(id, CompletionResponse::Array(items))
+            }
+            <SignatureHelpRequest as lsp_types::request::Request>::METHOD => {
+                let (id, params) = extract_request::<SignatureHelpRequest>(request)?;
+                let position = params.text_document_position_params;
+                let help = self
+                    .documents
+                    .get(&position.text_document.uri)
+                    .and_then(|doc| {
+                        let offset = doc.index.offset(position.position);
+                        doc.analysis
+                            .completions
+                            .parameter_hint(doc.index.text_before(offset))
+                    })
+                    .map(signature_help);
+                Response::new_ok(id, help)
+            }
+            <SemanticTokensFullRequest as lsp_types::request::Request>::METHOD => {
+                let (id, params) = extract_request::<SemanticTokensFullRequest>(request)?;
+                let tokens = self.documents.get(&params.text_document.uri).map(|doc| {
+                    SemanticTokensResult::Tokens(SemanticTokens {
+                        result_id: None,
+                        data: analysis::semantic_tokens(&doc.index),
+                    })
+                });
+                Response::new_ok(id, tokens)
+            }
+            <HoverRequest as lsp_types::request::Request>::METHOD => {
+                let (id, params) = extract_request::<HoverRequest>(request)?;
+                let position = params.text_document_position_params;
+                let hover = self
+                    .documents
+                    .get(&position.text_document.uri)
+                    .and_then(|doc| {
+                        let offset = doc.index.offset(position.position);
+                        let name = doc.index.identifier_at(offset)?;
+                        doc.analysis.completions.hover(name)
+                    })
+                    .map(hover_contents);
+                Response::new_ok(id, hover)
+            }
+            <GotoDefinition as lsp_types::request::Request>::METHOD => {
+                let (id, params) = extract_request::<GotoDefinition>(request)?;
+                let position = params.text_document_position_params;
+                let uri = position.text_document.uri;
+                let location = self.documents.get(&uri).and_then(|doc| {
+                    let offset = doc.index.offset(position.position);
+                    let definition = doc.analysis.navigation.definition(MAIN_FILE_ID, offset)?;
+                    doc.analysis.lsp_location(&uri, &definition)
+                });
+                Response::new_ok(id, location.map(GotoDefinitionResponse::Scalar))
+            }
+            <References as lsp_types::request::Request>::METHOD => {
+                let (id, params) = extract_request::<References>(request)?;
+                let position = params.text_document_position;
+                let uri = position.text_document.uri;
+                let locations: Vec<lsp_types::Location> = self
+                    .documents
+                    .get(&uri)
+                    .map(|doc| {
+                        let offset = doc.index.offset(position.position);
+                        let references = doc.analysis.navigation.references(MAIN_FILE_ID, offset);
+                        // The definition always comes first
+                        let skip = usize::from(!params.context.include_declaration);
+                        references
+                            .iter()
+                            .skip(skip)
+                            .filter_map(|l| doc.analysis.lsp_location(&uri, l))
+                            .collect()
+                    })
+                    .unwrap_or_default();
+                Response::new_ok(id, locations)
+            }
+            <DocumentSymbolRequest as lsp_types::request::Request>::METHOD => {
+                let (id, params) = extract_request::<DocumentSymbolRequest>(request)?;
+                let symbols = self
+                    .documents
+                    .get(&params.text_document.uri)
+                    .map(|doc| doc.analysis.symbols.clone())
+                    .unwrap_or_default();
+                Response::new_ok(id, DocumentSymbolResponse::Nested(symbols))
+            }
+            <CodeActionRequest as lsp_types::request::Request>::METHOD => {
+                let (id, params) = extract_request::<CodeActionRequest>(request)?;
+                let actions =
+                    analysis::quick_fixes(&params.text_document.uri, &params.context.diagnostics);
+                Response::new_ok(id, actions)
+            }
+            _ => Response::new_err(
+                request.id,
+                lsp_server::ErrorCode::MethodNotFound as i32,
+                format!("unsupported request '{}'", request.method),
+            ),
+        };
+        self.connection.sender.send(Message::Response(response))?;
+        Ok(())
+    }
+
+    fn handle_notification(&mut self, notification: Notification) -> anyhow::Result<()> {
+        match notification.method.as_str() {
+            <DidOpenTextDocument as lsp_types::notification::Notification>::METHOD => {
+                let params: lsp_types::DidOpenTextDocumentParams =
+                    serde_json::from_value(notification.params)?;
+                self.update(params.text_document.uri, params.text_document.text)?;
+            }
+            <DidChangeTextDocument as lsp_types::notification::Notification>::METHOD => {
+                let params: lsp_types::DidChangeTextDocumentParams =
+                    serde_json::from_value(notification.params)?;
+                // We only advertise full sync, so the last change holds the whole document
+                if let Some(change) = params.content_changes.into_iter().last() {
+                    self.update(params.text_document.uri, change.text)?;
+                }
+            }
+            <DidCloseTextDocument as lsp_types::notification::Notification>::METHOD => {
+                let params: lsp_types::DidCloseTextDocumentParams =
+                    serde_json::from_value(notification.params)?;
+                self.documents.remove(&params.text_document.uri);
+                self.publish(params.text_document.uri, Vec::new())?;
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+
+    fn update(&mut self, uri: Url, text: String) -> anyhow::Result<()> {
+        let index = LineIndex::new(&text);
+        let path = uri
+            .to_file_path()
+            .unwrap_or_else(|_| PathBuf::from(uri.path()));
+        let (diagnostics, analysis) =
+            analysis::analyze(self.game, path, &text, &index, &self.functions);
+        let analysis = match (analysis, self.documents.remove(&uri)) {
+            (Some(analysis), _) => analysis,
+            (None, Some(previous)) => previous.analysis,
+            (None, None) => Analysis::default(),
+        };
+        self.documents
+            .insert(uri.clone(), Document { index, analysis });
+        self.publish(uri, diagnostics)
+    }
+
+    fn publish(&self, uri: Url, diagnostics: Vec<lsp_types::Diagnostic>) -> anyhow::Result<()> {
+        let version = None;
+        let params = PublishDiagnosticsParams::new(uri, diagnostics, version);
+        let notification = Notification::new(
+            <PublishDiagnostics as lsp_types::notification::Notification>::METHOD.to_owned(),
+            params,
+        );
+        self.connection
+            .sender
+            .send(Message::Notification(notification))?;
+        Ok(())
+    }
+}
+
+fn completion_item(completion: &CompletionSuggestion) -> CompletionItem {
+    let kind = match completion.kind {
+        CompletionKind::Constant => CompletionItemKind::CONSTANT,
+        CompletionKind::Enum => CompletionItemKind::ENUM,
+        CompletionKind::EnumVariant => CompletionItemKind::ENUM_MEMBER,
+        CompletionKind::Function => CompletionItemKind::FUNCTION,
+        CompletionKind::Label => CompletionItemKind::REFERENCE,
+    };
+    CompletionItem {
+        label: completion.label.clone(),
+        kind: Some(kind),
+        detail: completion.detail.clone(),
+        ..Default::default()
+    }
+}
+
+fn signature_help(hint: ParameterHint) -> SignatureHelp {
+    // Offsets in signature labels are measured in UTF-16 code units
+    let utf16 = |offset: usize| hint.label[..offset].encode_utf16().count() as u32;
+    let parameters = hint
+        .parameters
+        .iter()
+        .map(|r| ParameterInformation {
+            label: ParameterLabel::LabelOffsets([utf16(r.start), utf16(r.end)]),
+            documentation: None,
+        })
+        .collect();
+    SignatureHelp {
+        signatures: vec![SignatureInformation {
+            label: hint.label.clone(),
+            documentation: hint.docs.clone().map(Documentation::String),
+            parameters: Some(parameters),
+            active_parameter: None,
+        }],
+        active_signature: Some(0),
+        active_parameter: Some(hint.active_parameter as u32),
+    }
+}
+
+fn hover_contents(hover: exalt_completions::Hover) -> Hover {
+    let mut text = format!("```exalt\n{}\n```", hover.signature);
+    if let Some(arity) = hover.arity {
+        text.push_str(&format!("\n\nTakes {} argument(s).", arity));
+    }
+    if let Some(docs) = hover.docs {
+        text.push_str("\n\n");
+        text.push_str(&docs);
+    }
+    Hover {
+        contents: HoverContents::Markup(MarkupContent {
+            kind: MarkupKind::Markdown,
+            value: text,
+        }),
+        range: None,
+    }
+}
+
+fn extract_request<R>(request: Request) -> anyhow::Result<(RequestId, R::Params)>
+where
+    R: lsp_types::request::Request,
+{
+    request
+        .extract(R::METHOD)
+        .map_err(|err| anyhow::anyhow!("bad request: {:?}", err))
+}
+
+fn main() -> anyhow::Result<()> {
+    let args = Args::parse();
+    let functions = match &args.functions {
+        Some(path) => FunctionDatabase::parse(
+            &std::fs::read_to_string(path).context("failed to read function list")?,
+        ),
+        None => FunctionDatabase::default(),
+    };
+    let (connection, io_threads) = Connection::stdio();
+    let capabilities = ServerCapabilities {
+        text_document_sync: Some(TextDocumentSyncCapability::Kind(TextDocumentSyncKind::FULL)),
+        completion_provider: Some(CompletionOptions {
+            trigger_characters: Some(vec![".".to_owned()]),
+            ..Default::default()
+        }),
+        signature_help_provider: Some(SignatureHelpOptions {
+            trigger_characters: Some(vec!["(".to_owned(), ",".to_owned()]),
+            ..Default::default()
+        }),
+        document_symbol_provider: Some(OneOf::Left(true)),
+        definition_provider: Some(OneOf::Left(true)),
+        hover_provider: Some(HoverProviderCapability::Simple(true)),
+        semantic_tokens_provider: Some(SemanticTokensServerCapabilities::SemanticTokensOptions(
+            SemanticTokensOptions {
+                legend: SemanticTokensLegend {
+                    token_types: analysis::TOKEN_TYPES.to_vec(),
+                    token_modifiers: Vec::new(),
+                },
+                full: Some(SemanticTokensFullOptions::Bool(true)),
+                ..Default::default()
+            },
+        )),
+        references_provider: Some(OneOf::Left(true)),
+        code_action_provider: Some(CodeActionProviderCapability::Simple(true)),
+        ..Default::default()
+    };
+    connection
+        .initialize(serde_json::to_value(capabilities)?)
+        .context("failed to initialize language server")?;
+    let mut server = Server {
+        game: args.game,
+        functions,
+        connection,
+        documents: HashMap::new(),
+    };
+    server.run()?;
+
+    // The writer thread only exits once the connection is gone
+    drop(server);
+    io_threads.join()?;
+    Ok(())
+}
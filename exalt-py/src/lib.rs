@@ -0,0 +1,122 @@
+//! Python bindings for the compiler, decompiler, assembler, and disassembler.
+//!
+//! Games are passed by name (ex. "FE14"). Raw scripts are plain dicts and lists with the same
+//! layout as `exalt-cli disassemble --format json`, so they can be edited and passed back to `assemble`.
+
+use std::collections::HashMap;
+use std::str::FromStr;
+
+use exalt_compiler::{CompilerError, MemoryIncludes};
+use exalt_decompiler::DecompilerOptions;
+use exalt_lir::{Game, RawScript};
+use pyo3::create_exception;
+use pyo3::exceptions::{PyException, PyValueError};
+use pyo3::prelude::*;
+use pyo3::types::PyBytes;
+
+create_exception!(
+    exalt,
+    CompileError,
+    PyException,
+    "Compiling failed. The first argument is the message and the second is a list of diagnostics."
+);
+
+fn parse_game(game: &str) -> PyResult<Game> {
+    Game::from_str(game).map_err(|_| PyValueError::new_err(format!("unknown game '{}'", game)))
+}
+
+fn to_py_err(err: impl std::fmt::Display) -> PyErr {
+    PyValueError::new_err(err.to_string())
+}
+
+/// Round trip through JSON so Python gets the same shape serde produces for the CLI.
+fn to_python(py: Python<'_>, script: &RawScript) -> PyResult<PyObject> {
+    let json = serde_json::to_string(script).map_err(to_py_err)?;
+    Ok(py.import("json")?.call_method1("loads", (json,))?.unbind())
+}
+
+fn from_python(py: Python<'_>, script: &Bound<'_, PyAny>) -> PyResult<RawScript> {
+    let json: String = py
+        .import("json")?
+        .call_method1("dumps", (script,))?
+        .extract()?;
+    serde_json::from_str(&json).map_err(to_py_err)
+}
+
+/// Compile a script to its binary form.
+/// Includes are looked up in `includes`, a dict mapping script names (ex. "std/fe14/prelude.exl") to their sources.
+#[pyfunction]
+#[pyo3(signature = (name, source, game, includes=None))]
+fn compile<'py>(
+    py: Python<'py>,
+    name: &str,
+    source: &str,
+    game: &str,
+    includes: Option<HashMap<String, String>>,
+) -> PyResult<Bound<'py, PyBytes>> {
+    let game = parse_game(game)?;
+    let includes = MemoryIncludes::from(includes.unwrap_or_default());
+    match exalt_compiler::compile_source(name, source, &includes, game) {
+        Ok(raw) => Ok(PyBytes::new(py, &raw)),
+        Err(CompilerError::ParseError(log)) => {
+            let diagnostics = py
+                .import("json")?
+                .call_method1("loads", (log.to_json(),))?
+                .unbind();
+            Err(CompileError::new_err((
+                CompilerError::ParseError(log).to_string(),
+                diagnostics,
+            )))
+        }
+        Err(err) => Err(to_py_err(err)),
+    }
+}
+
+/// Decompile a binary script to source without the standard library's names.
+#[pyfunction]
+fn decompile(script: &[u8], game: &str) -> PyResult<String> {
+    let game = parse_game(game)?;
+    let script = exalt_disassembler::disassemble(script, game).map_err(to_py_err)?;
+    exalt_decompiler::decompile(
+        &script,
+        None,
+        &DecompilerOptions::default(),
+        Vec::new(),
+        game,
+        false,
+        false,
+    )
+    .map_err(to_py_err)
+}
+
+/// Assemble a raw script (as returned by `disassemble`) to its binary form.
+#[pyfunction]
+fn assemble<'py>(
+    py: Python<'py>,
+    script: &Bound<'py, PyAny>,
+    script_name: &str,
+    game: &str,
+) -> PyResult<Bound<'py, PyBytes>> {
+    let game = parse_game(game)?;
+    let script = from_python(py, script)?;
+    let raw = exalt_assembler::assemble(&script, script_name, game).map_err(to_py_err)?;
+    Ok(PyBytes::new(py, &raw))
+}
+
+/// Disassemble a binary script to a raw script.
+#[pyfunction]
+fn disassemble(py: Python<'_>, script: &[u8], game: &str) -> PyResult<PyObject> {
+    let game = parse_game(game)?;
+    let script = exalt_disassembler::disassemble(script, game).map_err(to_py_err)?;
+    to_python(py, &script)
+}
+
+#[pymodule]
+fn exalt(m: &Bound<'_, PyModule>) -> PyResult<()> {
+    m.add("CompileError", m.py().get_type::<CompileError>())?;
+    m.add_function(wrap_pyfunction!(compile, m)?)?;
+    m.add_function(wrap_pyfunction!(decompile, m)?)?;
+    m.add_function(wrap_pyfunction!(assemble, m)?)?;
+    m.add_function(wrap_pyfunction!(disassemble, m)?)?;
+    Ok(())
+}
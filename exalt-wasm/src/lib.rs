@@ -0,0 +1,67 @@
+//! JavaScript bindings for the compiler, decompiler, assembler, and disassembler.
+//!
+//! Games are passed by name (ex. "FE14") and raw scripts are exchanged as JSON,
+//! the same format `exalt-cli disassemble --format json` writes.
+//! Errors are thrown as strings. Compiler diagnostics are thrown as a JSON array of records.
+
+use std::collections::HashMap;
+use std::str::FromStr;
+
+use exalt_compiler::{CompilerError, MemoryIncludes};
+use exalt_decompiler::DecompilerOptions;
+use exalt_lir::{Game, RawScript};
+use wasm_bindgen::prelude::*;
+
+fn parse_game(game: &str) -> Result<Game, JsValue> {
+    Game::from_str(game).map_err(|_| JsValue::from_str(&format!("unknown game '{}'", game)))
+}
+
+fn to_js_error(err: impl std::fmt::Display) -> JsValue {
+    JsValue::from_str(&err.to_string())
+}
+
+/// Compile a script to its binary form.
+/// `includes` is a JSON object mapping script names (ex. "std/fe14/prelude.exl") to their sources.
+#[wasm_bindgen]
+pub fn compile(name: &str, source: &str, includes: &str, game: &str) -> Result<Vec<u8>, JsValue> {
+    let game = parse_game(game)?;
+    let includes: HashMap<String, String> = serde_json::from_str(includes).map_err(to_js_error)?;
+    let includes = MemoryIncludes::from(includes);
+    exalt_compiler::compile_source(name, source, &includes, game).map_err(|err| match err {
+        CompilerError::ParseError(log) => JsValue::from_str(&log.to_json()),
+        err => to_js_error(err),
+    })
+}
+
+/// Decompile a binary script to source without the standard library's names.
+#[wasm_bindgen]
+pub fn decompile(script: &[u8], game: &str) -> Result<String, JsValue> {
+    let game = parse_game(game)?;
+    let script = exalt_disassembler::disassemble(script, game).map_err(to_js_error)?;
+    exalt_decompiler::decompile(
+        &script,
+        None,
+        &DecompilerOptions::default(),
+        Vec::new(),
+        game,
+        false,
+        false,
+    )
+    .map_err(to_js_error)
+}
+
+/// Assemble a raw script from its JSON form.
+#[wasm_bindgen]
+pub fn assemble(script: &str, script_name: &str, game: &str) -> Result<Vec<u8>, JsValue> {
+    let game = parse_game(game)?;
+    let script: RawScript = serde_json::from_str(script).map_err(to_js_error)?;
+    exalt_assembler::assemble(&script, script_name, game).map_err(to_js_error)
+}
+
+/// Disassemble a binary script to the JSON form of its raw script.
+#[wasm_bindgen]
+pub fn disassemble(script: &[u8], game: &str) -> Result<String, JsValue> {
+    let game = parse_game(game)?;
+    let script = exalt_disassembler::disassemble(script, game).map_err(to_js_error)?;
+    serde_json::to_string_pretty(&script).map_err(to_js_error)
+}